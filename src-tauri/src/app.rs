@@ -0,0 +1,919 @@
+use crate::benchmark::{BenchmarkConfig, BenchmarkRunner};
+use crate::broker::{BrokerStatus, EmbeddedBroker};
+use crate::bundle::{apply_import, Bundle, ConflictStrategy, ImportSummary};
+use crate::diff::PayloadDiff;
+use crate::export::ExportFormat;
+use crate::filter::{CompiledFilter, MessageFilter, MessageStreams};
+use crate::history::HistoryLog;
+use crate::http_api::{Backend, HttpApi, HttpApiStatus};
+use crate::importers::{ImportPreview, ImportSource};
+use crate::mqtt::{EventSink, Message, MqttClient};
+use crate::recording::{Recorder, RecordingStatus, ReplayOptions, Replayer};
+use crate::repeat::{RepeatScheduler, RepeatStatus};
+use crate::request::Reply;
+use crate::schedule::{ScheduleRun, Scheduler};
+use crate::script::ScriptRunner;
+use crate::sequence::SequenceRunner;
+use crate::simulator::{SimulationConfig, SimulationStatus, Simulator};
+use crate::sparkplug::{CommandMetric, EdgeNodeState};
+use crate::storage::{Storage, StorageInfo, StorageOptions};
+use crate::types::{AppData, Button, Connection, ConnectionStatus, QoS, ScheduleTimezone};
+use crate::{
+    benchmark, broker, diff, export, filter, importers, project_file, protobuf, recording, repeat,
+    request, schedule, schema, script, sequence, simulator,
+};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, State};
+use tauri_plugin_window_state::StateFlags;
+use tokio::sync::RwLock;
+
+struct AppState {
+    storage: Arc<RwLock<Storage>>,
+    mqtt_client: Arc<RwLock<MqttClient>>,
+    sequences: SequenceRunner,
+    scripts: ScriptRunner,
+    repeats: RepeatScheduler,
+    scheduler: Arc<Scheduler>,
+    simulator: Simulator,
+    benchmarks: BenchmarkRunner,
+    recorder: Recorder,
+    streams: MessageStreams,
+    replayer: Replayer,
+    http_api: Arc<HttpApi>,
+    broker: Arc<EmbeddedBroker>,
+}
+
+impl EventSink for AppHandle {
+    fn status(&self, status: ConnectionStatus) {
+        let _ = self.emit("mqtt-status", status);
+    }
+
+    fn message(&self, msg: &Message) {
+        let _ = self.emit("mqtt-message", msg);
+    }
+}
+
+/// Brings the schedules, HTTP API, embedded broker, schemas and the rules of
+/// the connected connection in line with saved data.
+async fn apply_data(state: &AppState, data: &AppData) {
+    state.scheduler.update(data).await;
+    if let Err(e) = state.http_api.update(&data.http_api).await {
+        warn!("HTTP API not started: {}", e);
+    }
+    if let Err(e) = state.broker.update(&data.embedded_broker).await {
+        warn!("Embedded broker not started: {}", e);
+    }
+    let client = state.mqtt_client.read().await;
+    client.set_schemas(&data.schemas).await;
+    if let Some(id) = client.connection_id() {
+        match data.connections.iter().find(|c| c.id == id) {
+            Some(connection) => {
+                client
+                    .set_responders(&connection.responders, &connection.variables)
+                    .await;
+                client
+                    .set_decoders(&connection.decoders, connection.auto_decompress)
+                    .await;
+                client.set_schema_rules(&connection.message_schemas).await;
+                client.set_diff_previous(connection.diff_previous).await;
+            }
+            None => {
+                client.set_responders(&[], &HashMap::new()).await;
+                client.set_decoders(&[], false).await;
+                client.set_schema_rules(&[]).await;
+                client.set_diff_previous(false).await;
+            }
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_data(state: State<'_, AppState>) -> Result<AppData, String> {
+    let storage = state.storage.read().await;
+    let data = storage.load_data().map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    Ok(data)
+}
+
+#[tauri::command]
+async fn save_data(state: State<'_, AppState>, data: AppData) -> Result<(), String> {
+    let storage = state.storage.read().await;
+    storage.save_data(&data).map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn delete_data(state: State<'_, AppState>) -> Result<(), String> {
+    let storage = state.storage.read().await;
+    storage.delete_data().map_err(|e| e.to_string())?;
+    apply_data(&state, &AppData::default()).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn undo(state: State<'_, AppState>) -> Result<AppData, String> {
+    let storage = state.storage.read().await;
+    let data = storage
+        .undo()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Nothing to undo".to_string())?;
+    apply_data(&state, &data).await;
+    Ok(data)
+}
+
+#[tauri::command]
+async fn redo(state: State<'_, AppState>) -> Result<AppData, String> {
+    let storage = state.storage.read().await;
+    let data = storage
+        .redo()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Nothing to redo".to_string())?;
+    apply_data(&state, &data).await;
+    Ok(data)
+}
+
+#[tauri::command]
+async fn get_history(state: State<'_, AppState>) -> Result<HistoryLog, String> {
+    let storage = state.storage.read().await;
+    Ok(storage.history())
+}
+
+#[tauri::command]
+async fn get_storage_info(state: State<'_, AppState>) -> Result<StorageInfo, String> {
+    let storage = state.storage.read().await;
+    storage.info().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn switch_workspace(state: State<'_, AppState>, name: String) -> Result<AppData, String> {
+    let mut storage = state.storage.write().await;
+    storage.switch_workspace(&name).map_err(|e| e.to_string())?;
+    let data = storage.load_data().map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    Ok(data)
+}
+
+#[tauri::command]
+async fn delete_workspace(state: State<'_, AppState>, name: String) -> Result<(), String> {
+    let storage = state.storage.read().await;
+    storage.delete_workspace(&name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_connections(
+    state: State<'_, AppState>,
+    path: String,
+    connection_ids: Vec<String>,
+    include_secrets: bool,
+) -> Result<(), String> {
+    let storage = state.storage.read().await;
+    let data = storage.load_data().map_err(|e| e.to_string())?;
+    let connections: Vec<Connection> = data
+        .connections
+        .into_iter()
+        .filter(|c| connection_ids.contains(&c.id))
+        .collect();
+    if connections.is_empty() {
+        return Err("No connections selected for export".to_string());
+    }
+    let content = Bundle::new(&connections, include_secrets)
+        .to_json()
+        .map_err(|e| e.to_string())?;
+    std::fs::write(&path, content).map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+struct ImportResult {
+    data: AppData,
+    summary: ImportSummary,
+}
+
+#[tauri::command]
+async fn import_connections(
+    state: State<'_, AppState>,
+    path: String,
+    strategy: ConflictStrategy,
+) -> Result<ImportResult, String> {
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let bundle = Bundle::parse(&content).map_err(|e| e.to_string())?;
+
+    let storage = state.storage.read().await;
+    let mut data = storage.load_data().map_err(|e| e.to_string())?;
+    let summary = apply_import(&mut data, bundle.connections, strategy);
+    if let Some(id) = summary.connection_ids.first() {
+        data.last_connection_id = Some(id.clone());
+    }
+    storage.save_data(&data).map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    info!(
+        "Imported {} connection(s) from {}",
+        summary.connection_ids.len(),
+        path
+    );
+    Ok(ImportResult { data, summary })
+}
+
+#[tauri::command]
+async fn preview_external_import(
+    path: String,
+    source: Option<ImportSource>,
+) -> Result<ImportPreview, String> {
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    importers::convert(&content, source).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_external(
+    state: State<'_, AppState>,
+    path: String,
+    source: Option<ImportSource>,
+    strategy: ConflictStrategy,
+) -> Result<ImportResult, String> {
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let preview = importers::convert(&content, source).map_err(|e| e.to_string())?;
+
+    let storage = state.storage.read().await;
+    let mut data = storage.load_data().map_err(|e| e.to_string())?;
+    let summary = apply_import(&mut data, preview.connections, strategy);
+    if let Some(id) = summary.connection_ids.first() {
+        data.last_connection_id = Some(id.clone());
+    }
+    storage.save_data(&data).map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    info!(
+        "Imported {} connection(s) from {:?} file {}",
+        summary.connection_ids.len(),
+        preview.source,
+        path
+    );
+    Ok(ImportResult { data, summary })
+}
+
+#[tauri::command]
+async fn save_project_file(
+    state: State<'_, AppState>,
+    connection_id: String,
+    path: String,
+) -> Result<AppData, String> {
+    let storage = state.storage.read().await;
+    let mut data = storage.load_data().map_err(|e| e.to_string())?;
+    let connection = data
+        .connections
+        .iter_mut()
+        .find(|c| c.id == connection_id)
+        .ok_or_else(|| format!("Connection not found: {}", connection_id))?;
+    connection.project_file = Some(path);
+    storage.save_data(&data).map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    Ok(data)
+}
+
+#[tauri::command]
+async fn open_project_file(state: State<'_, AppState>, path: String) -> Result<AppData, String> {
+    let mut connection =
+        project_file::read(std::path::Path::new(&path)).map_err(|e| e.to_string())?;
+    connection.project_file = Some(path.clone());
+
+    let storage = state.storage.read().await;
+    let mut data = storage.load_data().map_err(|e| e.to_string())?;
+    let existing = data
+        .connections
+        .iter()
+        .position(|c| c.project_file.as_deref() == Some(path.as_str()) || c.id == connection.id);
+    match existing {
+        Some(index) => {
+            connection.id = data.connections[index].id.clone();
+            if connection.password.is_none() {
+                connection.password = data.connections[index].password.take();
+            }
+            data.connections[index] = connection;
+        }
+        None => data.connections.push(connection),
+    }
+    data.last_connection_id = data
+        .connections
+        .iter()
+        .find(|c| c.project_file.as_deref() == Some(path.as_str()))
+        .map(|c| c.id.clone());
+    storage.save_data(&data).map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    info!("Opened project file {}", path);
+    Ok(data)
+}
+
+#[tauri::command]
+async fn unlink_project_file(
+    state: State<'_, AppState>,
+    connection_id: String,
+) -> Result<AppData, String> {
+    let storage = state.storage.read().await;
+    let mut data = storage.load_data().map_err(|e| e.to_string())?;
+    if let Some(connection) = data.connections.iter_mut().find(|c| c.id == connection_id) {
+        connection.project_file = None;
+    }
+    storage.save_data(&data).map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    Ok(data)
+}
+
+#[tauri::command]
+async fn connect(state: State<'_, AppState>, connection: Connection) -> Result<(), String> {
+    let mut client = state.mqtt_client.write().await;
+    client.connect(&connection).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn disconnect(state: State<'_, AppState>) -> Result<(), String> {
+    state.repeats.stop_all().await;
+    let mut client = state.mqtt_client.write().await;
+    client.disconnect().await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn publish(
+    state: State<'_, AppState>,
+    topic: String,
+    payload: String,
+    qos: QoS,
+    retain: bool,
+) -> Result<(), String> {
+    let client = state.mqtt_client.read().await;
+    client
+        .publish(&topic, &payload, qos, retain)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn publish_button(
+    state: State<'_, AppState>,
+    button: Button,
+    variables: HashMap<String, String>,
+) -> Result<(), String> {
+    let client = state.mqtt_client.read().await;
+    client
+        .publish_button(&button, &variables)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn check_schema(schema: serde_json::Value) -> Result<(), String> {
+    schema::compile(&schema).map(|_| ())
+}
+
+#[tauri::command]
+async fn diff_messages(
+    state: State<'_, AppState>,
+    before: u64,
+    after: u64,
+) -> Result<PayloadDiff, String> {
+    let client = state.mqtt_client.read().await;
+    let mut messages = Vec::with_capacity(2);
+    for id in [before, after] {
+        let message = client.message(id).await;
+        messages.push(message.ok_or_else(|| format!("Message not found: {}", id))?);
+    }
+    Ok(diff::diff_messages(&messages[0], &messages[1]))
+}
+
+#[tauri::command]
+async fn subscribe(state: State<'_, AppState>, topic: String, qos: QoS) -> Result<(), String> {
+    let client = state.mqtt_client.read().await;
+    client
+        .subscribe(&topic, qos)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn unsubscribe(state: State<'_, AppState>, topic: String) -> Result<(), String> {
+    let client = state.mqtt_client.read().await;
+    client.unsubscribe(&topic).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_messages(state: State<'_, AppState>) -> Result<Vec<Message>, String> {
+    let client = state.mqtt_client.read().await;
+    Ok(client.get_messages().await)
+}
+
+#[tauri::command]
+async fn query_messages(
+    state: State<'_, AppState>,
+    filter: MessageFilter,
+    limit: Option<usize>,
+) -> Result<Vec<Message>, String> {
+    let filter = CompiledFilter::new(filter).map_err(|e| e.to_string())?;
+    let client = state.mqtt_client.read().await;
+    Ok(client.query_messages(&filter, limit).await)
+}
+
+#[tauri::command]
+async fn export_messages(
+    state: State<'_, AppState>,
+    path: PathBuf,
+    format: ExportFormat,
+    filter: Option<MessageFilter>,
+    limit: Option<usize>,
+) -> Result<usize, String> {
+    let filter = CompiledFilter::new(filter.unwrap_or_default()).map_err(|e| e.to_string())?;
+    let client = state.mqtt_client.read().await;
+    let messages = client.query_messages(&filter, limit).await;
+    export::export(&path, &messages, format).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_message_stream(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    filter: MessageFilter,
+) -> Result<String, String> {
+    let messages = state.mqtt_client.read().await.subscribe_messages();
+    state
+        .streams
+        .start(messages, filter, move |message| {
+            let _ = app.emit(filter::STREAM_EVENT, message);
+        })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_message_stream(state: State<'_, AppState>, stream_id: String) -> Result<(), String> {
+    if state.streams.stop(&stream_id).await {
+        Ok(())
+    } else {
+        Err(format!("Message stream not found: {}", stream_id))
+    }
+}
+
+#[tauri::command]
+fn list_message_types(path: PathBuf) -> Result<Vec<String>, String> {
+    protobuf::message_types(&path).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_sparkplug_nodes(state: State<'_, AppState>) -> Result<Vec<EdgeNodeState>, String> {
+    Ok(state.mqtt_client.read().await.sparkplug_nodes().await)
+}
+
+#[tauri::command]
+async fn send_sparkplug_command(
+    state: State<'_, AppState>,
+    group: String,
+    edge_node: String,
+    device: Option<String>,
+    metrics: Vec<CommandMetric>,
+) -> Result<String, String> {
+    let client = state.mqtt_client.read().await;
+    client
+        .publish_sparkplug_command(&group, &edge_node, device.as_deref(), &metrics)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn clear_messages(state: State<'_, AppState>) -> Result<(), String> {
+    let client = state.mqtt_client.read().await;
+    client.clear_messages().await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_subscriptions(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let client = state.mqtt_client.read().await;
+    Ok(client.get_subscriptions().await)
+}
+
+#[tauri::command]
+async fn send_request(
+    state: State<'_, AppState>,
+    button: Button,
+    variables: HashMap<String, String>,
+) -> Result<Reply, String> {
+    request::send_request(&state.mqtt_client, &button, &variables)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_repeat(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    button: Button,
+    variables: HashMap<String, String>,
+) -> Result<RepeatStatus, String> {
+    Ok(state
+        .repeats
+        .start(
+            Arc::clone(&state.mqtt_client),
+            button,
+            variables,
+            move |status| {
+                let _ = app.emit(repeat::REPEAT_EVENT, status);
+            },
+        )
+        .await)
+}
+
+#[tauri::command]
+async fn stop_repeat(state: State<'_, AppState>, button_id: String) -> Result<(), String> {
+    state.repeats.stop(&button_id).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn list_repeats(state: State<'_, AppState>) -> Result<Vec<RepeatStatus>, String> {
+    Ok(state.repeats.list().await)
+}
+
+#[tauri::command]
+async fn get_schedule_log(state: State<'_, AppState>) -> Result<Vec<ScheduleRun>, String> {
+    Ok(state.scheduler.log().await)
+}
+
+#[tauri::command]
+async fn preview_schedule(
+    cron: String,
+    timezone: ScheduleTimezone,
+    count: usize,
+) -> Result<Vec<u64>, String> {
+    let times = schedule::upcoming(&cron, timezone, count)?;
+    Ok(times.iter().map(|t| t.timestamp_millis() as u64).collect())
+}
+
+#[tauri::command]
+async fn run_sequence(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    connection: Connection,
+    sequence_id: String,
+) -> Result<String, String> {
+    state
+        .sequences
+        .start(
+            Arc::clone(&state.mqtt_client),
+            connection,
+            &sequence_id,
+            move |progress| {
+                let _ = app.emit(sequence::PROGRESS_EVENT, progress);
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cancel_sequence(state: State<'_, AppState>, run_id: String) -> Result<(), String> {
+    if state.sequences.cancel(&run_id).await {
+        Ok(())
+    } else {
+        Err(format!("Sequence run not found: {}", run_id))
+    }
+}
+
+#[tauri::command]
+async fn get_running_sequences(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    Ok(state.sequences.active_runs().await)
+}
+
+#[tauri::command]
+async fn run_script(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    connection: Connection,
+    script_id: String,
+) -> Result<String, String> {
+    state
+        .scripts
+        .start(
+            Arc::clone(&state.mqtt_client),
+            connection,
+            &script_id,
+            move |output| {
+                let _ = app.emit(script::SCRIPT_EVENT, output);
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cancel_script(state: State<'_, AppState>, run_id: String) -> Result<(), String> {
+    if state.scripts.cancel(&run_id).await {
+        Ok(())
+    } else {
+        Err(format!("Script run not found: {}", run_id))
+    }
+}
+
+#[tauri::command]
+async fn get_running_scripts(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    Ok(state.scripts.active_runs().await)
+}
+
+#[tauri::command]
+fn check_script(source: String) -> Result<(), String> {
+    script::check(&source).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_simulation(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    connection: Connection,
+    config: SimulationConfig,
+) -> Result<(), String> {
+    state
+        .simulator
+        .start(connection, config, move |status| {
+            let _ = app.emit(simulator::SIMULATOR_EVENT, status);
+        })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_simulation(state: State<'_, AppState>) -> Result<(), String> {
+    state.simulator.stop().await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_simulation_status(state: State<'_, AppState>) -> Result<SimulationStatus, String> {
+    Ok(state.simulator.status().await)
+}
+
+#[tauri::command]
+async fn start_benchmark(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    connection: Connection,
+    config: BenchmarkConfig,
+) -> Result<(), String> {
+    state
+        .benchmarks
+        .start(connection, config, move |report| {
+            let _ = app.emit(benchmark::BENCHMARK_EVENT, report);
+        })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cancel_benchmark(state: State<'_, AppState>) -> Result<(), String> {
+    if state.benchmarks.cancel().await {
+        Ok(())
+    } else {
+        Err("No benchmark is running".to_string())
+    }
+}
+
+#[tauri::command]
+async fn start_recording(
+    state: State<'_, AppState>,
+    path: PathBuf,
+    filter: Option<String>,
+) -> Result<RecordingStatus, String> {
+    let client = state.mqtt_client.read().await;
+    let id = client.connection_id().ok_or("Not connected")?;
+    let data = state
+        .storage
+        .read()
+        .await
+        .load_data()
+        .map_err(|e| e.to_string())?;
+    let (name, broker) = match data.connections.iter().find(|c| c.id == id) {
+        Some(c) => (c.name.clone(), format!("{}:{}", c.broker_url, c.port)),
+        None => (id.to_string(), String::new()),
+    };
+    state
+        .recorder
+        .start(
+            client.subscribe_messages(),
+            path,
+            name,
+            broker,
+            filter.filter(|f| !f.is_empty()),
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_recording(state: State<'_, AppState>) -> Result<RecordingStatus, String> {
+    state
+        .recorder
+        .stop()
+        .await
+        .ok_or_else(|| "No recording is running".to_string())
+}
+
+#[tauri::command]
+async fn get_http_api_status(state: State<'_, AppState>) -> Result<HttpApiStatus, String> {
+    Ok(state.http_api.status().await)
+}
+
+#[tauri::command]
+async fn get_embedded_broker_status(state: State<'_, AppState>) -> Result<BrokerStatus, String> {
+    Ok(state.broker.status().await)
+}
+
+#[tauri::command]
+async fn add_embedded_broker_connection(state: State<'_, AppState>) -> Result<AppData, String> {
+    let storage = state.storage.read().await;
+    let mut data = storage.load_data().map_err(|e| e.to_string())?;
+    broker::add_preset_connection(&mut data);
+    storage.save_data(&data).map_err(|e| e.to_string())?;
+    apply_data(&state, &data).await;
+    Ok(data)
+}
+
+#[tauri::command]
+async fn get_recording_status(
+    state: State<'_, AppState>,
+) -> Result<Option<RecordingStatus>, String> {
+    Ok(state.recorder.status().await)
+}
+
+#[tauri::command]
+async fn start_replay(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    connection_id: String,
+    path: PathBuf,
+    options: ReplayOptions,
+) -> Result<(), String> {
+    state
+        .replayer
+        .start(
+            Arc::clone(&state.mqtt_client),
+            &connection_id,
+            path,
+            options,
+            move |progress| {
+                let _ = app.emit(recording::REPLAY_EVENT, progress);
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cancel_replay(state: State<'_, AppState>) -> Result<(), String> {
+    if state.replayer.cancel().await {
+        Ok(())
+    } else {
+        Err("No replay is running".to_string())
+    }
+}
+
+pub fn run() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{}] [{}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.args()
+            )
+        })
+        .init();
+    info!("Starting MQTT Topic Lab");
+
+    let storage = Storage::new(&StorageOptions::from_env()).expect("Failed to initialize storage");
+    info!(
+        "Using data directory {} (workspace '{}')",
+        storage.app_dir().display(),
+        storage.workspace()
+    );
+    let initial_data = storage.load_data().unwrap_or_default();
+    let storage = Arc::new(RwLock::new(storage));
+    let mqtt_client = Arc::new(RwLock::new(MqttClient::new()));
+    let scheduler = Arc::new(Scheduler::default());
+    let http_api = Arc::new(HttpApi::default());
+    let broker = Arc::new(EmbeddedBroker::default());
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_fs::init())
+        .plugin(
+            tauri_plugin_window_state::Builder::new()
+                .with_state_flags(StateFlags::all())
+                .build(),
+        )
+        .manage(AppState {
+            storage: Arc::clone(&storage),
+            mqtt_client: Arc::clone(&mqtt_client),
+            sequences: SequenceRunner::default(),
+            scripts: ScriptRunner::default(),
+            repeats: RepeatScheduler::default(),
+            scheduler: Arc::clone(&scheduler),
+            http_api: Arc::clone(&http_api),
+            broker: Arc::clone(&broker),
+            simulator: Simulator::default(),
+            benchmarks: BenchmarkRunner::default(),
+            recorder: Recorder::default(),
+            streams: MessageStreams::default(),
+            replayer: Replayer::default(),
+        })
+        .setup(move |app| {
+            let handle = app.handle().clone();
+            let client = Arc::clone(&mqtt_client);
+            tauri::async_runtime::block_on(async {
+                client
+                    .write()
+                    .await
+                    .set_event_sink(Arc::new(handle.clone()));
+                scheduler.update(&initial_data).await;
+                http_api.set_backend(Backend {
+                    storage: Arc::clone(&storage),
+                    mqtt_client: Arc::clone(&client),
+                });
+                if let Err(e) = http_api.update(&initial_data.http_api).await {
+                    warn!("HTTP API not started: {}", e);
+                }
+                if let Err(e) = broker.update(&initial_data.embedded_broker).await {
+                    warn!("Embedded broker not started: {}", e);
+                }
+            });
+            tauri::async_runtime::spawn(scheduler.run(mqtt_client, move |run| {
+                let _ = handle.emit(schedule::SCHEDULE_EVENT, run);
+            }));
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            get_data,
+            save_data,
+            delete_data,
+            undo,
+            redo,
+            get_history,
+            get_storage_info,
+            switch_workspace,
+            delete_workspace,
+            export_connections,
+            import_connections,
+            preview_external_import,
+            import_external,
+            save_project_file,
+            open_project_file,
+            unlink_project_file,
+            connect,
+            disconnect,
+            publish,
+            publish_button,
+            check_schema,
+            diff_messages,
+            subscribe,
+            unsubscribe,
+            get_messages,
+            query_messages,
+            export_messages,
+            start_message_stream,
+            stop_message_stream,
+            list_message_types,
+            get_sparkplug_nodes,
+            send_sparkplug_command,
+            clear_messages,
+            get_subscriptions,
+            send_request,
+            start_repeat,
+            stop_repeat,
+            list_repeats,
+            get_schedule_log,
+            preview_schedule,
+            run_sequence,
+            cancel_sequence,
+            get_running_sequences,
+            run_script,
+            cancel_script,
+            get_running_scripts,
+            check_script,
+            start_simulation,
+            stop_simulation,
+            get_simulation_status,
+            start_benchmark,
+            cancel_benchmark,
+            start_recording,
+            stop_recording,
+            get_recording_status,
+            get_http_api_status,
+            get_embedded_broker_status,
+            add_embedded_broker_connection,
+            start_replay,
+            cancel_replay,
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
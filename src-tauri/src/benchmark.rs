@@ -0,0 +1,670 @@
+use crate::mqtt::build_options;
+use crate::template::substitute_variables;
+use crate::types::{Connection, QoS};
+use log::{info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, Outgoing, Packet};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{oneshot, watch, Mutex};
+
+pub const BENCHMARK_EVENT: &str = "benchmark-progress";
+pub const MAX_CLIENTS: u32 = 100;
+pub const MAX_PAYLOAD_SIZE: usize = 1024 * 1024;
+/// Bounds of the total publish rate, in messages per second.
+pub const MIN_RATE: f64 = 0.001;
+pub const MAX_RATE: f64 = 1_000_000.0;
+const MIN_PERIOD: Duration = Duration::from_micros(1);
+const MAX_PERIOD: Duration = Duration::from_secs(3600);
+const DEFAULT_TOPIC: &str = "bench/{run}/{client}";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// Marks benchmark payloads so foreign messages on the topic are ignored.
+const MAGIC: &[u8; 4] = b"MTLB";
+const HEADER_LEN: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum BenchmarkError {
+    #[error("Client count must be between 1 and {MAX_CLIENTS}")]
+    InvalidClientCount,
+    #[error("Payload size must be at most {MAX_PAYLOAD_SIZE} bytes")]
+    PayloadTooLarge,
+    #[error("Set a message count or a duration")]
+    NoLimit,
+    #[error("Rate must be between {MIN_RATE} and {MAX_RATE} messages per second")]
+    InvalidRate,
+    #[error("A benchmark is already running")]
+    AlreadyRunning,
+    #[error("Client {0} failed to connect: {1}")]
+    Connect(u32, String),
+    #[error("Clients did not connect within {0} s")]
+    ConnectTimeout(u64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkConfig {
+    pub client_count: u32,
+    #[serde(default)]
+    pub qos: QoS,
+    /// Never smaller than the 16 byte header carrying the send time.
+    pub payload_size: usize,
+    /// Topic template with the connection variables, `{client}` and `{run}`.
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// As fast as possible when unset.
+    #[serde(default)]
+    pub rate: Option<f64>,
+    #[serde(default)]
+    pub message_count: Option<u64>,
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    #[serde(default = "default_true")]
+    pub measure_latency: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyStats {
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkReport {
+    pub running: bool,
+    pub cancelled: bool,
+    pub elapsed_ms: u64,
+    pub sent: u64,
+    /// QoS 0 publishes count once written to the socket.
+    pub acked: u64,
+    pub received: u64,
+    pub errors: u64,
+    pub throughput: f64,
+    pub latency: Option<LatencyStats>,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Stats {
+    sent: AtomicU64,
+    acked: AtomicU64,
+    received: AtomicU64,
+    errors: AtomicU64,
+    latencies_us: StdMutex<Vec<u64>>,
+}
+
+impl Stats {
+    fn report(&self, elapsed: Duration, running: bool, cancelled: bool) -> BenchmarkReport {
+        let sent = self.sent.load(Ordering::Relaxed);
+        let seconds = elapsed.as_secs_f64();
+        BenchmarkReport {
+            running,
+            cancelled,
+            elapsed_ms: elapsed.as_millis() as u64,
+            sent,
+            acked: self.acked.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            throughput: if seconds > 0.0 {
+                sent as f64 / seconds
+            } else {
+                0.0
+            },
+            latency: latency_stats(&self.latencies_us.lock().unwrap()),
+            error: None,
+        }
+    }
+}
+
+struct Shared {
+    config: BenchmarkConfig,
+    /// Send times in payloads are relative to this instant.
+    epoch: Instant,
+    stats: Stats,
+}
+
+fn latency_stats(samples_us: &[u64]) -> Option<LatencyStats> {
+    if samples_us.is_empty() {
+        return None;
+    }
+    let mut sorted = samples_us.to_vec();
+    sorted.sort_unstable();
+    let ms = |us: u64| us as f64 / 1000.0;
+    let percentile =
+        |p: f64| ms(sorted[((p / 100.0) * (sorted.len() - 1) as f64).round() as usize]);
+    Some(LatencyStats {
+        min_ms: ms(sorted[0]),
+        mean_ms: ms(sorted.iter().sum::<u64>() / sorted.len() as u64),
+        p50_ms: percentile(50.0),
+        p90_ms: percentile(90.0),
+        p99_ms: percentile(99.0),
+        max_ms: ms(sorted[sorted.len() - 1]),
+    })
+}
+
+fn encode_payload(client: u32, sent_us: u64, size: usize) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(size.max(HEADER_LEN));
+    payload.extend_from_slice(MAGIC);
+    payload.extend_from_slice(&client.to_be_bytes());
+    payload.extend_from_slice(&sent_us.to_be_bytes());
+    payload.resize(size.max(HEADER_LEN), b'x');
+    payload
+}
+
+fn decode_payload(payload: &[u8]) -> Option<(u32, u64)> {
+    if payload.len() < HEADER_LEN || &payload[..4] != MAGIC {
+        return None;
+    }
+    let client = u32::from_be_bytes(payload[4..8].try_into().ok()?);
+    let sent_us = u64::from_be_bytes(payload[8..16].try_into().ok()?);
+    Some((client, sent_us))
+}
+
+fn validate(config: &BenchmarkConfig) -> Result<(), BenchmarkError> {
+    if config.client_count == 0 || config.client_count > MAX_CLIENTS {
+        return Err(BenchmarkError::InvalidClientCount);
+    }
+    if config.payload_size > MAX_PAYLOAD_SIZE {
+        return Err(BenchmarkError::PayloadTooLarge);
+    }
+    if config.message_count.unwrap_or(0) == 0 && config.duration_ms.unwrap_or(0) == 0 {
+        return Err(BenchmarkError::NoLimit);
+    }
+    if config
+        .rate
+        .is_some_and(|rate| !(MIN_RATE..=MAX_RATE).contains(&rate))
+    {
+        return Err(BenchmarkError::InvalidRate);
+    }
+    Ok(())
+}
+
+fn publish_period(clients: u32, rate: f64) -> Duration {
+    let secs = f64::from(clients) / rate;
+    if secs.is_nan() || secs >= MAX_PERIOD.as_secs_f64() {
+        MAX_PERIOD
+    } else {
+        Duration::from_secs_f64(secs.max(0.0)).max(MIN_PERIOD)
+    }
+}
+
+/// The remainder is spread over the first clients.
+fn client_share(total: u64, clients: u32, index: u32) -> u64 {
+    let clients = u64::from(clients);
+    total / clients + u64::from(u64::from(index) < total % clients)
+}
+
+#[derive(Default)]
+pub struct BenchmarkRunner {
+    cancel: Arc<Mutex<Option<watch::Sender<bool>>>>,
+}
+
+impl BenchmarkRunner {
+    /// `on_progress` receives a report twice a second and a final one at the end.
+    pub async fn start<F>(
+        &self,
+        connection: Connection,
+        config: BenchmarkConfig,
+        on_progress: F,
+    ) -> Result<(), BenchmarkError>
+    where
+        F: Fn(BenchmarkReport) + Send + Sync + 'static,
+    {
+        validate(&config)?;
+        let mut current = self.cancel.lock().await;
+        if current.is_some() {
+            return Err(BenchmarkError::AlreadyRunning);
+        }
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        *current = Some(cancel_tx);
+
+        let slot = Arc::clone(&self.cancel);
+        tokio::spawn(async move {
+            if let Err(e) = run(&connection, &config, cancel_rx, &on_progress).await {
+                warn!("Benchmark failed: {}", e);
+                on_progress(BenchmarkReport {
+                    error: Some(e.to_string()),
+                    ..Stats::default().report(Duration::ZERO, false, false)
+                });
+            }
+            *slot.lock().await = None;
+        });
+        Ok(())
+    }
+
+    pub async fn cancel(&self) -> bool {
+        match self.cancel.lock().await.as_ref() {
+            Some(tx) => {
+                let _ = tx.send(true);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub async fn run<F>(
+    connection: &Connection,
+    config: &BenchmarkConfig,
+    mut cancel: watch::Receiver<bool>,
+    on_progress: &F,
+) -> Result<BenchmarkReport, BenchmarkError>
+where
+    F: Fn(BenchmarkReport),
+{
+    validate(config)?;
+    let run_id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+    info!(
+        "Starting benchmark {} with {} clients",
+        run_id, config.client_count
+    );
+
+    let shared = Arc::new(Shared {
+        config: config.clone(),
+        epoch: Instant::now(),
+        stats: Stats::default(),
+    });
+    let stats = &shared.stats;
+    let (done_tx, done_rx) = watch::channel(false);
+    let mut clients = Vec::new();
+    let mut pollers = Vec::new();
+    let mut ready = Vec::new();
+    for index in 0..config.client_count {
+        let mut variables = connection.variables.clone();
+        variables.insert("client".to_string(), index.to_string());
+        variables.insert("run".to_string(), run_id.clone());
+        let topic =
+            substitute_variables(config.topic.as_deref().unwrap_or(DEFAULT_TOPIC), &variables);
+
+        let client_id = format!("{}-bench-{}-{}", connection.client_id, run_id, index);
+        let mut options = build_options(connection, &client_id);
+        options.set_inflight(100);
+        let (client, eventloop) = AsyncClient::new(options, 100);
+        if config.measure_latency {
+            let _ = client.try_subscribe(topic.clone(), config.qos.into());
+        }
+
+        let (ready_tx, ready_rx) = oneshot::channel();
+        pollers.push(tokio::spawn(poll(
+            eventloop,
+            client.clone(),
+            index,
+            Arc::clone(&shared),
+            ready_tx,
+            done_rx.clone(),
+        )));
+        ready.push(ready_rx);
+        clients.push((client, topic));
+    }
+
+    let finish = |result| {
+        let _ = done_tx.send(true);
+        async move {
+            for poller in pollers {
+                let _ = poller.await;
+            }
+            result
+        }
+    };
+
+    let connected = tokio::time::timeout(CONNECT_TIMEOUT, async {
+        for (index, rx) in ready.into_iter().enumerate() {
+            match rx.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Err(BenchmarkError::Connect(index as u32, e)),
+                Err(_) => return Err(BenchmarkError::Connect(index as u32, "closed".to_string())),
+            }
+        }
+        Ok(())
+    })
+    .await
+    .unwrap_or(Err(BenchmarkError::ConnectTimeout(
+        CONNECT_TIMEOUT.as_secs(),
+    )));
+    if let Err(e) = connected {
+        return finish(Err(e)).await;
+    }
+
+    let started = Instant::now();
+    let deadline = config
+        .duration_ms
+        .filter(|ms| *ms > 0)
+        .map(|ms| started + Duration::from_millis(ms));
+    let publishers: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(index, (client, topic))| {
+            let index = index as u32;
+            let limit = config
+                .message_count
+                .filter(|count| *count > 0)
+                .map(|count| client_share(count, config.client_count, index));
+            tokio::spawn(publish(
+                client,
+                topic,
+                index,
+                limit,
+                deadline,
+                Arc::clone(&shared),
+                cancel.clone(),
+            ))
+        })
+        .collect();
+
+    let publishers = join_all(publishers);
+    tokio::pin!(publishers);
+    let mut ticker = tokio::time::interval(PROGRESS_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => on_progress(stats.report(started.elapsed(), true, false)),
+            _ = &mut publishers => break,
+        }
+    }
+
+    // Give the broker time to acknowledge and echo what was sent.
+    let drain_until = Instant::now() + DRAIN_TIMEOUT;
+    while !*cancel.borrow() && Instant::now() < drain_until {
+        let sent = stats.sent.load(Ordering::Relaxed);
+        let drained = stats.acked.load(Ordering::Relaxed) >= sent
+            && (!config.measure_latency || stats.received.load(Ordering::Relaxed) >= sent);
+        if drained {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+            _ = cancel.changed() => {}
+        }
+    }
+
+    let cancelled = *cancel.borrow();
+    let report = stats.report(started.elapsed(), false, cancelled);
+    info!(
+        "Benchmark {} finished: {} sent, {:.0} msg/s",
+        run_id, report.sent, report.throughput
+    );
+    on_progress(report.clone());
+    finish(Ok(report)).await
+}
+
+async fn join_all(tasks: Vec<tokio::task::JoinHandle<()>>) {
+    for task in tasks {
+        let _ = task.await;
+    }
+}
+
+async fn publish(
+    client: AsyncClient,
+    topic: String,
+    index: u32,
+    limit: Option<u64>,
+    deadline: Option<Instant>,
+    shared: Arc<Shared>,
+    mut cancel: watch::Receiver<bool>,
+) {
+    let Shared {
+        config,
+        epoch,
+        stats,
+    } = &*shared;
+    let mut ticker = config
+        .rate
+        .map(|rate| tokio::time::interval(publish_period(config.client_count, rate)));
+    let mut count = 0;
+    loop {
+        if limit.is_some_and(|limit| count >= limit)
+            || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            || *cancel.borrow()
+        {
+            break;
+        }
+        if let Some(ref mut ticker) = ticker {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = cancel.changed() => continue,
+            }
+        }
+        let payload = encode_payload(
+            index,
+            epoch.elapsed().as_micros() as u64,
+            config.payload_size,
+        );
+        let result = tokio::select! {
+            result = client.publish(topic.as_str(), config.qos.into(), false, payload) => result,
+            _ = cancel.changed() => continue,
+        };
+        match result {
+            Ok(()) => {
+                stats.sent.fetch_add(1, Ordering::Relaxed);
+                count += 1;
+            }
+            Err(e) => {
+                warn!("Benchmark client {} failed to publish: {}", index, e);
+                stats.errors.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
+        }
+    }
+}
+
+async fn poll(
+    mut eventloop: EventLoop,
+    client: AsyncClient,
+    index: u32,
+    shared: Arc<Shared>,
+    ready: oneshot::Sender<Result<(), String>>,
+    mut done: watch::Receiver<bool>,
+) {
+    let Shared {
+        config,
+        epoch,
+        stats,
+    } = &*shared;
+    let mut ready = Some(ready);
+    loop {
+        let event = tokio::select! {
+            _ = done.wait_for(|done| *done) => break,
+            event = eventloop.poll() => event,
+        };
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) if !config.measure_latency => {
+                if let Some(tx) = ready.take() {
+                    let _ = tx.send(Ok(()));
+                }
+            }
+            Ok(Event::Incoming(Packet::SubAck(_))) => {
+                if let Some(tx) = ready.take() {
+                    let _ = tx.send(Ok(()));
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                if let Some((client, sent_us)) = decode_payload(&publish.payload) {
+                    if client == index {
+                        let latency_us =
+                            (epoch.elapsed().as_micros() as u64).saturating_sub(sent_us);
+                        stats.received.fetch_add(1, Ordering::Relaxed);
+                        stats.latencies_us.lock().unwrap().push(latency_us);
+                    }
+                }
+            }
+            Ok(Event::Incoming(Packet::PubAck(_) | Packet::PubComp(_))) => {
+                stats.acked.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(Event::Outgoing(Outgoing::Publish(_))) if config.qos == QoS::AtMostOnce => {
+                stats.acked.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                if let Some(tx) = ready.take() {
+                    let _ = tx.send(Err(e.to_string()));
+                    break;
+                }
+                stats.errors.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        }
+    }
+
+    let _ = client.try_disconnect();
+    let _ = tokio::time::timeout(Duration::from_millis(500), async {
+        while eventloop.poll().await.is_ok() {}
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_config() -> BenchmarkConfig {
+        BenchmarkConfig {
+            client_count: 2,
+            qos: QoS::AtLeastOnce,
+            payload_size: 64,
+            topic: None,
+            rate: None,
+            message_count: Some(100),
+            duration_ms: None,
+            measure_latency: true,
+        }
+    }
+
+    #[test]
+    fn test_payload_roundtrip() {
+        let payload = encode_payload(3, 123_456, 64);
+        assert_eq!(payload.len(), 64);
+        assert_eq!(decode_payload(&payload), Some((3, 123_456)));
+        assert_eq!(encode_payload(1, 1, 0).len(), HEADER_LEN);
+        assert_eq!(decode_payload(b"hello world, not a benchmark"), None);
+        assert_eq!(decode_payload(b"MTLB"), None);
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        assert_eq!(latency_stats(&[]), None);
+        let samples: Vec<u64> = (1..=100).map(|ms| ms * 1000).collect();
+        let stats = latency_stats(&samples).unwrap();
+        assert_eq!(stats.min_ms, 1.0);
+        assert_eq!(stats.max_ms, 100.0);
+        assert_eq!(stats.p50_ms, 51.0);
+        assert_eq!(stats.p90_ms, 90.0);
+        assert_eq!(stats.p99_ms, 99.0);
+        assert_eq!(stats.mean_ms, 50.5);
+    }
+
+    #[test]
+    fn test_client_share() {
+        let shares: Vec<u64> = (0..3).map(|i| client_share(10, 3, i)).collect();
+        assert_eq!(shares, vec![4, 3, 3]);
+        assert_eq!(client_share(2, 4, 3), 0);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&create_config()).is_ok());
+        let invalid = [
+            (
+                BenchmarkConfig {
+                    client_count: 0,
+                    ..create_config()
+                },
+                "Client count",
+            ),
+            (
+                BenchmarkConfig {
+                    payload_size: MAX_PAYLOAD_SIZE + 1,
+                    ..create_config()
+                },
+                "Payload size",
+            ),
+            (
+                BenchmarkConfig {
+                    message_count: Some(0),
+                    ..create_config()
+                },
+                "message count",
+            ),
+            (
+                BenchmarkConfig {
+                    rate: Some(0.0),
+                    ..create_config()
+                },
+                "Rate",
+            ),
+            (
+                BenchmarkConfig {
+                    rate: Some(f64::INFINITY),
+                    ..create_config()
+                },
+                "Rate",
+            ),
+            (
+                BenchmarkConfig {
+                    rate: Some(f64::NAN),
+                    ..create_config()
+                },
+                "Rate",
+            ),
+            (
+                BenchmarkConfig {
+                    rate: Some(1e-12),
+                    ..create_config()
+                },
+                "Rate",
+            ),
+        ];
+        for (config, message) in invalid {
+            let error = validate(&config).unwrap_err().to_string();
+            assert!(error.contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_publish_period_is_clamped() {
+        assert_eq!(publish_period(4, 2.0), Duration::from_secs(2));
+        assert_eq!(publish_period(1, f64::INFINITY), MIN_PERIOD);
+        assert_eq!(publish_period(1, 1e12), MIN_PERIOD);
+        assert_eq!(publish_period(MAX_CLIENTS, MIN_RATE), MAX_PERIOD);
+        assert_eq!(publish_period(1, 1e-300), MAX_PERIOD);
+    }
+
+    #[test]
+    fn test_unreachable_broker_fails_to_connect() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let connection: Connection = serde_json::from_value(serde_json::json!({
+                "id": "c1",
+                "name": "Bench",
+                "broker_url": "127.0.0.1",
+                "port": port,
+                "client_id": "bench",
+            }))
+            .unwrap();
+            let result = run(
+                &connection,
+                &create_config(),
+                watch::channel(false).1,
+                &|_| {},
+            )
+            .await;
+            assert!(matches!(result, Err(BenchmarkError::Connect(0, _))));
+        });
+    }
+}
@@ -0,0 +1,5 @@
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    mqtt_topic_lab_lib::cli::run()
+}
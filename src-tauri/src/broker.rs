@@ -0,0 +1,311 @@
+use crate::types::{AppData, Connection, EmbeddedBrokerSettings};
+use log::{info, warn};
+use rumqttd::{Broker, Config, ConnectionSettings, RouterConfig, ServerSettings};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+pub const HOST: &str = "127.0.0.1";
+
+pub const CONNECTION_NAME: &str = "Embedded broker";
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Error, Debug)]
+pub enum BrokerError {
+    #[error("Cannot listen on port {0}: {1}")]
+    Bind(u16, std::io::Error),
+    #[error("Cannot start the embedded broker: {0}")]
+    Start(std::io::Error),
+    #[error("The embedded broker did not start listening on port {0}; restart the app to retry")]
+    NotListening(u16),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrokerStatus {
+    pub url: Option<String>,
+    pub error: Option<String>,
+    /// The saved settings apply after a restart.
+    pub restart_required: bool,
+}
+
+#[derive(Default)]
+struct State {
+    settings: EmbeddedBrokerSettings,
+    /// rumqttd cannot be shut down, so once a broker thread is spawned a second
+    /// one is never started, even if the first did not come up in time.
+    port: Option<u16>,
+    listening: bool,
+    starting: bool,
+    /// Retried only once the settings change.
+    failed: Option<EmbeddedBrokerSettings>,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct EmbeddedBroker {
+    state: Mutex<State>,
+}
+
+impl EmbeddedBroker {
+    pub async fn update(&self, settings: &EmbeddedBrokerSettings) -> Result<(), BrokerError> {
+        {
+            let mut state = self.state.lock().await;
+            state.settings = settings.clone();
+            if state.port.is_some() || state.starting {
+                return Ok(());
+            }
+            if !settings.enabled {
+                state.failed = None;
+                state.error = None;
+                return Ok(());
+            }
+            if state.failed.as_ref() == Some(settings) {
+                return Ok(());
+            }
+            state.starting = true;
+            state.error = None;
+        }
+
+        // Starting blocks for up to STARTUP_TIMEOUT, so the status stays
+        // readable meanwhile.
+        let port = settings.port;
+        let result = tokio::task::spawn_blocking(move || start(port))
+            .await
+            .unwrap_or_else(|e| Err(BrokerError::Start(std::io::Error::other(e))));
+
+        let mut state = self.state.lock().await;
+        state.starting = false;
+        match result {
+            Ok(port) => {
+                info!("Embedded broker listening on {}:{}", HOST, port);
+                state.port = Some(port);
+                state.listening = true;
+                Ok(())
+            }
+            Err(e) => {
+                if let BrokerError::NotListening(port) = e {
+                    state.port = Some(port);
+                }
+                state.failed = Some(settings.clone());
+                state.error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn status(&self) -> BrokerStatus {
+        let state = self.state.lock().await;
+        BrokerStatus {
+            url: state
+                .port
+                .filter(|_| state.listening)
+                .map(|port| format!("mqtt://{}:{}", HOST, port)),
+            error: state.error.clone(),
+            restart_required: state
+                .port
+                .is_some_and(|port| !state.settings.enabled || state.settings.port != port),
+        }
+    }
+}
+
+/// On a free port when `port` is 0. Returns the port it listens on.
+pub fn start(port: u16) -> Result<u16, BrokerError> {
+    // rumqttd only logs bind failures from its server thread, so check that the
+    // port is free up front. Losing a race for it is reported as NotListening.
+    let port = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .and_then(|listener| listener.local_addr())
+        .map_err(|e| BrokerError::Bind(port, e))?
+        .port();
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    run(config(addr), addr)
+}
+
+fn run(config: Config, addr: SocketAddr) -> Result<u16, BrokerError> {
+    let port = addr.port();
+    let mut broker = Broker::new(config);
+    std::thread::Builder::new()
+        .name("embedded-broker".to_string())
+        .spawn(move || {
+            if let Err(e) = broker.start() {
+                warn!("Embedded broker stopped: {}", e);
+            }
+        })
+        .map_err(BrokerError::Start)?;
+
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while TcpStream::connect_timeout(&addr, Duration::from_millis(100)).is_err() {
+        if Instant::now() >= deadline {
+            return Err(BrokerError::NotListening(port));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Ok(port)
+}
+
+fn config(listen: SocketAddr) -> Config {
+    let server = ServerSettings {
+        name: "v4".to_string(),
+        listen,
+        tls: None,
+        next_connection_delay_ms: 1,
+        connections: ConnectionSettings {
+            connection_timeout_ms: 60_000,
+            max_payload_size: 16 * 1024 * 1024,
+            max_inflight_count: 100,
+            auth: None,
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    };
+    Config {
+        router: RouterConfig {
+            // Room for a full device fleet simulation.
+            max_connections: 10_010,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 100 * 1024 * 1024,
+            max_segment_count: 10,
+            ..Default::default()
+        },
+        v4: Some(HashMap::from([("local".to_string(), server)])),
+        ..Default::default()
+    }
+}
+
+pub fn preset_connection(port: u16) -> Connection {
+    Connection {
+        id: Uuid::new_v4().to_string(),
+        name: CONNECTION_NAME.to_string(),
+        broker_url: HOST.to_string(),
+        port,
+        client_id: format!(
+            "mqtt-topic-lab-{}",
+            &Uuid::new_v4().simple().to_string()[..6]
+        ),
+        subscriptions: vec!["#".to_string()],
+        ..Default::default()
+    }
+}
+
+/// Adds the preset connection if there is none yet. Returns the connection id.
+pub fn add_preset_connection(data: &mut AppData) -> String {
+    data.embedded_broker.enabled = true;
+    let port = data.embedded_broker.port;
+    let existing = data.connections.iter().find(|c| {
+        c.port == port && !c.use_tls && matches!(c.broker_url.as_str(), HOST | "localhost")
+    });
+    let id = match existing {
+        Some(connection) => connection.id.clone(),
+        None => {
+            let connection = preset_connection(port);
+            let id = connection.id.clone();
+            data.connections.push(connection);
+            id
+        }
+    };
+    data.last_connection_id = Some(id.clone());
+    id
+}
+
+#[cfg(test)]
+pub(crate) fn test_port() -> u16 {
+    static PORT: std::sync::OnceLock<u16> = std::sync::OnceLock::new();
+    *PORT.get_or_init(|| start(0).expect("embedded broker"))
+}
+
+#[cfg(test)]
+pub(crate) fn test_v5_port() -> u16 {
+    static PORT: std::sync::OnceLock<u16> = std::sync::OnceLock::new();
+    *PORT.get_or_init(|| {
+        let addr = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let mut config = config(addr);
+        config.v5 = config.v4.take();
+        run(config, addr).expect("MQTT 5 broker")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_starts_one_broker() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .and_then(|listener| listener.local_addr())
+                .unwrap()
+                .port();
+            let broker = EmbeddedBroker::default();
+            let settings = EmbeddedBrokerSettings {
+                enabled: true,
+                port,
+            };
+            broker.update(&settings).await.unwrap();
+            let status = broker.status().await;
+            assert_eq!(status.url, Some(format!("mqtt://{}:{}", HOST, port)));
+            assert!(!status.restart_required);
+
+            // Running brokers are kept; other settings need a restart.
+            broker.update(&settings).await.unwrap();
+            assert!(broker.status().await.error.is_none());
+            broker.update(&Default::default()).await.unwrap();
+            let status = broker.status().await;
+            assert!(status.url.is_some());
+            assert!(status.restart_required);
+        });
+    }
+
+    #[test]
+    fn test_add_preset_connection_reuses_existing() {
+        let mut data = AppData::default();
+        let id = add_preset_connection(&mut data);
+        assert!(data.embedded_broker.enabled);
+        assert_eq!(data.connections.len(), 1);
+        assert_eq!(data.connections[0].port, 1884);
+        assert_eq!(data.last_connection_id.as_deref(), Some(id.as_str()));
+
+        data.last_connection_id = None;
+        assert_eq!(add_preset_connection(&mut data), id);
+        assert_eq!(data.connections.len(), 1);
+
+        data.embedded_broker.port = 1999;
+        assert_ne!(add_preset_connection(&mut data), id);
+        assert_eq!(data.connections.len(), 2);
+    }
+
+    #[test]
+    fn test_update_reports_port_in_use() {
+        let port = test_port();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let broker = EmbeddedBroker::default();
+            let settings = EmbeddedBrokerSettings {
+                enabled: true,
+                port,
+            };
+            assert!(matches!(
+                broker.update(&settings).await,
+                Err(BrokerError::Bind(p, _)) if p == port
+            ));
+            let status = broker.status().await;
+            assert!(status.url.is_none());
+            assert!(status.error.is_some());
+            assert!(!status.restart_required);
+
+            // The same settings are not retried.
+            broker.update(&settings).await.unwrap();
+            assert!(broker.status().await.error.is_some());
+
+            broker.update(&Default::default()).await.unwrap();
+            assert!(broker.status().await.error.is_none());
+        });
+    }
+}
@@ -0,0 +1,574 @@
+use crate::topic::{validate_topic_filter, validate_topic_name};
+use crate::types::{AppData, Button, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const BUNDLE_FORMAT: &str = "mqtt-topic-lab";
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error("Invalid JSON at line {line}, column {column}: {message}")]
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("Invalid value at '{path}': {message}")]
+    Schema { path: String, message: String },
+    #[error("Unknown file format '{0}'")]
+    UnknownFormat(String),
+    #[error("Unsupported bundle version {0} (supported up to {BUNDLE_VERSION})")]
+    UnsupportedVersion(u32),
+    #[error("File does not contain any connections")]
+    Empty,
+    #[error("Invalid bundle: {0}")]
+    Validation(ValidationErrors),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationErrors(pub Vec<ValidationIssue>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let issues: Vec<String> = self
+            .0
+            .iter()
+            .map(|issue| format!("{}: {}", issue.path, issue.message))
+            .collect();
+        write!(f, "{}", issues.join("; "))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    pub format: String,
+    pub version: u32,
+    #[serde(default)]
+    pub exported_at: Option<u64>,
+    pub connections: Vec<Connection>,
+}
+
+impl Bundle {
+    pub fn new(connections: &[Connection], include_secrets: bool) -> Self {
+        let connections = connections
+            .iter()
+            .cloned()
+            .map(|mut connection| {
+                if !include_secrets {
+                    connection.password = None;
+                }
+                connection.project_file = None;
+                connection
+            })
+            .collect();
+
+        Self {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            exported_at: Some(now_millis()),
+            connections,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, BundleError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Also accepts a bare connection object without an id, as written by older
+    /// versions.
+    pub fn parse(content: &str) -> Result<Self, BundleError> {
+        let mut value: Value = serde_json::from_str(content).map_err(|e| BundleError::Syntax {
+            line: e.line(),
+            column: e.column(),
+            message: e.to_string(),
+        })?;
+
+        let mut bundle = match value.get("format").cloned() {
+            Some(format) => {
+                let format = format.as_str().unwrap_or_default().to_string();
+                if format != BUNDLE_FORMAT {
+                    return Err(BundleError::UnknownFormat(format));
+                }
+                let version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+                if version > BUNDLE_VERSION as u64 {
+                    return Err(BundleError::UnsupportedVersion(version as u32));
+                }
+                if let Some(connections) =
+                    value.get_mut("connections").and_then(Value::as_array_mut)
+                {
+                    connections.iter_mut().for_each(fill_missing_ids);
+                }
+                deserialize::<Bundle>(value, "")?
+            }
+            None if value.get("broker_url").is_some() => {
+                fill_missing_ids(&mut value);
+                let connection = deserialize::<Connection>(value, "connections[0]")?;
+                Bundle {
+                    format: BUNDLE_FORMAT.to_string(),
+                    version: 0,
+                    exported_at: None,
+                    connections: vec![connection],
+                }
+            }
+            None => return Err(BundleError::UnknownFormat("unrecognized".to_string())),
+        };
+
+        if bundle.connections.is_empty() {
+            return Err(BundleError::Empty);
+        }
+        // Project file links are local paths from the exporting machine.
+        for connection in &mut bundle.connections {
+            connection.project_file = None;
+        }
+        validate_connections(&bundle.connections)?;
+        Ok(bundle)
+    }
+}
+
+pub fn validate_connections(connections: &[Connection]) -> Result<(), BundleError> {
+    let mut issues = Vec::new();
+    for (i, connection) in connections.iter().enumerate() {
+        validate_connection(connection, &format!("connections[{}]", i), &mut issues);
+    }
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(BundleError::Validation(ValidationErrors(issues)))
+    }
+}
+
+fn deserialize<T: serde::de::DeserializeOwned>(
+    value: Value,
+    prefix: &str,
+) -> Result<T, BundleError> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let inner = e.path().to_string();
+        let path = match (prefix.is_empty(), inner.as_str()) {
+            (true, _) => inner,
+            (false, ".") => prefix.to_string(),
+            (false, _) => format!("{}.{}", prefix, inner),
+        };
+        BundleError::Schema {
+            path,
+            message: e.into_inner().to_string(),
+        }
+    })
+}
+
+/// Ids are regenerated on import, so files may omit them.
+fn fill_missing_ids(connection: &mut Value) {
+    let Some(object) = connection.as_object_mut() else {
+        return;
+    };
+    object
+        .entry("id")
+        .or_insert_with(|| Value::String(String::new()));
+    if let Some(buttons) = object.get_mut("buttons").and_then(Value::as_array_mut) {
+        for button in buttons.iter_mut().filter_map(Value::as_object_mut) {
+            button
+                .entry("id")
+                .or_insert_with(|| Value::String(String::new()));
+        }
+    }
+}
+
+fn validate_connection(connection: &Connection, path: &str, issues: &mut Vec<ValidationIssue>) {
+    let mut issue = |field: String, message: &str| {
+        issues.push(ValidationIssue {
+            path: field,
+            message: message.to_string(),
+        })
+    };
+
+    if connection.name.trim().is_empty() {
+        issue(format!("{}.name", path), "must not be empty");
+    }
+    if connection.broker_url.trim().is_empty() {
+        issue(format!("{}.broker_url", path), "must not be empty");
+    }
+    if connection.port == 0 {
+        issue(format!("{}.port", path), "must be between 1 and 65535");
+    }
+    for (i, button) in connection.buttons.iter().enumerate() {
+        let button_path = format!("{}.buttons[{}]", path, i);
+        if button.name.trim().is_empty() {
+            issue(format!("{}.name", button_path), "must not be empty");
+        }
+        if let Err(message) = validate_topic_name(&button.topic) {
+            issue(format!("{}.topic", button_path), &message);
+        }
+    }
+    for (i, filter) in connection.subscriptions.iter().enumerate() {
+        if let Err(message) = validate_topic_filter(filter) {
+            issue(format!("{}.subscriptions[{}]", path, i), &message);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    /// Add new buttons and update same-named ones in the existing connection.
+    #[default]
+    Merge,
+    /// Overwrite the existing connection, keeping its id.
+    Replace,
+    /// Import as an additional connection under a unique name.
+    Duplicate,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub merged: usize,
+    pub replaced: usize,
+    pub buttons_added: usize,
+    pub buttons_updated: usize,
+    pub connection_ids: Vec<String>,
+}
+
+/// Ids are regenerated for everything that is newly added.
+pub fn apply_import(
+    data: &mut AppData,
+    connections: Vec<Connection>,
+    strategy: ConflictStrategy,
+) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+
+    for mut imported in connections {
+        let existing = data.connections.iter().position(|c| {
+            (!imported.id.is_empty() && c.id == imported.id) || c.name == imported.name
+        });
+
+        match (existing, strategy) {
+            (Some(index), ConflictStrategy::Merge) => {
+                let target = &mut data.connections[index];
+                let (added, updated) = merge_connection(target, imported);
+                summary.merged += 1;
+                summary.buttons_added += added;
+                summary.buttons_updated += updated;
+                summary.connection_ids.push(target.id.clone());
+            }
+            (Some(index), ConflictStrategy::Replace) => {
+                let target = &mut data.connections[index];
+                imported.id = target.id.clone();
+                if imported.password.is_none() {
+                    imported.password = target.password.take();
+                }
+                regenerate_button_ids(&mut imported.buttons);
+                summary.buttons_added += imported.buttons.len();
+                *target = imported;
+                summary.replaced += 1;
+                summary.connection_ids.push(target.id.clone());
+            }
+            (existing, _) => {
+                if existing.is_some() {
+                    imported.name = unique_name(data, &imported.name);
+                }
+                imported.id = Uuid::new_v4().to_string();
+                regenerate_button_ids(&mut imported.buttons);
+                summary.buttons_added += imported.buttons.len();
+                summary.added += 1;
+                summary.connection_ids.push(imported.id.clone());
+                data.connections.push(imported);
+            }
+        }
+    }
+
+    summary
+}
+
+fn merge_connection(target: &mut Connection, imported: Connection) -> (usize, usize) {
+    let mut added = 0;
+    let mut updated = 0;
+
+    for mut button in imported.buttons {
+        match target.buttons.iter_mut().find(|b| b.name == button.name) {
+            Some(existing) => {
+                button.id = existing.id.clone();
+                *existing = button;
+                updated += 1;
+            }
+            None => {
+                button.id = Uuid::new_v4().to_string();
+                target.buttons.push(button);
+                added += 1;
+            }
+        }
+    }
+
+    target.variables.extend(imported.variables);
+    for filter in imported.subscriptions {
+        if !target.subscriptions.contains(&filter) {
+            target.subscriptions.push(filter);
+        }
+    }
+
+    (added, updated)
+}
+
+fn regenerate_button_ids(buttons: &mut [Button]) {
+    for button in buttons {
+        button.id = Uuid::new_v4().to_string();
+    }
+}
+
+fn unique_name(data: &AppData, name: &str) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| !data.connections.iter().any(|c| &c.name == candidate))
+        .unwrap_or_else(|| name.to_string())
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn create_button(name: &str, topic: &str) -> Button {
+        Button {
+            id: format!("{}-id", name),
+            name: name.to_string(),
+            topic: topic.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn create_connection(id: &str, name: &str) -> Connection {
+        Connection {
+            id: id.to_string(),
+            name: name.to_string(),
+            broker_url: "localhost".to_string(),
+            client_id: "client".to_string(),
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+            variables: HashMap::from([("device_id".to_string(), "abc".to_string())]),
+            buttons: vec![create_button("On", "devices/{device_id}/on")],
+            subscriptions: vec!["devices/#".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_export_strips_secrets() {
+        let bundle = Bundle::new(&[create_connection("c1", "Lab")], false);
+        assert_eq!(bundle.version, BUNDLE_VERSION);
+        assert_eq!(bundle.connections[0].password, None);
+        assert_eq!(bundle.connections[0].username, Some("user".to_string()));
+
+        let bundle = Bundle::new(&[create_connection("c1", "Lab")], true);
+        assert_eq!(bundle.connections[0].password, Some("secret".to_string()));
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let bundle = Bundle::new(
+            &[
+                create_connection("c1", "Lab"),
+                create_connection("c2", "Prod"),
+            ],
+            false,
+        );
+        let parsed = Bundle::parse(&bundle.to_json().unwrap()).unwrap();
+        assert_eq!(parsed.connections.len(), 2);
+        assert_eq!(parsed.connections[1].name, "Prod");
+    }
+
+    #[test]
+    fn test_parse_legacy_single_connection() {
+        let json = r#"{
+            "name": "Old export",
+            "broker_url": "localhost",
+            "port": 1883,
+            "client_id": "abc",
+            "buttons": [{ "name": "Ping", "topic": "ping" }]
+        }"#;
+        let bundle = Bundle::parse(json).unwrap();
+        assert_eq!(bundle.version, 0);
+        assert_eq!(bundle.connections[0].name, "Old export");
+        assert_eq!(bundle.connections[0].buttons[0].topic, "ping");
+    }
+
+    #[test]
+    fn test_parse_reports_syntax_location() {
+        let err = Bundle::parse("{\n  \"format\": \"mqtt-topic-lab\",\n  oops\n}").unwrap_err();
+        assert!(
+            matches!(err, BundleError::Syntax { line: 3, .. }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_parse_reports_schema_path() {
+        let json = r#"{
+            "format": "mqtt-topic-lab",
+            "version": 1,
+            "connections": [{
+                "id": "c1", "name": "Lab", "broker_url": "localhost", "port": 1883,
+                "client_id": "abc",
+                "buttons": [{ "id": "b1", "name": "On", "topic": "t", "qos": "high" }]
+            }]
+        }"#;
+        match Bundle::parse(json).unwrap_err() {
+            BundleError::Schema { path, .. } => assert_eq!(path, "connections[0].buttons[0].qos"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_newer_version_and_unknown_format() {
+        let json = r#"{ "format": "mqtt-topic-lab", "version": 99, "connections": [] }"#;
+        assert!(matches!(
+            Bundle::parse(json),
+            Err(BundleError::UnsupportedVersion(99))
+        ));
+        let json = r#"{ "format": "something-else", "version": 1, "connections": [] }"#;
+        assert!(matches!(
+            Bundle::parse(json),
+            Err(BundleError::UnknownFormat(_))
+        ));
+        assert!(matches!(
+            Bundle::parse("[1, 2]"),
+            Err(BundleError::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_validation_issues() {
+        let mut connection = create_connection("c1", "");
+        connection.port = 0;
+        connection
+            .buttons
+            .push(create_button("Bad", "devices/+/cmd"));
+        connection.subscriptions.push("a/#/b".to_string());
+        let json = Bundle::new(&[connection], false).to_json().unwrap();
+
+        match Bundle::parse(&json).unwrap_err() {
+            BundleError::Validation(errors) => {
+                let paths: Vec<&str> = errors.0.iter().map(|i| i.path.as_str()).collect();
+                assert_eq!(
+                    paths,
+                    vec![
+                        "connections[0].name",
+                        "connections[0].port",
+                        "connections[0].buttons[1].topic",
+                        "connections[0].subscriptions[1]",
+                    ]
+                );
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_import_new_connection_regenerates_ids() {
+        let mut data = AppData::default();
+        let summary = apply_import(
+            &mut data,
+            vec![create_connection("c1", "Lab")],
+            ConflictStrategy::Merge,
+        );
+        assert_eq!(summary.added, 1);
+        assert_ne!(data.connections[0].id, "c1");
+        assert_ne!(data.connections[0].buttons[0].id, "On-id");
+        assert_eq!(summary.connection_ids, vec![data.connections[0].id.clone()]);
+    }
+
+    #[test]
+    fn test_import_merge_buttons() {
+        let mut data = AppData {
+            connections: vec![create_connection("c1", "Lab")],
+            last_connection_id: None,
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        };
+        let mut imported = create_connection("other", "Lab");
+        imported.buttons[0].payload = Some("1".to_string());
+        imported
+            .buttons
+            .push(create_button("Off", "devices/{device_id}/off"));
+        imported.subscriptions.push("status/#".to_string());
+
+        let summary = apply_import(&mut data, vec![imported], ConflictStrategy::Merge);
+        assert_eq!(summary.merged, 1);
+        assert_eq!(summary.buttons_added, 1);
+        assert_eq!(summary.buttons_updated, 1);
+
+        let connection = &data.connections[0];
+        assert_eq!(connection.id, "c1");
+        assert_eq!(connection.buttons.len(), 2);
+        assert_eq!(connection.buttons[0].id, "On-id");
+        assert_eq!(connection.buttons[0].payload, Some("1".to_string()));
+        assert_eq!(connection.subscriptions, vec!["devices/#", "status/#"]);
+    }
+
+    #[test]
+    fn test_import_replace_keeps_id_and_password() {
+        let mut data = AppData {
+            connections: vec![create_connection("c1", "Lab")],
+            last_connection_id: None,
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        };
+        let mut imported = create_connection("c1", "Lab renamed");
+        imported.password = None;
+        imported.buttons.clear();
+
+        let summary = apply_import(&mut data, vec![imported], ConflictStrategy::Replace);
+        assert_eq!(summary.replaced, 1);
+        assert_eq!(data.connections.len(), 1);
+        assert_eq!(data.connections[0].id, "c1");
+        assert_eq!(data.connections[0].name, "Lab renamed");
+        assert_eq!(data.connections[0].password, Some("secret".to_string()));
+        assert!(data.connections[0].buttons.is_empty());
+    }
+
+    #[test]
+    fn test_import_duplicate_gets_unique_name() {
+        let mut data = AppData {
+            connections: vec![create_connection("c1", "Lab")],
+            last_connection_id: None,
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        };
+        apply_import(
+            &mut data,
+            vec![create_connection("c1", "Lab")],
+            ConflictStrategy::Duplicate,
+        );
+        apply_import(
+            &mut data,
+            vec![create_connection("c1", "Lab")],
+            ConflictStrategy::Duplicate,
+        );
+        let names: Vec<&str> = data.connections.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["Lab", "Lab (2)", "Lab (3)"]);
+        assert_ne!(data.connections[1].id, "c1");
+    }
+}
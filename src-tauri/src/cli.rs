@@ -0,0 +1,601 @@
+use crate::mqtt::{next_message, Message, MqttClient, MqttError};
+use crate::request::{self, RequestError};
+use crate::storage::{Storage, StorageError, StorageOptions};
+use crate::template::substitute_variables;
+use crate::types::{AppData, Button, Connection, ConnectionStatus, QoS};
+use log::info;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::ExitCode;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+const USAGE: &str = "\
+Usage: mqtt-topic-lab-cli [--data-dir <dir>] [--workspace <name>] [--portable] <command>
+
+Commands:
+  connections                     List the saved connections
+  buttons <connection>            List the buttons of a connection
+  fire <connection> <button>      Publish a button, or send a request button
+       [--var <name>=<value>]...  and print the reply
+  subscribe <connection> [<topic>...]
+       [--qos <0|1|2>] [--count <n>] [--timeout <seconds>]
+                                  Print incoming messages; subscribes to the
+                                  connection's saved subscriptions by default
+
+Options:
+  --json                          Print JSON instead of text
+  --connect-timeout <seconds>     Time allowed to connect (default 10)
+  -h, --help                      Print this help
+
+Connections and buttons are looked up by name, then by id.
+
+Exit codes:
+  0  success
+  1  publishing, subscribing or storage failed
+  2  invalid arguments
+  3  connection or button not found
+  4  could not connect to the broker
+  5  timed out waiting for a reply or for --count messages";
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Flags handled by `StorageOptions`, with whether they take a value.
+const STORAGE_FLAGS: [(&str, bool); 3] = [
+    ("--data-dir", true),
+    ("--workspace", true),
+    ("--portable", false),
+];
+
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("Connection not found: {0}")]
+    ConnectionNotFound(String),
+    #[error("Button '{0}' not found in '{1}'")]
+    ButtonNotFound(String, String),
+    #[error("Could not connect to {0}")]
+    ConnectFailed(String),
+    #[error("Timed out after {0} s")]
+    Timeout(u64),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Mqtt(Box<MqttError>),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Storage(_) | CliError::Mqtt(_) | CliError::Io(_) => 1,
+            CliError::Usage(_) => 2,
+            CliError::ConnectionNotFound(_) | CliError::ButtonNotFound(..) => 3,
+            CliError::ConnectFailed(_) => 4,
+            CliError::Timeout(_) => 5,
+        }
+    }
+}
+
+impl From<MqttError> for CliError {
+    fn from(e: MqttError) -> Self {
+        CliError::Mqtt(Box::new(e))
+    }
+}
+
+impl From<RequestError> for CliError {
+    fn from(e: RequestError) -> Self {
+        match e {
+            RequestError::Timeout(_, ms) | RequestError::NoCorrelatedReply(_, _, ms) => {
+                CliError::Timeout(ms.div_ceil(1000))
+            }
+            RequestError::Mqtt(e) => e.into(),
+            RequestError::NotARequest(_) => CliError::Usage(e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    Connections,
+    Buttons {
+        connection: String,
+    },
+    Fire {
+        connection: String,
+        button: String,
+        variables: HashMap<String, String>,
+    },
+    Subscribe {
+        connection: String,
+        topics: Vec<String>,
+        qos: QoS,
+        count: Option<usize>,
+        timeout: Option<u64>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    command: Command,
+    json: bool,
+    connect_timeout: Duration,
+}
+
+pub fn run() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args = match parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    if args.command == Command::Help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start runtime: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(execute(args)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, CliError> {
+    let mut positional = Vec::new();
+    let mut variables = HashMap::new();
+    let mut json = false;
+    let mut connect_timeout = DEFAULT_CONNECT_TIMEOUT;
+    let mut qos = QoS::AtMostOnce;
+    let mut count = None;
+    let mut timeout = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| CliError::Usage(format!("{} needs a value", flag)))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => {
+                return Ok(Args {
+                    command: Command::Help,
+                    json,
+                    connect_timeout,
+                })
+            }
+            "--json" => json = true,
+            "--var" => {
+                let assignment = value()?;
+                let (name, value) = assignment.split_once('=').ok_or_else(|| {
+                    CliError::Usage(format!("--var needs <name>=<value>, got '{}'", assignment))
+                })?;
+                variables.insert(name.to_string(), value.to_string());
+            }
+            "--qos" => {
+                qos = match value()?.as_str() {
+                    "0" => QoS::AtMostOnce,
+                    "1" => QoS::AtLeastOnce,
+                    "2" => QoS::ExactlyOnce,
+                    other => return Err(CliError::Usage(format!("Invalid QoS: {}", other))),
+                }
+            }
+            "--count" => count = Some(number(&flag, &value()?)? as usize),
+            "--timeout" => timeout = Some(number(&flag, &value()?)?),
+            "--connect-timeout" => connect_timeout = Duration::from_secs(number(&flag, &value()?)?),
+            _ => match STORAGE_FLAGS.iter().find(|(name, _)| *name == flag) {
+                // Read by `StorageOptions::from_env`.
+                Some((_, takes_value)) => {
+                    if *takes_value && inline_value.is_none() {
+                        args.next();
+                    }
+                }
+                None if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(CliError::Usage(format!("Unknown option: {}", flag)))
+                }
+                None => positional.push(flag),
+            },
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        None | Some("help") => Command::Help,
+        Some("connections") => Command::Connections,
+        Some("buttons") => Command::Buttons {
+            connection: required(&mut positional, "connection")?,
+        },
+        Some("fire") => Command::Fire {
+            connection: required(&mut positional, "connection")?,
+            button: required(&mut positional, "button")?,
+            variables,
+        },
+        Some("subscribe") => Command::Subscribe {
+            connection: required(&mut positional, "connection")?,
+            topics: positional.by_ref().collect(),
+            qos,
+            count,
+            timeout,
+        },
+        Some(other) => return Err(CliError::Usage(format!("Unknown command: {}", other))),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(CliError::Usage(format!("Unexpected argument: {}", extra)));
+    }
+    Ok(Args {
+        command,
+        json,
+        connect_timeout,
+    })
+}
+
+fn required(args: &mut impl Iterator<Item = String>, name: &str) -> Result<String, CliError> {
+    args.next()
+        .ok_or_else(|| CliError::Usage(format!("Missing <{}>", name)))
+}
+
+fn number(flag: &str, value: &str) -> Result<u64, CliError> {
+    value
+        .parse()
+        .map_err(|_| CliError::Usage(format!("{} needs a number, got '{}'", flag, value)))
+}
+
+async fn execute(args: Args) -> Result<(), CliError> {
+    let storage = Storage::new(&StorageOptions::from_env())?;
+    let data = storage.load_data()?;
+    let mut out = std::io::stdout();
+
+    match args.command {
+        Command::Help => unreachable!("help is printed before executing"),
+        Command::Connections => {
+            #[derive(Serialize)]
+            struct Entry<'a> {
+                id: &'a str,
+                name: &'a str,
+                broker: String,
+            }
+            let entries: Vec<Entry> = data
+                .connections
+                .iter()
+                .map(|c| Entry {
+                    id: &c.id,
+                    name: &c.name,
+                    broker: format!("{}:{}", c.broker_url, c.port),
+                })
+                .collect();
+            if args.json {
+                print_json(&mut out, &entries)?;
+            } else {
+                for entry in entries {
+                    writeln!(out, "{}\t{}\t{}", entry.name, entry.broker, entry.id)?;
+                }
+            }
+        }
+        Command::Buttons { connection } => {
+            let connection = find_connection(&data, &connection)?;
+            if args.json {
+                print_json(&mut out, &connection.buttons)?;
+            } else {
+                for button in &connection.buttons {
+                    writeln!(out, "{}\t{}\t{}", button.name, button.topic, button.id)?;
+                }
+            }
+        }
+        Command::Fire {
+            connection,
+            button,
+            variables,
+        } => {
+            let connection = find_connection(&data, &connection)?;
+            let button = find_button(connection, &button)?;
+            let mut resolved = connection.variables.clone();
+            resolved.extend(variables);
+
+            let client = RwLock::new(connect(connection, &data, args.connect_timeout).await?);
+            let result = fire(&client, button, &resolved).await;
+            client.write().await.disconnect().await?;
+            match result? {
+                Some(reply) if args.json => print_json(&mut out, &reply)?,
+                Some(reply) => writeln!(out, "{}", reply.payload)?,
+                None => {}
+            }
+        }
+        Command::Subscribe {
+            connection,
+            topics,
+            qos,
+            count,
+            timeout,
+        } => {
+            let connection = find_connection(&data, &connection)?;
+            let topics = if topics.is_empty() {
+                connection.subscriptions.clone()
+            } else {
+                topics
+            };
+            if topics.is_empty() {
+                return Err(CliError::Usage(format!(
+                    "'{}' has no saved subscriptions; name a topic",
+                    connection.name
+                )));
+            }
+
+            let mut client = connect(connection, &data, args.connect_timeout).await?;
+            let result = subscribe(&client, &topics, qos, count, timeout, args.json).await;
+            client.disconnect().await?;
+            result?;
+        }
+    }
+    Ok(())
+}
+
+fn find_connection<'a>(data: &'a AppData, key: &str) -> Result<&'a Connection, CliError> {
+    data.connections
+        .iter()
+        .find(|c| c.name == key)
+        .or_else(|| data.connections.iter().find(|c| c.id == key))
+        .ok_or_else(|| CliError::ConnectionNotFound(key.to_string()))
+}
+
+fn find_button<'a>(connection: &'a Connection, key: &str) -> Result<&'a Button, CliError> {
+    connection
+        .buttons
+        .iter()
+        .find(|b| b.name == key)
+        .or_else(|| connection.buttons.iter().find(|b| b.id == key))
+        .ok_or_else(|| CliError::ButtonNotFound(key.to_string(), connection.name.clone()))
+}
+
+async fn connect(
+    connection: &Connection,
+    data: &AppData,
+    timeout: Duration,
+) -> Result<MqttClient, CliError> {
+    let broker = format!("{}:{}", connection.broker_url, connection.port);
+    let mut client = MqttClient::new();
+    client.set_schemas(&data.schemas).await;
+    client.connect(connection).await?;
+
+    let connected = tokio::time::timeout(timeout, async {
+        loop {
+            match client.get_status().await {
+                ConnectionStatus::Connected => return true,
+                ConnectionStatus::Error => return false,
+                _ => tokio::time::sleep(CONNECT_POLL_INTERVAL).await,
+            }
+        }
+    })
+    .await
+    .unwrap_or(false);
+    if !connected {
+        client.disconnect().await?;
+        return Err(CliError::ConnectFailed(broker));
+    }
+    Ok(client)
+}
+
+async fn fire(
+    client: &RwLock<MqttClient>,
+    button: &Button,
+    variables: &HashMap<String, String>,
+) -> Result<Option<request::Reply>, CliError> {
+    if button.response.is_some() {
+        return Ok(Some(
+            request::send_request(client, button, variables).await?,
+        ));
+    }
+    client
+        .read()
+        .await
+        .publish_button(button, variables)
+        .await?;
+    info!(
+        "Published '{}' to '{}'",
+        button.name,
+        substitute_variables(&button.topic, variables)
+    );
+    Ok(None)
+}
+
+/// Without `count` or `timeout`, listens until interrupted.
+async fn subscribe(
+    client: &MqttClient,
+    topics: &[String],
+    qos: QoS,
+    count: Option<usize>,
+    timeout: Option<u64>,
+    json: bool,
+) -> Result<(), CliError> {
+    let mut messages = client.subscribe_messages();
+    for topic in topics {
+        client.subscribe(topic, qos).await?;
+    }
+
+    let print = async {
+        let mut received = 0;
+        while count.is_none_or(|count| received < count) {
+            let msg = next_message(&mut messages, |_| true).await?;
+            print_message(&mut std::io::stdout(), &msg, json)?;
+            received += 1;
+        }
+        Ok::<(), CliError>(())
+    };
+    match timeout {
+        None => print.await,
+        Some(seconds) => match tokio::time::timeout(Duration::from_secs(seconds), print).await {
+            Ok(result) => result,
+            // Without a count, the timeout just bounds how long to listen.
+            Err(_) if count.is_none() => Ok(()),
+            Err(_) => Err(CliError::Timeout(seconds)),
+        },
+    }
+}
+
+/// Binary payloads are printed as their decoded text.
+fn print_message(out: &mut impl Write, msg: &Message, json: bool) -> Result<(), CliError> {
+    if json {
+        return print_json(out, msg);
+    }
+    let payload = match (&msg.raw, &msg.decoded) {
+        (Some(_), Some(decoded)) => decoded.text.replace('\n', " "),
+        _ => msg.payload.clone(),
+    };
+    writeln!(out, "{} {}", msg.topic, payload)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn print_json(out: &mut impl Write, value: &impl Serialize) -> Result<(), CliError> {
+    serde_json::to_writer(&mut *out, value).map_err(std::io::Error::from)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Args, CliError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse_args(&[]).unwrap().command, Command::Help);
+        assert_eq!(
+            parse_args(&["--data-dir", "/tmp/x", "connections", "--workspace=ci"])
+                .unwrap()
+                .command,
+            Command::Connections
+        );
+
+        let args = parse_args(&[
+            "fire",
+            "Lab",
+            "Set level",
+            "--var",
+            "level=50",
+            "--var=room=a=b",
+            "--json",
+        ])
+        .unwrap();
+        assert!(args.json);
+        assert_eq!(
+            args.command,
+            Command::Fire {
+                connection: "Lab".to_string(),
+                button: "Set level".to_string(),
+                variables: HashMap::from([
+                    ("level".to_string(), "50".to_string()),
+                    ("room".to_string(), "a=b".to_string()),
+                ]),
+            }
+        );
+
+        let args = parse_args(&[
+            "subscribe",
+            "Lab",
+            "sensors/#",
+            "status/+",
+            "--qos",
+            "1",
+            "--count=3",
+            "--timeout",
+            "5",
+            "--connect-timeout",
+            "2",
+        ])
+        .unwrap();
+        assert_eq!(args.connect_timeout, Duration::from_secs(2));
+        assert_eq!(
+            args.command,
+            Command::Subscribe {
+                connection: "Lab".to_string(),
+                topics: vec!["sensors/#".to_string(), "status/+".to_string()],
+                qos: QoS::AtLeastOnce,
+                count: Some(3),
+                timeout: Some(5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_are_usage_errors() {
+        for args in [
+            &["buttons"][..],
+            &["fire", "Lab"],
+            &["fire", "Lab", "b", "--var", "novalue"],
+            &["subscribe", "Lab", "--qos", "3"],
+            &["subscribe", "Lab", "--count"],
+            &["connections", "extra"],
+            &["connections", "--verbose"],
+            &["publish"],
+        ] {
+            let err = parse_args(args).unwrap_err();
+            assert_eq!(err.exit_code(), 2, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_lookup_by_name_then_id() {
+        let mut data = AppData::default();
+        let mut connection: Connection = serde_json::from_str(
+            r#"{"id":"c1","name":"Lab","broker_url":"localhost","port":1883,"client_id":"cli"}"#,
+        )
+        .unwrap();
+        connection
+            .buttons
+            .push(serde_json::from_str(r#"{"id":"b1","name":"On","topic":"lights/cmd"}"#).unwrap());
+        data.connections.push(connection);
+
+        let connection = find_connection(&data, "Lab").unwrap();
+        assert_eq!(find_connection(&data, "c1").unwrap().name, "Lab");
+        assert_eq!(find_button(connection, "b1").unwrap().name, "On");
+        assert_eq!(find_connection(&data, "Other").unwrap_err().exit_code(), 3);
+        assert_eq!(find_button(connection, "Off").unwrap_err().exit_code(), 3);
+    }
+
+    #[test]
+    fn test_print_message() {
+        let mut msg: Message =
+            serde_json::from_str(r#"{"topic":"t","payload":"on","timestamp":1}"#).unwrap();
+        let mut out = Vec::new();
+        print_message(&mut out, &msg, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "t on\n");
+
+        msg.raw = Some(vec![0xff]);
+        msg.decoded = crate::decode::decode(crate::types::DecoderKind::Hex, &[0xff]);
+        let mut out = Vec::new();
+        print_message(&mut out, &msg, false).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("t 00000000  ff"));
+    }
+}
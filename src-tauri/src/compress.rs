@@ -0,0 +1,116 @@
+use crate::types::Compression;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression as Level;
+use std::fmt;
+use std::io::{Read, Write};
+use thiserror::Error;
+
+/// Keeps a small message from expanding into an unbounded one.
+const MAX_DECOMPRESSED: usize = 16 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum CompressError {
+    #[error("Invalid {0} payload: {1}")]
+    Invalid(Compression, String),
+    #[error("Decompressed payload exceeds {} bytes", MAX_DECOMPRESSED)]
+    TooLarge,
+    #[error("Cannot compress payload: {0}")]
+    Compress(#[from] std::io::Error),
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Zlib => "zlib",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+/// zlib has no magic number; a valid deflate header without a preset
+/// dictionary is taken as one.
+pub fn detect(payload: &[u8]) -> Option<Compression> {
+    match *payload {
+        [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+        [cmf, flg, ..]
+            if cmf & 0x0f == 8
+                && cmf >> 4 <= 7
+                && flg & 0x20 == 0
+                && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0 =>
+        {
+            Some(Compression::Zlib)
+        }
+        _ => None,
+    }
+}
+
+pub fn decompress(kind: Compression, payload: &[u8]) -> Result<Vec<u8>, CompressError> {
+    let invalid = |e: std::io::Error| CompressError::Invalid(kind, e.to_string());
+    let reader: Box<dyn Read + '_> = match kind {
+        Compression::Gzip => Box::new(GzDecoder::new(payload)),
+        Compression::Zlib => Box::new(ZlibDecoder::new(payload)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(payload).map_err(invalid)?),
+    };
+    let mut bytes = Vec::new();
+    reader
+        .take(MAX_DECOMPRESSED as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(invalid)?;
+    if bytes.len() > MAX_DECOMPRESSED {
+        return Err(CompressError::TooLarge);
+    }
+    Ok(bytes)
+}
+
+pub fn compress(kind: Compression, payload: &[u8]) -> Result<Vec<u8>, CompressError> {
+    let bytes = match kind {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+            encoder.write_all(payload)?;
+            encoder.finish()?
+        }
+        Compression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Level::default());
+            encoder.write_all(payload)?;
+            encoder.finish()?
+        }
+        Compression::Zstd => zstd::encode_all(payload, 0)?,
+    };
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Compression; 3] = [Compression::Gzip, Compression::Zlib, Compression::Zstd];
+
+    #[test]
+    fn test_round_trip_and_detect() {
+        let payload = br#"{"readings":[1,2,3,4,5,6,7,8,9,10]}"#;
+        for kind in ALL {
+            let compressed = compress(kind, payload).unwrap();
+            assert_eq!(detect(&compressed), Some(kind));
+            assert_eq!(decompress(kind, &compressed).unwrap(), payload);
+        }
+        assert_eq!(detect(payload), None);
+        assert_eq!(detect(b"hello"), None);
+        assert_eq!(detect(b""), None);
+    }
+
+    #[test]
+    fn test_invalid_and_oversized_payloads() {
+        for kind in ALL {
+            let err = decompress(kind, b"not compressed").unwrap_err();
+            assert!(matches!(err, CompressError::Invalid(k, _) if k == kind));
+        }
+        let bomb = compress(Compression::Gzip, &vec![0; MAX_DECOMPRESSED + 1]).unwrap();
+        assert!(matches!(
+            decompress(Compression::Gzip, &bomb),
+            Err(CompressError::TooLarge)
+        ));
+    }
+}
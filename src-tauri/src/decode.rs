@@ -0,0 +1,470 @@
+use crate::compress;
+use crate::protobuf::{self, ProtoError};
+use crate::topic::{matches_filter, validate_topic_filter};
+use crate::types::{Compression, DecoderKind, DecoderRule};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::warn;
+use prost_reflect::MessageDescriptor;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::Path;
+
+const MAX_HEX_DUMP: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Decoded {
+    pub format: DecoderKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    pub text: String,
+    /// Why the configured decoder failed; `text` then holds a hex dump.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+}
+
+struct Rule {
+    topic_filter: String,
+    decoder: DecoderKind,
+    /// Or why it could not be loaded.
+    message: Option<Result<MessageDescriptor, String>>,
+    compression: Option<Compression>,
+}
+
+#[derive(Default)]
+pub struct Decoders {
+    rules: Vec<Rule>,
+    /// Only for topics whose rule sets no compression.
+    auto_decompress: bool,
+}
+
+impl Decoders {
+    pub fn new(rules: &[DecoderRule], auto_decompress: bool) -> Self {
+        let rules = rules
+            .iter()
+            .filter(|rule| match validate_topic_filter(&rule.topic_filter) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Skipping decoder for '{}': {}", rule.topic_filter, e);
+                    false
+                }
+            })
+            .map(|rule| Rule {
+                topic_filter: rule.topic_filter.clone(),
+                decoder: rule.decoder,
+                message: (rule.decoder == DecoderKind::Protobuf).then(|| {
+                    load_message(rule).map_err(|e| {
+                        warn!("Protobuf decoder for '{}': {}", rule.topic_filter, e);
+                        e.to_string()
+                    })
+                }),
+                compression: rule.compression,
+            })
+            .collect();
+        Self {
+            rules,
+            auto_decompress,
+        }
+    }
+
+    fn rule(&self, topic: &str) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|rule| matches_filter(&rule.topic_filter, topic))
+    }
+
+    /// The first rule whose filter matches wins; without one the format is
+    /// detected. Returns None for plain text. Decompression failures are only
+    /// reported when the rule sets the compression.
+    pub fn decode(&self, topic: &str, payload: &[u8]) -> Option<Decoded> {
+        let rule = self.rule(topic);
+        let decompressed = match rule.and_then(|rule| rule.compression) {
+            Some(kind) => Some((kind, compress::decompress(kind, payload))),
+            None if self.auto_decompress => compress::detect(payload)
+                .map(|kind| (kind, compress::decompress(kind, payload)))
+                .filter(|(_, result)| result.is_ok()),
+            None => None,
+        };
+        let Some((kind, result)) = decompressed else {
+            return self.decode_with(rule, payload);
+        };
+        let decoded = match result {
+            Ok(bytes) => self.decode_with(rule, &bytes).unwrap_or_else(|| {
+                view(
+                    DecoderKind::Text,
+                    None,
+                    String::from_utf8_lossy(&bytes).to_string(),
+                )
+            }),
+            Err(e) => failed(
+                rule.map_or(DecoderKind::Auto, |rule| rule.decoder),
+                payload,
+                e.to_string(),
+            ),
+        };
+        Some(Decoded {
+            compression: Some(kind),
+            ..decoded
+        })
+    }
+
+    fn decode_with(&self, rule: Option<&Rule>, payload: &[u8]) -> Option<Decoded> {
+        match rule {
+            Some(Rule {
+                message: Some(message),
+                ..
+            }) => {
+                let kind = DecoderKind::Protobuf;
+                Some(
+                    match message
+                        .as_ref()
+                        .map_err(Clone::clone)
+                        .and_then(|m| protobuf::decode(m, payload).map_err(|e| e.to_string()))
+                    {
+                        Ok(value) => structured(kind, value),
+                        Err(e) => failed(kind, payload, e),
+                    },
+                )
+            }
+            Some(rule) => decode(rule.decoder, payload),
+            None => decode(DecoderKind::Auto, payload),
+        }
+    }
+
+    /// Only for topics with a protobuf rule.
+    pub fn encode(&self, topic: &str, payload: &str) -> Option<Result<Vec<u8>, String>> {
+        let message = self.rule(topic)?.message.as_ref()?;
+        Some(
+            message
+                .as_ref()
+                .map_err(Clone::clone)
+                .and_then(|m| protobuf::encode(m, payload).map_err(|e| e.to_string())),
+        )
+    }
+}
+
+fn load_message(rule: &DecoderRule) -> Result<MessageDescriptor, ProtoError> {
+    match (&rule.descriptor_set, &rule.message_type) {
+        (Some(path), Some(name)) => protobuf::load_message(Path::new(path), name),
+        _ => Err(ProtoError::MissingType),
+    }
+}
+
+pub fn decode(kind: DecoderKind, payload: &[u8]) -> Option<Decoded> {
+    match kind {
+        DecoderKind::Auto => detect(payload),
+        // Protobuf needs a message type, which only rules carry, and
+        // Sparkplug the state kept by `sparkplug::Sparkplug`.
+        DecoderKind::Text | DecoderKind::Protobuf | DecoderKind::Sparkplug => None,
+        DecoderKind::Hex => Some(view(kind, None, hex_dump(payload))),
+        DecoderKind::Json | DecoderKind::Cbor | DecoderKind::MsgPack => {
+            Some(match parse(kind, payload) {
+                Ok(value) => structured(kind, value),
+                Err(e) => failed(kind, payload, e),
+            })
+        }
+        DecoderKind::Base64 => {
+            let text = String::from_utf8_lossy(payload);
+            Some(match BASE64.decode(text.trim()) {
+                Ok(bytes) => match String::from_utf8(bytes) {
+                    Ok(text) => view(kind, None, text),
+                    Err(e) => view(kind, None, hex_dump(e.as_bytes())),
+                },
+                Err(e) => failed(kind, payload, e.to_string()),
+            })
+        }
+    }
+}
+
+/// Scalars are not detected since almost any short payload parses as one.
+fn detect(payload: &[u8]) -> Option<Decoded> {
+    let is_container = |value: &Value| value.is_object() || value.is_array();
+    if std::str::from_utf8(payload).is_ok() {
+        return parse(DecoderKind::Json, payload)
+            .ok()
+            .filter(is_container)
+            .map(|value| structured(DecoderKind::Json, value));
+    }
+    [DecoderKind::Cbor, DecoderKind::MsgPack]
+        .into_iter()
+        .find_map(|kind| {
+            parse(kind, payload)
+                .ok()
+                .filter(is_container)
+                .map(|value| structured(kind, value))
+        })
+        .or_else(|| Some(view(DecoderKind::Hex, None, hex_dump(payload))))
+}
+
+/// Trailing bytes are an error.
+fn parse(kind: DecoderKind, payload: &[u8]) -> Result<Value, String> {
+    match kind {
+        DecoderKind::Json => serde_json::from_slice(payload).map_err(|e| e.to_string()),
+        DecoderKind::Cbor => {
+            let mut rest = payload;
+            let value: Value = ciborium::from_reader(&mut rest).map_err(|e| e.to_string())?;
+            trailing(rest)?;
+            Ok(value)
+        }
+        DecoderKind::MsgPack => {
+            let mut rest = payload;
+            let value: Value = rmp_serde::from_read(&mut rest).map_err(|e| e.to_string())?;
+            trailing(rest)?;
+            Ok(value)
+        }
+        _ => Err(format!("{:?} payloads have no structure", kind)),
+    }
+}
+
+fn trailing(rest: &[u8]) -> Result<(), String> {
+    if rest.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} trailing bytes", rest.len()))
+    }
+}
+
+pub fn structured(kind: DecoderKind, value: Value) -> Decoded {
+    let text = serde_json::to_string_pretty(&value).unwrap_or_default();
+    view(kind, Some(value), text)
+}
+
+pub fn failed(kind: DecoderKind, payload: &[u8], error: String) -> Decoded {
+    Decoded {
+        error: Some(error),
+        ..view(kind, None, hex_dump(payload))
+    }
+}
+
+fn view(format: DecoderKind, value: Option<Value>, text: String) -> Decoded {
+    Decoded {
+        format,
+        value,
+        text,
+        error: None,
+        compression: None,
+    }
+}
+
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes[..bytes.len().min(MAX_HEX_DUMP)]
+        .chunks(16)
+        .enumerate()
+    {
+        let _ = write!(out, "{:08x} ", line * 16);
+        for i in 0..16 {
+            if i == 8 {
+                out.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(out, " {:02x}", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+    if bytes.len() > MAX_HEX_DUMP {
+        let _ = writeln!(out, "... {} more bytes", bytes.len() - MAX_HEX_DUMP);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(topic_filter: &str, decoder: DecoderKind) -> DecoderRule {
+        DecoderRule {
+            topic_filter: topic_filter.to_string(),
+            decoder,
+            descriptor_set: None,
+            message_type: None,
+            compression: None,
+        }
+    }
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_detect() {
+        let decoded = decode(DecoderKind::Auto, br#"{"a":1}"#).unwrap();
+        assert_eq!(decoded.format, DecoderKind::Json);
+        assert_eq!(decoded.value, Some(json!({"a": 1})));
+        assert_eq!(decoded.text, "{\n  \"a\": 1\n}");
+
+        assert_eq!(decode(DecoderKind::Auto, b"21.5"), None);
+        assert_eq!(decode(DecoderKind::Auto, b"hello"), None);
+
+        let value = json!({"temp": 21.5, "tags": ["a"]});
+        let decoded = decode(DecoderKind::Auto, &cbor(&value)).unwrap();
+        assert_eq!(decoded.format, DecoderKind::Cbor);
+        assert_eq!(decoded.value, Some(value.clone()));
+
+        let packed = rmp_serde::to_vec_named(&value).unwrap();
+        let decoded = decode(DecoderKind::Auto, &packed).unwrap();
+        assert_eq!(decoded.format, DecoderKind::MsgPack);
+        assert_eq!(decoded.value, Some(value));
+
+        let decoded = decode(DecoderKind::Auto, &[0xff, 0xfe, 0x00]).unwrap();
+        assert_eq!(decoded.format, DecoderKind::Hex);
+    }
+
+    #[test]
+    fn test_configured_decoder_errors() {
+        let decoded = decode(DecoderKind::Json, b"{broken").unwrap();
+        assert!(decoded.error.is_some());
+        assert!(decoded.text.starts_with("00000000  7b 62"));
+
+        let mut bytes = cbor(&json!([1, 2]));
+        bytes.push(0x01);
+        let decoded = decode(DecoderKind::Cbor, &bytes).unwrap();
+        assert_eq!(decoded.error.as_deref(), Some("1 trailing bytes"));
+    }
+
+    #[test]
+    fn test_base64() {
+        let decoded = decode(DecoderKind::Base64, b"aGVsbG8=\n").unwrap();
+        assert_eq!(decoded.text, "hello");
+        assert!(decoded.error.is_none());
+        let decoded = decode(DecoderKind::Base64, b"//8=").unwrap();
+        assert!(decoded.text.starts_with("00000000  ff ff"));
+        assert!(decode(DecoderKind::Base64, b"not base64!")
+            .unwrap()
+            .error
+            .is_some());
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(b"Hello, MQTT!\x00\x01\x02\x03\x04");
+        assert_eq!(
+            dump,
+            "00000000  48 65 6c 6c 6f 2c 20 4d  51 54 54 21 00 01 02 03  |Hello, MQTT!....|\n\
+             00000010  04                                                |.|\n"
+        );
+        assert!(hex_dump(&[0; MAX_HEX_DUMP + 3]).ends_with("... 3 more bytes\n"));
+    }
+
+    #[test]
+    fn test_rules_first_match_wins() {
+        let decoders = Decoders::new(
+            &[
+                rule("raw/#", DecoderKind::Hex),
+                rule("bad/#/filter", DecoderKind::Json),
+                rule("#", DecoderKind::Text),
+            ],
+            false,
+        );
+        assert_eq!(
+            decoders.decode("raw/a", b"{}").unwrap().format,
+            DecoderKind::Hex
+        );
+        assert_eq!(decoders.decode("json/a", b"{}"), None);
+        assert_eq!(
+            Decoders::default().decode("json/a", b"{}").unwrap().format,
+            DecoderKind::Json
+        );
+    }
+
+    #[test]
+    fn test_protobuf_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.desc");
+        crate::protobuf::tests::write_descriptor_set(&path);
+        let decoders = Decoders::new(
+            &[
+                DecoderRule {
+                    descriptor_set: Some(path.to_string_lossy().to_string()),
+                    message_type: Some("telemetry.Reading".to_string()),
+                    ..rule("sensors/#", DecoderKind::Protobuf)
+                },
+                DecoderRule {
+                    message_type: Some("telemetry.Reading".to_string()),
+                    ..rule("broken/#", DecoderKind::Protobuf)
+                },
+            ],
+            false,
+        );
+
+        let bytes = decoders
+            .encode("sensors/t1", r#"{"sensor":"t1","value":21.5}"#)
+            .unwrap()
+            .unwrap();
+        let decoded = decoders.decode("sensors/t1", &bytes).unwrap();
+        assert_eq!(decoded.format, DecoderKind::Protobuf);
+        assert_eq!(
+            decoded.value,
+            Some(json!({"sensor": "t1", "value": 21.5, "tags": []}))
+        );
+        assert!(decoders.encode("sensors/t1", "not json").unwrap().is_err());
+        assert!(decoders.encode("other", "{}").is_none());
+
+        let decoded = decoders.decode("broken/a", &bytes).unwrap();
+        assert_eq!(
+            decoded.error.as_deref(),
+            Some("Protobuf decoder rules need a descriptor set and a message type")
+        );
+        assert!(decoders.encode("broken/a", "{}").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_compressed_payloads() {
+        let gzipped = compress::compress(Compression::Gzip, br#"{"a":1}"#).unwrap();
+        let decoders = Decoders::new(
+            &[
+                DecoderRule {
+                    compression: Some(Compression::Zlib),
+                    ..rule("zlib/#", DecoderKind::Text)
+                },
+                rule("#", DecoderKind::Auto),
+            ],
+            true,
+        );
+        let decoded = decoders.decode("batch/a", &gzipped).unwrap();
+        assert_eq!(decoded.format, DecoderKind::Json);
+        assert_eq!(decoded.value, Some(json!({"a": 1})));
+        assert_eq!(decoded.compression, Some(Compression::Gzip));
+
+        let zlibbed = compress::compress(Compression::Zlib, b"plain text").unwrap();
+        let decoded = decoders.decode("zlib/a", &zlibbed).unwrap();
+        assert_eq!(decoded.format, DecoderKind::Text);
+        assert_eq!(decoded.text, "plain text");
+
+        let decoded = decoders.decode("zlib/a", b"not zlib").unwrap();
+        assert_eq!(decoded.compression, Some(Compression::Zlib));
+        assert!(decoded.error.is_some());
+
+        let truncated = &gzipped[..gzipped.len() / 2];
+        assert_eq!(
+            decoders.decode("batch/a", truncated).unwrap().compression,
+            None
+        );
+        assert_eq!(
+            Decoders::new(&[], false)
+                .decode("batch/a", &gzipped)
+                .unwrap()
+                .compression,
+            None
+        );
+    }
+}
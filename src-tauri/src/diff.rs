@@ -0,0 +1,431 @@
+use crate::mqtt::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const MAX_CHANGES: usize = 100;
+
+const MAX_TRACKED_TOPICS: usize = 10_000;
+
+/// Over all topics.
+const MAX_TRACKED_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOp {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// `path` is written like `readings[0].value`, or `$` for the whole payload.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonChange {
+    pub path: String,
+    pub op: ChangeOp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+}
+
+/// As hex. One side is shorter when the payload lengths differ.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ByteRange {
+    pub offset: usize,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(
+    tag = "kind",
+    rename_all = "lowercase",
+    rename_all_fields = "camelCase"
+)]
+pub enum PayloadDiff {
+    Json {
+        changes: Vec<JsonChange>,
+        truncated: bool,
+    },
+    Bytes {
+        before_len: usize,
+        after_len: usize,
+        ranges: Vec<ByteRange>,
+        truncated: bool,
+    },
+}
+
+pub fn diff_messages(before: &Message, after: &Message) -> PayloadDiff {
+    match (before.json_value(), after.json_value()) {
+        (Some(before), Some(after)) => diff_json(&before, &after),
+        _ => diff_bytes(before.bytes(), after.bytes()),
+    }
+}
+
+pub fn diff_json(before: &Value, after: &Value) -> PayloadDiff {
+    let mut changes = Vec::new();
+    let truncated = !collect_changes("$", before, after, &mut changes);
+    PayloadDiff::Json { changes, truncated }
+}
+
+/// Returns false once the change limit is reached.
+fn collect_changes(
+    path: &str,
+    before: &Value,
+    after: &Value,
+    changes: &mut Vec<JsonChange>,
+) -> bool {
+    match (before, after) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, value) in old {
+                let path = key_path(path, key);
+                let complete = match new.get(key) {
+                    Some(updated) => collect_changes(&path, value, updated, changes),
+                    None => push_change(changes, path, ChangeOp::Removed, Some(value), None),
+                };
+                if !complete {
+                    return false;
+                }
+            }
+            for (key, value) in new {
+                if !old.contains_key(key)
+                    && !push_change(
+                        changes,
+                        key_path(path, key),
+                        ChangeOp::Added,
+                        None,
+                        Some(value),
+                    )
+                {
+                    return false;
+                }
+            }
+            true
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for i in 0..old.len().max(new.len()) {
+                let path = format!("{}[{}]", path, i);
+                let complete = match (old.get(i), new.get(i)) {
+                    (Some(value), Some(updated)) => collect_changes(&path, value, updated, changes),
+                    (Some(value), None) => {
+                        push_change(changes, path, ChangeOp::Removed, Some(value), None)
+                    }
+                    (None, value) => push_change(changes, path, ChangeOp::Added, None, value),
+                };
+                if !complete {
+                    return false;
+                }
+            }
+            true
+        }
+        _ if before == after => true,
+        _ => push_change(
+            changes,
+            path.to_string(),
+            ChangeOp::Changed,
+            Some(before),
+            Some(after),
+        ),
+    }
+}
+
+/// Returns false instead of appending once the change limit is reached.
+fn push_change(
+    changes: &mut Vec<JsonChange>,
+    path: String,
+    op: ChangeOp,
+    before: Option<&Value>,
+    after: Option<&Value>,
+) -> bool {
+    if changes.len() == MAX_CHANGES {
+        return false;
+    }
+    changes.push(JsonChange {
+        path,
+        op,
+        before: before.cloned(),
+        after: after.cloned(),
+    });
+    true
+}
+
+fn key_path(parent: &str, key: &str) -> String {
+    if parent == "$" {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+/// Adjacent differences are merged into ranges.
+pub fn diff_bytes(before: &[u8], after: &[u8]) -> PayloadDiff {
+    let mut ranges: Vec<ByteRange> = Vec::new();
+    let mut truncated = false;
+    let mut start = None;
+    for i in 0..=before.len().max(after.len()) {
+        let differs = i < before.len().max(after.len()) && before.get(i) != after.get(i);
+        match (differs, start) {
+            (true, None) => start = Some(i),
+            (false, Some(from)) => {
+                if ranges.len() == MAX_CHANGES {
+                    truncated = true;
+                    break;
+                }
+                ranges.push(ByteRange {
+                    offset: from,
+                    before: hex(&before[from.min(before.len())..i.min(before.len())]),
+                    after: hex(&after[from.min(after.len())..i.min(after.len())]),
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    PayloadDiff::Bytes {
+        before_len: before.len(),
+        after_len: after.len(),
+        ranges,
+        truncated,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+enum Snapshot {
+    Json(Value),
+    Bytes(Vec<u8>),
+}
+
+struct Previous {
+    snapshot: Snapshot,
+    size: usize,
+}
+
+#[derive(Default)]
+pub struct PreviousMessages {
+    enabled: bool,
+    last: HashMap<String, Previous>,
+    bytes: usize,
+}
+
+impl PreviousMessages {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.last.clear();
+            self.bytes = 0;
+        }
+    }
+
+    /// Returns None when disabled, for the first message on a topic, or once
+    /// too many topics or bytes are tracked. A JSON payload followed by a
+    /// non-JSON one is diffed against the compact JSON, as the original bytes
+    /// are not kept.
+    pub fn diff(&mut self, msg: &Message) -> Option<PayloadDiff> {
+        if !self.enabled {
+            return None;
+        }
+        let value = msg.json_value();
+        let diff = self
+            .last
+            .get(&msg.topic)
+            .map(|previous| match (&previous.snapshot, &value) {
+                (Snapshot::Json(before), Some(after)) => diff_json(before, after),
+                (Snapshot::Json(before), None) => {
+                    diff_bytes(&serde_json::to_vec(before).unwrap_or_default(), msg.bytes())
+                }
+                (Snapshot::Bytes(before), _) => diff_bytes(before, msg.bytes()),
+            });
+
+        if let Some(previous) = self.last.remove(&msg.topic) {
+            self.bytes -= previous.size;
+        }
+        let size = msg.topic.len() + msg.bytes().len();
+        if self.last.len() < MAX_TRACKED_TOPICS && self.bytes + size <= MAX_TRACKED_BYTES {
+            let snapshot = match value {
+                Some(value) => Snapshot::Json(value),
+                None => Snapshot::Bytes(msg.bytes().to_vec()),
+            };
+            self.bytes += size;
+            self.last
+                .insert(msg.topic.clone(), Previous { snapshot, size });
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(topic: &str, payload: &str) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn change(path: &str, op: ChangeOp, before: Option<Value>, after: Option<Value>) -> JsonChange {
+        JsonChange {
+            path: path.to_string(),
+            op,
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let before = json!({"state": "on", "level": 5, "tags": ["a", "b"], "meta": {"fw": "1.0"}});
+        let after = json!({"state": "on", "level": 7, "tags": ["a"], "meta": {"fw": "1.0", "ip": "10.0.0.2"}});
+        assert_eq!(
+            diff_json(&before, &after),
+            PayloadDiff::Json {
+                changes: vec![
+                    change("level", ChangeOp::Changed, Some(json!(5)), Some(json!(7))),
+                    change("meta.ip", ChangeOp::Added, None, Some(json!("10.0.0.2"))),
+                    change("tags[1]", ChangeOp::Removed, Some(json!("b")), None),
+                ],
+                truncated: false,
+            }
+        );
+        assert_eq!(
+            diff_json(&json!(1), &json!("1")),
+            PayloadDiff::Json {
+                changes: vec![change(
+                    "$",
+                    ChangeOp::Changed,
+                    Some(json!(1)),
+                    Some(json!("1"))
+                )],
+                truncated: false,
+            }
+        );
+
+        let many: serde_json::Map<String, Value> =
+            (0..150).map(|i| (format!("k{}", i), json!(i))).collect();
+        let PayloadDiff::Json { changes, truncated } = diff_json(&json!({}), &Value::Object(many))
+        else {
+            panic!("expected a JSON diff");
+        };
+        assert_eq!(changes.len(), MAX_CHANGES);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn test_diff_bytes() {
+        assert_eq!(
+            diff_bytes(&[1, 2, 3, 4, 5], &[1, 9, 9, 4, 5, 6]),
+            PayloadDiff::Bytes {
+                before_len: 5,
+                after_len: 6,
+                ranges: vec![
+                    ByteRange {
+                        offset: 1,
+                        before: "0203".to_string(),
+                        after: "0909".to_string(),
+                    },
+                    ByteRange {
+                        offset: 5,
+                        before: String::new(),
+                        after: "06".to_string(),
+                    },
+                ],
+                truncated: false,
+            }
+        );
+        let PayloadDiff::Bytes { ranges, .. } = diff_bytes(b"same", b"same") else {
+            panic!("expected a byte diff");
+        };
+        assert!(ranges.is_empty());
+    }
+
+    #[test]
+    fn test_diff_messages_picks_the_diff_kind() {
+        let json = diff_messages(&message("t", r#"{"a":1}"#), &message("t", r#"{"a":2}"#));
+        assert!(matches!(json, PayloadDiff::Json { .. }));
+        let text = diff_messages(&message("t", "on"), &message("t", r#"{"a":2}"#));
+        assert!(matches!(text, PayloadDiff::Bytes { .. }));
+
+        let mut decoded = message("t", "\u{FFFD}");
+        decoded.raw = Some(vec![0xa1, 0x61, 0x61, 0x01]);
+        decoded.decoded = Some(crate::decode::structured(
+            crate::types::DecoderKind::Cbor,
+            json!({"a": 1}),
+        ));
+        let PayloadDiff::Json { changes, .. } =
+            diff_messages(&decoded, &message("t", r#"{"a":1,"b":true}"#))
+        else {
+            panic!("expected a JSON diff");
+        };
+        assert_eq!(
+            changes,
+            vec![change("b", ChangeOp::Added, None, Some(json!(true)))]
+        );
+    }
+
+    #[test]
+    fn test_previous_messages() {
+        let mut previous = PreviousMessages::default();
+        assert_eq!(previous.diff(&message("t", "1")), None);
+        assert_eq!(previous.diff(&message("t", "2")), None);
+
+        previous.set_enabled(true);
+        assert_eq!(previous.diff(&message("t", "1")), None);
+        assert_eq!(previous.diff(&message("u", "1")), None);
+        assert_eq!(
+            previous.diff(&message("t", "2")),
+            Some(diff_json(&json!(1), &json!(2)))
+        );
+        assert_eq!(
+            previous.diff(&message("t", "3")),
+            Some(diff_json(&json!(2), &json!(3)))
+        );
+
+        assert_eq!(
+            previous.diff(&message("t", "on")),
+            Some(diff_bytes(b"3", b"on"))
+        );
+        assert_eq!(
+            previous.diff(&message("t", "off")),
+            Some(diff_bytes(b"on", b"off"))
+        );
+
+        previous.set_enabled(false);
+        previous.set_enabled(true);
+        assert_eq!(previous.diff(&message("t", "4")), None);
+    }
+
+    #[test]
+    fn test_previous_messages_byte_limit() {
+        let mut previous = PreviousMessages::default();
+        previous.set_enabled(true);
+        let large = "x".repeat(MAX_TRACKED_BYTES / 2 - 1);
+        previous.diff(&message("a", &large));
+        previous.diff(&message("b", &large));
+        // Too large to keep next to "a".
+        assert_eq!(previous.diff(&message("c", "1")), None);
+        assert_eq!(previous.diff(&message("c", "2")), None);
+        assert!(previous.diff(&message("a", "small")).is_some());
+        assert_eq!(previous.bytes, "a".len() + "small".len() + 1 + large.len());
+
+        // With "a" shrunk there is room again.
+        previous.diff(&message("c", "1"));
+        assert_eq!(
+            previous.diff(&message("c", "2")),
+            Some(diff_json(&json!(1), &json!(2)))
+        );
+    }
+}
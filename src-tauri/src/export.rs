@@ -0,0 +1,301 @@
+use crate::mqtt::Message;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use thiserror::Error;
+
+const FIELD_PREFIX: &str = "payload.";
+
+const METADATA_COLUMNS: [&str; 10] = [
+    "timestamp",
+    "time",
+    "topic",
+    "qos",
+    "retain",
+    "payload",
+    "raw",
+    "format",
+    "compression",
+    "violations",
+];
+
+/// First characters that make a cell a formula in spreadsheet apps.
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Serialization error: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    Json,
+}
+
+/// Returns the number of messages written.
+pub fn export(
+    path: &Path,
+    messages: &[Message],
+    format: ExportFormat,
+) -> Result<usize, ExportError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_messages(&mut out, messages, format)?;
+    out.flush()?;
+    Ok(messages.len())
+}
+
+pub fn write_messages<W: Write>(
+    out: &mut W,
+    messages: &[Message],
+    format: ExportFormat,
+) -> Result<(), ExportError> {
+    match format {
+        ExportFormat::Csv => write_csv(out, messages),
+        ExportFormat::Ndjson => {
+            for msg in messages {
+                serde_json::to_writer(&mut *out, msg)?;
+                out.write_all(b"\n")?;
+            }
+            Ok(())
+        }
+        ExportFormat::Json => {
+            out.write_all(b"[")?;
+            for (i, msg) in messages.iter().enumerate() {
+                out.write_all(if i == 0 { b"\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut *out, msg)?;
+            }
+            out.write_all(if messages.is_empty() {
+                b"]\n"
+            } else {
+                b"\n]\n"
+            })?;
+            Ok(())
+        }
+    }
+}
+
+fn write_csv<W: Write>(out: &mut W, messages: &[Message]) -> Result<(), ExportError> {
+    let fields: Vec<Vec<(String, String)>> = messages
+        .iter()
+        .map(|msg| {
+            let mut fields = Vec::new();
+            if let Some(value) = msg.json_value() {
+                flatten("", &value, &mut fields);
+            }
+            fields
+        })
+        .collect();
+
+    // Field columns in order of first appearance.
+    let mut columns: Vec<&str> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (path, _) in fields.iter().flatten() {
+        index.entry(path).or_insert_with(|| {
+            columns.push(path);
+            columns.len() - 1
+        });
+    }
+
+    let header = METADATA_COLUMNS
+        .iter()
+        .map(|column| column.to_string())
+        .chain(
+            columns
+                .iter()
+                .map(|path| format!("{}{}", FIELD_PREFIX, path)),
+        );
+    write_row(out, header)?;
+
+    for (msg, fields) in messages.iter().zip(&fields) {
+        let mut values = vec![String::new(); columns.len()];
+        for (path, value) in fields {
+            values[index[path.as_str()]] = value.clone();
+        }
+        let metadata = [
+            msg.timestamp.to_string(),
+            DateTime::from_timestamp_millis(msg.timestamp as i64)
+                .map(|time| time.to_rfc3339_opts(SecondsFormat::Millis, true))
+                .unwrap_or_default(),
+            msg.topic.clone(),
+            (msg.qos as u8).to_string(),
+            msg.retain.to_string(),
+            msg.payload.clone(),
+            msg.raw
+                .as_ref()
+                .map(|raw| BASE64.encode(raw))
+                .unwrap_or_default(),
+            msg.decoded
+                .as_ref()
+                .and_then(|d| serde_json::to_value(d.format).ok())
+                .map(|format| format.as_str().unwrap_or_default().to_string())
+                .unwrap_or_default(),
+            msg.decoded
+                .as_ref()
+                .and_then(|d| d.compression)
+                .map(|c| c.to_string())
+                .unwrap_or_default(),
+            msg.violations.join("; "),
+        ];
+        write_row(out, metadata.into_iter().chain(values))?;
+    }
+    Ok(())
+}
+
+/// Leaves become `payload.<path>` CSV columns so spreadsheets can chart
+/// individual fields. Scalar payloads have no fields.
+fn flatten(path: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                flatten(&path, value, fields);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (i, value) in items.iter().enumerate() {
+                flatten(&format!("{}[{}]", path, i), value, fields);
+            }
+        }
+        _ if path.is_empty() => {}
+        Value::String(text) => fields.push((path.to_string(), text.clone())),
+        _ => fields.push((path.to_string(), value.to_string())),
+    }
+}
+
+fn write_row<W: Write>(out: &mut W, cells: impl Iterator<Item = String>) -> std::io::Result<()> {
+    for (i, cell) in cells.enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        out.write_all(escape(&cell).as_bytes())?;
+    }
+    out.write_all(b"\r\n")
+}
+
+/// Cells that spreadsheets would run as formulas are prefixed with `'`;
+/// numbers such as `-5` are left as they are.
+fn escape(cell: &str) -> std::borrow::Cow<'_, str> {
+    let cell: std::borrow::Cow<'_, str> =
+        if cell.starts_with(FORMULA_PREFIXES) && cell.parse::<f64>().is_err() {
+            format!("'{}", cell).into()
+        } else {
+            cell.into()
+        };
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\"")).into()
+    } else {
+        cell
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::QoS;
+
+    fn message(topic: &str, payload: &str, timestamp: u64) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            timestamp,
+            qos: QoS::AtLeastOnce,
+            ..Default::default()
+        }
+    }
+
+    fn write(messages: &[Message], format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        write_messages(&mut out, messages, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_csv_flattens_json_fields() {
+        let messages = [
+            message("sensors/t1", r#"{"temp":21.5,"meta":{"unit":"C"}}"#, 0),
+            message("sensors/t1", r#"{"temp":22,"tags":["a","b,c"]}"#, 1000),
+            message("status", "online", 2000),
+        ];
+        let csv = write(&messages, ExportFormat::Csv);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(
+            lines[0],
+            "timestamp,time,topic,qos,retain,payload,raw,format,compression,violations,\
+             payload.meta.unit,payload.temp,payload.tags[0],payload.tags[1]"
+        );
+        assert_eq!(
+            lines[1],
+            "0,1970-01-01T00:00:00.000Z,sensors/t1,1,false,\
+             \"{\"\"temp\"\":21.5,\"\"meta\"\":{\"\"unit\"\":\"\"C\"\"}}\",,,,,C,21.5,,"
+        );
+        assert!(lines[2].ends_with(",,22,a,\"b,c\""));
+        assert_eq!(
+            lines[3],
+            "2000,1970-01-01T00:00:02.000Z,status,1,false,online,,,,,,,,"
+        );
+        assert_eq!(lines[4], "");
+    }
+
+    #[test]
+    fn test_csv_neutralizes_formulas() {
+        assert_eq!(escape("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(escape("+1+cmd|' /C calc'!A0"), "'+1+cmd|' /C calc'!A0");
+        assert_eq!(escape("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(escape("-2+3"), "'-2+3");
+        assert_eq!(escape("\tx"), "'\tx");
+        assert_eq!(escape("-5"), "-5");
+        assert_eq!(escape("+1.5e3"), "+1.5e3");
+        assert_eq!(escape("a=b"), "a=b");
+
+        let csv = write(&[message("=cmd", "@x", 0)], ExportFormat::Csv);
+        assert!(csv.contains(",'=cmd,1,false,'@x,"));
+    }
+
+    #[test]
+    fn test_ndjson_and_json_round_trip() {
+        let mut binary = message("raw", "\u{FFFD}", 5);
+        binary.raw = Some(vec![0xff, 0x00]);
+        binary.violations = vec!["bad".to_string()];
+        let messages = [message("a", "1", 1), binary];
+
+        let ndjson = write(&messages, ExportFormat::Ndjson);
+        let parsed: Vec<Message> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].raw, Some(vec![0xff, 0x00]));
+        assert_eq!(parsed[1].violations, vec!["bad".to_string()]);
+
+        let json = write(&messages, ExportFormat::Json);
+        let parsed: Vec<Message> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[0].topic, "a");
+        assert_eq!(parsed[1].qos, QoS::AtLeastOnce);
+        assert_eq!(write(&[], ExportFormat::Json), "[]\n");
+    }
+
+    #[test]
+    fn test_export_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("messages.ndjson");
+        let count = export(&path, &[message("a", "1", 1)], ExportFormat::Ndjson).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+}
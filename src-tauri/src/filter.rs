@@ -0,0 +1,445 @@
+use crate::json_path;
+use crate::mqtt::{next_message, Message};
+use crate::topic::{matches_filter, validate_topic_filter};
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::{broadcast, watch, Mutex};
+
+pub const STREAM_EVENT: &str = "mqtt-message-filtered";
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+    #[error("Invalid topic filter: {0}")]
+    InvalidTopicFilter(String),
+    #[error("Invalid payload pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+    #[error("Invalid JSON path: {0}")]
+    InvalidJsonPath(String),
+    #[error("JSON predicate on '{0}' needs a value")]
+    MissingValue(String),
+    #[error("JSON predicate on '{0}' needs a number")]
+    InvalidNumber(String),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonOperator {
+    Exists,
+    Equals,
+    NotEquals,
+    Contains,
+    Greater,
+    Less,
+}
+
+/// Values are compared as text, except for `greater` and `less` which
+/// compare numbers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonPredicate {
+    pub path: String,
+    pub operator: JsonOperator,
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+/// Unset criteria match everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageFilter {
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub payload_contains: Option<String>,
+    #[serde(default)]
+    pub payload_pattern: Option<String>,
+    #[serde(default)]
+    pub json: Vec<JsonPredicate>,
+    /// Inclusive, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub since: Option<u64>,
+    #[serde(default)]
+    pub until: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilteredMessage {
+    pub stream_id: String,
+    pub message: Message,
+}
+
+enum Predicate {
+    Exists,
+    Equals(String),
+    NotEquals(String),
+    Contains(String),
+    Greater(f64),
+    Less(f64),
+}
+
+pub struct CompiledFilter {
+    filter: MessageFilter,
+    pattern: Option<Regex>,
+    json: Vec<(String, Predicate)>,
+}
+
+impl CompiledFilter {
+    pub fn new(filter: MessageFilter) -> Result<Self, FilterError> {
+        if let Some(topic) = &filter.topic {
+            validate_topic_filter(topic).map_err(FilterError::InvalidTopicFilter)?;
+        }
+        let pattern = filter
+            .payload_pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()?;
+        let json = filter
+            .json
+            .iter()
+            .map(|p| {
+                if !json_path::is_valid(&p.path) {
+                    return Err(FilterError::InvalidJsonPath(p.path.clone()));
+                }
+                let value = || {
+                    p.value
+                        .clone()
+                        .ok_or_else(|| FilterError::MissingValue(p.path.clone()))
+                };
+                let number = || {
+                    value()?
+                        .trim()
+                        .parse::<f64>()
+                        .map_err(|_| FilterError::InvalidNumber(p.path.clone()))
+                };
+                let predicate = match p.operator {
+                    JsonOperator::Exists => Predicate::Exists,
+                    JsonOperator::Equals => Predicate::Equals(value()?),
+                    JsonOperator::NotEquals => Predicate::NotEquals(value()?),
+                    JsonOperator::Contains => Predicate::Contains(value()?),
+                    JsonOperator::Greater => Predicate::Greater(number()?),
+                    JsonOperator::Less => Predicate::Less(number()?),
+                };
+                Ok((p.path.clone(), predicate))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            filter,
+            pattern,
+            json,
+        })
+    }
+
+    pub fn matches(&self, msg: &Message) -> bool {
+        let filter = &self.filter;
+        if filter.since.is_some_and(|since| msg.timestamp < since)
+            || filter.until.is_some_and(|until| msg.timestamp > until)
+        {
+            return false;
+        }
+        if filter
+            .topic
+            .as_deref()
+            .is_some_and(|topic| !matches_filter(topic, &msg.topic))
+        {
+            return false;
+        }
+        if filter
+            .payload_contains
+            .as_deref()
+            .is_some_and(|text| !msg.payload.contains(text))
+        {
+            return false;
+        }
+        if self
+            .pattern
+            .as_ref()
+            .is_some_and(|re| !re.is_match(&msg.payload))
+        {
+            return false;
+        }
+        if self.json.is_empty() {
+            return true;
+        }
+        let Some(payload) = msg.json_value() else {
+            return false;
+        };
+        self.json.iter().all(|(path, predicate)| {
+            let Some(value) = json_path::get(&payload, path) else {
+                return false;
+            };
+            match predicate {
+                Predicate::Exists => true,
+                Predicate::Equals(expected) => json_path::to_text(value) == *expected,
+                Predicate::NotEquals(expected) => json_path::to_text(value) != *expected,
+                Predicate::Contains(text) => json_path::to_text(value).contains(text.as_str()),
+                Predicate::Greater(bound) => as_number(value).is_some_and(|n| n > *bound),
+                Predicate::Less(bound) => as_number(value).is_some_and(|n| n < *bound),
+            }
+        })
+    }
+}
+
+/// Strings holding numbers count too.
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Keeps the newest `limit` messages.
+pub fn query<'a, I>(messages: I, filter: &CompiledFilter, limit: Option<usize>) -> Vec<Message>
+where
+    I: IntoIterator<Item = &'a Message>,
+    I::IntoIter: DoubleEndedIterator,
+{
+    let mut matched: Vec<Message> = messages
+        .into_iter()
+        .rev()
+        .filter(|m| filter.matches(m))
+        .take(limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    matched.reverse();
+    matched
+}
+
+#[derive(Default)]
+pub struct MessageStreams {
+    streams: Arc<Mutex<HashMap<String, watch::Sender<bool>>>>,
+}
+
+impl MessageStreams {
+    pub async fn start<F>(
+        &self,
+        mut messages: broadcast::Receiver<Message>,
+        filter: MessageFilter,
+        on_message: F,
+    ) -> Result<String, FilterError>
+    where
+        F: Fn(FilteredMessage) + Send + Sync + 'static,
+    {
+        let filter = CompiledFilter::new(filter)?;
+        let stream_id = uuid::Uuid::new_v4().to_string();
+        let (stop_tx, mut stop_rx) = watch::channel(false);
+        self.streams.lock().await.insert(stream_id.clone(), stop_tx);
+
+        let streams = Arc::clone(&self.streams);
+        let id = stream_id.clone();
+        tokio::spawn(async move {
+            info!("Started message stream {}", id);
+            loop {
+                let message = tokio::select! {
+                    _ = stop_rx.changed() => break,
+                    message = next_message(&mut messages, |m| filter.matches(m)) => message,
+                };
+                match message {
+                    Ok(message) => on_message(FilteredMessage {
+                        stream_id: id.clone(),
+                        message,
+                    }),
+                    Err(_) => break,
+                }
+            }
+            streams.lock().await.remove(&id);
+            info!("Stopped message stream {}", id);
+        });
+
+        Ok(stream_id)
+    }
+
+    pub async fn stop(&self, stream_id: &str) -> bool {
+        match self.streams.lock().await.get(stream_id) {
+            Some(tx) => {
+                let _ = tx.send(true);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn message(topic: &str, payload: &str, timestamp: u64) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    fn predicate(path: &str, operator: JsonOperator, value: Option<&str>) -> JsonPredicate {
+        JsonPredicate {
+            path: path.to_string(),
+            operator,
+            value: value.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_topic_payload_and_time() {
+        let filter = CompiledFilter::new(MessageFilter {
+            topic: Some("sensors/+/temp".to_string()),
+            payload_pattern: Some(r"^\d+(\.\d+)?$".to_string()),
+            since: Some(100),
+            until: Some(200),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(filter.matches(&message("sensors/a/temp", "21.5", 150)));
+        assert!(!filter.matches(&message("sensors/a/hum", "21.5", 150)));
+        assert!(!filter.matches(&message("sensors/a/temp", "warm", 150)));
+        assert!(!filter.matches(&message("sensors/a/temp", "21.5", 99)));
+        assert!(!filter.matches(&message("sensors/a/temp", "21.5", 201)));
+
+        let contains = CompiledFilter::new(MessageFilter {
+            payload_contains: Some("error".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(contains.matches(&message("log", "disk error", 0)));
+        assert!(!contains.matches(&message("log", "ok", 0)));
+    }
+
+    #[test]
+    fn test_json_predicates() {
+        let filter = CompiledFilter::new(MessageFilter {
+            json: vec![
+                predicate("status", JsonOperator::Equals, Some("ok")),
+                predicate("readings[0].value", JsonOperator::Greater, Some("20")),
+                predicate("meta", JsonOperator::Exists, None),
+            ],
+            ..Default::default()
+        })
+        .unwrap();
+        let matching = r#"{"status":"ok","readings":[{"value":21.5}],"meta":{}}"#;
+        assert!(filter.matches(&message("t", matching, 0)));
+        let too_cold = r#"{"status":"ok","readings":[{"value":"19"}],"meta":{}}"#;
+        assert!(!filter.matches(&message("t", too_cold, 0)));
+        let no_meta = r#"{"status":"ok","readings":[{"value":25}]}"#;
+        assert!(!filter.matches(&message("t", no_meta, 0)));
+        assert!(!filter.matches(&message("t", "not json", 0)));
+    }
+
+    #[test]
+    fn test_json_predicates_on_decoded_payload() {
+        let filter = CompiledFilter::new(MessageFilter {
+            json: vec![predicate("temp", JsonOperator::Greater, Some("20"))],
+            ..Default::default()
+        })
+        .unwrap();
+        let bytes = rmp_serde::to_vec_named(&serde_json::json!({"temp": 21.5})).unwrap();
+        let mut msg = message("t", &String::from_utf8_lossy(&bytes), 0);
+        msg.raw = Some(bytes.clone());
+        msg.decoded = crate::decode::decode(crate::types::DecoderKind::MsgPack, &bytes);
+        assert!(filter.matches(&msg));
+
+        msg.decoded = None;
+        assert!(!filter.matches(&msg));
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        assert!(matches!(
+            CompiledFilter::new(MessageFilter {
+                topic: Some("a/#/b".to_string()),
+                ..Default::default()
+            }),
+            Err(FilterError::InvalidTopicFilter(_))
+        ));
+        assert!(matches!(
+            CompiledFilter::new(MessageFilter {
+                payload_pattern: Some("(".to_string()),
+                ..Default::default()
+            }),
+            Err(FilterError::InvalidPattern(_))
+        ));
+        assert!(matches!(
+            CompiledFilter::new(MessageFilter {
+                json: vec![predicate("a[x]", JsonOperator::Exists, None)],
+                ..Default::default()
+            }),
+            Err(FilterError::InvalidJsonPath(_))
+        ));
+        assert!(matches!(
+            CompiledFilter::new(MessageFilter {
+                json: vec![predicate("a", JsonOperator::Less, Some("low"))],
+                ..Default::default()
+            }),
+            Err(FilterError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            CompiledFilter::new(MessageFilter {
+                json: vec![predicate("a", JsonOperator::Equals, None)],
+                ..Default::default()
+            }),
+            Err(FilterError::MissingValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_query_keeps_newest() {
+        let messages: Vec<_> = (0..5)
+            .map(|i| message(&format!("t/{}", i % 2), &i.to_string(), i))
+            .collect();
+        let filter = CompiledFilter::new(MessageFilter {
+            topic: Some("t/0".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let payloads: Vec<_> = query(&messages, &filter, Some(2))
+            .into_iter()
+            .map(|m| m.payload)
+            .collect();
+        assert_eq!(payloads, ["2", "4"]);
+        assert_eq!(query(&messages, &filter, None).len(), 3);
+    }
+
+    #[test]
+    fn test_stream_forwards_matching_messages() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let (tx, rx) = broadcast::channel(16);
+            let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+            let streams = MessageStreams::default();
+            let id = streams
+                .start(
+                    rx,
+                    MessageFilter {
+                        topic: Some("alerts/#".to_string()),
+                        ..Default::default()
+                    },
+                    move |m| {
+                        let _ = out_tx.send(m);
+                    },
+                )
+                .await
+                .unwrap();
+
+            tx.send(message("sensors/a", "skip", 0)).unwrap();
+            tx.send(message("alerts/fire", "!", 1)).unwrap();
+            let forwarded = tokio::time::timeout(Duration::from_secs(1), out_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(forwarded.stream_id, id);
+            assert_eq!(forwarded.message.topic, "alerts/fire");
+
+            assert!(streams.stop(&id).await);
+            assert!(out_rx.recv().await.is_none());
+            assert!(!streams.stop(&id).await);
+        });
+    }
+}
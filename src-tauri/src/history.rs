@@ -0,0 +1,489 @@
+use crate::types::{AppData, Connection};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_HISTORY: usize = 50;
+
+/// Undo entries hold the data before an edit, redo entries the data after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub timestamp: u64,
+    pub changes: Vec<String>,
+    pub data: AppData,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    undo: Vec<Revision>,
+    #[serde(default)]
+    redo: Vec<Revision>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    pub changes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryLog {
+    pub undo: Vec<HistoryEntry>,
+    pub redo: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Edits that only switch the active connection are not recorded.
+    pub fn record(&mut self, previous: &AppData, current: &AppData) -> bool {
+        let changes = describe_changes(previous, current);
+        if changes.is_empty() {
+            return false;
+        }
+        self.undo.push(Revision {
+            timestamp: now_millis(),
+            changes,
+            data: previous.clone(),
+        });
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+        true
+    }
+
+    pub fn undo(&mut self, current: &AppData) -> Option<AppData> {
+        let revision = self.undo.pop()?;
+        let restored = keep_active_connection(revision.data, current);
+        self.redo.push(Revision {
+            timestamp: revision.timestamp,
+            changes: revision.changes,
+            data: current.clone(),
+        });
+        Some(restored)
+    }
+
+    pub fn redo(&mut self, current: &AppData) -> Option<AppData> {
+        let revision = self.redo.pop()?;
+        let restored = keep_active_connection(revision.data, current);
+        self.undo.push(Revision {
+            timestamp: revision.timestamp,
+            changes: revision.changes,
+            data: current.clone(),
+        });
+        Some(restored)
+    }
+
+    pub fn log(&self) -> HistoryLog {
+        let entries = |revisions: &[Revision]| {
+            revisions
+                .iter()
+                .rev()
+                .map(|r| HistoryEntry {
+                    timestamp: r.timestamp,
+                    changes: r.changes.clone(),
+                })
+                .collect()
+        };
+        HistoryLog {
+            undo: entries(&self.undo),
+            redo: entries(&self.redo),
+        }
+    }
+}
+
+/// Undo should not jump to another connection unless the active one
+/// disappears with the restored revision.
+fn keep_active_connection(mut restored: AppData, current: &AppData) -> AppData {
+    if let Some(ref id) = current.last_connection_id {
+        if restored.connections.iter().any(|c| &c.id == id) {
+            restored.last_connection_id = Some(id.clone());
+        }
+    }
+    restored
+}
+
+pub fn describe_changes(previous: &AppData, current: &AppData) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    for old in &previous.connections {
+        match current.connections.iter().find(|c| c.id == old.id) {
+            Some(new) => describe_connection_changes(old, new, &mut changes),
+            None => changes.push(format!("Deleted connection '{}'", old.name)),
+        }
+    }
+    for new in &current.connections {
+        if !previous.connections.iter().any(|c| c.id == new.id) {
+            changes.push(format!("Added connection '{}'", new.name));
+        }
+    }
+
+    for old in &previous.schedules {
+        match current.schedules.iter().find(|s| s.id == old.id) {
+            Some(new) => {
+                if serde_json::to_value(old).ok() != serde_json::to_value(new).ok() {
+                    changes.push(format!("Edited schedule '{}'", new.name));
+                }
+            }
+            None => changes.push(format!("Deleted schedule '{}'", old.name)),
+        }
+    }
+    for new in &current.schedules {
+        if !previous.schedules.iter().any(|s| s.id == new.id) {
+            changes.push(format!("Added schedule '{}'", new.name));
+        }
+    }
+
+    for old in &previous.schemas {
+        match current.schemas.iter().find(|s| s.id == old.id) {
+            Some(new) => {
+                if old.name != new.name || old.schema != new.schema {
+                    changes.push(format!("Edited schema '{}'", new.name));
+                }
+            }
+            None => changes.push(format!("Deleted schema '{}'", old.name)),
+        }
+    }
+    for new in &current.schemas {
+        if !previous.schemas.iter().any(|s| s.id == new.id) {
+            changes.push(format!("Added schema '{}'", new.name));
+        }
+    }
+
+    if previous.http_api != current.http_api {
+        changes.push("Changed HTTP API settings".to_string());
+    }
+
+    if previous.embedded_broker != current.embedded_broker {
+        changes.push("Changed embedded broker settings".to_string());
+    }
+
+    changes
+}
+
+fn describe_connection_changes(old: &Connection, new: &Connection, changes: &mut Vec<String>) {
+    if old.name != new.name {
+        changes.push(format!(
+            "Renamed connection '{}' to '{}'",
+            old.name, new.name
+        ));
+    }
+
+    let mut settings = Vec::new();
+    if old.broker_url != new.broker_url {
+        settings.push("broker URL");
+    }
+    if old.port != new.port {
+        settings.push("port");
+    }
+    if old.client_id != new.client_id {
+        settings.push("client ID");
+    }
+    if old.username != new.username || old.password != new.password {
+        settings.push("credentials");
+    }
+    if old.use_tls != new.use_tls {
+        settings.push("TLS");
+    }
+    if old.auto_connect != new.auto_connect {
+        settings.push("auto-connect");
+    }
+    if old.diff_previous != new.diff_previous {
+        settings.push("payload diffs");
+    }
+    if old.project_file != new.project_file {
+        settings.push("project file");
+    }
+    if !settings.is_empty() {
+        changes.push(format!("Changed {} of '{}'", settings.join(", "), new.name));
+    }
+
+    let mut variables: Vec<&String> = old
+        .variables
+        .keys()
+        .chain(new.variables.keys())
+        .filter(|key| old.variables.get(*key) != new.variables.get(*key))
+        .collect();
+    variables.sort();
+    variables.dedup();
+    if !variables.is_empty() {
+        let names: Vec<&str> = variables.iter().map(|k| k.as_str()).collect();
+        changes.push(format!(
+            "Changed variables {} in '{}'",
+            names.join(", "),
+            new.name
+        ));
+    }
+
+    if old.subscriptions != new.subscriptions {
+        changes.push(format!("Changed subscriptions of '{}'", new.name));
+    }
+
+    let sequences = |c: &Connection| serde_json::to_value(&c.sequences).ok();
+    if sequences(old) != sequences(new) {
+        changes.push(format!("Changed sequences of '{}'", new.name));
+    }
+
+    let responders = |c: &Connection| serde_json::to_value(&c.responders).ok();
+    if responders(old) != responders(new) {
+        changes.push(format!("Changed responders of '{}'", new.name));
+    }
+
+    let scripts = |c: &Connection| serde_json::to_value(&c.scripts).ok();
+    if scripts(old) != scripts(new) {
+        changes.push(format!("Changed scripts of '{}'", new.name));
+    }
+
+    let decoders = |c: &Connection| serde_json::to_value((&c.decoders, c.auto_decompress)).ok();
+    if decoders(old) != decoders(new) {
+        changes.push(format!("Changed decoders of '{}'", new.name));
+    }
+
+    let schemas = |c: &Connection| serde_json::to_value(&c.message_schemas).ok();
+    if schemas(old) != schemas(new) {
+        changes.push(format!("Changed schema rules of '{}'", new.name));
+    }
+
+    for button in &old.buttons {
+        match new.buttons.iter().find(|b| b.id == button.id) {
+            Some(updated) => {
+                let before = serde_json::to_value(button).ok();
+                let after = serde_json::to_value(updated).ok();
+                if before != after {
+                    changes.push(format!(
+                        "Edited button '{}' in '{}'",
+                        updated.name, new.name
+                    ));
+                }
+            }
+            None => changes.push(format!(
+                "Deleted button '{}' from '{}'",
+                button.name, new.name
+            )),
+        }
+    }
+    for button in &new.buttons {
+        if !old.buttons.iter().any(|b| b.id == button.id) {
+            changes.push(format!("Added button '{}' to '{}'", button.name, new.name));
+        }
+    }
+
+    let old_order: Vec<&str> = old
+        .buttons
+        .iter()
+        .filter(|b| new.buttons.iter().any(|n| n.id == b.id))
+        .map(|b| b.id.as_str())
+        .collect();
+    let new_order: Vec<&str> = new
+        .buttons
+        .iter()
+        .filter(|b| old.buttons.iter().any(|o| o.id == b.id))
+        .map(|b| b.id.as_str())
+        .collect();
+    if old_order != new_order {
+        changes.push(format!("Reordered buttons in '{}'", new.name));
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Button, Schedule, ScheduleTimezone};
+    use std::collections::HashMap;
+
+    fn create_button(id: &str, name: &str) -> Button {
+        Button {
+            id: id.to_string(),
+            name: name.to_string(),
+            topic: "t".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn create_data() -> AppData {
+        AppData {
+            connections: vec![Connection {
+                id: "c1".to_string(),
+                name: "Lab".to_string(),
+                broker_url: "localhost".to_string(),
+                client_id: "client".to_string(),
+                variables: HashMap::from([("device".to_string(), "1".to_string())]),
+                buttons: vec![create_button("b1", "On"), create_button("b2", "Off")],
+                ..Default::default()
+            }],
+            last_connection_id: Some("c1".to_string()),
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_describe_button_changes() {
+        let old = create_data();
+        let mut new = create_data();
+        new.connections[0].buttons.remove(1);
+        new.connections[0].buttons[0].payload = Some("1".to_string());
+        new.connections[0]
+            .buttons
+            .push(create_button("b3", "Reset"));
+
+        assert_eq!(
+            describe_changes(&old, &new),
+            vec![
+                "Edited button 'On' in 'Lab'",
+                "Deleted button 'Off' from 'Lab'",
+                "Added button 'Reset' to 'Lab'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_describe_connection_changes() {
+        let old = create_data();
+        let mut new = create_data();
+        new.connections[0].name = "Bench".to_string();
+        new.connections[0].port = 8883;
+        new.connections[0].use_tls = true;
+        new.connections[0]
+            .variables
+            .insert("site".to_string(), "a".to_string());
+        new.connections[0].buttons.reverse();
+
+        assert_eq!(
+            describe_changes(&old, &new),
+            vec![
+                "Renamed connection 'Lab' to 'Bench'",
+                "Changed port, TLS of 'Bench'",
+                "Changed variables site in 'Bench'",
+                "Reordered buttons in 'Bench'",
+            ]
+        );
+
+        assert_eq!(
+            describe_changes(&old, &AppData::default()),
+            vec!["Deleted connection 'Lab'"]
+        );
+        assert_eq!(
+            describe_changes(&AppData::default(), &old),
+            vec!["Added connection 'Lab'"]
+        );
+    }
+
+    #[test]
+    fn test_describe_schedule_changes() {
+        let old = create_data();
+        let mut new = create_data();
+        new.schedules.push(Schedule {
+            id: "s1".to_string(),
+            name: "Heartbeat".to_string(),
+            connection_id: "c1".to_string(),
+            button_id: "b1".to_string(),
+            cron: "*/15 * * * *".to_string(),
+            timezone: ScheduleTimezone::Local,
+            enabled: true,
+        });
+        assert_eq!(
+            describe_changes(&old, &new),
+            vec!["Added schedule 'Heartbeat'"]
+        );
+
+        let mut edited = new.clone();
+        edited.schedules[0].enabled = false;
+        assert_eq!(
+            describe_changes(&new, &edited),
+            vec!["Edited schedule 'Heartbeat'"]
+        );
+        assert_eq!(
+            describe_changes(&new, &old),
+            vec!["Deleted schedule 'Heartbeat'"]
+        );
+    }
+
+    #[test]
+    fn test_switching_connection_is_not_recorded() {
+        let mut history = History::default();
+        let old = create_data();
+        let mut new = create_data();
+        new.last_connection_id = None;
+        assert!(!history.record(&old, &new));
+        assert!(history.log().undo.is_empty());
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::default();
+        let v1 = create_data();
+        let mut v2 = create_data();
+        v2.connections[0].buttons.remove(0);
+
+        assert!(history.record(&v1, &v2));
+        let undone = history.undo(&v2).unwrap();
+        assert_eq!(undone.connections[0].buttons.len(), 2);
+        assert_eq!(
+            history.log().redo[0].changes,
+            vec!["Deleted button 'On' from 'Lab'"]
+        );
+
+        let redone = history.redo(&undone).unwrap();
+        assert_eq!(redone.connections[0].buttons.len(), 1);
+        assert!(history.redo(&redone).is_none());
+        assert_eq!(history.log().undo.len(), 1);
+    }
+
+    #[test]
+    fn test_new_edit_clears_redo() {
+        let mut history = History::default();
+        let v1 = create_data();
+        let mut v2 = create_data();
+        v2.connections[0].name = "Two".to_string();
+        history.record(&v1, &v2);
+        history.undo(&v2);
+
+        let mut v3 = create_data();
+        v3.connections[0].name = "Three".to_string();
+        history.record(&v1, &v3);
+        assert!(history.log().redo.is_empty());
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut history = History::default();
+        let mut previous = create_data();
+        for i in 0..MAX_HISTORY + 10 {
+            let mut next = previous.clone();
+            next.connections[0].name = format!("Lab {}", i);
+            history.record(&previous, &next);
+            previous = next;
+        }
+        let log = history.log();
+        assert_eq!(log.undo.len(), MAX_HISTORY);
+        assert_eq!(
+            log.undo[0].changes,
+            vec![format!(
+                "Renamed connection 'Lab {}' to 'Lab {}'",
+                MAX_HISTORY + 8,
+                MAX_HISTORY + 9
+            )]
+        );
+    }
+
+    #[test]
+    fn test_undo_keeps_active_connection() {
+        let mut history = History::default();
+        let mut v1 = create_data();
+        v1.last_connection_id = None;
+        let mut v2 = create_data();
+        v2.connections[0].port = 1;
+        history.record(&v1, &v2);
+
+        let undone = history.undo(&v2).unwrap();
+        assert_eq!(undone.last_connection_id, Some("c1".to_string()));
+    }
+}
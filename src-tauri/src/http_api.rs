@@ -0,0 +1,662 @@
+use crate::filter::{CompiledFilter, FilterError, MessageFilter};
+use crate::mqtt::{next_message, MqttClient, MqttError};
+use crate::request::{self, RequestError};
+use crate::storage::{Storage, StorageError};
+use crate::types::{Connection, ConnectionStatus, HttpApiSettings, QoS};
+use axum::body::Bytes;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::stream::{self, Stream};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+/// How long a restart waits for open requests before dropping them.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum HttpApiError {
+    #[error("The HTTP API needs a token")]
+    MissingToken,
+    #[error("Cannot listen on port {0}: {1}")]
+    Bind(u16, std::io::Error),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpApiStatus {
+    pub url: Option<String>,
+    pub error: Option<String>,
+}
+
+struct Running {
+    settings: HttpApiSettings,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl Running {
+    /// Waits until the port is released.
+    async fn stop(self) {
+        let _ = self.shutdown.send(true);
+        let abort = self.task.abort_handle();
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, self.task)
+            .await
+            .is_err()
+        {
+            abort.abort();
+        }
+        info!("Stopped HTTP API on port {}", self.settings.port);
+    }
+}
+
+#[derive(Clone)]
+pub struct Backend {
+    pub storage: Arc<RwLock<Storage>>,
+    pub mqtt_client: Arc<RwLock<MqttClient>>,
+}
+
+#[derive(Default)]
+struct Server {
+    running: Option<Running>,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct HttpApi {
+    backend: OnceLock<Backend>,
+    server: Mutex<Server>,
+}
+
+impl HttpApi {
+    /// The server only starts once a backend is set.
+    pub fn set_backend(&self, backend: Backend) {
+        let _ = self.backend.set(backend);
+    }
+
+    pub async fn update(&self, settings: &HttpApiSettings) -> Result<(), HttpApiError> {
+        let mut server = self.server.lock().await;
+        let wanted = settings.enabled.then_some(settings);
+        if server.running.as_ref().map(|r| &r.settings) == wanted {
+            return Ok(());
+        }
+        if let Some(running) = server.running.take() {
+            running.stop().await;
+        }
+        server.error = None;
+        let Some(settings) = wanted else {
+            return Ok(());
+        };
+        match self.start(settings).await {
+            Ok(running) => server.running = running,
+            Err(e) => {
+                server.error = Some(e.to_string());
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn start(&self, settings: &HttpApiSettings) -> Result<Option<Running>, HttpApiError> {
+        if settings.token.is_empty() {
+            return Err(HttpApiError::MissingToken);
+        }
+        let Some(backend) = self.backend.get() else {
+            return Ok(None);
+        };
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, settings.port))
+            .await
+            .map_err(|e| HttpApiError::Bind(settings.port, e))?;
+        let (shutdown, mut stop) = watch::channel(false);
+        let router = router(backend.clone(), settings.token.clone(), stop.clone());
+        let task = tokio::spawn(async move {
+            let signal = async move {
+                let _ = stop.wait_for(|stop| *stop).await;
+            };
+            if let Err(e) = axum::serve(listener, router)
+                .with_graceful_shutdown(signal)
+                .await
+            {
+                warn!("HTTP API stopped: {}", e);
+            }
+        });
+        info!("HTTP API listening on http://127.0.0.1:{}", settings.port);
+        Ok(Some(Running {
+            settings: settings.clone(),
+            shutdown,
+            task,
+        }))
+    }
+
+    pub async fn status(&self) -> HttpApiStatus {
+        let server = self.server.lock().await;
+        HttpApiStatus {
+            url: server
+                .running
+                .as_ref()
+                .map(|r| format!("http://127.0.0.1:{}", r.settings.port)),
+            error: server.error.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Api {
+    backend: Backend,
+    stop: watch::Receiver<bool>,
+}
+
+fn router(backend: Backend, token: String, stop: watch::Receiver<bool>) -> Router {
+    Router::new()
+        .route("/api/status", get(status))
+        .route("/api/connections", get(list_connections))
+        .route("/api/connections/{id}/buttons", get(list_buttons))
+        .route("/api/buttons/{id}/trigger", post(trigger_button))
+        .route("/api/publish", post(publish))
+        .route("/api/messages", get(messages))
+        .route("/api/messages/stream", get(stream_messages))
+        .layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            authorize,
+        ))
+        .with_state(Api { backend, stop })
+}
+
+async fn authorize(State(token): State<Arc<str>>, request: Request, next: Next) -> Response {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query = query_token(request.uri());
+    if bearer
+        .or(query.as_deref())
+        .is_some_and(|given| tokens_match(given, &token))
+    {
+        next.run(request).await
+    } else {
+        ApiError::Unauthorized.into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// `EventSource` clients cannot set headers, so the token may also be passed
+/// as `?token=`.
+fn query_token(uri: &axum::http::Uri) -> Option<String> {
+    Query::<TokenQuery>::try_from_uri(uri).ok()?.0.token
+}
+
+/// Compares in constant time for tokens of equal length.
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[derive(Debug, Error)]
+enum ApiError {
+    #[error("Missing or invalid token")]
+    Unauthorized,
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("Not connected")]
+    NotConnected,
+    #[error("{0}")]
+    Timeout(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotConnected => StatusCode::CONFLICT,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let body = serde_json::json!({ "error": self.to_string() });
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<MqttError> for ApiError {
+    fn from(e: MqttError) -> Self {
+        match e {
+            MqttError::NotConnected => ApiError::NotConnected,
+            MqttError::Encode(..) | MqttError::Schema(_) => ApiError::BadRequest(e.to_string()),
+            _ => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<RequestError> for ApiError {
+    fn from(e: RequestError) -> Self {
+        match e {
+            RequestError::Timeout(..) | RequestError::NoCorrelatedReply(..) => {
+                ApiError::Timeout(e.to_string())
+            }
+            RequestError::Mqtt(e) => e.into(),
+            RequestError::NotARequest(_) => ApiError::BadRequest(e.to_string()),
+        }
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(e: StorageError) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<FilterError> for ApiError {
+    fn from(e: FilterError) -> Self {
+        ApiError::BadRequest(e.to_string())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusResponse {
+    status: ConnectionStatus,
+    connection_id: Option<String>,
+}
+
+async fn status(State(api): State<Api>) -> Json<StatusResponse> {
+    let state = &api.backend;
+    let client = state.mqtt_client.read().await;
+    Json(StatusResponse {
+        status: client.get_status().await,
+        connection_id: client.connection_id().map(str::to_string),
+    })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectionEntry {
+    id: String,
+    name: String,
+    broker: String,
+    connected: bool,
+    buttons: usize,
+}
+
+async fn list_connections(State(api): State<Api>) -> Result<Json<Vec<ConnectionEntry>>, ApiError> {
+    let state = &api.backend;
+    let data = state.storage.read().await.load_data()?;
+    let connected = state
+        .mqtt_client
+        .read()
+        .await
+        .connection_id()
+        .map(str::to_string);
+    Ok(Json(
+        data.connections
+            .into_iter()
+            .map(|c| ConnectionEntry {
+                connected: connected.as_deref() == Some(c.id.as_str()),
+                broker: format!("{}:{}", c.broker_url, c.port),
+                buttons: c.buttons.len(),
+                id: c.id,
+                name: c.name,
+            })
+            .collect(),
+    ))
+}
+
+async fn list_buttons(
+    State(api): State<Api>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    let state = &api.backend;
+    let data = state.storage.read().await.load_data()?;
+    let connection = find_connection(&data.connections, &id)?;
+    Ok(Json(&connection.buttons).into_response())
+}
+
+fn find_connection<'a>(
+    connections: &'a [Connection],
+    key: &str,
+) -> Result<&'a Connection, ApiError> {
+    connections
+        .iter()
+        .find(|c| c.id == key)
+        .or_else(|| connections.iter().find(|c| c.name == key))
+        .ok_or_else(|| ApiError::NotFound(format!("Connection not found: {}", key)))
+}
+
+#[derive(Deserialize, Default)]
+struct TriggerRequest {
+    #[serde(default)]
+    variables: HashMap<String, String>,
+}
+
+/// Request buttons answer with the reply; others with 204 once published.
+async fn trigger_button(
+    State(api): State<Api>,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request: TriggerRequest = if body.is_empty() {
+        TriggerRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| ApiError::BadRequest(e.to_string()))?
+    };
+    let state = &api.backend;
+    let connection_id = state
+        .mqtt_client
+        .read()
+        .await
+        .connection_id()
+        .map(str::to_string)
+        .ok_or(ApiError::NotConnected)?;
+    let data = state.storage.read().await.load_data()?;
+    let connection = find_connection(&data.connections, &connection_id)?;
+    let button = connection
+        .buttons
+        .iter()
+        .find(|b| b.id == id)
+        .or_else(|| connection.buttons.iter().find(|b| b.name == id))
+        .ok_or_else(|| ApiError::NotFound(format!("Button not found: {}", id)))?;
+    let mut variables = connection.variables.clone();
+    variables.extend(request.variables);
+
+    if button.response.is_some() {
+        let reply = request::send_request(&state.mqtt_client, button, &variables).await?;
+        return Ok(Json(reply).into_response());
+    }
+    state
+        .mqtt_client
+        .read()
+        .await
+        .publish_button(button, &variables)
+        .await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[derive(Deserialize)]
+struct PublishRequest {
+    topic: String,
+    #[serde(default)]
+    payload: String,
+    #[serde(default)]
+    qos: QoS,
+    #[serde(default)]
+    retain: bool,
+}
+
+async fn publish(
+    State(api): State<Api>,
+    Json(request): Json<PublishRequest>,
+) -> Result<StatusCode, ApiError> {
+    let state = &api.backend;
+    state
+        .mqtt_client
+        .read()
+        .await
+        .publish(
+            &request.topic,
+            &request.payload,
+            request.qos,
+            request.retain,
+        )
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct MessagesQuery {
+    topic: Option<String>,
+    limit: Option<usize>,
+}
+
+fn compile(topic: Option<String>) -> Result<CompiledFilter, ApiError> {
+    Ok(CompiledFilter::new(MessageFilter {
+        topic,
+        ..MessageFilter::default()
+    })?)
+}
+
+async fn messages(
+    State(api): State<Api>,
+    Query(query): Query<MessagesQuery>,
+) -> Result<Response, ApiError> {
+    let filter = compile(query.topic)?;
+    let state = &api.backend;
+    let client = state.mqtt_client.read().await;
+    let messages = client.query_messages(&filter, query.limit).await;
+    Ok(Json(messages).into_response())
+}
+
+async fn stream_messages(
+    State(api): State<Api>,
+    Query(query): Query<MessagesQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let filter = compile(query.topic)?;
+    let messages = api.backend.mqtt_client.read().await.subscribe_messages();
+    let events = stream::unfold(
+        (messages, filter, api.stop),
+        |(mut messages, filter, mut stop)| async move {
+            let msg = tokio::select! {
+                msg = next_message(&mut messages, |m| filter.matches(m)) => msg.ok()?,
+                _ = stop.wait_for(|stop| *stop) => return None,
+            };
+            let event = Event::default().event("message").json_data(&msg).ok()?;
+            Some((Ok(event), (messages, filter, stop)))
+        },
+    );
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::Message;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    async fn start_api(dir: &tempfile::TempDir, token: &str) -> (HttpApi, HttpApiSettings) {
+        let api = HttpApi::default();
+        api.set_backend(Backend {
+            storage: Arc::new(RwLock::new(
+                Storage::open(dir.path().to_path_buf(), None).unwrap(),
+            )),
+            mqtt_client: Arc::new(RwLock::new(MqttClient::new())),
+        });
+        let settings = HttpApiSettings {
+            enabled: true,
+            port: free_port(),
+            token: token.to_string(),
+        };
+        api.update(&settings).await.unwrap();
+        (api, settings)
+    }
+
+    async fn send_get(port: u16, path: &str, token: Option<&str>) -> TcpStream {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        let auth = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+            path, auth
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+    }
+
+    async fn get(port: u16, path: &str, token: Option<&str>) -> String {
+        let mut response = String::new();
+        send_get(port, path, token)
+            .await
+            .read_to_string(&mut response)
+            .await
+            .unwrap();
+        response
+    }
+
+    async fn read_until(stream: &mut TcpStream, received: &mut String, needle: &str) {
+        let mut buf = [0; 4096];
+        while !received.contains(needle) {
+            let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .expect("response within 5s")
+                .unwrap();
+            assert!(n > 0, "connection closed before {:?}: {}", needle, received);
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+    }
+
+    fn message(topic: &str) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: "42".to_string(),
+            timestamp: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_requests_need_the_token() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let (api, settings) = start_api(&dir, "a b/c").await;
+            let port = settings.port;
+
+            assert!(get(port, "/api/status", None)
+                .await
+                .starts_with("HTTP/1.1 401"));
+            let response = get(port, "/api/status", Some("a b/d")).await;
+            assert!(response.starts_with("HTTP/1.1 401"));
+            assert!(response.contains("Missing or invalid token"));
+
+            let response = get(port, "/api/status", Some("a b/c")).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+            assert!(response.contains("\"status\":\"disconnected\""));
+            let response = get(port, "/api/connections?token=a%20b%2Fc", None).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+            assert!(response.ends_with("[]"));
+
+            api.update(&HttpApiSettings::default()).await.unwrap();
+            assert!(api.status().await.url.is_none());
+        });
+    }
+
+    #[test]
+    fn test_stream_messages_until_restart() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let (api, mut settings) = start_api(&dir, "secret").await;
+            let port = settings.port;
+
+            let mut stream =
+                send_get(port, "/api/messages/stream?topic=lab/%23", Some("secret")).await;
+            let mut received = String::new();
+            read_until(&mut stream, &mut received, "\r\n\r\n").await;
+            assert!(received.starts_with("HTTP/1.1 200"), "{}", received);
+            assert!(received.contains("text/event-stream"));
+
+            let backend = api.backend.get().unwrap();
+            let client = backend.mqtt_client.read().await;
+            client.inject_message(message("other/a"));
+            client.inject_message(message("lab/a"));
+            drop(client);
+            read_until(&mut stream, &mut received, "\"topic\":\"lab/a\"").await;
+            assert!(received.contains("event: message"));
+            assert!(!received.contains("other/a"));
+
+            // A new token restarts the server on the same port while the
+            // stream is still open, which ends the stream.
+            settings.token = "rotated".to_string();
+            api.update(&settings).await.unwrap();
+            let mut rest = Vec::new();
+            tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest))
+                .await
+                .expect("stream ends")
+                .unwrap();
+
+            assert!(get(port, "/api/status", Some("secret"))
+                .await
+                .starts_with("HTTP/1.1 401"));
+            assert!(get(port, "/api/status", Some("rotated"))
+                .await
+                .starts_with("HTTP/1.1 200"));
+        });
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("secret2", "secret"));
+        assert!(!tokens_match("", "secret"));
+
+        let token = |uri: &str| query_token(&uri.parse().unwrap());
+        assert_eq!(token("/api?topic=a%2Fb&token=abc").as_deref(), Some("abc"));
+        assert_eq!(token("/api?token=a%20b%2Bc").as_deref(), Some("a b+c"));
+        assert_eq!(token("/api?mytoken=abc"), None);
+        assert_eq!(token("/api"), None);
+    }
+
+    #[test]
+    fn test_update_needs_a_token() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let api = HttpApi::default();
+            let mut settings = HttpApiSettings {
+                enabled: true,
+                ..HttpApiSettings::default()
+            };
+            assert!(matches!(
+                api.update(&settings).await,
+                Err(HttpApiError::MissingToken)
+            ));
+            assert_eq!(
+                api.status().await.error.as_deref(),
+                Some("The HTTP API needs a token")
+            );
+
+            // Without a backend nothing is started yet.
+            settings.token = "secret".to_string();
+            api.update(&settings).await.unwrap();
+            let status = api.status().await;
+            assert_eq!(status.url, None);
+            assert_eq!(status.error, None);
+        });
+    }
+}
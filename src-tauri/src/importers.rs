@@ -0,0 +1,981 @@
+use crate::bundle::{self, BundleError};
+use crate::types::{Button, Connection, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum ImporterError {
+    #[error("Could not detect the file format")]
+    UnknownFormat,
+    #[error("Invalid {source_name} file: {message}")]
+    Invalid {
+        source_name: &'static str,
+        message: String,
+    },
+    #[error("No connections found in file")]
+    Empty,
+    #[error(transparent)]
+    Validation(#[from] BundleError),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSource {
+    Mqttx,
+    MqttExplorer,
+    Mosquitto,
+}
+
+impl ImportSource {
+    fn name(self) -> &'static str {
+        match self {
+            ImportSource::Mqttx => "MQTTX",
+            ImportSource::MqttExplorer => "MQTT Explorer",
+            ImportSource::Mosquitto => "mosquitto",
+        }
+    }
+
+    pub fn detect(content: &str) -> Option<Self> {
+        let Ok(value) = serde_json::from_str::<Value>(content) else {
+            return Some(ImportSource::Mosquitto);
+        };
+        if value.get("ConnectionManager_connections").is_some() {
+            return Some(ImportSource::MqttExplorer);
+        }
+        let first = match &value {
+            Value::Array(items) => items.first(),
+            Value::Object(map) if map.contains_key("host") => Some(&value),
+            Value::Object(map) => map.values().next(),
+            _ => None,
+        }?;
+        if first.get("clientId").is_some() && first.get("host").is_some() {
+            if first.get("encryption").is_some() || first.get("certValidation").is_some() {
+                Some(ImportSource::MqttExplorer)
+            } else {
+                Some(ImportSource::Mqttx)
+            }
+        } else if first.get("encryption").is_some() {
+            Some(ImportSource::MqttExplorer)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ImportNote {
+    pub connection: String,
+    pub field: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportPreview {
+    pub source: ImportSource,
+    pub connections: Vec<Connection>,
+    pub mapped: Vec<ImportNote>,
+    pub dropped: Vec<ImportNote>,
+}
+
+impl ImportPreview {
+    fn new(source: ImportSource) -> Self {
+        Self {
+            source,
+            connections: Vec::new(),
+            mapped: Vec::new(),
+            dropped: Vec::new(),
+        }
+    }
+
+    fn mapped(&mut self, connection: &str, field: &str, detail: impl Into<String>) {
+        self.mapped.push(ImportNote {
+            connection: connection.to_string(),
+            field: field.to_string(),
+            detail: detail.into(),
+        });
+    }
+
+    fn dropped(&mut self, connection: &str, field: &str, detail: impl Into<String>) {
+        self.dropped.push(ImportNote {
+            connection: connection.to_string(),
+            field: field.to_string(),
+            detail: detail.into(),
+        });
+    }
+}
+
+pub fn convert(
+    content: &str,
+    source: Option<ImportSource>,
+) -> Result<ImportPreview, ImporterError> {
+    let source = match source {
+        Some(source) => source,
+        None => ImportSource::detect(content).ok_or(ImporterError::UnknownFormat)?,
+    };
+    let preview = match source {
+        ImportSource::Mqttx => convert_mqttx(content)?,
+        ImportSource::MqttExplorer => convert_mqtt_explorer(content)?,
+        ImportSource::Mosquitto => convert_mosquitto(content),
+    };
+    if preview.connections.is_empty() {
+        return Err(ImporterError::Empty);
+    }
+    bundle::validate_connections(&preview.connections)?;
+    Ok(preview)
+}
+
+fn new_connection(name: &str) -> Connection {
+    Connection {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        client_id: generated_client_id(),
+        auto_connect: false,
+        ..Default::default()
+    }
+}
+
+fn new_button(name: &str, topic: &str) -> Button {
+    Button {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        topic: topic.to_string(),
+        ..Default::default()
+    }
+}
+
+fn generated_client_id() -> String {
+    format!(
+        "mqtt-topic-lab-{}",
+        &Uuid::new_v4().simple().to_string()[..6]
+    )
+}
+
+fn qos_from_u64(qos: u64) -> QoS {
+    match qos {
+        1 => QoS::AtLeastOnce,
+        2 => QoS::ExactlyOnce,
+        _ => QoS::AtMostOnce,
+    }
+}
+
+/// WebSocket protocols are reported because the client only speaks plain
+/// TCP/TLS.
+fn apply_scheme(preview: &mut ImportPreview, connection: &mut Connection, protocol: Option<&str>) {
+    let protocol = protocol.unwrap_or("mqtt").to_lowercase();
+    connection.use_tls |= matches!(protocol.as_str(), "mqtts" | "wss" | "ssl" | "tls");
+    if protocol.starts_with("ws") {
+        preview.dropped(
+            &connection.name,
+            "protocol",
+            format!("'{}' transport is not supported, using TCP", protocol),
+        );
+    }
+}
+
+fn strip_scheme(host: &str) -> (&str, Option<&str>) {
+    match host.split_once("://") {
+        Some((scheme, rest)) => (rest.trim_end_matches('/'), Some(scheme)),
+        None => (host, None),
+    }
+}
+
+/// Keys that only carry bookkeeping for the source tool and are not worth
+/// reporting as dropped.
+const MQTTX_METADATA: &[&str] = &[
+    "id",
+    "orderId",
+    "createAt",
+    "updateAt",
+    "isCollection",
+    "parentId",
+    "client",
+    "clientIdWithTime",
+];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MqttxConnection {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    host: String,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    ssl: bool,
+    #[serde(default)]
+    protocol: Option<String>,
+    #[serde(default)]
+    subscriptions: Vec<MqttxSubscription>,
+    #[serde(default)]
+    messages: Vec<MqttxMessage>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct MqttxSubscription {
+    topic: String,
+}
+
+#[derive(Deserialize)]
+struct MqttxMessage {
+    #[serde(default)]
+    topic: String,
+    #[serde(default)]
+    payload: Option<String>,
+    #[serde(default)]
+    qos: u64,
+    #[serde(default)]
+    retain: bool,
+    #[serde(default)]
+    out: bool,
+}
+
+fn convert_mqttx(content: &str) -> Result<ImportPreview, ImporterError> {
+    let invalid = |message: String| ImporterError::Invalid {
+        source_name: ImportSource::Mqttx.name(),
+        message,
+    };
+    let value: Value = serde_json::from_str(content).map_err(|e| invalid(e.to_string()))?;
+    let items = match value {
+        Value::Array(items) => items,
+        Value::Object(ref map) if map.contains_key("host") => vec![value],
+        Value::Object(map) => map.into_iter().map(|(_, v)| v).collect(),
+        _ => {
+            return Err(invalid(
+                "expected a connection or a list of connections".to_string(),
+            ))
+        }
+    };
+
+    let mut preview = ImportPreview::new(ImportSource::Mqttx);
+    for (i, item) in items.into_iter().enumerate() {
+        let source: MqttxConnection = serde_json::from_value(item)
+            .map_err(|e| invalid(format!("connection {}: {}", i, e)))?;
+        let name = if source.name.is_empty() {
+            format!("MQTTX {}", i + 1)
+        } else {
+            source.name.clone()
+        };
+        let mut connection = new_connection(&name);
+
+        let (host, scheme) = strip_scheme(&source.host);
+        connection.broker_url = host.to_string();
+        connection.use_tls = source.ssl;
+        apply_scheme(
+            &mut preview,
+            &mut connection,
+            source.protocol.as_deref().or(scheme),
+        );
+        if let Some(port) = source.port {
+            connection.port = port;
+        }
+        match source.client_id.filter(|id| !id.is_empty()) {
+            Some(id) => connection.client_id = id,
+            None => preview.mapped(&name, "clientId", "empty, generated a new client id"),
+        }
+        connection.username = source.username.filter(|u| !u.is_empty());
+        connection.password = source.password.filter(|p| !p.is_empty());
+
+        for subscription in source.subscriptions {
+            if !connection.subscriptions.contains(&subscription.topic) {
+                connection.subscriptions.push(subscription.topic);
+            }
+        }
+
+        let mut seen = Vec::new();
+        for message in source.messages.into_iter().filter(|m| m.out) {
+            let key = (message.topic.clone(), message.payload.clone());
+            if message.topic.is_empty() || seen.contains(&key) {
+                continue;
+            }
+            seen.push(key);
+            let mut button = new_button(&message.topic, &message.topic);
+            button.payload = message.payload.filter(|p| !p.is_empty());
+            button.qos = qos_from_u64(message.qos);
+            button.retain = message.retain;
+            connection.buttons.push(button);
+        }
+        if !connection.buttons.is_empty() {
+            preview.mapped(
+                &name,
+                "messages",
+                format!(
+                    "{} published message(s) converted to buttons",
+                    connection.buttons.len()
+                ),
+            );
+        }
+
+        let mut extra: Vec<_> = source
+            .extra
+            .into_iter()
+            .filter(|(key, value)| !MQTTX_METADATA.contains(&key.as_str()) && !is_empty(value))
+            .map(|(key, _)| key)
+            .collect();
+        extra.sort();
+        for key in extra {
+            preview.dropped(&name, &key, "not supported");
+        }
+
+        preview.connections.push(connection);
+    }
+    Ok(preview)
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(s) => s.is_empty(),
+        Value::Array(a) => a.is_empty(),
+        Value::Object(o) => o.is_empty(),
+        _ => false,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExplorerConnection {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    host: String,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    protocol: Option<String>,
+    #[serde(default)]
+    encryption: bool,
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    subscriptions: Vec<ExplorerSubscription>,
+    #[serde(default)]
+    cert_validation: Option<bool>,
+    #[serde(default)]
+    base_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExplorerSubscription {
+    Topic(String),
+    Detailed { topic: String },
+}
+
+fn convert_mqtt_explorer(content: &str) -> Result<ImportPreview, ImporterError> {
+    let invalid = |message: String| ImporterError::Invalid {
+        source_name: ImportSource::MqttExplorer.name(),
+        message,
+    };
+    let mut value: Value = serde_json::from_str(content).map_err(|e| invalid(e.to_string()))?;
+    if let Some(connections) = value.get_mut("ConnectionManager_connections") {
+        value = connections.take();
+    }
+    let items: Vec<Value> = match value {
+        Value::Object(ref map) if map.contains_key("host") => vec![value],
+        Value::Object(map) => map.into_iter().map(|(_, v)| v).collect(),
+        Value::Array(items) => items,
+        _ => return Err(invalid("expected a map of connections".to_string())),
+    };
+
+    let mut preview = ImportPreview::new(ImportSource::MqttExplorer);
+    for (i, item) in items.into_iter().enumerate() {
+        let source: ExplorerConnection = serde_json::from_value(item)
+            .map_err(|e| invalid(format!("connection {}: {}", i, e)))?;
+        let name = if source.name.is_empty() {
+            source.host.clone()
+        } else {
+            source.name.clone()
+        };
+        let mut connection = new_connection(&name);
+
+        let (host, scheme) = strip_scheme(&source.host);
+        connection.broker_url = host.to_string();
+        connection.use_tls = source.encryption;
+        apply_scheme(
+            &mut preview,
+            &mut connection,
+            source.protocol.as_deref().or(scheme),
+        );
+        if let Some(port) = source.port {
+            connection.port = port;
+        }
+        if let Some(id) = source.client_id.filter(|id| !id.is_empty()) {
+            connection.client_id = id;
+        }
+        connection.username = source.username.filter(|u| !u.is_empty());
+        connection.password = source.password.filter(|p| !p.is_empty());
+        for subscription in source.subscriptions {
+            let topic = match subscription {
+                ExplorerSubscription::Topic(topic) => topic,
+                ExplorerSubscription::Detailed { topic } => topic,
+            };
+            if !connection.subscriptions.contains(&topic) {
+                connection.subscriptions.push(topic);
+            }
+        }
+
+        if source.cert_validation == Some(false) {
+            preview.dropped(
+                &name,
+                "certValidation",
+                "disabling certificate validation is not supported",
+            );
+        }
+        if source.base_path.is_some_and(|p| !p.is_empty()) {
+            preview.dropped(&name, "basePath", "WebSocket paths are not supported");
+        }
+
+        preview.connections.push(connection);
+    }
+    Ok(preview)
+}
+
+/// Splits a line into shell-like words, honouring quotes and backslashes.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+                in_word = true;
+            }
+            (Some(_), c) => current.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+fn convert_mosquitto(content: &str) -> ImportPreview {
+    let is_bridge = content.lines().any(|line| {
+        let line = line.trim_start();
+        line.starts_with("connection ") || line.starts_with("address ")
+    });
+    if is_bridge {
+        convert_mosquitto_bridge(content)
+    } else {
+        convert_mosquitto_pub(content)
+    }
+}
+
+fn convert_mosquitto_bridge(content: &str) -> ImportPreview {
+    let mut preview = ImportPreview::new(ImportSource::Mosquitto);
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words = split_words(line);
+        let Some((key, args)) = words.split_first() else {
+            continue;
+        };
+        let key = key.as_str();
+
+        if key == "connection" {
+            let name = args
+                .first()
+                .cloned()
+                .unwrap_or_else(|| "bridge".to_string());
+            preview.connections.push(new_connection(&name));
+            continue;
+        }
+        let Some(connection) = preview.connections.last_mut() else {
+            continue;
+        };
+        let name = connection.name.clone();
+        let mut dropped = None;
+
+        match key {
+            "address" | "addresses" => {
+                if let Some(address) = args.first() {
+                    let (host, port) = match address.rsplit_once(':') {
+                        Some((host, port)) => (host, port.parse().ok()),
+                        None => (address.as_str(), None),
+                    };
+                    connection.broker_url = host.to_string();
+                    if let Some(port) = port {
+                        connection.port = port;
+                    }
+                }
+                if args.len() > 1 {
+                    dropped = Some((key, "only the first address is used".to_string()));
+                }
+            }
+            "remote_clientid" | "clientid" => {
+                if let Some(id) = args.first() {
+                    connection.client_id = id.clone();
+                }
+            }
+            "remote_username" | "username" => connection.username = args.first().cloned(),
+            "remote_password" | "password" => connection.password = args.first().cloned(),
+            "bridge_cafile" | "bridge_capath" | "bridge_certfile" | "bridge_keyfile" => {
+                connection.use_tls = true;
+                dropped = Some((
+                    key,
+                    "certificate files are not supported, using system roots".to_string(),
+                ));
+            }
+            "topic" => {
+                let pattern = args.first().map(String::as_str).unwrap_or("");
+                let remote_prefix = args.get(4).map(String::as_str).unwrap_or("");
+                let filter = format!("{}{}", remote_prefix, pattern);
+                if !filter.is_empty() && !connection.subscriptions.contains(&filter) {
+                    connection.subscriptions.push(filter.clone());
+                }
+                if let Some(direction) = args.get(1) {
+                    preview.mapped(
+                        &name,
+                        "topic",
+                        format!(
+                            "'{}' ({}) subscribed on the remote broker",
+                            filter, direction
+                        ),
+                    );
+                }
+            }
+            _ => dropped = Some((key, "bridge option not supported".to_string())),
+        }
+
+        if let Some((field, detail)) = dropped {
+            preview.dropped(&name, field, detail);
+        }
+    }
+
+    preview
+}
+
+#[derive(Default)]
+struct PubInvocation {
+    topic: Option<String>,
+    message: Option<String>,
+    qos: QoS,
+    retain: bool,
+    repeat_count: Option<u64>,
+    repeat_delay: Option<f64>,
+}
+
+/// Handles both a `mosquitto_pub` options file (one option per line, as read
+/// from `~/.config/mosquitto_pub`) and a script of `mosquitto_pub` command
+/// lines. Each command with a topic becomes a button on the connection for
+/// its broker.
+fn convert_mosquitto_pub(content: &str) -> ImportPreview {
+    let mut preview = ImportPreview::new(ImportSource::Mosquitto);
+
+    let lines: Vec<Vec<String>> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(split_words)
+        .collect();
+    let is_command =
+        |words: &Vec<String>| words.first().is_some_and(|w| w.ends_with("mosquitto_pub"));
+    let invocations: Vec<Vec<String>> = if lines.iter().any(is_command) {
+        lines
+            .into_iter()
+            .filter(is_command)
+            .map(|words| words[1..].to_vec())
+            .collect()
+    } else {
+        vec![lines.into_iter().flatten().collect()]
+    };
+
+    for args in invocations {
+        let mut connection = new_connection("mosquitto");
+        connection.broker_url = "localhost".to_string();
+        let mut invocation = PubInvocation::default();
+        let mut dropped = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().unwrap_or_default();
+            match arg.as_str() {
+                "-h" | "--host" => connection.broker_url = value(),
+                "-p" | "--port" => {
+                    if let Ok(port) = value().parse() {
+                        connection.port = port;
+                    }
+                }
+                "-u" | "--username" => connection.username = Some(value()),
+                "-P" | "--pw" => connection.password = Some(value()),
+                "-i" | "--id" => connection.client_id = value(),
+                "-I" | "--id-prefix" => {
+                    connection.client_id =
+                        format!("{}{}", value(), &Uuid::new_v4().simple().to_string()[..6]);
+                }
+                "-L" | "--url" => apply_mosquitto_url(&mut connection, &mut invocation, &value()),
+                "-t" | "--topic" => invocation.topic = Some(value()),
+                "-m" | "--message" => invocation.message = Some(value()),
+                "-n" | "--null-message" => invocation.message = Some(String::new()),
+                "-q" | "--qos" => invocation.qos = qos_from_u64(value().parse().unwrap_or(0)),
+                "-r" | "--retain" => invocation.retain = true,
+                "--repeat" => invocation.repeat_count = value().parse().ok(),
+                "--repeat-delay" => invocation.repeat_delay = value().parse().ok(),
+                "--cafile" | "--capath" | "--cert" | "--key" => {
+                    value();
+                    connection.use_tls = true;
+                    dropped.push((
+                        arg,
+                        "certificate files are not supported, using system roots",
+                    ));
+                }
+                "-d" | "--debug" | "--quiet" => {}
+                "-k" | "--keepalive" | "-V" | "--protocol-version" | "-f" | "--file" | "-A"
+                | "--will-topic" | "--will-payload" | "--will-qos" => {
+                    value();
+                    dropped.push((arg, "option not supported"));
+                }
+                _ => dropped.push((arg, "option not supported")),
+            }
+        }
+
+        let index = match preview
+            .connections
+            .iter()
+            .position(|c| c.broker_url == connection.broker_url && c.port == connection.port)
+        {
+            Some(index) => index,
+            None => {
+                connection.name = connection.broker_url.clone();
+                preview.connections.push(connection);
+                preview.connections.len() - 1
+            }
+        };
+        let name = preview.connections[index].name.clone();
+        for (field, detail) in dropped {
+            preview.dropped(&name, &field, detail);
+        }
+
+        if let Some(topic) = invocation.topic {
+            let mut button = new_button(&topic, &topic);
+            button.payload = invocation.message.filter(|m| !m.is_empty());
+            button.qos = invocation.qos;
+            button.retain = invocation.retain;
+            if invocation.repeat_count.is_some() || invocation.repeat_delay.is_some() {
+                button.multi_send_enabled = Some(true);
+                button.multi_send_interval =
+                    invocation.repeat_delay.map(|d| (d * 1000.0).round() as u64);
+                button.multi_send_max_count = invocation.repeat_count;
+            }
+            preview.connections[index].buttons.push(button);
+        }
+    }
+
+    for connection in &preview.connections {
+        if !connection.buttons.is_empty() {
+            let detail = format!(
+                "{} publish command(s) converted to buttons",
+                connection.buttons.len()
+            );
+            preview.mapped.push(ImportNote {
+                connection: connection.name.clone(),
+                field: "--topic".to_string(),
+                detail,
+            });
+        }
+    }
+    preview
+}
+
+/// Applies `mqtt(s)://[user[:pass]@]host[:port]/topic` from `-L`.
+fn apply_mosquitto_url(connection: &mut Connection, invocation: &mut PubInvocation, url: &str) {
+    let (rest, scheme) = strip_scheme(url);
+    connection.use_tls |= scheme == Some("mqtts");
+    let (authority, topic) = rest.split_once('/').unwrap_or((rest, ""));
+    let host_port = match authority.rsplit_once('@') {
+        Some((credentials, host_port)) => {
+            let (user, password) = match credentials.split_once(':') {
+                Some((user, password)) => (user, Some(password)),
+                None => (credentials, None),
+            };
+            connection.username = Some(user.to_string());
+            connection.password = password.map(str::to_string);
+            host_port
+        }
+        None => authority,
+    };
+    match host_port.rsplit_once(':') {
+        Some((host, port)) => {
+            connection.broker_url = host.to_string();
+            if let Ok(port) = port.parse() {
+                connection.port = port;
+            }
+        }
+        None => connection.broker_url = host_port.to_string(),
+    }
+    if !topic.is_empty() {
+        invocation.topic = Some(topic.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MQTTX_EXPORT: &str = r#"[
+        {
+            "id": "a1",
+            "name": "Factory",
+            "host": "broker.example.com",
+            "port": 8883,
+            "protocol": "mqtts",
+            "clientId": "mqttx_123",
+            "username": "factory",
+            "password": "secret",
+            "ssl": true,
+            "keepalive": 60,
+            "will": { "lastWillTopic": "status", "lastWillPayload": "offline" },
+            "subscriptions": [{ "topic": "factory/#", "qos": 0 }],
+            "messages": [
+                { "topic": "factory/cmd", "payload": "start", "qos": 1, "retain": false, "out": true },
+                { "topic": "factory/cmd", "payload": "start", "qos": 1, "retain": false, "out": true },
+                { "topic": "factory/state", "payload": "running", "qos": 0, "retain": false, "out": false }
+            ],
+            "createAt": "2024-01-01"
+        }
+    ]"#;
+
+    const EXPLORER_SETTINGS: &str = r##"{
+        "ConnectionManager_connections": {
+            "mqtt.eclipseprojects.io": {
+                "id": "mqtt.eclipseprojects.io",
+                "type": "mqtt",
+                "host": "mqtt.eclipseprojects.io",
+                "port": 1883,
+                "protocol": "mqtt",
+                "encryption": false,
+                "certValidation": true,
+                "clientId": "",
+                "name": "mqtt.eclipseprojects.io",
+                "subscriptions": [{ "topic": "#", "qos": 0 }, { "topic": "$SYS/#", "qos": 0 }]
+            },
+            "secure": {
+                "host": "secure.example.com",
+                "port": 443,
+                "protocol": "ws",
+                "encryption": true,
+                "certValidation": false,
+                "basePath": "mqtt",
+                "clientId": "explorer",
+                "name": "Secure",
+                "subscriptions": ["devices/#"]
+            }
+        }
+    }"##;
+
+    #[test]
+    fn test_detect_source() {
+        assert_eq!(
+            ImportSource::detect(MQTTX_EXPORT),
+            Some(ImportSource::Mqttx)
+        );
+        assert_eq!(
+            ImportSource::detect(EXPLORER_SETTINGS),
+            Some(ImportSource::MqttExplorer)
+        );
+        assert_eq!(
+            ImportSource::detect("-h localhost\n-p 1883\n"),
+            Some(ImportSource::Mosquitto)
+        );
+        assert_eq!(ImportSource::detect("{\"unrelated\": 1}"), None);
+    }
+
+    #[test]
+    fn test_convert_mqttx() {
+        let preview = convert(MQTTX_EXPORT, None).unwrap();
+        assert_eq!(preview.source, ImportSource::Mqttx);
+        let connection = &preview.connections[0];
+        assert_eq!(connection.name, "Factory");
+        assert_eq!(connection.broker_url, "broker.example.com");
+        assert_eq!(connection.port, 8883);
+        assert!(connection.use_tls);
+        assert_eq!(connection.client_id, "mqttx_123");
+        assert_eq!(connection.password, Some("secret".to_string()));
+        assert_eq!(connection.subscriptions, vec!["factory/#"]);
+        assert_eq!(connection.buttons.len(), 1);
+        assert_eq!(connection.buttons[0].payload, Some("start".to_string()));
+        assert_eq!(connection.buttons[0].qos, QoS::AtLeastOnce);
+
+        let dropped: Vec<&str> = preview.dropped.iter().map(|n| n.field.as_str()).collect();
+        assert_eq!(dropped, vec!["keepalive", "will"]);
+    }
+
+    #[test]
+    fn test_convert_mqtt_explorer() {
+        let preview = convert(EXPLORER_SETTINGS, None).unwrap();
+        assert_eq!(preview.connections.len(), 2);
+
+        let eclipse = preview
+            .connections
+            .iter()
+            .find(|c| c.name == "mqtt.eclipseprojects.io")
+            .unwrap();
+        assert!(eclipse.client_id.starts_with("mqtt-topic-lab-"));
+        assert_eq!(eclipse.subscriptions, vec!["#", "$SYS/#"]);
+
+        let secure = preview
+            .connections
+            .iter()
+            .find(|c| c.name == "Secure")
+            .unwrap();
+        assert!(secure.use_tls);
+        assert_eq!(secure.subscriptions, vec!["devices/#"]);
+
+        let dropped: Vec<&str> = preview.dropped.iter().map(|n| n.field.as_str()).collect();
+        assert!(dropped.contains(&"protocol"));
+        assert!(dropped.contains(&"certValidation"));
+        assert!(dropped.contains(&"basePath"));
+    }
+
+    #[test]
+    fn test_convert_mosquitto_bridge() {
+        let config = r#"
+            # main broker
+            listener 1883
+
+            connection cloud
+            address cloud.example.com:8883
+            remote_username bridge
+            remote_password "p@ss word"
+            remote_clientid edge-01
+            bridge_cafile /etc/ssl/ca.pem
+            topic sensors/# out 1 "" site1/
+            topic commands/# in 1
+            cleansession true
+        "#;
+        let preview = convert(config, Some(ImportSource::Mosquitto)).unwrap();
+        assert_eq!(preview.connections.len(), 1);
+        let connection = &preview.connections[0];
+        assert_eq!(connection.name, "cloud");
+        assert_eq!(connection.broker_url, "cloud.example.com");
+        assert_eq!(connection.port, 8883);
+        assert_eq!(connection.client_id, "edge-01");
+        assert_eq!(connection.password, Some("p@ss word".to_string()));
+        assert!(connection.use_tls);
+        assert_eq!(
+            connection.subscriptions,
+            vec!["site1/sensors/#", "commands/#"]
+        );
+
+        let dropped: Vec<&str> = preview.dropped.iter().map(|n| n.field.as_str()).collect();
+        assert_eq!(dropped, vec!["bridge_cafile", "cleansession"]);
+    }
+
+    #[test]
+    fn test_convert_mosquitto_pub_options_file() {
+        let options = "-h broker.local\n-p 1884\n-u admin\n-P 'top secret'\n-i lab-client\n-k 30\n";
+        let preview = convert(options, Some(ImportSource::Mosquitto)).unwrap();
+        let connection = &preview.connections[0];
+        assert_eq!(connection.broker_url, "broker.local");
+        assert_eq!(connection.port, 1884);
+        assert_eq!(connection.username, Some("admin".to_string()));
+        assert_eq!(connection.password, Some("top secret".to_string()));
+        assert_eq!(connection.client_id, "lab-client");
+        assert!(connection.buttons.is_empty());
+        assert_eq!(preview.dropped[0].field, "-k");
+    }
+
+    #[test]
+    fn test_convert_mosquitto_pub_script() {
+        let script = r#"
+            mosquitto_pub -h broker.local -t devices/1/power -m '{"on": true}' -q 1 -r
+            mosquitto_pub -h broker.local -t devices/1/ping -n --repeat 10 --repeat-delay 0.5
+            /usr/bin/mosquitto_pub -L mqtts://user:pw@other.local:8883/devices/2/reset
+        "#;
+        let preview = convert(script, Some(ImportSource::Mosquitto)).unwrap();
+        assert_eq!(preview.connections.len(), 2);
+        let connection = &preview.connections[0];
+        assert_eq!(connection.name, "broker.local");
+        assert_eq!(connection.buttons.len(), 2);
+
+        let power = &connection.buttons[0];
+        assert_eq!(power.topic, "devices/1/power");
+        assert_eq!(power.payload, Some("{\"on\": true}".to_string()));
+        assert_eq!(power.qos, QoS::AtLeastOnce);
+        assert!(power.retain);
+
+        let ping = &connection.buttons[1];
+        assert_eq!(ping.payload, None);
+        assert_eq!(ping.multi_send_enabled, Some(true));
+        assert_eq!(ping.multi_send_interval, Some(500));
+        assert_eq!(ping.multi_send_max_count, Some(10));
+
+        let other = &preview.connections[1];
+        assert_eq!(other.broker_url, "other.local");
+        assert_eq!(other.port, 8883);
+        assert!(other.use_tls);
+        assert_eq!(other.username, Some("user".to_string()));
+        assert_eq!(other.buttons[0].topic, "devices/2/reset");
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words(r#"-m "a \"quoted\" value" -t 'single q' plain"#),
+            vec!["-m", "a \"quoted\" value", "-t", "single q", "plain"]
+        );
+        assert_eq!(split_words("-m ''"), vec!["-m", ""]);
+        assert_eq!(split_words(r"\x \ "), vec!["x", " "]);
+    }
+
+    #[test]
+    fn test_convert_mosquitto_bridge_is_validated() {
+        let config = "connection cloud\naddress broker.local\n\\x\n";
+        let preview = convert(config, Some(ImportSource::Mosquitto)).unwrap();
+        assert_eq!(preview.connections[0].broker_url, "broker.local");
+
+        let result = convert("connection cloud\n\\x\n", Some(ImportSource::Mosquitto));
+        assert!(matches!(result, Err(ImporterError::Validation(_))));
+    }
+
+    #[test]
+    fn test_convert_empty_file_fails() {
+        assert!(matches!(
+            convert("[]", Some(ImportSource::Mqttx)),
+            Err(ImporterError::Empty)
+        ));
+        assert!(matches!(
+            convert("{\"unrelated\": 1}", None),
+            Err(ImporterError::UnknownFormat)
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+// Minimal JSON paths: dot-separated keys with optional array indexes, such
+// as `meta.id` or `readings[0].value`. A leading `$.` is accepted.
+
+use serde_json::Value;
+
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+fn parse(path: &str) -> Option<Vec<Segment<'_>>> {
+    let path = path.strip_prefix("$.").unwrap_or(path);
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut rest) = match part.find('[') {
+            Some(i) => (&part[..i], &part[i..]),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(Segment::Key(key));
+        }
+        while let Some(stripped) = rest.strip_prefix('[') {
+            let end = stripped.find(']')?;
+            segments.push(Segment::Index(stripped[..end].parse().ok()?));
+            rest = &stripped[end + 1..];
+        }
+        if !rest.is_empty() || (key.is_empty() && part.is_empty()) {
+            return None;
+        }
+    }
+    Some(segments)
+}
+
+pub fn is_valid(path: &str) -> bool {
+    parse(path).is_some()
+}
+
+pub fn get<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    parse(path)?
+        .iter()
+        .try_fold(value, |current, segment| match segment {
+            Segment::Key(key) => current.get(*key),
+            Segment::Index(index) => current.get(*index),
+        })
+}
+
+/// Creates intermediate objects for missing keys.
+pub fn set(value: &mut Value, path: &str, new_value: Value) -> bool {
+    let Some(segments) = parse(path) else {
+        return false;
+    };
+    let Some((last, parents)) = segments.split_last() else {
+        return false;
+    };
+
+    let mut current = value;
+    for segment in parents {
+        current = match segment {
+            Segment::Key(key) => match current {
+                Value::Object(map) => map
+                    .entry(key.to_string())
+                    .or_insert_with(|| Value::Object(Default::default())),
+                _ => return false,
+            },
+            Segment::Index(index) => match current.get_mut(*index) {
+                Some(next) => next,
+                None => return false,
+            },
+        };
+    }
+
+    match (last, current) {
+        (Segment::Key(key), Value::Object(map)) => {
+            map.insert(key.to_string(), new_value);
+            true
+        }
+        (Segment::Index(index), Value::Array(items)) if *index < items.len() => {
+            items[*index] = new_value;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Renders a JSON value for comparisons with plain text: strings without
+/// quotes, everything else as JSON.
+pub fn to_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_get() {
+        let value = json!({"meta": {"id": "abc"}, "readings": [{"value": 1}, {"value": 2}]});
+        assert_eq!(get(&value, "meta.id"), Some(&json!("abc")));
+        assert_eq!(get(&value, "$.meta.id"), Some(&json!("abc")));
+        assert_eq!(get(&value, "readings[1].value"), Some(&json!(2)));
+        assert_eq!(get(&value, "readings[5].value"), None);
+        assert_eq!(get(&value, "meta.missing"), None);
+        assert_eq!(get(&value, "meta..id"), None);
+        assert_eq!(get(&value, "readings[x]"), None);
+        assert_eq!(get(&json!([[1, 2]]), "[0][1]"), Some(&json!(2)));
+    }
+
+    #[test]
+    fn test_set() {
+        let mut value = json!({"cmd": "reboot"});
+        assert!(set(&mut value, "meta.id", json!("abc")));
+        assert_eq!(value, json!({"cmd": "reboot", "meta": {"id": "abc"}}));
+
+        let mut list = json!({"items": [1, 2]});
+        assert!(set(&mut list, "items[1]", json!(3)));
+        assert_eq!(list, json!({"items": [1, 3]}));
+        assert!(!set(&mut list, "items[2]", json!(3)));
+        assert!(!set(&mut list, "items[0].x", json!(3)));
+        assert!(!set(&mut json!("text"), "id", json!(1)));
+    }
+
+    #[test]
+    fn test_to_text() {
+        assert_eq!(to_text(&json!("abc")), "abc");
+        assert_eq!(to_text(&json!(42)), "42");
+        assert_eq!(to_text(&json!({"a": true})), "{\"a\":true}");
+    }
+}
@@ -0,0 +1,132 @@
+use crate::filter::{self, CompiledFilter};
+use crate::mqtt::Message;
+use std::collections::VecDeque;
+
+pub const RECENT_MESSAGES: usize = 100;
+const MAX_MESSAGES: usize = 100_000;
+const MAX_BYTES: usize = 64 * 1024 * 1024;
+
+pub struct MessageLog {
+    messages: VecDeque<Message>,
+    bytes: usize,
+    max_messages: usize,
+    max_bytes: usize,
+}
+
+impl Default for MessageLog {
+    fn default() -> Self {
+        Self::with_limits(MAX_MESSAGES, MAX_BYTES)
+    }
+}
+
+impl MessageLog {
+    fn with_limits(max_messages: usize, max_bytes: usize) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            bytes: 0,
+            max_messages,
+            max_bytes,
+        }
+    }
+
+    pub fn push(&mut self, msg: Message) {
+        self.bytes += size(&msg);
+        self.messages.push_back(msg);
+        while self.messages.len() > self.max_messages
+            || (self.bytes > self.max_bytes && self.messages.len() > 1)
+        {
+            if let Some(dropped) = self.messages.pop_front() {
+                self.bytes -= size(&dropped);
+            }
+        }
+    }
+
+    /// Oldest first.
+    pub fn recent(&self, count: usize) -> Vec<Message> {
+        let skip = self.messages.len().saturating_sub(count);
+        self.messages.iter().skip(skip).cloned().collect()
+    }
+
+    pub fn query(&self, filter: &CompiledFilter, limit: Option<usize>) -> Vec<Message> {
+        filter::query(&self.messages, filter, limit)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Message> {
+        self.messages.iter().rev().find(|m| m.id == id)
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.bytes = 0;
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+fn size(msg: &Message) -> usize {
+    msg.topic.len()
+        + msg.payload.len()
+        + msg.raw.as_ref().map_or(0, Vec::len)
+        + msg.decoded.as_ref().map_or(0, |d| d.text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::MessageFilter;
+
+    fn message(topic: &str, payload: &str) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_limits_drop_oldest() {
+        let mut log = MessageLog::with_limits(3, 1000);
+        for i in 0..5 {
+            log.push(message(&format!("t/{}", i), "x"));
+        }
+        let topics: Vec<String> = log.recent(10).into_iter().map(|m| m.topic).collect();
+        assert_eq!(topics, ["t/2", "t/3", "t/4"]);
+        assert_eq!(log.recent(1)[0].topic, "t/4");
+
+        let mut log = MessageLog::with_limits(100, 10);
+        log.push(message("a", "1234"));
+        log.push(message("b", "1234"));
+        assert_eq!(log.len(), 2);
+        log.push(message("c", "1234"));
+        assert_eq!(log.len(), 2);
+        // A single message above the byte limit is still kept.
+        log.push(message("d", &"x".repeat(100)));
+        assert_eq!(log.len(), 1);
+        log.clear();
+        assert_eq!(log.bytes, 0);
+    }
+
+    #[test]
+    fn test_query_beyond_recent_messages() {
+        let mut log = MessageLog::default();
+        log.push(message("alarm/1", "fire"));
+        for i in 0..RECENT_MESSAGES * 2 {
+            log.push(message("sensors/t", &i.to_string()));
+        }
+        let filter = CompiledFilter::new(MessageFilter {
+            topic: Some("alarm/#".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let found = log.query(&filter, Some(10));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].payload, "fire");
+
+        let all = CompiledFilter::new(MessageFilter::default()).unwrap();
+        let last = log.query(&all, Some(2));
+        assert_eq!(last[1].payload, (RECENT_MESSAGES * 2 - 1).to_string());
+    }
+}
@@ -1,15 +1,32 @@
-use crate::types::{Connection, ConnectionStatus, QoS};
+use crate::compress;
+use crate::decode::{Decoded, Decoders};
+use crate::diff::{PayloadDiff, PreviousMessages};
+use crate::filter::CompiledFilter;
+use crate::message_log::{MessageLog, RECENT_MESSAGES};
+use crate::responder::{Responders, Response};
+use crate::schema::{SchemaError, Schemas};
+use crate::sparkplug::{self, CommandMetric, EdgeNodeState, Sparkplug};
+use crate::template::substitute_variables;
+use crate::topic::matches_filter;
+use crate::types::{
+    Button, Connection, ConnectionStatus, DecoderRule, JsonSchema, QoS, ResponderRule, SchemaRule,
+};
 use log::{debug, error, info, warn};
-use rumqttc::{AsyncClient, Event, MqttOptions, Packet, Transport};
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, Packet, Transport};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tauri::{AppHandle, Emitter};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, RwLock};
 
-const MAX_MESSAGES: usize = 100;
+const MESSAGE_CHANNEL_CAPACITY: usize = 256;
+/// How long disconnecting waits for queued publishes and the DISCONNECT
+/// packet to be sent before the event loop is stopped.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
 
 #[derive(Error, Debug)]
 pub enum MqttError {
@@ -17,15 +34,94 @@ pub enum MqttError {
     Client(#[from] rumqttc::ClientError),
     #[error("Connection error: {0}")]
     Connection(#[from] rumqttc::ConnectionError),
+    #[error("MQTT 5 client error: {0}")]
+    ClientV5(Box<rumqttc::v5::ClientError>),
+    #[error("MQTT 5 connection error: {0}")]
+    ConnectionV5(Box<rumqttc::v5::ConnectionError>),
     #[error("Not connected")]
     NotConnected,
+    #[error("Cannot encode payload for '{0}': {1}")]
+    Encode(String, String),
+    #[error("{0}")]
+    Schema(#[from] SchemaError),
+}
+
+impl From<rumqttc::v5::ClientError> for MqttError {
+    fn from(e: rumqttc::v5::ClientError) -> Self {
+        MqttError::ClientV5(Box::new(e))
+    }
+}
+
+impl From<rumqttc::v5::ConnectionError> for MqttError {
+    fn from(e: rumqttc::v5::ConnectionError) -> Self {
+        MqttError::ConnectionV5(Box::new(e))
+    }
+}
+
+pub trait EventSink: Send + Sync {
+    fn status(&self, status: ConnectionStatus);
+    fn message(&self, msg: &Message);
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Message {
+    /// Unique per received message; 0 for messages not from the broker.
+    #[serde(default)]
+    pub id: u64,
     pub topic: String,
     pub payload: String,
     pub timestamp: u64,
+    #[serde(default)]
+    pub qos: QoS,
+    #[serde(default)]
+    pub retain: bool,
+    /// Original payload when it is not valid UTF-8; `payload` then holds a
+    /// lossy rendering. Serialized as base64.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "base64_bytes"
+    )]
+    pub raw: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<Decoded>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<PayloadDiff>,
+}
+
+impl Message {
+    pub fn bytes(&self) -> &[u8] {
+        self.raw.as_deref().unwrap_or(self.payload.as_bytes())
+    }
+
+    /// The decoded structured value, or the payload parsed as JSON.
+    pub fn json_value(&self) -> Option<serde_json::Value> {
+        match self.decoded.as_ref().and_then(|d| d.value.as_ref()) {
+            Some(value) => Some(value.clone()),
+            None => serde_json::from_slice(self.bytes()).ok(),
+        }
+    }
+}
+
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => s.serialize_str(&BASE64.encode(bytes)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|text| BASE64.decode(text).map_err(serde::de::Error::custom))
+            .transpose()
+    }
 }
 
 pub struct MqttClient {
@@ -33,9 +129,17 @@ pub struct MqttClient {
     status: Arc<RwLock<ConnectionStatus>>,
     shutdown_tx: Option<mpsc::Sender<()>>,
     connection_info: Option<(String, String)>,
-    messages: Arc<RwLock<VecDeque<Message>>>,
+    connection_id: Option<String>,
+    connection: Option<Connection>,
+    messages: Arc<RwLock<MessageLog>>,
     subscriptions: Arc<RwLock<Vec<String>>>,
-    app_handle: Option<AppHandle>,
+    message_tx: broadcast::Sender<Message>,
+    responders: Arc<RwLock<Responders>>,
+    decoders: Arc<RwLock<Decoders>>,
+    sparkplug: Arc<RwLock<Sparkplug>>,
+    schemas: Arc<RwLock<Schemas>>,
+    previous: Arc<RwLock<PreviousMessages>>,
+    events: Option<Arc<dyn EventSink>>,
 }
 
 impl MqttClient {
@@ -45,14 +149,22 @@ impl MqttClient {
             status: Arc::new(RwLock::new(ConnectionStatus::Disconnected)),
             shutdown_tx: None,
             connection_info: None,
-            messages: Arc::new(RwLock::new(VecDeque::with_capacity(MAX_MESSAGES))),
+            connection_id: None,
+            connection: None,
+            messages: Arc::new(RwLock::new(MessageLog::default())),
             subscriptions: Arc::new(RwLock::new(Vec::new())),
-            app_handle: None,
+            message_tx: broadcast::channel(MESSAGE_CHANNEL_CAPACITY).0,
+            responders: Arc::new(RwLock::new(Responders::default())),
+            decoders: Arc::new(RwLock::new(Decoders::default())),
+            sparkplug: Arc::new(RwLock::new(Sparkplug::default())),
+            schemas: Arc::new(RwLock::new(Schemas::default())),
+            previous: Arc::new(RwLock::new(PreviousMessages::default())),
+            events: None,
         }
     }
 
-    pub fn set_app_handle(&mut self, handle: AppHandle) {
-        self.app_handle = Some(handle);
+    pub fn set_event_sink(&mut self, events: Arc<dyn EventSink>) {
+        self.events = Some(events);
     }
 
     pub async fn connect(&mut self, config: &Connection) -> Result<(), MqttError> {
@@ -66,34 +178,38 @@ impl MqttClient {
             config.name, config.broker_url, config.port
         );
         *self.status.write().await = ConnectionStatus::Connecting;
-        if let Some(ref handle) = self.app_handle {
-            let _ = handle.emit("mqtt-status", "connecting");
+        if let Some(ref events) = self.events {
+            events.status(ConnectionStatus::Connecting);
         }
         self.messages.write().await.clear();
         self.subscriptions.write().await.clear();
 
-        let broker_host = strip_protocol(&config.broker_url);
-
-        let mut mqtt_options = MqttOptions::new(&config.client_id, broker_host, config.port);
-
-        mqtt_options.set_keep_alive(Duration::from_secs(30));
-
-        if let (Some(username), Some(password)) = (&config.username, &config.password) {
-            mqtt_options.set_credentials(username, password);
-        }
-
-        if config.use_tls {
-            let transport = Transport::tls_with_default_config();
-            mqtt_options.set_transport(transport);
-        }
-
+        let mqtt_options = build_options(config, &config.client_id);
         let (client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
+        let responder_client = client.clone();
         self.client = Some(client);
         self.connection_info = Some((config.name.clone(), config.broker_url.clone()));
+        self.connection_id = Some(config.id.clone());
+        self.connection = Some(config.clone());
 
         let status = Arc::clone(&self.status);
         let messages = Arc::clone(&self.messages);
-        let app_handle = self.app_handle.clone();
+        let message_tx = self.message_tx.clone();
+        *self.responders.write().await = Responders::default();
+        self.set_responders(&config.responders, &config.variables)
+            .await;
+        let responders = Arc::clone(&self.responders);
+        self.set_decoders(&config.decoders, config.auto_decompress)
+            .await;
+        let decoders = Arc::clone(&self.decoders);
+        *self.sparkplug.write().await = Sparkplug::default();
+        let sparkplug_state = Arc::clone(&self.sparkplug);
+        self.set_schema_rules(&config.message_schemas).await;
+        let schemas = Arc::clone(&self.schemas);
+        *self.previous.write().await = PreviousMessages::default();
+        self.set_diff_previous(config.diff_previous).await;
+        let previous = Arc::clone(&self.previous);
+        let events = self.events.clone();
         let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
         self.shutdown_tx = Some(shutdown_tx);
 
@@ -112,35 +228,56 @@ impl MqttClient {
                                 info!("MQTT connected successfully");
                                 *status.write().await = ConnectionStatus::Connected;
                                 consecutive_errors = 0;
-                                if let Some(ref handle) = app_handle {
-                                    let _ = handle.emit("mqtt-status", "connected");
+                                if let Some(ref events) = events {
+                                    events.status(ConnectionStatus::Connected);
                                 }
                             }
                             Ok(Event::Incoming(Packet::Publish(publish))) => {
                                 consecutive_errors = 0;
-                                let payload = String::from_utf8_lossy(&publish.payload).to_string();
+                                let (payload, raw) = match std::str::from_utf8(&publish.payload) {
+                                    Ok(text) => (text.to_string(), None),
+                                    Err(_) => (
+                                        String::from_utf8_lossy(&publish.payload).to_string(),
+                                        Some(publish.payload.to_vec()),
+                                    ),
+                                };
                                 debug!(
                                     "Received message on '{}': {} bytes",
                                     publish.topic,
                                     publish.payload.len()
                                 );
-                                let msg = Message {
+                                let timestamp = now_ms();
+                                let decoded = match sparkplug::parse_topic(&publish.topic) {
+                                    Some(topic) => Some(sparkplug_state.write().await.process(&topic, &publish.payload, timestamp)),
+                                    None => decoders.read().await.decode(&publish.topic, &publish.payload),
+                                };
+                                let violations = schemas.read().await.check_message(&publish.topic, &payload, decoded.as_ref());
+                                let mut msg = Message {
+                                    id: NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed),
                                     topic: publish.topic.clone(),
                                     payload,
-                                    timestamp: std::time::SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .unwrap_or_default()
-                                        .as_millis() as u64,
+                                    timestamp,
+                                    qos: publish.qos.into(),
+                                    retain: publish.retain,
+                                    decoded,
+                                    violations,
+                                    raw,
+                                    diff: None,
                                 };
-                                let mut msgs = messages.write().await;
-                                if msgs.len() >= MAX_MESSAGES {
-                                    msgs.pop_front();
+                                msg.diff = previous.write().await.diff(&msg);
+                                messages.write().await.push(msg.clone());
+                                let _ = message_tx.send(msg.clone());
+                                for response in responders.write().await.evaluate(&msg, Instant::now()) {
+                                    spawn_response(responder_client.clone(), response);
                                 }
-                                msgs.push_back(msg.clone());
-                                if let Some(ref handle) = app_handle {
-                                    let _ = handle.emit("mqtt-message", msg);
+                                if let Some(ref events) = events {
+                                    events.message(&msg);
                                 }
                             }
+                            Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                                debug!("MQTT disconnect sent");
+                                break;
+                            }
                             Ok(_) => {
                                 consecutive_errors = 0;
                             }
@@ -152,8 +289,8 @@ impl MqttClient {
                                 );
 
                                 *status.write().await = ConnectionStatus::Error;
-                                if let Some(ref handle) = app_handle {
-                                    let _ = handle.emit("mqtt-status", "error");
+                                if let Some(ref events) = events {
+                                    events.status(ConnectionStatus::Error);
                                 }
 
                                 if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
@@ -179,19 +316,24 @@ impl MqttClient {
             info!("Disconnecting from {} ({})", name, url);
         }
 
-        if let Some(tx) = self.shutdown_tx.take() {
-            let _ = tx.send(()).await;
-        }
-
+        // The DISCONNECT request is queued behind pending publishes and
+        // ends the event loop once sent.
         if let Some(client) = self.client.take() {
             let _ = client.disconnect().await;
         }
 
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tokio::time::timeout(DISCONNECT_TIMEOUT, tx.closed()).await;
+            let _ = tx.send(()).await;
+        }
+
         self.subscriptions.write().await.clear();
         let info = self.connection_info.take();
+        self.connection_id = None;
+        self.connection = None;
         *self.status.write().await = ConnectionStatus::Disconnected;
-        if let Some(ref handle) = self.app_handle {
-            let _ = handle.emit("mqtt-status", "disconnected");
+        if let Some(ref events) = self.events {
+            events.status(ConnectionStatus::Disconnected);
         }
         debug!("Disconnected successfully");
         Ok(info)
@@ -209,6 +351,21 @@ impl MqttClient {
         Ok(())
     }
 
+    /// Subscribes to `filter` unless an existing subscription already covers
+    /// it. Returns whether a subscription was added, so callers can remove it
+    /// again when they are done.
+    pub async fn subscribe_if_needed(&self, filter: &str, qos: QoS) -> Result<bool, MqttError> {
+        let covered =
+            self.subscriptions.read().await.iter().any(|s| {
+                s == filter || (!filter.contains(['+', '#']) && matches_filter(s, filter))
+            });
+        if covered {
+            return Ok(false);
+        }
+        self.subscribe(filter, qos).await?;
+        Ok(true)
+    }
+
     pub async fn unsubscribe(&self, topic: &str) -> Result<(), MqttError> {
         debug!("Unsubscribing from '{}'", topic);
         let client = self.client.as_ref().ok_or(MqttError::NotConnected)?;
@@ -224,6 +381,70 @@ impl MqttClient {
         payload: &str,
         qos: QoS,
         retain: bool,
+    ) -> Result<(), MqttError> {
+        let bytes = self.encode(topic, payload).await?;
+        self.publish_bytes(topic, &bytes, qos, retain).await
+    }
+
+    /// Encodes a text payload for `topic`, as protobuf when its decoder rule
+    /// asks for it.
+    async fn encode(&self, topic: &str, payload: &str) -> Result<Vec<u8>, MqttError> {
+        match self.decoders.read().await.encode(topic, payload) {
+            Some(Ok(bytes)) => Ok(bytes),
+            Some(Err(e)) => Err(MqttError::Encode(topic.to_string(), e)),
+            None => Ok(payload.as_bytes().to_vec()),
+        }
+    }
+
+    pub async fn publish_button_payload(
+        &self,
+        button: &Button,
+        topic: &str,
+        payload: &str,
+    ) -> Result<(), MqttError> {
+        let bytes = self.button_bytes(button, topic, payload).await?;
+        self.publish_bytes(topic, &bytes, button.qos, button.retain)
+            .await
+    }
+
+    /// Checked against the button's schema, then encoded and compressed as the
+    /// button asks.
+    pub async fn button_bytes(
+        &self,
+        button: &Button,
+        topic: &str,
+        payload: &str,
+    ) -> Result<Vec<u8>, MqttError> {
+        if let Some(schema_id) = &button.schema_id {
+            self.schemas
+                .read()
+                .await
+                .validate_payload(schema_id, payload)?;
+        }
+        let bytes = self.encode(topic, payload).await?;
+        match button.compression {
+            Some(compression) => compress::compress(compression, &bytes)
+                .map_err(|e| MqttError::Encode(topic.to_string(), e.to_string())),
+            None => Ok(bytes),
+        }
+    }
+
+    pub async fn publish_button(
+        &self,
+        button: &Button,
+        variables: &HashMap<String, String>,
+    ) -> Result<(), MqttError> {
+        let topic = substitute_variables(&button.topic, variables);
+        let payload = substitute_variables(button.payload.as_deref().unwrap_or(""), variables);
+        self.publish_button_payload(button, &topic, &payload).await
+    }
+
+    pub async fn publish_bytes(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
     ) -> Result<(), MqttError> {
         debug!(
             "Publishing to '{}': {} bytes (QoS {:?}, retain: {})",
@@ -233,24 +454,112 @@ impl MqttClient {
             retain
         );
         let client = self.client.as_ref().ok_or(MqttError::NotConnected)?;
-        client
-            .publish(topic, qos.into(), retain, payload.as_bytes())
-            .await?;
+        client.publish(topic, qos.into(), retain, payload).await?;
         if payload.is_empty() {
             info!("Published to '{}'", topic);
         } else {
-            info!("Published '{}' to '{}'", payload.replace('\n', " "), topic);
+            info!(
+                "Published '{}' to '{}'",
+                String::from_utf8_lossy(payload).replace('\n', " "),
+                topic
+            );
         }
         Ok(())
     }
 
+    pub async fn set_responders(
+        &self,
+        rules: &[ResponderRule],
+        variables: &HashMap<String, String>,
+    ) {
+        self.responders.write().await.update(rules, variables);
+    }
+
+    pub async fn set_decoders(&self, rules: &[DecoderRule], auto_decompress: bool) {
+        *self.decoders.write().await = Decoders::new(rules, auto_decompress);
+    }
+
+    pub async fn set_schemas(&self, schemas: &[JsonSchema]) {
+        self.schemas.write().await.set_schemas(schemas);
+    }
+
+    pub async fn set_schema_rules(&self, rules: &[SchemaRule]) {
+        self.schemas.write().await.set_rules(rules);
+    }
+
+    pub async fn set_diff_previous(&self, enabled: bool) {
+        self.previous.write().await.set_enabled(enabled);
+    }
+
+    pub async fn sparkplug_nodes(&self) -> Vec<EdgeNodeState> {
+        self.sparkplug.read().await.nodes()
+    }
+
+    /// Publishes an NCMD, or a DCMD when `device` is set, with the next
+    /// command sequence number for the edge node. Returns the topic.
+    pub async fn publish_sparkplug_command(
+        &self,
+        group: &str,
+        edge_node: &str,
+        device: Option<&str>,
+        metrics: &[CommandMetric],
+    ) -> Result<String, MqttError> {
+        if self.client.is_none() {
+            return Err(MqttError::NotConnected);
+        }
+        let (topic, payload) = self
+            .sparkplug
+            .write()
+            .await
+            .command(group, edge_node, device, metrics, now_ms())
+            .map_err(|e| MqttError::Encode(format!("{}/{}", group, edge_node), e.to_string()))?;
+        self.publish_bytes(&topic, &payload, QoS::AtMostOnce, false)
+            .await?;
+        Ok(topic)
+    }
+
+    pub fn connection_id(&self) -> Option<&str> {
+        self.connection_id.as_deref()
+    }
+
+    pub fn connection(&self) -> Option<&Connection> {
+        self.connection.as_ref()
+    }
+
+    pub fn subscribe_messages(&self) -> broadcast::Receiver<Message> {
+        self.message_tx.subscribe()
+    }
+
+    #[cfg(test)]
+    pub fn inject_message(&self, msg: Message) {
+        let _ = self.message_tx.send(msg);
+    }
+
     #[cfg(test)]
+    pub async fn add_subscription(&self, topic: &str) {
+        self.subscriptions.write().await.push(topic.to_string());
+    }
+
     pub async fn get_status(&self) -> ConnectionStatus {
         self.status.read().await.clone()
     }
 
+    /// The most recent messages, for the viewer.
     pub async fn get_messages(&self) -> Vec<Message> {
-        self.messages.read().await.iter().cloned().collect()
+        self.messages.read().await.recent(RECENT_MESSAGES)
+    }
+
+    /// Searches all logged messages, not just the recent ones.
+    pub async fn query_messages(
+        &self,
+        filter: &CompiledFilter,
+        limit: Option<usize>,
+    ) -> Vec<Message> {
+        self.messages.read().await.query(filter, limit)
+    }
+
+    pub async fn message(&self, id: u64) -> Option<Message> {
+        self.messages.read().await.get(id).cloned()
     }
 
     pub async fn clear_messages(&self) {
@@ -262,12 +571,95 @@ impl MqttClient {
     }
 }
 
+/// Milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub async fn next_message(
+    messages: &mut broadcast::Receiver<Message>,
+    mut accept: impl FnMut(&Message) -> bool,
+) -> Result<Message, MqttError> {
+    loop {
+        match messages.recv().await {
+            Ok(message) if accept(&message) => return Ok(message),
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Skipped {} messages while waiting", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => return Err(MqttError::NotConnected),
+        }
+    }
+}
+
 impl Default for MqttClient {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Publishes an auto-responder reply once its delay has passed, without
+/// holding up the receive loop.
+fn spawn_response(client: AsyncClient, response: Response) {
+    tokio::spawn(async move {
+        tokio::time::sleep(response.delay).await;
+        let result = client
+            .publish(
+                &response.topic,
+                response.qos.into(),
+                response.retain,
+                response.payload.as_bytes(),
+            )
+            .await;
+        match result {
+            Ok(()) => info!(
+                "Responder '{}' published to '{}'",
+                response.rule_name, response.topic
+            ),
+            Err(e) => warn!(
+                "Responder '{}' failed to publish: {}",
+                response.rule_name, e
+            ),
+        }
+    });
+}
+
+pub fn build_options(config: &Connection, client_id: &str) -> MqttOptions {
+    let broker_host = strip_protocol(&config.broker_url);
+
+    let mut mqtt_options = MqttOptions::new(client_id, broker_host, config.port);
+
+    mqtt_options.set_keep_alive(Duration::from_secs(30));
+
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        mqtt_options.set_credentials(username, password);
+    }
+
+    if config.use_tls {
+        let transport = Transport::tls_with_default_config();
+        mqtt_options.set_transport(transport);
+    }
+
+    mqtt_options
+}
+
+/// Options for an MQTT 5 client of `config`, for features MQTT 3.1.1 lacks.
+pub fn build_options_v5(config: &Connection, client_id: &str) -> rumqttc::v5::MqttOptions {
+    let broker_host = strip_protocol(&config.broker_url);
+    let mut mqtt_options = rumqttc::v5::MqttOptions::new(client_id, broker_host, config.port);
+    mqtt_options.set_keep_alive(Duration::from_secs(30));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        mqtt_options.set_credentials(username, password);
+    }
+    if config.use_tls {
+        mqtt_options.set_transport(Transport::tls_with_default_config());
+    }
+    mqtt_options
+}
+
 fn strip_protocol(url: &str) -> &str {
     let url = url.trim();
     for prefix in ["mqtt://", "mqtts://", "tcp://", "ssl://", "ws://", "wss://"] {
@@ -281,6 +673,7 @@ fn strip_protocol(url: &str) -> &str {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::Compression;
 
     fn create_test_connection(broker_url: &str, port: u16) -> Connection {
         Connection {
@@ -289,13 +682,9 @@ mod tests {
             broker_url: broker_url.to_string(),
             port,
             client_id: format!("test-client-{}", std::process::id()),
-            username: None,
-            password: None,
-            use_tls: false,
             auto_connect: false,
             variables: std::collections::HashMap::new(),
-            buttons: vec![],
-            subscriptions: vec![],
+            ..Default::default()
         }
     }
 
@@ -331,6 +720,39 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_publish_button_checks_schema_first() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = MqttClient::new();
+            client
+                .set_schemas(&[JsonSchema {
+                    id: "s1".to_string(),
+                    name: "Level".to_string(),
+                    schema: serde_json::json!({"type": "object", "required": ["level"]}),
+                }])
+                .await;
+            let button: Button = serde_json::from_value(serde_json::json!({
+                "id": "b1",
+                "name": "Dim",
+                "topic": "lights/{room}",
+                "payload": "{\"level\": {level}}",
+                "schemaId": "s1"
+            }))
+            .unwrap();
+            let mut variables = HashMap::from([("room".to_string(), "hall".to_string())]);
+
+            let result = client.publish_button(&button, &variables).await;
+            assert!(matches!(
+                result,
+                Err(MqttError::Schema(SchemaError::NotJson(_)))
+            ));
+            variables.insert("level".to_string(), "40".to_string());
+            let result = client.publish_button(&button, &variables).await;
+            assert!(matches!(result, Err(MqttError::NotConnected)));
+        });
+    }
+
     #[test]
     fn test_subscribe_without_connection_fails() {
         let rt = tokio::runtime::Runtime::new().unwrap();
@@ -373,18 +795,25 @@ mod tests {
     }
 
     #[test]
-    fn test_connect_sets_connecting_status() {
+    fn test_connect_reaches_connected_status() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
             let mut client = MqttClient::new();
-            let config = create_test_connection("invalid.broker.local", 1883);
-            let _ = client.connect(&config).await;
-            let status = client.get_status().await;
-            assert!(
-                status == ConnectionStatus::Connecting || status == ConnectionStatus::Error,
-                "Expected Connecting or Error, got {:?}",
-                status
-            );
+            let mut config =
+                create_test_connection(crate::broker::HOST, crate::broker::test_port());
+            config.client_id = format!("connect-{}", uuid::Uuid::new_v4().simple());
+            client.connect(&config).await.unwrap();
+            assert_ne!(client.get_status().await, ConnectionStatus::Disconnected);
+
+            let mut status = client.get_status().await;
+            for _ in 0..50 {
+                if status == ConnectionStatus::Connected {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                status = client.get_status().await;
+            }
+            assert_eq!(status, ConnectionStatus::Connected);
             let _ = client.disconnect().await;
         });
     }
@@ -394,7 +823,12 @@ mod tests {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
             let mut client = MqttClient::new();
-            let config = create_test_connection("nonexistent.invalid.host", 1883);
+            // Nothing listens on a port that was just released.
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .and_then(|listener| listener.local_addr())
+                .unwrap()
+                .port();
+            let config = create_test_connection("127.0.0.1", port);
             let _ = client.connect(&config).await;
             tokio::time::sleep(Duration::from_secs(1)).await;
             let status = client.get_status().await;
@@ -407,28 +841,174 @@ mod tests {
     fn test_disconnect_resets_status() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            let mut client = MqttClient::new();
-            let config = create_test_connection("invalid.broker.local", 1883);
-            let _ = client.connect(&config).await;
-            let _ = client.disconnect().await;
+            let mut config = create_test_connection("", 0);
+            let mut client = connect_to_test_broker(&mut config).await;
+            client.disconnect().await.unwrap();
             assert_eq!(client.get_status().await, ConnectionStatus::Disconnected);
         });
     }
 
+    #[test]
+    fn test_disconnect_sends_queued_publishes() {
+        // On one thread the publishes are still queued when disconnecting.
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut config = create_test_connection("", 0);
+            let mut subscriber = connect_to_test_broker(&mut config).await;
+            subscriber
+                .subscribe("lab/last-words", QoS::AtLeastOnce)
+                .await
+                .unwrap();
+            let mut messages = subscriber.subscribe_messages();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            let mut publisher = connect_to_test_broker(&mut config).await;
+            for i in 0..5 {
+                publisher
+                    .publish("lab/last-words", &i.to_string(), QoS::AtLeastOnce, false)
+                    .await
+                    .unwrap();
+            }
+            publisher.disconnect().await.unwrap();
+            assert_eq!(publisher.get_status().await, ConnectionStatus::Disconnected);
+
+            for i in 0..5 {
+                let msg = receive(&mut messages, "lab/last-words").await;
+                assert_eq!(msg.payload, i.to_string());
+            }
+            subscriber.disconnect().await.unwrap();
+        });
+    }
+
     #[test]
     fn test_connect_already_connected_noop() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            let mut client = MqttClient::new();
-            let config = create_test_connection("invalid.broker.local", 1883);
-            let result1 = client.connect(&config).await;
-            assert!(result1.is_ok());
-            let result2 = client.connect(&config).await;
-            assert!(result2.is_ok());
+            let mut config = create_test_connection("", 0);
+            let mut client = connect_to_test_broker(&mut config).await;
+            let result = client.connect(&config).await;
+            assert!(result.is_ok());
+            assert_eq!(client.get_status().await, ConnectionStatus::Connected);
             let _ = client.disconnect().await;
         });
     }
 
+    async fn connect_to_test_broker(config: &mut Connection) -> MqttClient {
+        config.broker_url = crate::broker::HOST.to_string();
+        config.port = crate::broker::test_port();
+        config.client_id = format!("{}-{}", config.client_id, uuid::Uuid::new_v4().simple());
+        let mut client = MqttClient::new();
+        client.connect(config).await.unwrap();
+        for _ in 0..50 {
+            if client.get_status().await == ConnectionStatus::Connected {
+                return client;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Not connected to the embedded broker");
+    }
+
+    async fn receive(messages: &mut broadcast::Receiver<Message>, topic: &str) -> Message {
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            next_message(messages, |m| m.topic == topic),
+        )
+        .await
+        .expect("message within 5s")
+        .unwrap()
+    }
+
+    #[test]
+    fn test_publish_and_receive_on_embedded_broker() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut config = create_test_connection("", 0);
+            let mut client = connect_to_test_broker(&mut config).await;
+            client
+                .subscribe("lab/roundtrip/#", QoS::AtLeastOnce)
+                .await
+                .unwrap();
+            let mut messages = client.subscribe_messages();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            client
+                .publish("lab/roundtrip/a", "{\"on\":true}", QoS::AtLeastOnce, false)
+                .await
+                .unwrap();
+            let msg = receive(&mut messages, "lab/roundtrip/a").await;
+            assert_eq!(msg.payload, "{\"on\":true}");
+            assert_eq!(msg.qos, QoS::AtLeastOnce);
+            assert_eq!(client.get_messages().await.len(), 1);
+
+            client.disconnect().await.unwrap();
+            assert_eq!(client.get_status().await, ConnectionStatus::Disconnected);
+        });
+    }
+
+    #[test]
+    fn test_retained_message_reaches_new_subscriber() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut config = create_test_connection("", 0);
+            let mut publisher = connect_to_test_broker(&mut config).await;
+            publisher
+                .publish("lab/retained", "kept", QoS::AtLeastOnce, true)
+                .await
+                .unwrap();
+            publisher.disconnect().await.unwrap();
+
+            let mut config = create_test_connection("", 0);
+            let mut subscriber = connect_to_test_broker(&mut config).await;
+            let mut messages = subscriber.subscribe_messages();
+            subscriber
+                .subscribe("lab/retained", QoS::AtLeastOnce)
+                .await
+                .unwrap();
+            let msg = receive(&mut messages, "lab/retained").await;
+            assert_eq!(msg.payload, "kept");
+            assert!(msg.retain);
+            subscriber.disconnect().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_compressed_button_payload_is_decoded() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut config = create_test_connection("", 0);
+            config.auto_decompress = true;
+            let mut client = connect_to_test_broker(&mut config).await;
+            client
+                .subscribe("lab/gzip", QoS::AtLeastOnce)
+                .await
+                .unwrap();
+            let mut messages = client.subscribe_messages();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            let button: Button = serde_json::from_value(serde_json::json!({
+                "id": "b1",
+                "name": "Compressed",
+                "topic": "lab/gzip",
+                "payload": "{\"level\": {level}}",
+                "qos": "atleastonce",
+                "compression": "gzip"
+            }))
+            .unwrap();
+            let variables = HashMap::from([("level".to_string(), "7".to_string())]);
+            client.publish_button(&button, &variables).await.unwrap();
+
+            let msg = receive(&mut messages, "lab/gzip").await;
+            assert!(msg.raw.is_some());
+            let decoded = msg.decoded.unwrap();
+            assert_eq!(decoded.compression, Some(Compression::Gzip));
+            assert_eq!(decoded.value, Some(serde_json::json!({"level": 7})));
+            client.disconnect().await.unwrap();
+        });
+    }
+
     #[test]
     fn test_qos_conversion() {
         assert_eq!(
@@ -0,0 +1,422 @@
+use crate::types::{
+    Button, ButtonColor, Compression, Connection, DecoderRule, QoS, ResponderRule, ResponseConfig,
+    SchemaRule, Script, Sequence,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+pub const PROJECT_FILE_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum ProjectFileError {
+    #[error("Unsupported project file extension (use .yaml, .yml or .toml)")]
+    UnsupportedExtension,
+    #[error("Unsupported project file version {0}")]
+    UnsupportedVersion(u32),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("TOML error: {0}")]
+    TomlRead(#[from] toml::de::Error),
+    #[error("TOML error: {0}")]
+    TomlWrite(#[from] toml::ser::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectFormat {
+    Yaml,
+    Toml,
+}
+
+impl ProjectFormat {
+    pub fn from_path(path: &Path) -> Result<Self, ProjectFileError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Ok(ProjectFormat::Yaml),
+            Some("toml") => Ok(ProjectFormat::Toml),
+            _ => Err(ProjectFileError::UnsupportedExtension),
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_default_qos(qos: &QoS) -> bool {
+    *qos == QoS::AtMostOnce
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProjectFile {
+    version: u32,
+    id: String,
+    name: String,
+    broker: BrokerSection,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    subscriptions: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    variables: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    buttons: Vec<ProjectButton>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sequences: Vec<Sequence>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    responders: Vec<ResponderRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    scripts: Vec<Script>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    decoders: Vec<DecoderRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    message_schemas: Vec<SchemaRule>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    auto_decompress: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    diff_previous: bool,
+}
+
+/// Passwords are never written to the file. `password_env` names an
+/// environment variable that holds it; otherwise the password stays in the
+/// app's local data only.
+#[derive(Debug, Serialize, Deserialize)]
+struct BrokerSection {
+    url: String,
+    port: u16,
+    client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_env: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    use_tls: bool,
+    #[serde(default)]
+    auto_connect: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProjectButton {
+    id: String,
+    name: String,
+    topic: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default_qos")]
+    qos: QoS,
+    #[serde(default, skip_serializing_if = "is_false")]
+    retain: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color: Option<ButtonColor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    multi_send_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    multi_send_interval: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    multi_send_max_count: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    multi_send_max_duration: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    multi_send_jitter: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<ResponseConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<Compression>,
+}
+
+impl ProjectFile {
+    fn from_connection(connection: &Connection, password_env: Option<String>) -> Self {
+        Self {
+            version: PROJECT_FILE_VERSION,
+            id: connection.id.clone(),
+            name: connection.name.clone(),
+            broker: BrokerSection {
+                url: connection.broker_url.clone(),
+                port: connection.port,
+                client_id: connection.client_id.clone(),
+                username: connection.username.clone(),
+                password_env,
+                use_tls: connection.use_tls,
+                auto_connect: connection.auto_connect,
+            },
+            subscriptions: connection.subscriptions.clone(),
+            variables: connection.variables.clone().into_iter().collect(),
+            buttons: connection
+                .buttons
+                .iter()
+                .map(|button| ProjectButton {
+                    id: button.id.clone(),
+                    name: button.name.clone(),
+                    topic: button.topic.clone(),
+                    payload: button.payload.clone(),
+                    qos: button.qos,
+                    retain: button.retain,
+                    color: button.color,
+                    multi_send_enabled: button.multi_send_enabled,
+                    multi_send_interval: button.multi_send_interval,
+                    multi_send_max_count: button.multi_send_max_count,
+                    multi_send_max_duration: button.multi_send_max_duration,
+                    multi_send_jitter: button.multi_send_jitter,
+                    response: button.response.clone(),
+                    schema_id: button.schema_id.clone(),
+                    compression: button.compression,
+                })
+                .collect(),
+            sequences: connection.sequences.clone(),
+            responders: connection.responders.clone(),
+            scripts: connection.scripts.clone(),
+            decoders: connection.decoders.clone(),
+            message_schemas: connection.message_schemas.clone(),
+            auto_decompress: connection.auto_decompress,
+            diff_previous: connection.diff_previous,
+        }
+    }
+
+    fn into_connection(self) -> Connection {
+        let password = self
+            .broker
+            .password_env
+            .as_deref()
+            .and_then(|name| std::env::var(name).ok());
+
+        Connection {
+            id: self.id,
+            name: self.name,
+            broker_url: self.broker.url,
+            port: self.broker.port,
+            client_id: self.broker.client_id,
+            username: self.broker.username,
+            password,
+            use_tls: self.broker.use_tls,
+            auto_connect: self.broker.auto_connect,
+            variables: self.variables.into_iter().collect(),
+            buttons: self
+                .buttons
+                .into_iter()
+                .map(|button| Button {
+                    id: button.id,
+                    name: button.name,
+                    topic: button.topic,
+                    payload: button.payload,
+                    qos: button.qos,
+                    retain: button.retain,
+                    color: button.color,
+                    multi_send_enabled: button.multi_send_enabled,
+                    multi_send_interval: button.multi_send_interval,
+                    multi_send_max_count: button.multi_send_max_count,
+                    multi_send_max_duration: button.multi_send_max_duration,
+                    multi_send_jitter: button.multi_send_jitter,
+                    response: button.response,
+                    schema_id: button.schema_id,
+                    compression: button.compression,
+                })
+                .collect(),
+            subscriptions: self.subscriptions,
+            sequences: self.sequences,
+            responders: self.responders,
+            scripts: self.scripts,
+            decoders: self.decoders,
+            message_schemas: self.message_schemas,
+            auto_decompress: self.auto_decompress,
+            diff_previous: self.diff_previous,
+            project_file: None,
+        }
+    }
+}
+
+fn parse(content: &str, format: ProjectFormat) -> Result<ProjectFile, ProjectFileError> {
+    let file: ProjectFile = match format {
+        ProjectFormat::Yaml => serde_yaml::from_str(content)?,
+        ProjectFormat::Toml => toml::from_str(content)?,
+    };
+    if file.version > PROJECT_FILE_VERSION {
+        return Err(ProjectFileError::UnsupportedVersion(file.version));
+    }
+    Ok(file)
+}
+
+/// Serializes `connection` deterministically: variables are sorted and
+/// default values are omitted so that only real edits show up in diffs.
+pub fn to_string(
+    connection: &Connection,
+    format: ProjectFormat,
+    password_env: Option<String>,
+) -> Result<String, ProjectFileError> {
+    let file = ProjectFile::from_connection(connection, password_env);
+    Ok(match format {
+        ProjectFormat::Yaml => serde_yaml::to_string(&file)?,
+        ProjectFormat::Toml => toml::to_string(&file)?,
+    })
+}
+
+pub fn from_str(content: &str, format: ProjectFormat) -> Result<Connection, ProjectFileError> {
+    Ok(parse(content, format)?.into_connection())
+}
+
+pub fn read(path: &Path) -> Result<Connection, ProjectFileError> {
+    let format = ProjectFormat::from_path(path)?;
+    let content = fs::read_to_string(path)?;
+    from_str(&content, format)
+}
+
+/// Writes `connection` to `path`, keeping the `password_env` reference of an
+/// existing file. The file is left untouched when nothing changed.
+pub fn write(connection: &Connection, path: &Path) -> Result<(), ProjectFileError> {
+    let format = ProjectFormat::from_path(path)?;
+    let existing = fs::read_to_string(path).ok();
+    let password_env = existing
+        .as_deref()
+        .and_then(|content| parse(content, format).ok())
+        .and_then(|file| file.broker.password_env);
+
+    let content = to_string(connection, format, password_env)?;
+    if existing.as_deref() != Some(content.as_str()) {
+        fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    fn create_connection() -> Connection {
+        Connection {
+            id: "conn-1".to_string(),
+            name: "Firmware".to_string(),
+            broker_url: "localhost".to_string(),
+            client_id: "fw-client".to_string(),
+            username: Some("dev".to_string()),
+            password: Some("hunter2".to_string()),
+            variables: HashMap::from([
+                ("zeta".to_string(), "1".to_string()),
+                ("alpha".to_string(), "2".to_string()),
+            ]),
+            buttons: vec![
+                Button {
+                    id: "btn-reboot".to_string(),
+                    name: "Reboot".to_string(),
+                    topic: "devices/{alpha}/cmd".to_string(),
+                    payload: Some("{\n  \"cmd\": \"reboot\"\n}".to_string()),
+                    qos: QoS::AtLeastOnce,
+                    color: Some(ButtonColor::Red),
+                    ..Default::default()
+                },
+                Button {
+                    id: "btn-ping".to_string(),
+                    name: "Ping".to_string(),
+                    topic: "ping".to_string(),
+                    multi_send_enabled: Some(true),
+                    multi_send_interval: Some(1000),
+                    ..Default::default()
+                },
+            ],
+            subscriptions: vec!["devices/#".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            ProjectFormat::from_path(Path::new("a/lab.yml")).unwrap(),
+            ProjectFormat::Yaml
+        );
+        assert_eq!(
+            ProjectFormat::from_path(Path::new("lab.toml")).unwrap(),
+            ProjectFormat::Toml
+        );
+        assert!(ProjectFormat::from_path(Path::new("lab.json")).is_err());
+    }
+
+    #[test]
+    fn test_yaml_roundtrip_without_secret() {
+        let yaml = to_string(&create_connection(), ProjectFormat::Yaml, None).unwrap();
+        assert!(!yaml.contains("hunter2"));
+        assert!(yaml.find("alpha").unwrap() < yaml.find("zeta").unwrap());
+
+        let connection = from_str(&yaml, ProjectFormat::Yaml).unwrap();
+        assert_eq!(connection.id, "conn-1");
+        assert_eq!(connection.password, None);
+        assert_eq!(connection.buttons[0].id, "btn-reboot");
+        assert_eq!(
+            connection.buttons[0].payload,
+            create_connection().buttons[0].payload
+        );
+        assert_eq!(connection.buttons[1].multi_send_enabled, Some(true));
+        assert_eq!(connection.variables.get("zeta"), Some(&"1".to_string()));
+    }
+
+    #[test]
+    fn test_toml_roundtrip() {
+        let toml = to_string(&create_connection(), ProjectFormat::Toml, None).unwrap();
+        assert!(toml.contains("[broker]"));
+        assert!(toml.contains("[[buttons]]"));
+
+        let connection = from_str(&toml, ProjectFormat::Toml).unwrap();
+        assert_eq!(connection.name, "Firmware");
+        assert_eq!(connection.buttons.len(), 2);
+        assert_eq!(connection.buttons[0].qos, QoS::AtLeastOnce);
+        assert_eq!(connection.subscriptions, vec!["devices/#"]);
+    }
+
+    #[test]
+    fn test_output_is_deterministic() {
+        let a = to_string(&create_connection(), ProjectFormat::Yaml, None).unwrap();
+        let b = to_string(&create_connection(), ProjectFormat::Yaml, None).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_password_env_reference() {
+        std::env::set_var("PROJECT_FILE_TEST_PASSWORD", "from-env");
+        let yaml = to_string(
+            &create_connection(),
+            ProjectFormat::Yaml,
+            Some("PROJECT_FILE_TEST_PASSWORD".to_string()),
+        )
+        .unwrap();
+        assert!(yaml.contains("password_env: PROJECT_FILE_TEST_PASSWORD"));
+
+        let connection = from_str(&yaml, ProjectFormat::Yaml).unwrap();
+        assert_eq!(connection.password, Some("from-env".to_string()));
+    }
+
+    #[test]
+    fn test_write_preserves_password_env_and_skips_unchanged() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("lab.yaml");
+        let content = to_string(
+            &create_connection(),
+            ProjectFormat::Yaml,
+            Some("LAB_PW".to_string()),
+        )
+        .unwrap();
+        fs::write(&path, &content).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+        write(&create_connection(), &path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+
+        let mut connection = create_connection();
+        connection.name = "Renamed".to_string();
+        write(&connection, &path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("name: Renamed"));
+        assert!(written.contains("password_env: LAB_PW"));
+    }
+
+    #[test]
+    fn test_rejects_newer_version() {
+        let yaml = "version: 99\nid: a\nname: b\nbroker:\n  url: x\n  port: 1\n  client_id: c\n";
+        assert!(matches!(
+            from_str(yaml, ProjectFormat::Yaml),
+            Err(ProjectFileError::UnsupportedVersion(99))
+        ));
+    }
+}
@@ -0,0 +1,206 @@
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, SerializeOptions};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProtoError {
+    #[error("Cannot read descriptor set: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid descriptor set: {0}")]
+    InvalidDescriptorSet(String),
+    #[error("Unknown message type: {0}")]
+    UnknownMessageType(String),
+    #[error("Protobuf decoder rules need a descriptor set and a message type")]
+    MissingType,
+    #[error("Invalid {0} payload: {1}")]
+    Decode(String, String),
+    #[error("Cannot encode {0}: {1}")]
+    Encode(String, String),
+}
+
+/// Keyed by path, with the file's modification time and size to detect changes.
+static POOLS: Mutex<BTreeMap<PathBuf, (SystemTime, u64, DescriptorPool)>> =
+    Mutex::new(BTreeMap::new());
+
+pub fn load_pool(path: &Path) -> Result<DescriptorPool, ProtoError> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata.modified()?;
+    if let Some((time, len, pool)) = POOLS.lock().unwrap().get(path) {
+        if *time == modified && *len == metadata.len() {
+            return Ok(pool.clone());
+        }
+    }
+
+    let bytes = std::fs::read(path)?;
+    let pool = DescriptorPool::decode(bytes.as_slice())
+        .map_err(|e| ProtoError::InvalidDescriptorSet(e.to_string()))?;
+    POOLS
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (modified, metadata.len(), pool.clone()));
+    Ok(pool)
+}
+
+/// `path` is a descriptor set from `protoc --include_imports --descriptor_set_out`.
+pub fn load_message(path: &Path, message_type: &str) -> Result<MessageDescriptor, ProtoError> {
+    let name = message_type.trim_start_matches('.');
+    load_pool(path)?
+        .get_message_by_name(name)
+        .ok_or_else(|| ProtoError::UnknownMessageType(name.to_string()))
+}
+
+pub fn message_types(path: &Path) -> Result<Vec<String>, ProtoError> {
+    let mut names: Vec<String> = load_pool(path)?
+        .all_messages()
+        .map(|m| m.full_name().to_string())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+pub fn decode(descriptor: &MessageDescriptor, payload: &[u8]) -> Result<Value, ProtoError> {
+    let message = DynamicMessage::decode(descriptor.clone(), payload)
+        .map_err(|e| ProtoError::Decode(descriptor.full_name().to_string(), e.to_string()))?;
+    let options = SerializeOptions::new().skip_default_fields(false);
+    message
+        .serialize_with_options(serde_json::value::Serializer, &options)
+        .map_err(|e| ProtoError::Decode(descriptor.full_name().to_string(), e.to_string()))
+}
+
+/// `json` uses the canonical protobuf JSON mapping.
+pub fn encode(descriptor: &MessageDescriptor, json: &str) -> Result<Vec<u8>, ProtoError> {
+    let encode_error = |e: serde_json::Error| {
+        ProtoError::Encode(descriptor.full_name().to_string(), e.to_string())
+    };
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let message =
+        DynamicMessage::deserialize(descriptor.clone(), &mut deserializer).map_err(encode_error)?;
+    deserializer.end().map_err(encode_error)?;
+    Ok(prost_reflect::prost::Message::encode_to_vec(&message))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use prost_reflect::prost::Message;
+    use prost_reflect::prost_types::field_descriptor_proto::{Label, Type};
+    use prost_reflect::prost_types::{
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+    };
+    use serde_json::json;
+
+    fn field(name: &str, number: i32, kind: Type, label: Label) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            r#type: Some(kind as i32),
+            label: Some(label as i32),
+            json_name: None,
+            ..Default::default()
+        }
+    }
+
+    /// Writes a descriptor set with `telemetry.Reading { string sensor = 1;
+    /// double value = 2; repeated string tags = 3; }` to `path`.
+    pub fn write_descriptor_set(path: &Path) {
+        let set = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("telemetry.proto".to_string()),
+                package: Some("telemetry".to_string()),
+                syntax: Some("proto3".to_string()),
+                message_type: vec![DescriptorProto {
+                    name: Some("Reading".to_string()),
+                    field: vec![
+                        field("sensor", 1, Type::String, Label::Optional),
+                        field("value", 2, Type::Double, Label::Optional),
+                        field("tags", 3, Type::String, Label::Repeated),
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        std::fs::write(path, set.encode_to_vec()).unwrap();
+    }
+
+    #[test]
+    fn test_pools_are_reloaded_when_the_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.desc");
+        write_descriptor_set(&path);
+        load_pool(&path).unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        // Same size and time: the cached pool is used.
+        let len = std::fs::metadata(&path).unwrap().len() as usize;
+        std::fs::write(&path, vec![0xff; len]).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified).unwrap();
+        assert!(load_pool(&path).is_ok());
+
+        file.set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert!(matches!(
+            load_pool(&path),
+            Err(ProtoError::InvalidDescriptorSet(_))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.desc");
+        write_descriptor_set(&path);
+        assert_eq!(message_types(&path).unwrap(), ["telemetry.Reading"]);
+
+        let descriptor = load_message(&path, ".telemetry.Reading").unwrap();
+        let bytes = encode(
+            &descriptor,
+            r#"{"sensor": "t1", "value": 21.5, "tags": ["a", "b"]}"#,
+        )
+        .unwrap();
+        assert_eq!(bytes[..4], [0x0a, 0x02, b't', b'1']);
+        assert_eq!(
+            decode(&descriptor, &bytes).unwrap(),
+            json!({"sensor": "t1", "value": 21.5, "tags": ["a", "b"]})
+        );
+        assert_eq!(
+            decode(&descriptor, &[]).unwrap(),
+            json!({"sensor": "", "value": 0.0, "tags": []})
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.desc");
+        write_descriptor_set(&path);
+        assert!(matches!(
+            load_message(&path, "telemetry.Missing"),
+            Err(ProtoError::UnknownMessageType(_))
+        ));
+        let descriptor = load_message(&path, "telemetry.Reading").unwrap();
+        assert!(matches!(
+            encode(&descriptor, r#"{"unknown": 1}"#),
+            Err(ProtoError::Encode(..))
+        ));
+        assert!(matches!(
+            encode(&descriptor, r#"{"value": "warm"}"#),
+            Err(ProtoError::Encode(..))
+        ));
+        assert!(matches!(
+            decode(&descriptor, &[0xff, 0xff]),
+            Err(ProtoError::Decode(..))
+        ));
+
+        std::fs::write(&path, b"not a descriptor set").unwrap();
+        assert!(matches!(
+            load_pool(&path),
+            Err(ProtoError::InvalidDescriptorSet(_))
+        ));
+    }
+}
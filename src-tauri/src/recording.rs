@@ -0,0 +1,751 @@
+use crate::mqtt::{now_ms, Message, MqttClient, MqttError};
+use crate::topic::matches_filter;
+use crate::types::QoS;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{broadcast, watch, Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+pub const REPLAY_EVENT: &str = "replay-progress";
+const FORMAT: &str = "mqtt-topic-lab-recording";
+const VERSION: u32 = 1;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Error, Debug)]
+pub enum RecordingError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid recording at line {0}: {1}")]
+    Parse(usize, String),
+    #[error("Not a recording file")]
+    NotARecording,
+    #[error("Unsupported recording version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("The recording contains no messages")]
+    Empty,
+    #[error("A recording is already running")]
+    AlreadyRecording,
+    #[error("A replay is already running")]
+    AlreadyReplaying,
+    #[error("Speed must be greater than zero")]
+    InvalidSpeed,
+    #[error("Invalid rewrite pattern '{0}': {1}")]
+    InvalidRewrite(String, regex::Error),
+    #[error("Not connected to the target connection")]
+    WrongConnection,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingHeader {
+    pub format: String,
+    pub version: u32,
+    /// Milliseconds since the Unix epoch.
+    pub started_at: u64,
+    pub connection: String,
+    pub broker: String,
+}
+
+/// One message line. Payloads that are not valid UTF-8 are stored as
+/// base64 so they replay byte for byte.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    offset_ms: u64,
+    topic: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload_base64: Option<String>,
+    #[serde(default)]
+    qos: QoS,
+    #[serde(default)]
+    retain: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMessage {
+    pub offset_ms: u64,
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingStatus {
+    pub path: PathBuf,
+    pub started_at: u64,
+    pub count: u64,
+    /// Messages lost because the recorder fell behind the receive loop.
+    pub dropped: u64,
+    pub active: bool,
+    pub error: Option<String>,
+}
+
+fn write_line<T: Serialize>(writer: &mut impl Write, value: &T) -> Result<(), RecordingError> {
+    serde_json::to_writer(&mut *writer, value)
+        .map_err(|e| RecordingError::Io(std::io::Error::other(e)))?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn entry(msg: &Message, started_at: u64) -> Entry {
+    let (payload, payload_base64) = match &msg.raw {
+        Some(raw) => (None, Some(BASE64.encode(raw))),
+        None if msg.payload.is_empty() => (None, None),
+        None => (Some(msg.payload.clone()), None),
+    };
+    Entry {
+        offset_ms: msg.timestamp.saturating_sub(started_at),
+        topic: msg.topic.clone(),
+        payload,
+        payload_base64,
+        qos: msg.qos,
+        retain: msg.retain,
+    }
+}
+
+pub fn load(path: &Path) -> Result<(RecordingHeader, Vec<RecordedMessage>), RecordingError> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: RecordingHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).map_err(|_| RecordingError::NotARecording)?,
+        None => return Err(RecordingError::NotARecording),
+    };
+    if header.format != FORMAT {
+        return Err(RecordingError::NotARecording);
+    }
+    if header.version > VERSION {
+        return Err(RecordingError::UnsupportedVersion(header.version));
+    }
+
+    let mut messages = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let number = index + 2;
+        let entry: Entry = serde_json::from_str(&line)
+            .map_err(|e| RecordingError::Parse(number, e.to_string()))?;
+        let payload = match (entry.payload, entry.payload_base64) {
+            (_, Some(encoded)) => BASE64
+                .decode(encoded)
+                .map_err(|e| RecordingError::Parse(number, e.to_string()))?,
+            (Some(text), None) => text.into_bytes(),
+            (None, None) => Vec::new(),
+        };
+        messages.push(RecordedMessage {
+            offset_ms: entry.offset_ms,
+            topic: entry.topic,
+            payload,
+            qos: entry.qos,
+            retain: entry.retain,
+        });
+    }
+    Ok((header, messages))
+}
+
+struct ActiveRecording {
+    stop: watch::Sender<bool>,
+    status: Arc<StdMutex<RecordingStatus>>,
+    task: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+pub struct Recorder {
+    active: Mutex<Option<ActiveRecording>>,
+}
+
+impl Recorder {
+    /// With a `filter`, only messages whose topic matches it are recorded.
+    pub async fn start(
+        &self,
+        messages: broadcast::Receiver<Message>,
+        path: PathBuf,
+        connection: String,
+        broker: String,
+        filter: Option<String>,
+    ) -> Result<RecordingStatus, RecordingError> {
+        let mut active = self.active.lock().await;
+        if active
+            .as_ref()
+            .is_some_and(|a| a.status.lock().unwrap().active)
+        {
+            return Err(RecordingError::AlreadyRecording);
+        }
+
+        let header = RecordingHeader {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            started_at: now_ms(),
+            connection,
+            broker,
+        };
+        let mut writer = BufWriter::new(File::create(&path)?);
+        write_line(&mut writer, &header)?;
+        info!("Recording to {}", path.display());
+
+        let status = Arc::new(StdMutex::new(RecordingStatus {
+            path,
+            started_at: header.started_at,
+            count: 0,
+            dropped: 0,
+            active: true,
+            error: None,
+        }));
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let task = tokio::spawn(record(
+            messages,
+            writer,
+            header.started_at,
+            filter,
+            stop_rx,
+            Arc::clone(&status),
+        ));
+        let current = status.lock().unwrap().clone();
+        *active = Some(ActiveRecording {
+            stop: stop_tx,
+            status,
+            task: Some(task),
+        });
+        Ok(current)
+    }
+
+    /// Waits until the file is complete. None if nothing was recorded since startup.
+    pub async fn stop(&self) -> Option<RecordingStatus> {
+        let mut active = self.active.lock().await;
+        let recording = active.as_mut()?;
+        let _ = recording.stop.send(true);
+        if let Some(task) = recording.task.take() {
+            if let Err(e) = task.await {
+                warn!("Recording task failed: {}", e);
+            }
+        }
+        let mut status = recording.status.lock().unwrap();
+        status.active = false;
+        Some(status.clone())
+    }
+
+    pub async fn status(&self) -> Option<RecordingStatus> {
+        let active = self.active.lock().await;
+        active.as_ref().map(|a| a.status.lock().unwrap().clone())
+    }
+}
+
+async fn record(
+    mut messages: broadcast::Receiver<Message>,
+    mut writer: BufWriter<File>,
+    started_at: u64,
+    filter: Option<String>,
+    mut stop: watch::Receiver<bool>,
+    status: Arc<StdMutex<RecordingStatus>>,
+) {
+    let mut stopping = false;
+    loop {
+        // Messages received before the stop are still written.
+        let msg = if stopping {
+            match messages.try_recv() {
+                Ok(msg) => Ok(msg),
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    Err(broadcast::error::RecvError::Lagged(skipped))
+                }
+                Err(_) => break,
+            }
+        } else {
+            tokio::select! {
+                _ = stop.changed() => {
+                    stopping = true;
+                    continue;
+                }
+                msg = messages.recv() => msg,
+            }
+        };
+        match msg {
+            Ok(msg) => {
+                if filter
+                    .as_deref()
+                    .is_some_and(|f| !matches_filter(f, &msg.topic))
+                {
+                    continue;
+                }
+                if let Err(e) = write_line(&mut writer, &entry(&msg, started_at)) {
+                    warn!("Recording stopped: {}", e);
+                    status.lock().unwrap().error = Some(e.to_string());
+                    break;
+                }
+                status.lock().unwrap().count += 1;
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Recording dropped {} messages", skipped);
+                status.lock().unwrap().dropped += skipped;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+    let mut status = status.lock().unwrap();
+    status.active = false;
+    info!(
+        "Recorded {} messages to {}",
+        status.count,
+        status.path.display()
+    );
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicRewrite {
+    pub pattern: String,
+    /// May refer to capture groups as `$1` or `$name`.
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayOptions {
+    /// 2 replays twice as fast.
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+    #[serde(default)]
+    pub rewrites: Vec<TopicRewrite>,
+    #[serde(default, rename = "loop")]
+    pub loop_playback: bool,
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            speed: default_speed(),
+            rewrites: Vec::new(),
+            loop_playback: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayProgress {
+    pub path: PathBuf,
+    pub running: bool,
+    pub cancelled: bool,
+    /// Across all loops.
+    pub published: u64,
+    pub total: usize,
+    /// Zero-based.
+    pub iteration: u32,
+    pub error: Option<String>,
+}
+
+struct Rewrites(Vec<(Regex, String)>);
+
+impl Rewrites {
+    fn new(rewrites: &[TopicRewrite]) -> Result<Self, RecordingError> {
+        rewrites
+            .iter()
+            .map(|r| {
+                Regex::new(&r.pattern)
+                    .map(|re| (re, r.replacement.clone()))
+                    .map_err(|e| RecordingError::InvalidRewrite(r.pattern.clone(), e))
+            })
+            .collect::<Result<_, _>>()
+            .map(Rewrites)
+    }
+
+    fn apply(&self, topic: &str) -> String {
+        self.0.iter().fold(topic.to_string(), |topic, (re, to)| {
+            re.replace_all(&topic, to.as_str()).into_owned()
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Replayer {
+    cancel: Arc<Mutex<Option<watch::Sender<bool>>>>,
+}
+
+impl Replayer {
+    /// `client` must be connected to `connection_id`.
+    pub async fn start<F>(
+        &self,
+        client: Arc<RwLock<MqttClient>>,
+        connection_id: &str,
+        path: PathBuf,
+        options: ReplayOptions,
+        on_progress: F,
+    ) -> Result<(), RecordingError>
+    where
+        F: Fn(ReplayProgress) + Send + Sync + 'static,
+    {
+        if !(options.speed.is_finite() && options.speed > 0.0) {
+            return Err(RecordingError::InvalidSpeed);
+        }
+        let rewrites = Rewrites::new(&options.rewrites)?;
+        if client.read().await.connection_id() != Some(connection_id) {
+            return Err(RecordingError::WrongConnection);
+        }
+        let (_, messages) = load(&path)?;
+        if messages.is_empty() {
+            return Err(RecordingError::Empty);
+        }
+
+        let mut current = self.cancel.lock().await;
+        if current.is_some() {
+            return Err(RecordingError::AlreadyReplaying);
+        }
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        *current = Some(cancel_tx);
+
+        info!(
+            "Replaying {} messages from {} at {}x",
+            messages.len(),
+            path.display(),
+            options.speed
+        );
+        let slot = Arc::clone(&self.cancel);
+        tokio::spawn(async move {
+            let publish = |msg: RecordedMessage| {
+                let client = Arc::clone(&client);
+                async move {
+                    client
+                        .read()
+                        .await
+                        .publish_bytes(&msg.topic, &msg.payload, msg.qos, msg.retain)
+                        .await
+                }
+            };
+            let progress = replay(
+                &path,
+                &messages,
+                &options,
+                &rewrites,
+                cancel_rx,
+                publish,
+                &on_progress,
+            )
+            .await;
+            if let Some(error) = &progress.error {
+                warn!("Replay failed: {}", error);
+            }
+            on_progress(progress);
+            *slot.lock().await = None;
+        });
+        Ok(())
+    }
+
+    pub async fn cancel(&self) -> bool {
+        match self.cancel.lock().await.as_ref() {
+            Some(tx) => {
+                let _ = tx.send(true);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Stops at the first publish error.
+async fn replay<P, Fut, F>(
+    path: &Path,
+    messages: &[RecordedMessage],
+    options: &ReplayOptions,
+    rewrites: &Rewrites,
+    mut cancel: watch::Receiver<bool>,
+    publish: P,
+    on_progress: &F,
+) -> ReplayProgress
+where
+    P: Fn(RecordedMessage) -> Fut,
+    Fut: Future<Output = Result<(), MqttError>>,
+    F: Fn(ReplayProgress),
+{
+    let mut progress = ReplayProgress {
+        path: path.to_path_buf(),
+        running: true,
+        cancelled: false,
+        published: 0,
+        total: messages.len(),
+        iteration: 0,
+        error: None,
+    };
+    let mut last_report = Instant::now();
+    on_progress(progress.clone());
+
+    'replay: loop {
+        let start = tokio::time::Instant::now();
+        for msg in messages {
+            let due =
+                start + Duration::from_secs_f64(msg.offset_ms as f64 / 1000.0 / options.speed);
+            tokio::select! {
+                _ = tokio::time::sleep_until(due) => {}
+                _ = cancel.changed() => {
+                    progress.cancelled = true;
+                    break 'replay;
+                }
+            }
+            let outgoing = RecordedMessage {
+                topic: rewrites.apply(&msg.topic),
+                ..msg.clone()
+            };
+            if let Err(e) = publish(outgoing).await {
+                progress.error = Some(e.to_string());
+                break 'replay;
+            }
+            progress.published += 1;
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                last_report = Instant::now();
+                on_progress(progress.clone());
+            }
+        }
+        if !options.loop_playback || *cancel.borrow() {
+            progress.cancelled = *cancel.borrow();
+            break;
+        }
+        progress.iteration += 1;
+    }
+
+    progress.running = false;
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    fn message(topic: &str, payload: &str, timestamp: u64) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            timestamp,
+            qos: QoS::AtLeastOnce,
+            ..Default::default()
+        }
+    }
+
+    fn recorded(offset_ms: u64, topic: &str) -> RecordedMessage {
+        RecordedMessage {
+            offset_ms,
+            topic: topic.to_string(),
+            payload: b"x".to_vec(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+        }
+    }
+
+    #[test]
+    fn test_record_and_load() {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("session.ndjson");
+            let (tx, rx) = broadcast::channel(16);
+            let recorder = Recorder::default();
+            let status = recorder
+                .start(
+                    rx,
+                    path.clone(),
+                    "Local".to_string(),
+                    "localhost:1883".to_string(),
+                    Some("sensors/#".to_string()),
+                )
+                .await
+                .unwrap();
+            assert!(status.active);
+            assert!(matches!(
+                recorder
+                    .start(
+                        tx.subscribe(),
+                        dir.path().join("other.ndjson"),
+                        String::new(),
+                        String::new(),
+                        None
+                    )
+                    .await,
+                Err(RecordingError::AlreadyRecording)
+            ));
+
+            let start = status.started_at;
+            tx.send(message("sensors/temp", "21.5", start + 10))
+                .unwrap();
+            tx.send(message("other/topic", "skipped", start + 20))
+                .unwrap();
+            let mut binary = message("sensors/raw", "\u{FFFD}\u{FFFD}", start + 30);
+            binary.raw = Some(vec![0xff, 0x00, 0xfe]);
+            binary.retain = true;
+            tx.send(binary).unwrap();
+            tx.send(message("sensors/empty", "", start + 40)).unwrap();
+            // Stopping waits until the queued messages are written.
+            let status = recorder.stop().await.unwrap();
+            assert!(!status.active);
+            assert_eq!(status.count, 3);
+
+            let (header, messages) = load(&path).unwrap();
+            assert_eq!(header.connection, "Local");
+            assert_eq!(header.started_at, start);
+            assert_eq!(messages.len(), 3);
+            assert_eq!(messages[0].topic, "sensors/temp");
+            assert_eq!(messages[0].payload, b"21.5");
+            assert_eq!(messages[0].offset_ms, 10);
+            assert_eq!(messages[0].qos, QoS::AtLeastOnce);
+            assert_eq!(messages[1].payload, vec![0xff, 0x00, 0xfe]);
+            assert!(messages[1].retain);
+            assert!(messages[2].payload.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_load_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.json");
+        std::fs::write(&path, "{\"connections\": []}\n").unwrap();
+        assert!(matches!(load(&path), Err(RecordingError::NotARecording)));
+
+        std::fs::write(
+            &path,
+            format!(
+                "{{\"format\":\"{FORMAT}\",\"version\":1,\"startedAt\":0,\"connection\":\"\",\"broker\":\"\"}}\nnot json\n"
+            ),
+        )
+        .unwrap();
+        assert!(matches!(load(&path), Err(RecordingError::Parse(2, _))));
+    }
+
+    #[test]
+    fn test_rewrites_apply_in_order() {
+        let rewrites = Rewrites::new(&[
+            TopicRewrite {
+                pattern: "^prod/".to_string(),
+                replacement: "staging/".to_string(),
+            },
+            TopicRewrite {
+                pattern: r"^staging/(?P<device>[^/]+)/".to_string(),
+                replacement: "staging/replay-$device/".to_string(),
+            },
+        ])
+        .unwrap();
+        assert_eq!(rewrites.apply("prod/d1/temp"), "staging/replay-d1/temp");
+        assert_eq!(rewrites.apply("other/d1"), "other/d1");
+        assert!(matches!(
+            Rewrites::new(&[TopicRewrite {
+                pattern: "(".to_string(),
+                replacement: String::new(),
+            }]),
+            Err(RecordingError::InvalidRewrite(..))
+        ));
+    }
+
+    #[test]
+    fn test_replay_timing_and_speed() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let messages = vec![recorded(0, "a"), recorded(100, "b"), recorded(200, "c")];
+            let options = ReplayOptions {
+                speed: 2.0,
+                ..Default::default()
+            };
+            let rewrites = Rewrites::new(&[]).unwrap();
+            let (_cancel_tx, cancel_rx) = watch::channel(false);
+            let published = StdMutex::new(Vec::new());
+            let start = tokio::time::Instant::now();
+            let progress = replay(
+                Path::new("test"),
+                &messages,
+                &options,
+                &rewrites,
+                cancel_rx,
+                |msg| {
+                    published.lock().unwrap().push((msg.topic, start.elapsed()));
+                    async { Ok(()) }
+                },
+                &|_| {},
+            )
+            .await;
+
+            assert_eq!(progress.published, 3);
+            assert!(!progress.running);
+            let published = published.into_inner().unwrap();
+            let topics: Vec<_> = published.iter().map(|(t, _)| t.as_str()).collect();
+            assert_eq!(topics, ["a", "b", "c"]);
+            // Time is paused, so the offsets are exact.
+            assert_eq!(published[1].1, Duration::from_millis(50));
+            assert_eq!(published[2].1, Duration::from_millis(100));
+        });
+    }
+
+    #[test]
+    fn test_replay_loop_until_cancelled() {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let messages = vec![recorded(0, "a"), recorded(10, "b")];
+            let options = ReplayOptions {
+                loop_playback: true,
+                ..Default::default()
+            };
+            let rewrites = Rewrites::new(&[]).unwrap();
+            let (cancel_tx, cancel_rx) = watch::channel(false);
+            let count = StdMutex::new(0);
+            let progress = replay(
+                Path::new("test"),
+                &messages,
+                &options,
+                &rewrites,
+                cancel_rx,
+                |_| {
+                    let mut count = count.lock().unwrap();
+                    *count += 1;
+                    if *count == 5 {
+                        let _ = cancel_tx.send(true);
+                    }
+                    async { Ok(()) }
+                },
+                &|_| {},
+            )
+            .await;
+
+            assert!(progress.cancelled);
+            assert_eq!(progress.published, 5);
+            assert_eq!(progress.iteration, 2);
+        });
+    }
+
+    #[test]
+    fn test_replay_stops_on_publish_error() {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let messages = vec![recorded(0, "a"), recorded(0, "b")];
+            let (_cancel_tx, cancel_rx) = watch::channel(false);
+            let progress = replay(
+                Path::new("test"),
+                &messages,
+                &ReplayOptions::default(),
+                &Rewrites::new(&[]).unwrap(),
+                cancel_rx,
+                |_| async { Err(MqttError::NotConnected) },
+                &|_| {},
+            )
+            .await;
+            assert_eq!(progress.published, 0);
+            assert_eq!(progress.error.as_deref(), Some("Not connected"));
+        });
+    }
+}
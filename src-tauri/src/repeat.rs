@@ -0,0 +1,473 @@
+use crate::mqtt::{MqttClient, MqttError};
+use crate::template::substitute_variables;
+use crate::types::Button;
+use log::{info, warn};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::time::Instant;
+
+pub const REPEAT_EVENT: &str = "repeat-progress";
+pub const MIN_INTERVAL_MS: u64 = 100;
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatOptions {
+    pub interval: Duration,
+    pub max_count: Option<u64>,
+    pub max_duration: Option<Duration>,
+    pub jitter: Duration,
+}
+
+impl RepeatOptions {
+    pub fn from_button(button: &Button) -> Self {
+        let interval = button
+            .multi_send_interval
+            .unwrap_or(DEFAULT_INTERVAL_MS)
+            .max(MIN_INTERVAL_MS);
+        Self {
+            interval: Duration::from_millis(interval),
+            max_count: button.multi_send_max_count.filter(|c| *c > 0),
+            max_duration: button
+                .multi_send_max_duration
+                .filter(|d| *d > 0)
+                .map(Duration::from_millis),
+            // More jitter than half the interval could reorder publishes.
+            jitter: Duration::from_millis(button.multi_send_jitter.unwrap_or(0).min(interval / 2)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepeatStatus {
+    pub button_id: String,
+    pub interval_ms: u64,
+    pub count: u64,
+    pub started_at: u64,
+    pub active: bool,
+    pub error: Option<String>,
+}
+
+struct RepeatTask {
+    token: u64,
+    stop_tx: watch::Sender<bool>,
+    status: Arc<std::sync::Mutex<RepeatStatus>>,
+}
+
+/// Starting a button that is already repeating restarts it.
+#[derive(Default)]
+pub struct RepeatScheduler {
+    tasks: Arc<Mutex<HashMap<String, RepeatTask>>>,
+    next_token: AtomicU64,
+}
+
+impl RepeatScheduler {
+    pub async fn start<F>(
+        &self,
+        client: Arc<RwLock<MqttClient>>,
+        button: Button,
+        variables: HashMap<String, String>,
+        on_event: F,
+    ) -> RepeatStatus
+    where
+        F: Fn(RepeatStatus) + Send + Sync + 'static,
+    {
+        let options = RepeatOptions::from_button(&button);
+        let status = Arc::new(std::sync::Mutex::new(RepeatStatus {
+            button_id: button.id.clone(),
+            interval_ms: options.interval.as_millis() as u64,
+            count: 0,
+            started_at: now_millis(),
+            active: true,
+            error: None,
+        }));
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let replaced = self.tasks.lock().await.insert(
+            button.id.clone(),
+            RepeatTask {
+                token,
+                stop_tx,
+                status: Arc::clone(&status),
+            },
+        );
+        if let Some(task) = replaced {
+            let _ = task.stop_tx.send(true);
+        }
+
+        let tasks = Arc::clone(&self.tasks);
+        let initial = status.lock().unwrap().clone();
+        tokio::spawn(async move {
+            info!(
+                "Starting multi-send of '{}' every {} ms",
+                button.name,
+                options.interval.as_millis()
+            );
+            let on_sent = |count| {
+                let mut current = status.lock().unwrap();
+                current.count = count;
+                on_event(current.clone());
+            };
+            let publish = |topic: String, payload: String| {
+                let client = Arc::clone(&client);
+                let button = button.clone();
+                async move {
+                    client
+                        .read()
+                        .await
+                        .publish_button_payload(&button, &topic, &payload)
+                        .await
+                }
+            };
+            let result = repeat(&button, &variables, &options, stop_rx, publish, on_sent).await;
+
+            {
+                let mut tasks = tasks.lock().await;
+                match tasks.get(&button.id) {
+                    Some(task) if task.token == token => {
+                        tasks.remove(&button.id);
+                    }
+                    // Restarted with new settings; the new run reports instead.
+                    Some(_) => return,
+                    None => {}
+                }
+            }
+            let mut last = status.lock().unwrap().clone();
+            last.active = false;
+            if let Err(e) = result {
+                warn!("Multi-send of '{}' stopped: {}", button.name, e);
+                last.error = Some(e.to_string());
+            } else {
+                info!(
+                    "Multi-send of '{}' stopped after {}",
+                    button.name, last.count
+                );
+            }
+            on_event(last);
+        });
+
+        initial
+    }
+
+    pub async fn stop(&self, button_id: &str) -> bool {
+        match self.tasks.lock().await.remove(button_id) {
+            Some(task) => {
+                let _ = task.stop_tx.send(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn stop_all(&self) {
+        let mut tasks = self.tasks.lock().await;
+        if !tasks.is_empty() {
+            info!("Stopping {} multi-send(s)", tasks.len());
+        }
+        for (_, task) in tasks.drain() {
+            let _ = task.stop_tx.send(true);
+        }
+    }
+
+    pub async fn list(&self) -> Vec<RepeatStatus> {
+        self.tasks
+            .lock()
+            .await
+            .values()
+            .map(|t| t.status.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+/// Publish times are computed from the start time, so slow publishes do not
+/// make the schedule drift; slots that were missed entirely are skipped.
+async fn repeat<P, Fut>(
+    button: &Button,
+    variables: &HashMap<String, String>,
+    options: &RepeatOptions,
+    mut stop_rx: watch::Receiver<bool>,
+    publish: P,
+    on_sent: impl Fn(u64),
+) -> Result<u64, MqttError>
+where
+    P: Fn(String, String) -> Fut,
+    Fut: Future<Output = Result<(), MqttError>>,
+{
+    let start = Instant::now();
+    let mut count = 0;
+    let mut slot: u32 = 0;
+
+    loop {
+        // Templates are resolved per publish so `{uuid}`, `{now}` etc. change.
+        let topic = substitute_variables(&button.topic, variables);
+        let payload = substitute_variables(button.payload.as_deref().unwrap_or(""), variables);
+        publish(topic, payload).await?;
+        count += 1;
+        on_sent(count);
+
+        if options.max_count.is_some_and(|max| count >= max) {
+            break;
+        }
+
+        slot += 1;
+        while start + options.interval * slot < Instant::now() {
+            slot += 1;
+        }
+        let mut next = start + options.interval * slot;
+        if !options.jitter.is_zero() {
+            let jitter = options.jitter.as_millis() as i64;
+            let offset = rand::thread_rng().gen_range(-jitter..=jitter);
+            next = if offset >= 0 {
+                next + Duration::from_millis(offset as u64)
+            } else {
+                next - Duration::from_millis(offset.unsigned_abs())
+            };
+        }
+        if options
+            .max_duration
+            .is_some_and(|max| next.duration_since(start) > max)
+        {
+            break;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(next) => {}
+            _ = stop_rx.wait_for(|stop| *stop) => break,
+        }
+    }
+
+    Ok(count)
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    fn create_button(interval: u64) -> Button {
+        Button {
+            id: "b1".to_string(),
+            name: "Ping".to_string(),
+            topic: "devices/{device}/ping".to_string(),
+            payload: Some("{rand}".to_string()),
+            multi_send_enabled: Some(true),
+            multi_send_interval: Some(interval),
+            ..Default::default()
+        }
+    }
+
+    type Sent = Arc<StdMutex<Vec<(String, Instant)>>>;
+
+    fn recording_publish() -> (
+        Sent,
+        impl Fn(String, String) -> std::future::Ready<Result<(), MqttError>>,
+    ) {
+        let sent = Arc::new(StdMutex::new(Vec::new()));
+        let sink = Arc::clone(&sent);
+        let publish = move |topic: String, _payload: String| {
+            sink.lock().unwrap().push((topic, Instant::now()));
+            std::future::ready(Ok(()))
+        };
+        (sent, publish)
+    }
+
+    fn paused_runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_options_from_button() {
+        let mut button = create_button(10);
+        button.multi_send_jitter = Some(500);
+        button.multi_send_max_count = Some(0);
+        let options = RepeatOptions::from_button(&button);
+        assert_eq!(options.interval, Duration::from_millis(MIN_INTERVAL_MS));
+        assert_eq!(options.jitter, Duration::from_millis(50));
+        assert_eq!(options.max_count, None);
+
+        button.multi_send_interval = None;
+        assert_eq!(
+            RepeatOptions::from_button(&button).interval,
+            Duration::from_millis(DEFAULT_INTERVAL_MS)
+        );
+    }
+
+    #[test]
+    fn test_repeat_stops_at_max_count() {
+        paused_runtime().block_on(async {
+            let mut button = create_button(100);
+            button.multi_send_max_count = Some(3);
+            let variables = HashMap::from([("device".to_string(), "abc".to_string())]);
+            let (sent, publish) = recording_publish();
+            let (_tx, rx) = watch::channel(false);
+
+            let start = Instant::now();
+            let count = repeat(
+                &button,
+                &variables,
+                &RepeatOptions::from_button(&button),
+                rx,
+                publish,
+                |_| {},
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(count, 3);
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent[0].0, "devices/abc/ping");
+            let offsets: Vec<_> = sent.iter().map(|(_, at)| *at - start).collect();
+            assert_eq!(offsets, [0, 100, 200].map(Duration::from_millis).to_vec());
+        });
+    }
+
+    #[test]
+    fn test_repeat_stops_at_max_duration() {
+        paused_runtime().block_on(async {
+            let mut button = create_button(100);
+            button.multi_send_max_duration = Some(250);
+            let (_tx, rx) = watch::channel(false);
+            let (_, publish) = recording_publish();
+
+            let count = repeat(
+                &button,
+                &HashMap::new(),
+                &RepeatOptions::from_button(&button),
+                rx,
+                publish,
+                |_| {},
+            )
+            .await
+            .unwrap();
+            assert_eq!(count, 3);
+        });
+    }
+
+    #[test]
+    fn test_repeat_stops_on_signal() {
+        paused_runtime().block_on(async {
+            let button = create_button(100);
+            let (tx, rx) = watch::channel(false);
+            let (_, publish) = recording_publish();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(150)).await;
+                let _ = tx.send(true);
+            });
+
+            let count = repeat(
+                &button,
+                &HashMap::new(),
+                &RepeatOptions::from_button(&button),
+                rx,
+                publish,
+                |_| {},
+            )
+            .await
+            .unwrap();
+            assert_eq!(count, 2);
+        });
+    }
+
+    #[test]
+    fn test_repeat_stops_on_publish_error() {
+        paused_runtime().block_on(async {
+            let button = create_button(100);
+            let (_tx, rx) = watch::channel(false);
+            let publish = |_: String, _: String| std::future::ready(Err(MqttError::NotConnected));
+            let result = repeat(
+                &button,
+                &HashMap::new(),
+                &RepeatOptions::from_button(&button),
+                rx,
+                publish,
+                |_| {},
+            )
+            .await;
+            assert!(matches!(result, Err(MqttError::NotConnected)));
+        });
+    }
+
+    #[test]
+    fn test_scheduler_reports_failure_and_cleans_up() {
+        paused_runtime().block_on(async {
+            let scheduler = RepeatScheduler::default();
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            let events = Arc::new(StdMutex::new(Vec::new()));
+            let sink = Arc::clone(&events);
+
+            let status = scheduler
+                .start(client, create_button(100), HashMap::new(), move |s| {
+                    sink.lock().unwrap().push(s)
+                })
+                .await;
+            assert!(status.active);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            let last = events.lock().unwrap().last().cloned().unwrap();
+            assert!(!last.active);
+            assert_eq!(last.error.as_deref(), Some("Not connected"));
+            assert!(scheduler.list().await.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_scheduler_restart_replaces_run() {
+        paused_runtime().block_on(async {
+            let scheduler = RepeatScheduler::default();
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            let events = Arc::new(StdMutex::new(Vec::new()));
+
+            for interval in [100, 200] {
+                let sink = Arc::clone(&events);
+                scheduler
+                    .start(
+                        Arc::clone(&client),
+                        create_button(interval),
+                        HashMap::new(),
+                        move |s| sink.lock().unwrap().push(s),
+                    )
+                    .await;
+            }
+            assert_eq!(scheduler.list().await[0].interval_ms, 200);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            let events = events.lock().unwrap().clone();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].interval_ms, 200);
+        });
+    }
+
+    #[test]
+    fn test_scheduler_stop() {
+        // The task cannot fail before it is stopped on a single thread.
+        paused_runtime().block_on(async {
+            let scheduler = RepeatScheduler::default();
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            assert!(!scheduler.stop("b1").await);
+
+            scheduler
+                .start(client, create_button(100), HashMap::new(), |_| {})
+                .await;
+            assert_eq!(scheduler.list().await.len(), 1);
+            scheduler.stop_all().await;
+            assert!(scheduler.list().await.is_empty());
+        });
+    }
+}
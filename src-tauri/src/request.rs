@@ -0,0 +1,527 @@
+use crate::json_path;
+use crate::mqtt::{build_options_v5, next_message, Message, MqttClient, MqttError};
+use crate::template::substitute_variables;
+use crate::topic::matches_filter;
+use crate::types::{Button, Connection, QoS};
+use log::{debug, warn};
+use rumqttc::v5::mqttbytes::v5::{Packet, Publish, PublishProperties};
+use rumqttc::v5::{AsyncClient, Event};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{broadcast, RwLock};
+
+pub const CORRELATION_VARIABLE: &str = "correlation_id";
+
+#[derive(Error, Debug)]
+pub enum RequestError {
+    #[error("Button '{0}' has no response topic")]
+    NotARequest(String),
+    #[error("No reply on '{0}' within {1} ms")]
+    Timeout(String, u64),
+    #[error("No reply on '{0}' with the correlation id at '{1}' within {2} ms")]
+    NoCorrelatedReply(String, String, u64),
+    #[error(transparent)]
+    Mqtt(#[from] MqttError),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reply {
+    pub topic: String,
+    pub payload: String,
+    pub round_trip_ms: u64,
+    pub correlation_id: Option<String>,
+}
+
+/// The response topic is subscribed for the duration of the request unless
+/// an existing subscription already covers it.
+pub async fn send_request(
+    client: &RwLock<MqttClient>,
+    button: &Button,
+    variables: &HashMap<String, String>,
+) -> Result<Reply, RequestError> {
+    let config = button
+        .response
+        .as_ref()
+        .ok_or_else(|| RequestError::NotARequest(button.name.clone()))?;
+
+    let mut variables = variables.clone();
+    let correlation_id = (config.mqtt5 || config.correlation_path.is_some())
+        .then(|| uuid::Uuid::new_v4().to_string());
+    if let Some(ref id) = correlation_id {
+        variables.insert(CORRELATION_VARIABLE.to_string(), id.clone());
+    }
+
+    let topic = substitute_variables(&button.topic, &variables);
+    let mut payload = substitute_variables(button.payload.as_deref().unwrap_or(""), &variables);
+    if let (Some(path), Some(id)) = (&config.correlation_path, &correlation_id) {
+        payload = with_correlation_id(&payload, path, id);
+    }
+    let response_topic = substitute_variables(&config.topic, &variables);
+
+    if let (true, Some(id)) = (config.mqtt5, &correlation_id) {
+        let (connection, bytes) = {
+            let client = client.read().await;
+            let connection = client.connection().ok_or(MqttError::NotConnected)?.clone();
+            (
+                connection,
+                client.button_bytes(button, &topic, &payload).await?,
+            )
+        };
+        let (reply, round_trip) = request_v5(
+            &connection,
+            button,
+            &topic,
+            bytes,
+            &response_topic,
+            id,
+            config.timeout_ms,
+        )
+        .await?;
+        return Ok(Reply {
+            topic: String::from_utf8_lossy(&reply.topic).into_owned(),
+            payload: String::from_utf8_lossy(&reply.payload).into_owned(),
+            round_trip_ms: round_trip.as_millis() as u64,
+            correlation_id,
+        });
+    }
+
+    let mut messages = client.read().await.subscribe_messages();
+    let subscribed = client
+        .read()
+        .await
+        .subscribe_if_needed(&response_topic, QoS::AtMostOnce)
+        .await?;
+
+    let result = async {
+        let started = Instant::now();
+        client
+            .read()
+            .await
+            .publish_button_payload(button, &topic, &payload)
+            .await?;
+        let correlation = config
+            .correlation_path
+            .as_deref()
+            .zip(correlation_id.as_deref());
+        let reply = wait_for_reply(
+            &mut messages,
+            &response_topic,
+            correlation,
+            config.timeout_ms,
+        )
+        .await?;
+        Ok(Reply {
+            topic: reply.topic,
+            payload: reply.payload,
+            round_trip_ms: started.elapsed().as_millis() as u64,
+            correlation_id: correlation_id.clone(),
+        })
+    }
+    .await;
+
+    if subscribed {
+        if let Err(e) = client.read().await.unsubscribe(&response_topic).await {
+            warn!("Failed to unsubscribe from '{}': {}", response_topic, e);
+        }
+    }
+
+    result
+}
+
+/// Uses its own MQTT 5 session because the app's connection speaks 3.1.1.
+async fn request_v5(
+    connection: &Connection,
+    button: &Button,
+    topic: &str,
+    payload: Vec<u8>,
+    response_topic: &str,
+    correlation_id: &str,
+    timeout_ms: u64,
+) -> Result<(Publish, Duration), RequestError> {
+    let client_id = format!(
+        "{}-request-{}",
+        connection.client_id,
+        &uuid::Uuid::new_v4().simple().to_string()[..6]
+    );
+    let (client, mut eventloop) = AsyncClient::new(build_options_v5(connection, &client_id), 10);
+    let properties = PublishProperties {
+        // Filters cannot be answered to; the reply then goes wherever the
+        // device sends it.
+        response_topic: (!response_topic.contains(['+', '#'])).then(|| response_topic.to_string()),
+        correlation_data: Some(correlation_id.as_bytes().to_vec().into()),
+        ..Default::default()
+    };
+
+    let session = async {
+        client
+            .subscribe(response_topic, QoS::AtMostOnce.into())
+            .await
+            .map_err(MqttError::from)?;
+        let mut started = None;
+        loop {
+            match eventloop.poll().await.map_err(MqttError::from)? {
+                // Publishing only once subscribed, so a fast reply is not missed.
+                Event::Incoming(Packet::SubAck(_)) if started.is_none() => {
+                    client
+                        .publish_with_properties(
+                            topic,
+                            button.qos.into(),
+                            button.retain,
+                            payload.clone(),
+                            properties.clone(),
+                        )
+                        .await
+                        .map_err(MqttError::from)?;
+                    started = Some(Instant::now());
+                }
+                Event::Incoming(Packet::Publish(publish))
+                    if !publish.retain
+                        && publish
+                            .properties
+                            .as_ref()
+                            .and_then(|p| p.correlation_data.as_deref())
+                            == Some(correlation_id.as_bytes()) =>
+                {
+                    let round_trip = started.map(|s| s.elapsed()).unwrap_or_default();
+                    return Ok::<_, RequestError>((publish, round_trip));
+                }
+                _ => {}
+            }
+        }
+    };
+    let result = match tokio::time::timeout(Duration::from_millis(timeout_ms), session).await {
+        Ok(result) => result,
+        Err(_) => Err(RequestError::Timeout(
+            response_topic.to_string(),
+            timeout_ms,
+        )),
+    };
+
+    debug!("Closing MQTT 5 request session '{}'", client_id);
+    let _ = client.disconnect().await;
+    let _ = tokio::time::timeout(Duration::from_millis(500), async {
+        while eventloop.poll().await.is_ok() {}
+    })
+    .await;
+    result
+}
+
+async fn wait_for_reply(
+    messages: &mut broadcast::Receiver<Message>,
+    filter: &str,
+    correlation: Option<(&str, &str)>,
+    timeout_ms: u64,
+) -> Result<Message, RequestError> {
+    let reply = tokio::time::timeout(
+        Duration::from_millis(timeout_ms),
+        next_message(messages, |m| {
+            !m.retain
+                && matches_filter(filter, &m.topic)
+                && matches_correlation(&m.payload, correlation)
+        }),
+    )
+    .await
+    .map_err(|_| match correlation {
+        Some((path, _)) => {
+            RequestError::NoCorrelatedReply(filter.to_string(), path.to_string(), timeout_ms)
+        }
+        None => RequestError::Timeout(filter.to_string(), timeout_ms),
+    })??;
+    Ok(reply)
+}
+
+/// When the first key of the path is new, the field is inserted at the start
+/// of the object and the rest is kept as written; otherwise the payload is
+/// re-serialized compactly.
+fn with_correlation_id(payload: &str, path: &str, id: &str) -> String {
+    let Ok(mut value) = serde_json::from_str::<Value>(payload) else {
+        return payload.to_string();
+    };
+    let Some(object) = value.as_object() else {
+        return payload.to_string();
+    };
+    if json_path::get(&value, path).is_some() {
+        return payload.to_string();
+    }
+
+    let mut field = Value::Object(Default::default());
+    if json_path::set(&mut field, path, Value::String(id.to_string())) {
+        if let Some((key, inner)) = field.as_object().and_then(|f| f.iter().next()) {
+            if !object.contains_key(key) {
+                let start = payload.find('{').unwrap_or(0) + 1;
+                let separator = if object.is_empty() { "" } else { "," };
+                return format!(
+                    "{}{}:{}{}{}",
+                    &payload[..start],
+                    Value::String(key.clone()),
+                    inner,
+                    separator,
+                    &payload[start..]
+                );
+            }
+        }
+    }
+
+    if json_path::set(&mut value, path, Value::String(id.to_string())) {
+        value.to_string()
+    } else {
+        payload.to_string()
+    }
+}
+
+fn matches_correlation(payload: &str, correlation: Option<(&str, &str)>) -> bool {
+    let Some((path, id)) = correlation else {
+        return true;
+    };
+    serde_json::from_str::<Value>(payload)
+        .ok()
+        .and_then(|value| json_path::get(&value, path).map(json_path::to_text))
+        .is_some_and(|value| value == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ResponseConfig;
+
+    fn create_button(response: Option<ResponseConfig>) -> Button {
+        Button {
+            id: "b1".to_string(),
+            name: "Get status".to_string(),
+            topic: "devices/{device}/cmd".to_string(),
+            payload: Some(r#"{"cmd": "status"}"#.to_string()),
+            response,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_with_correlation_id() {
+        assert_eq!(
+            with_correlation_id(r#"{"cmd":"status"}"#, "meta.id", "abc"),
+            r#"{"meta":{"id":"abc"},"cmd":"status"}"#
+        );
+        assert_eq!(
+            with_correlation_id("{\n  \"cmd\": 1.50\n}", "id", "abc"),
+            "{\"id\":\"abc\",\n  \"cmd\": 1.50\n}"
+        );
+        assert_eq!(with_correlation_id(" {}", "id", "abc"), r#" {"id":"abc"}"#);
+        assert_eq!(
+            with_correlation_id(r#"{"meta":{"v":1}}"#, "meta.id", "abc"),
+            r#"{"meta":{"id":"abc","v":1}}"#
+        );
+        assert_eq!(
+            with_correlation_id(r#"{"id":"given"}"#, "id", "abc"),
+            r#"{"id":"given"}"#
+        );
+        assert_eq!(with_correlation_id("status", "id", "abc"), "status");
+        assert_eq!(with_correlation_id("[1]", "id", "abc"), "[1]");
+    }
+
+    #[test]
+    fn test_matches_correlation() {
+        let correlation = Some(("meta.id", "abc"));
+        assert!(matches_correlation("anything", None));
+        assert!(matches_correlation(r#"{"meta":{"id":"abc"}}"#, correlation));
+        assert!(!matches_correlation(
+            r#"{"meta":{"id":"xyz"}}"#,
+            correlation
+        ));
+        assert!(!matches_correlation("abc", correlation));
+    }
+
+    #[test]
+    fn test_button_without_response_is_rejected() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = RwLock::new(MqttClient::new());
+            let result = send_request(&client, &create_button(None), &HashMap::new()).await;
+            assert!(matches!(result, Err(RequestError::NotARequest(_))));
+        });
+    }
+
+    #[test]
+    fn test_request_without_connection_fails() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = RwLock::new(MqttClient::new());
+            let button = create_button(Some(ResponseConfig {
+                topic: "devices/+/resp".to_string(),
+                timeout_ms: 100,
+                correlation_path: None,
+                mqtt5: false,
+            }));
+            let result = send_request(&client, &button, &HashMap::new()).await;
+            assert!(matches!(
+                result,
+                Err(RequestError::Mqtt(MqttError::NotConnected))
+            ));
+        });
+    }
+
+    fn message(topic: &str, payload: &str) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reply_is_matched_by_correlation() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = MqttClient::new();
+            let mut messages = client.subscribe_messages();
+            client.inject_message(message("devices/abc/status", r#"{"meta":{"id":"abc"}}"#));
+            client.inject_message(message("devices/abc/resp", r#"{"meta":{"id":"other"}}"#));
+            client.inject_message(message(
+                "devices/abc/resp",
+                r#"{"meta":{"id":"abc"},"ok":true}"#,
+            ));
+
+            let reply = wait_for_reply(
+                &mut messages,
+                "devices/+/resp",
+                Some(("meta.id", "abc")),
+                1000,
+            )
+            .await
+            .unwrap();
+            assert_eq!(reply.topic, "devices/abc/resp");
+            assert!(reply.payload.contains("\"ok\":true"));
+        });
+    }
+
+    #[test]
+    fn test_retained_message_is_not_a_reply() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = MqttClient::new();
+            let mut messages = client.subscribe_messages();
+            let mut retained = message("devices/abc/resp", "stale");
+            retained.retain = true;
+            client.inject_message(retained);
+            client.inject_message(message("devices/abc/resp", "fresh"));
+
+            let reply = wait_for_reply(&mut messages, "devices/+/resp", None, 1000)
+                .await
+                .unwrap();
+            assert_eq!(reply.payload, "fresh");
+        });
+    }
+
+    #[test]
+    fn test_reply_timeout() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = MqttClient::new();
+            let mut messages = client.subscribe_messages();
+            client.inject_message(message("devices/abc/resp", r#"{"meta":{"id":"other"}}"#));
+
+            let result = wait_for_reply(
+                &mut messages,
+                "devices/+/resp",
+                Some(("meta.id", "abc")),
+                50,
+            )
+            .await;
+            assert!(matches!(
+                result,
+                Err(RequestError::NoCorrelatedReply(_, _, 50))
+            ));
+
+            let result = wait_for_reply(&mut messages, "other", None, 50).await;
+            assert!(matches!(result, Err(RequestError::Timeout(_, 50))));
+        });
+    }
+
+    /// Answers every request on `devices/+/cmd` twice: first without and
+    /// then with its correlation data.
+    async fn run_v5_device(connection: &Connection) {
+        let options = build_options_v5(connection, "device-v5");
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+        client
+            .subscribe("devices/+/cmd", QoS::AtMostOnce.into())
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            while let Ok(event) = eventloop.poll().await {
+                let Event::Incoming(Packet::Publish(request)) = event else {
+                    continue;
+                };
+                let Some(properties) = request.properties else {
+                    continue;
+                };
+                let topic = properties.response_topic.unwrap();
+                for correlation_data in [None, properties.correlation_data] {
+                    let reply = PublishProperties {
+                        correlation_data: correlation_data.clone(),
+                        ..Default::default()
+                    };
+                    let payload = if correlation_data.is_some() {
+                        "ok"
+                    } else {
+                        "other"
+                    };
+                    client
+                        .publish_with_properties(
+                            topic.clone(),
+                            QoS::AtMostOnce.into(),
+                            false,
+                            payload,
+                            reply,
+                        )
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_mqtt5_reply_is_matched_by_correlation_data() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let connection = Connection {
+                broker_url: crate::broker::HOST.to_string(),
+                port: crate::broker::test_v5_port(),
+                client_id: "app".to_string(),
+                ..Default::default()
+            };
+            run_v5_device(&connection).await;
+            // Let the device subscribe first.
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            let (reply, _) = request_v5(
+                &connection,
+                &create_button(None),
+                "devices/abc/cmd",
+                b"status".to_vec(),
+                "devices/abc/resp",
+                "abc",
+                5000,
+            )
+            .await
+            .unwrap();
+            assert_eq!(reply.topic, "devices/abc/resp");
+            assert_eq!(reply.payload, "ok");
+
+            let result = request_v5(
+                &connection,
+                &create_button(None),
+                "devices/abc/none",
+                Vec::new(),
+                "devices/abc/resp",
+                "abc",
+                100,
+            )
+            .await;
+            assert!(matches!(result, Err(RequestError::Timeout(_, 100))));
+        });
+    }
+}
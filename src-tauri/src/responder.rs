@@ -0,0 +1,387 @@
+use crate::json_path;
+use crate::mqtt::Message;
+use crate::template::substitute_variables;
+use crate::topic::matches_filter;
+use crate::types::{QoS, ResponderRule};
+use log::warn;
+use rand::Rng;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+const MAX_PENDING_ECHOES: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub rule_name: String,
+    pub topic: String,
+    pub payload: String,
+    pub qos: QoS,
+    pub retain: bool,
+    pub delay: Duration,
+}
+
+struct CompiledRule {
+    rule: ResponderRule,
+    pattern: Option<Regex>,
+    last_fired: Option<Instant>,
+}
+
+/// A response on a topic some rule listens to. Its echo must not trigger
+/// rules again, or a rule answering on its own filter would loop forever.
+struct Echo {
+    topic: String,
+    payload: String,
+}
+
+#[derive(Default)]
+pub struct Responders {
+    rules: Vec<CompiledRule>,
+    variables: HashMap<String, String>,
+    echoes: VecDeque<Echo>,
+}
+
+impl Responders {
+    /// Rules with an invalid payload pattern are skipped with a warning.
+    pub fn new(rules: &[ResponderRule], variables: &HashMap<String, String>) -> Self {
+        let rules = rules
+            .iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| {
+                let pattern = match rule.payload_pattern.as_deref().map(Regex::new) {
+                    Some(Ok(pattern)) => Some(pattern),
+                    Some(Err(e)) => {
+                        warn!("Skipping responder '{}': {}", rule.name, e);
+                        return None;
+                    }
+                    None => None,
+                };
+                Some(CompiledRule {
+                    rule: rule.clone(),
+                    pattern,
+                    last_fired: None,
+                })
+            })
+            .collect();
+        Self {
+            rules,
+            variables: variables.clone(),
+            echoes: VecDeque::new(),
+        }
+    }
+
+    /// Keeps the rate limits of rules whose id is unchanged.
+    pub fn update(&mut self, rules: &[ResponderRule], variables: &HashMap<String, String>) {
+        let mut updated = Self::new(rules, variables);
+        for compiled in &mut updated.rules {
+            compiled.last_fired = self
+                .rules
+                .iter()
+                .find(|old| old.rule.id == compiled.rule.id)
+                .and_then(|old| old.last_fired);
+        }
+        updated.echoes = std::mem::take(&mut self.echoes);
+        *self = updated;
+    }
+
+    fn take_echo(&mut self, msg: &Message) -> bool {
+        match self
+            .echoes
+            .iter()
+            .position(|echo| echo.topic == msg.topic && echo.payload == msg.payload)
+        {
+            Some(index) => {
+                self.echoes.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Templates can use the connection variables, `{topic}`, `{payload}`,
+    /// `{match}` for the JSON path value and named groups of the payload pattern.
+    pub fn evaluate(&mut self, msg: &Message, now: Instant) -> Vec<Response> {
+        let mut responses = Vec::new();
+        if self.take_echo(msg) {
+            return responses;
+        }
+        for compiled in &mut self.rules {
+            let rule = &compiled.rule;
+            if !matches_filter(&rule.topic_filter, &msg.topic) {
+                continue;
+            }
+
+            let mut variables = self.variables.clone();
+            variables.insert("topic".to_string(), msg.topic.clone());
+            variables.insert("payload".to_string(), msg.payload.clone());
+
+            if let Some(ref pattern) = compiled.pattern {
+                let Some(captures) = pattern.captures(&msg.payload) else {
+                    continue;
+                };
+                for name in pattern.capture_names().flatten() {
+                    if let Some(value) = captures.name(name) {
+                        variables.insert(name.to_string(), value.as_str().to_string());
+                    }
+                }
+            }
+
+            if let Some(ref path) = rule.json_path {
+                let Some(value) = serde_json::from_str::<Value>(&msg.payload)
+                    .ok()
+                    .and_then(|payload| json_path::get(&payload, path).map(json_path::to_text))
+                else {
+                    continue;
+                };
+                if rule
+                    .json_value
+                    .as_ref()
+                    .is_some_and(|expected| *expected != value)
+                {
+                    continue;
+                }
+                variables.insert("match".to_string(), value);
+            }
+
+            let min_interval = Duration::from_millis(rule.min_interval_ms.unwrap_or(0));
+            if compiled
+                .last_fired
+                .is_some_and(|last| now.duration_since(last) < min_interval)
+            {
+                continue;
+            }
+            if let Some(probability) = rule.probability {
+                if rand::thread_rng().gen::<f64>() >= probability {
+                    continue;
+                }
+            }
+            compiled.last_fired = Some(now);
+
+            let delay = Duration::from_millis(rule.delay_ms.unwrap_or(0));
+            let topic = substitute_variables(&rule.response_topic, &variables);
+            let payload =
+                substitute_variables(rule.response_payload.as_deref().unwrap_or(""), &variables);
+            responses.push(Response {
+                rule_name: rule.name.clone(),
+                topic,
+                payload,
+                qos: rule.qos,
+                retain: rule.retain,
+                delay,
+            });
+        }
+        for response in &responses {
+            if !self
+                .rules
+                .iter()
+                .any(|compiled| matches_filter(&compiled.rule.topic_filter, &response.topic))
+            {
+                continue;
+            }
+            if self.echoes.len() >= MAX_PENDING_ECHOES {
+                self.echoes.pop_front();
+            }
+            self.echoes.push_back(Echo {
+                topic: response.topic.clone(),
+                payload: response.payload.clone(),
+            });
+        }
+        responses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_rule() -> ResponderRule {
+        ResponderRule {
+            id: "r1".to_string(),
+            name: "Status reply".to_string(),
+            topic_filter: "devices/+/cmd".to_string(),
+            response_topic: "devices/{device}/resp".to_string(),
+            response_payload: Some(r#"{"id":"{match}","fw":"{fw}"}"#.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn message(topic: &str, payload: &str) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn variables() -> HashMap<String, String> {
+        HashMap::from([("fw".to_string(), "1.2.0".to_string())])
+    }
+
+    #[test]
+    fn test_json_path_match() {
+        let rule = ResponderRule {
+            json_path: Some("meta.id".to_string()),
+            payload_pattern: Some(r"(?P<cmd>status|reboot)".to_string()),
+            topic_filter: "devices/+/cmd".to_string(),
+            response_topic: "devices/{cmd}/resp".to_string(),
+            ..create_rule()
+        };
+        let mut responders = Responders::new(&[rule], &variables());
+        let now = Instant::now();
+
+        let responses = responders.evaluate(
+            &message("devices/a/cmd", r#"{"cmd":"status","meta":{"id":"42"}}"#),
+            now,
+        );
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].topic, "devices/status/resp");
+        assert_eq!(responses[0].payload, r#"{"id":"42","fw":"1.2.0"}"#);
+
+        assert!(responders
+            .evaluate(&message("devices/a/cmd", r#"{"cmd":"status"}"#), now)
+            .is_empty());
+        assert!(responders
+            .evaluate(
+                &message("devices/a/cmd", r#"{"cmd":"halt","meta":{"id":"1"}}"#),
+                now
+            )
+            .is_empty());
+        assert!(responders
+            .evaluate(&message("devices/a/status", r#"{"meta":{"id":"1"}}"#), now)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_json_value_must_match() {
+        let rule = ResponderRule {
+            json_path: Some("cmd".to_string()),
+            json_value: Some("reboot".to_string()),
+            ..create_rule()
+        };
+        let mut responders = Responders::new(&[rule], &HashMap::new());
+        let now = Instant::now();
+        assert_eq!(
+            responders
+                .evaluate(&message("devices/a/cmd", r#"{"cmd":"reboot"}"#), now)
+                .len(),
+            1
+        );
+        assert!(responders
+            .evaluate(&message("devices/a/cmd", r#"{"cmd":"status"}"#), now)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit_and_delay() {
+        let rule = ResponderRule {
+            min_interval_ms: Some(1000),
+            delay_ms: Some(250),
+            ..create_rule()
+        };
+        let mut responders = Responders::new(&[rule], &HashMap::new());
+        let start = Instant::now();
+        let msg = message("devices/a/cmd", "ping");
+
+        let responses = responders.evaluate(&msg, start);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].delay, Duration::from_millis(250));
+        assert!(responders
+            .evaluate(&msg, start + Duration::from_millis(500))
+            .is_empty());
+        assert_eq!(
+            responders
+                .evaluate(&msg, start + Duration::from_millis(1000))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_disabled_invalid_and_unlikely_rules() {
+        let rules = [
+            ResponderRule {
+                enabled: false,
+                ..create_rule()
+            },
+            ResponderRule {
+                payload_pattern: Some("(".to_string()),
+                ..create_rule()
+            },
+            ResponderRule {
+                probability: Some(0.0),
+                ..create_rule()
+            },
+        ];
+        let mut responders = Responders::new(&rules, &HashMap::new());
+        assert_eq!(responders.rules.len(), 1);
+        assert!(responders
+            .evaluate(&message("devices/a/cmd", "ping"), Instant::now())
+            .is_empty());
+
+        let always = ResponderRule {
+            probability: Some(1.0),
+            ..create_rule()
+        };
+        let mut responders = Responders::new(&[always], &HashMap::new());
+        assert_eq!(
+            responders
+                .evaluate(&message("devices/a/cmd", "ping"), Instant::now())
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_own_responses_do_not_trigger_rules() {
+        let rule = ResponderRule {
+            topic_filter: "devices/+/cmd".to_string(),
+            response_topic: "devices/{device}/cmd".to_string(),
+            response_payload: Some("pong".to_string()),
+            ..create_rule()
+        };
+        let variables = HashMap::from([("device".to_string(), "a".to_string())]);
+        let mut responders = Responders::new(&[rule], &variables);
+        let now = Instant::now();
+
+        let responses = responders.evaluate(&message("devices/a/cmd", "ping"), now);
+        assert_eq!(responses[0].topic, "devices/a/cmd");
+        let echo = message("devices/a/cmd", "pong");
+        // However late it arrives, only the one expected echo is swallowed.
+        let later = now + Duration::from_secs(3600);
+        assert!(responders.evaluate(&echo, later).is_empty());
+        assert_eq!(responders.evaluate(&echo, later).len(), 1);
+        assert!(responders.evaluate(&echo, later).is_empty());
+    }
+
+    #[test]
+    fn test_responses_no_rule_listens_to_are_not_tracked() {
+        let mut responders = Responders::new(&[create_rule()], &HashMap::new());
+        let now = Instant::now();
+        let responses = responders.evaluate(&message("devices/a/cmd", "ping"), now);
+        assert_eq!(responses.len(), 1);
+        assert!(responders.echoes.is_empty());
+    }
+
+    #[test]
+    fn test_update_keeps_rate_limits() {
+        let rule = ResponderRule {
+            min_interval_ms: Some(1000),
+            ..create_rule()
+        };
+        let mut responders = Responders::new(std::slice::from_ref(&rule), &HashMap::new());
+        let now = Instant::now();
+        let msg = message("devices/a/cmd", "ping");
+        assert_eq!(responders.evaluate(&msg, now).len(), 1);
+
+        responders.update(std::slice::from_ref(&rule), &HashMap::new());
+        assert!(responders.evaluate(&msg, now).is_empty());
+
+        let renamed = ResponderRule {
+            id: "r2".to_string(),
+            ..rule
+        };
+        responders.update(&[renamed], &HashMap::new());
+        assert_eq!(responders.evaluate(&msg, now).len(), 1);
+    }
+}
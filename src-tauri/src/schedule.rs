@@ -0,0 +1,438 @@
+use crate::mqtt::MqttClient;
+use crate::template::substitute_variables;
+use crate::types::{AppData, Connection, Schedule, ScheduleTimezone};
+use chrono::{DateTime, Local, Utc};
+use croner::Cron;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+pub const SCHEDULE_EVENT: &str = "schedule-run";
+const MAX_LOG_ENTRIES: usize = 200;
+const MAX_PREVIEW: usize = 100;
+/// Upper bound on how long the loop sleeps, so edits are picked up quickly.
+const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleRun {
+    pub schedule_id: String,
+    pub schedule_name: String,
+    pub timestamp: u64,
+    pub topic: Option<String>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+pub fn parse_cron(expression: &str) -> Result<Cron, String> {
+    Cron::new(expression)
+        .with_seconds_optional()
+        .parse()
+        .map_err(|e| format!("Invalid cron expression '{}': {}", expression, e))
+}
+
+pub fn next_occurrence(
+    cron: &Cron,
+    timezone: ScheduleTimezone,
+    after: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    match timezone {
+        ScheduleTimezone::Utc => cron.find_next_occurrence(&after, false).ok(),
+        ScheduleTimezone::Local => cron
+            .find_next_occurrence(&after.with_timezone(&Local), false)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+    }
+}
+
+/// Capped at `MAX_PREVIEW` fire times.
+pub fn upcoming(
+    expression: &str,
+    timezone: ScheduleTimezone,
+    count: usize,
+) -> Result<Vec<DateTime<Utc>>, String> {
+    let cron = parse_cron(expression)?;
+    let count = count.min(MAX_PREVIEW);
+    let mut times = Vec::with_capacity(count);
+    let mut after = Utc::now();
+    while times.len() < count {
+        match next_occurrence(&cron, timezone, after) {
+            Some(next) => {
+                times.push(next);
+                after = next;
+            }
+            None => break,
+        }
+    }
+    Ok(times)
+}
+
+struct Entry {
+    schedule: Schedule,
+    cron: Cron,
+    next: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: Vec<Entry>,
+    connections: Vec<Connection>,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    state: RwLock<State>,
+    log: RwLock<VecDeque<ScheduleRun>>,
+}
+
+impl Scheduler {
+    /// Schedules whose expression did not change keep their next fire time.
+    pub async fn update(&self, data: &AppData) {
+        let mut state = self.state.write().await;
+        let mut previous: HashMap<String, Entry> = state
+            .entries
+            .drain(..)
+            .map(|e| (e.schedule.id.clone(), e))
+            .collect();
+
+        let now = Utc::now();
+        for schedule in data.schedules.iter().filter(|s| s.enabled) {
+            if let Some(entry) = previous.remove(&schedule.id) {
+                if entry.schedule.cron == schedule.cron
+                    && entry.schedule.timezone == schedule.timezone
+                {
+                    state.entries.push(Entry {
+                        schedule: schedule.clone(),
+                        ..entry
+                    });
+                    continue;
+                }
+            }
+            match parse_cron(&schedule.cron) {
+                Ok(cron) => {
+                    let next = next_occurrence(&cron, schedule.timezone, now);
+                    state.entries.push(Entry {
+                        schedule: schedule.clone(),
+                        cron,
+                        next,
+                    });
+                }
+                Err(e) => warn!("Skipping schedule '{}': {}", schedule.name, e),
+            }
+        }
+        state.connections = data.connections.clone();
+    }
+
+    /// A schedule that was missed several times (e.g. while the machine slept)
+    /// fires only once.
+    async fn take_due(&self, now: DateTime<Utc>) -> Vec<(Schedule, Option<Connection>)> {
+        let mut state = self.state.write().await;
+        let mut due = Vec::new();
+        for entry in &mut state.entries {
+            if entry.next.is_some_and(|next| next <= now) {
+                entry.next = next_occurrence(&entry.cron, entry.schedule.timezone, now);
+                due.push(entry.schedule.clone());
+            }
+        }
+        due.into_iter()
+            .map(|schedule| {
+                let connection = state
+                    .connections
+                    .iter()
+                    .find(|c| c.id == schedule.connection_id)
+                    .cloned();
+                (schedule, connection)
+            })
+            .collect()
+    }
+
+    async fn next_due(&self) -> Option<DateTime<Utc>> {
+        let state = self.state.read().await;
+        state.entries.iter().filter_map(|e| e.next).min()
+    }
+
+    pub async fn log(&self) -> Vec<ScheduleRun> {
+        self.log.read().await.iter().rev().cloned().collect()
+    }
+
+    async fn record(&self, run: ScheduleRun) {
+        if run.success {
+            info!(
+                "Schedule '{}' published to '{}'",
+                run.schedule_name,
+                run.topic.as_deref().unwrap_or_default()
+            );
+        } else {
+            warn!(
+                "Schedule '{}' failed: {}",
+                run.schedule_name,
+                run.error.as_deref().unwrap_or_default()
+            );
+        }
+        let mut log = self.log.write().await;
+        if log.len() >= MAX_LOG_ENTRIES {
+            log.pop_front();
+        }
+        log.push_back(run);
+    }
+
+    pub async fn run<F>(self: Arc<Self>, client: Arc<RwLock<MqttClient>>, on_run: F)
+    where
+        F: Fn(ScheduleRun) + Send + Sync + 'static,
+    {
+        loop {
+            for (schedule, connection) in self.take_due(Utc::now()).await {
+                let run = fire(&client, &schedule, connection.as_ref()).await;
+                on_run(run.clone());
+                self.record(run).await;
+            }
+
+            let sleep = match self.next_due().await {
+                Some(next) => (next - Utc::now())
+                    .to_std()
+                    .unwrap_or(Duration::ZERO)
+                    .min(MAX_SLEEP),
+                None => MAX_SLEEP,
+            };
+            tokio::time::sleep(sleep).await;
+        }
+    }
+}
+
+/// The schedule only fires while its connection is the active one.
+async fn fire(
+    client: &RwLock<MqttClient>,
+    schedule: &Schedule,
+    connection: Option<&Connection>,
+) -> ScheduleRun {
+    let mut run = ScheduleRun {
+        schedule_id: schedule.id.clone(),
+        schedule_name: schedule.name.clone(),
+        timestamp: Utc::now().timestamp_millis() as u64,
+        topic: None,
+        success: false,
+        error: None,
+    };
+
+    let Some(connection) = connection else {
+        run.error = Some(format!("Connection not found: {}", schedule.connection_id));
+        return run;
+    };
+    let Some(button) = connection
+        .buttons
+        .iter()
+        .find(|b| b.id == schedule.button_id)
+    else {
+        run.error = Some(format!("Button not found: {}", schedule.button_id));
+        return run;
+    };
+
+    let client = client.read().await;
+    if client.connection_id() != Some(connection.id.as_str()) {
+        run.error = Some(format!("Not connected to '{}'", connection.name));
+        return run;
+    }
+
+    let topic = substitute_variables(&button.topic, &connection.variables);
+    let payload = substitute_variables(
+        button.payload.as_deref().unwrap_or(""),
+        &connection.variables,
+    );
+    run.topic = Some(topic.clone());
+    match client
+        .publish_button_payload(button, &topic, &payload)
+        .await
+    {
+        Ok(()) => run.success = true,
+        Err(e) => run.error = Some(e.to_string()),
+    }
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Button;
+    use chrono::{Datelike, TimeZone, Timelike, Weekday};
+
+    fn create_schedule(id: &str, cron: &str) -> Schedule {
+        Schedule {
+            id: id.to_string(),
+            name: format!("Schedule {}", id),
+            connection_id: "c1".to_string(),
+            button_id: "b1".to_string(),
+            cron: cron.to_string(),
+            timezone: ScheduleTimezone::Utc,
+            enabled: true,
+        }
+    }
+
+    fn create_data(schedules: Vec<Schedule>) -> AppData {
+        AppData {
+            connections: vec![Connection {
+                id: "c1".to_string(),
+                name: "Lab".to_string(),
+                broker_url: "localhost".to_string(),
+                client_id: "client".to_string(),
+                auto_connect: false,
+                buttons: vec![Button {
+                    id: "b1".to_string(),
+                    name: "Heartbeat".to_string(),
+                    topic: "devices/heartbeat".to_string(),
+                    payload: Some("{now:unix}".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            last_connection_id: None,
+            schedules,
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_cron() {
+        assert!(parse_cron("*/15 * * * *").is_ok());
+        assert!(parse_cron("0 8 * * 1-5").is_ok());
+        assert!(parse_cron("30 0 8 * * MON-FRI").is_ok());
+        assert!(parse_cron("").is_err());
+        assert!(parse_cron("61 * * * *").is_err());
+        assert!(parse_cron("not a cron").is_err());
+    }
+
+    #[test]
+    fn test_next_occurrence_weekdays() {
+        let cron = parse_cron("0 8 * * 1-5").unwrap();
+        // Friday 2024-03-08 09:00 UTC -> Monday 2024-03-11 08:00 UTC.
+        let friday = Utc.with_ymd_and_hms(2024, 3, 8, 9, 0, 0).unwrap();
+        let next = next_occurrence(&cron, ScheduleTimezone::Utc, friday).unwrap();
+        assert_eq!(next.weekday(), Weekday::Mon);
+        assert_eq!((next.day(), next.hour(), next.minute()), (11, 8, 0));
+    }
+
+    #[test]
+    fn test_next_occurrence_is_strictly_after() {
+        let cron = parse_cron("*/15 * * * *").unwrap();
+        let at = Utc.with_ymd_and_hms(2024, 3, 8, 9, 15, 0).unwrap();
+        let next = next_occurrence(&cron, ScheduleTimezone::Utc, at).unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 3, 8, 9, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn test_upcoming() {
+        let times = upcoming("0 * * * *", ScheduleTimezone::Local, 3).unwrap();
+        assert_eq!(times.len(), 3);
+        assert_eq!(times[1] - times[0], chrono::Duration::hours(1));
+        assert!(upcoming("bad", ScheduleTimezone::Utc, 3).is_err());
+        let capped = upcoming("* * * * *", ScheduleTimezone::Utc, usize::MAX).unwrap();
+        assert_eq!(capped.len(), MAX_PREVIEW);
+    }
+
+    #[test]
+    fn test_update_skips_disabled_and_invalid() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let scheduler = Scheduler::default();
+            let mut disabled = create_schedule("s2", "* * * * *");
+            disabled.enabled = false;
+            scheduler
+                .update(&create_data(vec![
+                    create_schedule("s1", "* * * * *"),
+                    disabled,
+                    create_schedule("s3", "bogus"),
+                ]))
+                .await;
+            let state = scheduler.state.read().await;
+            assert_eq!(state.entries.len(), 1);
+            assert_eq!(state.entries[0].schedule.id, "s1");
+        });
+    }
+
+    #[test]
+    fn test_take_due_fires_once_and_advances() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let scheduler = Scheduler::default();
+            scheduler
+                .update(&create_data(vec![create_schedule("s1", "* * * * *")]))
+                .await;
+            let next = scheduler.next_due().await.unwrap();
+
+            assert!(scheduler
+                .take_due(next - chrono::Duration::seconds(1))
+                .await
+                .is_empty());
+            // Even an hour late, the schedule fires once.
+            let due = scheduler.take_due(next + chrono::Duration::hours(1)).await;
+            assert_eq!(due.len(), 1);
+            assert_eq!(due[0].1.as_ref().unwrap().id, "c1");
+            assert!(scheduler.next_due().await.unwrap() > next + chrono::Duration::hours(1));
+        });
+    }
+
+    #[test]
+    fn test_update_keeps_next_fire_time() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let scheduler = Scheduler::default();
+            let data = create_data(vec![create_schedule("s1", "* * * * *")]);
+            scheduler.update(&data).await;
+            scheduler.state.write().await.entries[0].next = None;
+
+            let mut renamed = data.clone();
+            renamed.schedules[0].name = "Renamed".to_string();
+            scheduler.update(&renamed).await;
+            assert!(scheduler.next_due().await.is_none());
+
+            let mut changed = data.clone();
+            changed.schedules[0].cron = "0 * * * *".to_string();
+            scheduler.update(&changed).await;
+            assert!(scheduler.next_due().await.is_some());
+        });
+    }
+
+    #[test]
+    fn test_fire_requires_active_connection() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = RwLock::new(MqttClient::new());
+            let data = create_data(vec![]);
+            let schedule = create_schedule("s1", "* * * * *");
+
+            let run = fire(&client, &schedule, data.connections.first()).await;
+            assert!(!run.success);
+            assert_eq!(run.error.as_deref(), Some("Not connected to 'Lab'"));
+
+            let run = fire(&client, &schedule, None).await;
+            assert_eq!(run.error.as_deref(), Some("Connection not found: c1"));
+        });
+    }
+
+    #[test]
+    fn test_log_is_bounded() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let scheduler = Scheduler::default();
+            for i in 0..MAX_LOG_ENTRIES + 5 {
+                scheduler
+                    .record(ScheduleRun {
+                        schedule_id: "s1".to_string(),
+                        schedule_name: "Heartbeat".to_string(),
+                        timestamp: i as u64,
+                        topic: None,
+                        success: true,
+                        error: None,
+                    })
+                    .await;
+            }
+            let log = scheduler.log().await;
+            assert_eq!(log.len(), MAX_LOG_ENTRIES);
+            assert_eq!(log[0].timestamp, (MAX_LOG_ENTRIES + 4) as u64);
+        });
+    }
+}
@@ -0,0 +1,228 @@
+use crate::decode::Decoded;
+use crate::topic::{matches_filter, validate_topic_filter};
+use crate::types::{JsonSchema, SchemaRule};
+use jsonschema::Validator;
+use log::warn;
+use serde_json::Value;
+use std::collections::HashMap;
+use thiserror::Error;
+
+const MAX_VIOLATIONS: usize = 20;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SchemaError {
+    #[error("Schema not found: {0}")]
+    NotFound(String),
+    #[error("Invalid schema '{0}': {1}")]
+    Invalid(String, String),
+    #[error("Payload is not JSON: {0}")]
+    NotJson(String),
+    #[error("Payload does not match schema '{0}': {}", .1.join("; "))]
+    Violations(String, Vec<String>),
+}
+
+struct Compiled {
+    name: String,
+    validator: Result<Validator, String>,
+}
+
+#[derive(Default)]
+pub struct Schemas {
+    schemas: HashMap<String, Compiled>,
+    rules: Vec<SchemaRule>,
+}
+
+pub fn compile(schema: &Value) -> Result<Validator, String> {
+    jsonschema::validator_for(schema).map_err(|e| e.to_string())
+}
+
+impl Schemas {
+    pub fn set_schemas(&mut self, schemas: &[JsonSchema]) {
+        self.schemas = schemas
+            .iter()
+            .map(|schema| {
+                let validator = compile(&schema.schema);
+                if let Err(e) = &validator {
+                    warn!("Invalid schema '{}': {}", schema.name, e);
+                }
+                let compiled = Compiled {
+                    name: schema.name.clone(),
+                    validator,
+                };
+                (schema.id.clone(), compiled)
+            })
+            .collect();
+    }
+
+    pub fn set_rules(&mut self, rules: &[SchemaRule]) {
+        self.rules = rules
+            .iter()
+            .filter(|rule| match validate_topic_filter(&rule.topic_filter) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Skipping schema rule for '{}': {}", rule.topic_filter, e);
+                    false
+                }
+            })
+            .cloned()
+            .collect();
+    }
+
+    pub fn validate(&self, schema_id: &str, value: &Value) -> Result<(), SchemaError> {
+        let compiled = self
+            .schemas
+            .get(schema_id)
+            .ok_or_else(|| SchemaError::NotFound(schema_id.to_string()))?;
+        let validator = compiled
+            .validator
+            .as_ref()
+            .map_err(|e| SchemaError::Invalid(compiled.name.clone(), e.clone()))?;
+        let mut violations: Vec<String> = validator
+            .iter_errors(value)
+            .map(|e| {
+                let path = e.instance_path.to_string();
+                if path.is_empty() {
+                    e.to_string()
+                } else {
+                    format!("{}: {}", path, e)
+                }
+            })
+            .collect();
+        if violations.is_empty() {
+            return Ok(());
+        }
+        if violations.len() > MAX_VIOLATIONS {
+            let more = violations.len() - MAX_VIOLATIONS;
+            violations.truncate(MAX_VIOLATIONS);
+            violations.push(format!("{} more", more));
+        }
+        Err(SchemaError::Violations(compiled.name.clone(), violations))
+    }
+
+    pub fn validate_payload(&self, schema_id: &str, payload: &str) -> Result<(), SchemaError> {
+        let value: Value =
+            serde_json::from_str(payload).map_err(|e| SchemaError::NotJson(e.to_string()))?;
+        self.validate(schema_id, &value)
+    }
+
+    /// Structured decoded values, such as protobuf messages, are validated
+    /// instead of the payload text.
+    pub fn check_message(
+        &self,
+        topic: &str,
+        payload: &str,
+        decoded: Option<&Decoded>,
+    ) -> Vec<String> {
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| matches_filter(&rule.topic_filter, topic))
+        else {
+            return Vec::new();
+        };
+        let result = match decoded.and_then(|d| d.value.as_ref()) {
+            Some(value) => self.validate(&rule.schema_id, value),
+            None => self.validate_payload(&rule.schema_id, payload),
+        };
+        match result {
+            Ok(()) => Vec::new(),
+            Err(SchemaError::Violations(_, violations)) => violations,
+            Err(e) => vec![e.to_string()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DecoderKind;
+    use serde_json::json;
+
+    fn schemas() -> Schemas {
+        let mut schemas = Schemas::default();
+        schemas.set_schemas(&[
+            JsonSchema {
+                id: "cmd".to_string(),
+                name: "Command".to_string(),
+                schema: json!({
+                    "type": "object",
+                    "required": ["action"],
+                    "properties": {
+                        "action": {"enum": ["on", "off"]},
+                        "level": {"type": "integer", "minimum": 0, "maximum": 100}
+                    }
+                }),
+            },
+            JsonSchema {
+                id: "broken".to_string(),
+                name: "Broken".to_string(),
+                schema: json!({"type": "nonsense"}),
+            },
+        ]);
+        schemas.set_rules(&[
+            SchemaRule {
+                topic_filter: "devices/+/cmd".to_string(),
+                schema_id: "cmd".to_string(),
+            },
+            SchemaRule {
+                topic_filter: "devices/#/bad".to_string(),
+                schema_id: "cmd".to_string(),
+            },
+        ]);
+        schemas
+    }
+
+    #[test]
+    fn test_validate_payload() {
+        let schemas = schemas();
+        assert_eq!(
+            schemas.validate_payload("cmd", r#"{"action":"on","level":5}"#),
+            Ok(())
+        );
+        let Err(SchemaError::Violations(name, violations)) =
+            schemas.validate_payload("cmd", r#"{"action":"dim","level":500}"#)
+        else {
+            panic!("expected violations");
+        };
+        assert_eq!(name, "Command");
+        assert_eq!(violations.len(), 2);
+        assert!(violations.iter().any(|v| v.starts_with("/action: ")));
+        assert!(violations.iter().any(|v| v.starts_with("/level: ")));
+
+        assert!(matches!(
+            schemas.validate_payload("cmd", "on"),
+            Err(SchemaError::NotJson(_))
+        ));
+        assert!(matches!(
+            schemas.validate_payload("missing", "{}"),
+            Err(SchemaError::NotFound(_))
+        ));
+        assert!(matches!(
+            schemas.validate_payload("broken", "{}"),
+            Err(SchemaError::Invalid(..))
+        ));
+    }
+
+    #[test]
+    fn test_check_message() {
+        let schemas = schemas();
+        assert!(schemas
+            .check_message("devices/d1/cmd", r#"{"action":"off"}"#, None)
+            .is_empty());
+        assert_eq!(schemas.check_message("devices/d1/cmd", "{}", None).len(), 1);
+        assert!(schemas
+            .check_message("devices/d1/status", "{}", None)
+            .is_empty());
+
+        let decoded = Decoded {
+            format: DecoderKind::Protobuf,
+            value: Some(json!({"action": "on"})),
+            text: String::new(),
+            error: None,
+            compression: None,
+        };
+        assert!(schemas
+            .check_message("devices/d1/cmd", "\u{FFFD}", Some(&decoded))
+            .is_empty());
+    }
+}
@@ -0,0 +1,663 @@
+use crate::mqtt::{next_message, Message, MqttClient, MqttError};
+use crate::template::substitute_variables;
+use crate::topic::matches_filter;
+use crate::types::{Connection, QoS, Script};
+use log::{info, warn};
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map, AST};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::runtime::Handle;
+use tokio::sync::{broadcast, watch, Mutex, RwLock};
+
+pub const SCRIPT_EVENT: &str = "script-output";
+const DEFAULT_TIMEOUT_MS: u64 = 60_000;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Error, Debug)]
+pub enum ScriptError {
+    #[error("Script not found: {0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Compile(String),
+    #[error("{0}")]
+    Runtime(String),
+    #[error("Script timed out after {0} ms")]
+    Timeout(u64),
+    #[error("Script cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputKind {
+    Started,
+    Log,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptOutput {
+    pub run_id: String,
+    pub script_id: String,
+    pub kind: OutputKind,
+    pub message: Option<String>,
+}
+
+#[derive(Default)]
+pub struct ScriptRunner {
+    runs: Arc<Mutex<HashMap<String, watch::Sender<bool>>>>,
+}
+
+impl ScriptRunner {
+    pub async fn start<F>(
+        &self,
+        client: Arc<RwLock<MqttClient>>,
+        connection: Connection,
+        script_id: &str,
+        on_output: F,
+    ) -> Result<String, ScriptError>
+    where
+        F: Fn(ScriptOutput) + Send + Sync + 'static,
+    {
+        let script = connection
+            .scripts
+            .iter()
+            .find(|s| s.id == script_id)
+            .cloned()
+            .ok_or_else(|| ScriptError::NotFound(script_id.to_string()))?;
+
+        let run_id = uuid::Uuid::new_v4().to_string();
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        self.runs.lock().await.insert(run_id.clone(), cancel_tx);
+
+        let runs = Arc::clone(&self.runs);
+        let variables = connection.variables;
+        let id = run_id.clone();
+        tokio::spawn(async move {
+            info!("Running script '{}' ({})", script.name, id);
+            if let Err(e) = run(
+                client,
+                variables,
+                &script,
+                &id,
+                cancel_rx,
+                Arc::new(on_output),
+            )
+            .await
+            {
+                warn!("Script '{}' stopped: {}", script.name, e);
+            }
+            runs.lock().await.remove(&id);
+        });
+
+        Ok(run_id)
+    }
+
+    pub async fn cancel(&self, run_id: &str) -> bool {
+        match self.runs.lock().await.get(run_id) {
+            Some(tx) => {
+                let _ = tx.send(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn active_runs(&self) -> Vec<String> {
+        self.runs.lock().await.keys().cloned().collect()
+    }
+}
+
+pub fn check(source: &str) -> Result<(), ScriptError> {
+    Engine::new()
+        .compile(source)
+        .map(|_| ())
+        .map_err(|e| ScriptError::Compile(e.to_string()))
+}
+
+pub async fn run<F>(
+    client: Arc<RwLock<MqttClient>>,
+    variables: HashMap<String, String>,
+    script: &Script,
+    run_id: &str,
+    cancel: watch::Receiver<bool>,
+    on_output: Arc<F>,
+) -> Result<(), ScriptError>
+where
+    F: Fn(ScriptOutput) + Send + Sync + 'static,
+{
+    let output = {
+        let on_output = Arc::clone(&on_output);
+        let run_id = run_id.to_string();
+        let script_id = script.id.clone();
+        move |kind, message| {
+            on_output(ScriptOutput {
+                run_id: run_id.clone(),
+                script_id: script_id.clone(),
+                kind,
+                message,
+            })
+        }
+    };
+
+    output(OutputKind::Started, None);
+    let timeout_ms = script.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+    // Listen from the start so messages arriving while the script body runs
+    // still reach its handlers.
+    let messages = client.read().await.subscribe_messages();
+    let context = Context {
+        client,
+        messages,
+        runtime: Handle::current(),
+        cancel,
+        deadline: (timeout_ms > 0).then(|| Instant::now() + Duration::from_millis(timeout_ms)),
+        timeout_ms,
+        variables,
+    };
+    let source = script.source.clone();
+    let log = output.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        execute(context, &source, move |message| {
+            log(OutputKind::Log, Some(message))
+        })
+    })
+    .await
+    .unwrap_or_else(|e| Err(ScriptError::Runtime(e.to_string())));
+
+    match result {
+        Ok(()) => output(OutputKind::Completed, None),
+        Err(ScriptError::Cancelled) => output(OutputKind::Cancelled, None),
+        Err(ref e) => output(OutputKind::Failed, Some(e.to_string())),
+    }
+    result
+}
+
+struct Context {
+    client: Arc<RwLock<MqttClient>>,
+    runtime: Handle,
+    messages: broadcast::Receiver<Message>,
+    cancel: watch::Receiver<bool>,
+    deadline: Option<Instant>,
+    timeout_ms: u64,
+    variables: HashMap<String, String>,
+}
+
+impl Context {
+    fn stop_reason(&self) -> Option<ScriptError> {
+        if *self.cancel.borrow() {
+            Some(ScriptError::Cancelled)
+        } else if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            Some(ScriptError::Timeout(self.timeout_ms))
+        } else {
+            None
+        }
+    }
+
+    fn check_stop(&self) -> Result<(), Box<EvalAltResult>> {
+        match self.stop_reason() {
+            Some(e) => Err(e.to_string().into()),
+            None => Ok(()),
+        }
+    }
+
+    fn mqtt<T>(
+        &self,
+        call: impl AsyncFnOnce(&MqttClient) -> Result<T, MqttError>,
+    ) -> Result<T, Box<EvalAltResult>> {
+        self.runtime
+            .block_on(async { call(&*self.client.read().await).await })
+            .map_err(|e| e.to_string().into())
+    }
+
+    /// Checks for stop requests while waiting.
+    fn wait_for(
+        &self,
+        filter: &str,
+        timeout: Duration,
+    ) -> Result<Option<Message>, Box<EvalAltResult>> {
+        let mut messages = self
+            .runtime
+            .block_on(async { self.client.read().await.subscribe_messages() });
+        let subscribed =
+            self.mqtt(async |c| c.subscribe_if_needed(filter, QoS::AtMostOnce).await)?;
+        let until = Instant::now() + timeout;
+        let result = loop {
+            if let Err(e) = self.check_stop() {
+                break Err(e);
+            }
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break Ok(None);
+            }
+            let received = self.runtime.block_on(tokio::time::timeout(
+                remaining.min(POLL_INTERVAL),
+                next_message(&mut messages, |m| matches_filter(filter, &m.topic)),
+            ));
+            match received {
+                Ok(Ok(msg)) => break Ok(Some(msg)),
+                Ok(Err(e)) => break Err(e.to_string().into()),
+                Err(_) => {}
+            }
+        };
+        if subscribed {
+            let _ = self.mqtt(async |c| c.unsubscribe(filter).await);
+        }
+        result
+    }
+}
+
+fn qos_from_int(qos: i64) -> Result<QoS, Box<EvalAltResult>> {
+    match qos {
+        0 => Ok(QoS::AtMostOnce),
+        1 => Ok(QoS::AtLeastOnce),
+        2 => Ok(QoS::ExactlyOnce),
+        other => Err(format!("Invalid QoS {}", other).into()),
+    }
+}
+
+fn execute(
+    context: Context,
+    source: &str,
+    log: impl Fn(String) + Clone + 'static,
+) -> Result<(), ScriptError> {
+    let context = Rc::new(RefCell::new(context));
+    let handlers: Rc<RefCell<Vec<(String, FnPtr)>>> = Rc::default();
+    let added: Rc<RefCell<Vec<String>>> = Rc::default();
+    let engine = build_engine(&context, &handlers, &added, log);
+
+    let result = engine
+        .compile(source)
+        .map_err(|e| ScriptError::Compile(e.to_string()))
+        .and_then(|ast| {
+            engine
+                .run_ast(&ast)
+                .and_then(|()| dispatch(&engine, &ast, &context, &handlers))
+                .map_err(|e| {
+                    context
+                        .borrow()
+                        .stop_reason()
+                        .unwrap_or_else(|| ScriptError::Runtime(e.to_string()))
+                })
+        });
+
+    let context = context.borrow();
+    for filter in added.borrow().iter() {
+        if let Err(e) = context.mqtt(async |c| c.unsubscribe(filter).await) {
+            warn!("Failed to unsubscribe from '{}': {}", filter, e);
+        }
+    }
+    result
+}
+
+fn dispatch(
+    engine: &Engine,
+    ast: &AST,
+    context: &RefCell<Context>,
+    handlers: &RefCell<Vec<(String, FnPtr)>>,
+) -> Result<(), Box<EvalAltResult>> {
+    while !handlers.borrow().is_empty() {
+        let received = {
+            let mut context = context.borrow_mut();
+            context.check_stop()?;
+            let Context {
+                runtime, messages, ..
+            } = &mut *context;
+            runtime.block_on(tokio::time::timeout(
+                POLL_INTERVAL,
+                next_message(messages, |_| true),
+            ))
+        };
+        let msg = match received {
+            Ok(Ok(msg)) => msg,
+            Ok(Err(e)) => return Err(e.to_string().into()),
+            Err(_) => continue,
+        };
+        let matching: Vec<FnPtr> = handlers
+            .borrow()
+            .iter()
+            .filter(|(filter, _)| matches_filter(filter, &msg.topic))
+            .map(|(_, handler)| handler.clone())
+            .collect();
+        for handler in matching {
+            let _: Dynamic = handler.call(engine, ast, (msg.topic.clone(), msg.payload.clone()))?;
+        }
+    }
+    Ok(())
+}
+
+fn build_engine(
+    context: &Rc<RefCell<Context>>,
+    handlers: &Rc<RefCell<Vec<(String, FnPtr)>>>,
+    added: &Rc<RefCell<Vec<String>>>,
+    log: impl Fn(String) + Clone + 'static,
+) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_call_levels(64)
+        .set_max_string_size(1 << 20)
+        .set_max_array_size(100_000)
+        .set_max_map_size(100_000);
+
+    let ctx = Rc::clone(context);
+    engine.on_progress(move |_| {
+        ctx.borrow()
+            .stop_reason()
+            .map(|e| Dynamic::from(e.to_string()))
+    });
+    let print = log.clone();
+    engine.on_print(move |message| print(message.to_string()));
+    let print = log.clone();
+    engine.on_debug(move |message, _, _| print(message.to_string()));
+    engine.register_fn("log", move |message: &str| log(message.to_string()));
+
+    let ctx = Rc::clone(context);
+    engine.register_fn(
+        "publish",
+        move |topic: &str, payload: &str| -> Result<(), Box<EvalAltResult>> {
+            ctx.borrow()
+                .mqtt(async |c| c.publish(topic, payload, QoS::AtMostOnce, false).await)
+        },
+    );
+    let ctx = Rc::clone(context);
+    engine.register_fn(
+        "publish",
+        move |topic: &str,
+              payload: &str,
+              qos: i64,
+              retain: bool|
+              -> Result<(), Box<EvalAltResult>> {
+            let qos = qos_from_int(qos)?;
+            ctx.borrow()
+                .mqtt(async |c| c.publish(topic, payload, qos, retain).await)
+        },
+    );
+
+    let ctx = Rc::clone(context);
+    let subscribed = Rc::clone(added);
+    engine.register_fn(
+        "subscribe",
+        move |filter: &str| -> Result<(), Box<EvalAltResult>> {
+            if ctx
+                .borrow()
+                .mqtt(async |c| c.subscribe_if_needed(filter, QoS::AtMostOnce).await)?
+            {
+                subscribed.borrow_mut().push(filter.to_string());
+            }
+            Ok(())
+        },
+    );
+    let ctx = Rc::clone(context);
+    let subscribed = Rc::clone(added);
+    let registered = Rc::clone(handlers);
+    engine.register_fn(
+        "on_message",
+        move |filter: &str, handler: FnPtr| -> Result<(), Box<EvalAltResult>> {
+            if ctx
+                .borrow()
+                .mqtt(async |c| c.subscribe_if_needed(filter, QoS::AtMostOnce).await)?
+            {
+                subscribed.borrow_mut().push(filter.to_string());
+            }
+            registered.borrow_mut().push((filter.to_string(), handler));
+            Ok(())
+        },
+    );
+    let ctx = Rc::clone(context);
+    engine.register_fn(
+        "wait_for",
+        move |filter: &str, timeout_ms: i64| -> Result<Dynamic, Box<EvalAltResult>> {
+            let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+            Ok(match ctx.borrow().wait_for(filter, timeout)? {
+                Some(msg) => {
+                    let mut map = Map::new();
+                    map.insert("topic".into(), msg.topic.into());
+                    map.insert("payload".into(), msg.payload.into());
+                    map.into()
+                }
+                None => Dynamic::UNIT,
+            })
+        },
+    );
+
+    let ctx = Rc::clone(context);
+    engine.register_fn("get_var", move |name: &str| -> Dynamic {
+        ctx.borrow()
+            .variables
+            .get(name)
+            .map_or(Dynamic::UNIT, |value| value.clone().into())
+    });
+    let ctx = Rc::clone(context);
+    engine.register_fn("set_var", move |name: &str, value: Dynamic| {
+        ctx.borrow_mut()
+            .variables
+            .insert(name.to_string(), value.to_string());
+    });
+    let ctx = Rc::clone(context);
+    engine.register_fn("render", move |template: &str| -> String {
+        substitute_variables(template, &ctx.borrow().variables)
+    });
+
+    let ctx = Rc::clone(context);
+    engine.register_fn("sleep", move |ms: i64| -> Result<(), Box<EvalAltResult>> {
+        let until = Instant::now() + Duration::from_millis(ms.max(0) as u64);
+        loop {
+            ctx.borrow().check_stop()?;
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            std::thread::sleep(remaining.min(POLL_INTERVAL));
+        }
+    });
+
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    fn create_script(source: &str, timeout_ms: Option<u64>) -> Script {
+        Script {
+            id: "s1".to_string(),
+            name: "Test".to_string(),
+            source: source.to_string(),
+            timeout_ms,
+        }
+    }
+
+    type Outputs = Arc<StdMutex<Vec<ScriptOutput>>>;
+
+    fn recorder() -> (Outputs, Arc<impl Fn(ScriptOutput) + Send + Sync + 'static>) {
+        let outputs: Outputs = Arc::default();
+        let sink = Arc::clone(&outputs);
+        (
+            outputs,
+            Arc::new(move |output: ScriptOutput| sink.lock().unwrap().push(output)),
+        )
+    }
+
+    fn logs(outputs: &Outputs) -> Vec<String> {
+        outputs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|o| o.kind == OutputKind::Log)
+            .filter_map(|o| o.message.clone())
+            .collect()
+    }
+
+    async fn run_script(
+        client: Arc<RwLock<MqttClient>>,
+        script: &Script,
+        cancel: watch::Receiver<bool>,
+    ) -> (Result<(), ScriptError>, Outputs) {
+        let (outputs, on_output) = recorder();
+        let variables = HashMap::from([("device".to_string(), "abc".to_string())]);
+        let result = run(client, variables, script, "run", cancel, on_output).await;
+        (result, outputs)
+    }
+
+    #[test]
+    fn test_variables_and_logging() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let script = create_script(
+                r#"
+                    let sum = 0;
+                    for b in [1, 2, 3] { sum += b; }
+                    set_var("checksum", sum);
+                    log(render("devices/{device}/{checksum}"));
+                    print(get_var("missing") == ());
+                "#,
+                None,
+            );
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            let (result, outputs) = run_script(client, &script, watch::channel(false).1).await;
+            result.unwrap();
+            assert_eq!(logs(&outputs), vec!["devices/abc/6", "true"]);
+            let kinds: Vec<_> = outputs.lock().unwrap().iter().map(|o| o.kind).collect();
+            assert_eq!(kinds.first(), Some(&OutputKind::Started));
+            assert_eq!(kinds.last(), Some(&OutputKind::Completed));
+        });
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+
+            let script = create_script("let x = ;", None);
+            let (result, _) =
+                run_script(Arc::clone(&client), &script, watch::channel(false).1).await;
+            assert!(matches!(result, Err(ScriptError::Compile(_))));
+
+            let script = create_script(r#"publish("a/b", "1");"#, None);
+            let (result, outputs) = run_script(client, &script, watch::channel(false).1).await;
+            let Err(ScriptError::Runtime(message)) = result else {
+                panic!("expected a runtime error");
+            };
+            assert!(message.contains("Not connected"));
+            let last = outputs.lock().unwrap().last().cloned().unwrap();
+            assert_eq!(last.kind, OutputKind::Failed);
+            assert!(last.message.unwrap().contains("line 1"));
+        });
+    }
+
+    #[test]
+    fn test_timeout_stops_endless_loop() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let script = create_script("loop { }", Some(200));
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            let (result, _) = run_script(client, &script, watch::channel(false).1).await;
+            assert!(matches!(result, Err(ScriptError::Timeout(200))));
+        });
+    }
+
+    #[test]
+    fn test_message_handlers_until_cancelled() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            client
+                .read()
+                .await
+                .add_subscription("devices/+/status")
+                .await;
+            let script = create_script(
+                r#"
+                    set_var("count", 0);
+                    on_message("devices/+/status", |topic, payload| {
+                        let count = parse_int(get_var("count")) + 1;
+                        set_var("count", count);
+                        log(`${count} ${topic} ${payload}`);
+                    });
+                "#,
+                Some(0),
+            );
+            let (cancel_tx, cancel_rx) = watch::channel(false);
+            let task = tokio::spawn({
+                let client = Arc::clone(&client);
+                async move { run_script(client, &script, cancel_rx).await }
+            });
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            for (topic, payload) in [
+                ("devices/a/status", "on"),
+                ("devices/a/cmd", "ignored"),
+                ("devices/b/status", "off"),
+            ] {
+                client.read().await.inject_message(Message {
+                    topic: topic.to_string(),
+                    payload: payload.to_string(),
+                    ..Default::default()
+                });
+            }
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            cancel_tx.send(true).unwrap();
+
+            let (result, outputs) = task.await.unwrap();
+            assert!(matches!(result, Err(ScriptError::Cancelled)));
+            assert_eq!(
+                logs(&outputs),
+                vec!["1 devices/a/status on", "2 devices/b/status off"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_runner_cancel() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let runner = ScriptRunner::default();
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            let mut connection: Connection = serde_json::from_str(
+                r#"{"id":"c1","name":"Lab","broker_url":"localhost","port":1883,"client_id":"x"}"#,
+            )
+            .unwrap();
+            connection.scripts = vec![create_script("sleep(10000);", Some(0))];
+
+            assert!(matches!(
+                runner
+                    .start(Arc::clone(&client), connection.clone(), "nope", |_| {})
+                    .await,
+                Err(ScriptError::NotFound(_))
+            ));
+            let (outputs, on_output) = recorder();
+            let run_id = runner
+                .start(client, connection, "s1", move |o| on_output(o))
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(runner.active_runs().await, vec![run_id.clone()]);
+            assert!(runner.cancel(&run_id).await);
+
+            for _ in 0..50 {
+                if runner.active_runs().await.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(runner.active_runs().await.is_empty());
+            let last = outputs.lock().unwrap().last().cloned().unwrap();
+            assert_eq!(last.kind, OutputKind::Cancelled);
+        });
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check(r#"publish("a", "b");"#).is_ok());
+        assert!(matches!(check("fn ("), Err(ScriptError::Compile(_))));
+    }
+}
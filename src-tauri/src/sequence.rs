@@ -0,0 +1,580 @@
+use crate::mqtt::{next_message, MqttClient, MqttError};
+use crate::template::substitute_variables;
+use crate::topic::matches_filter;
+use crate::types::{Connection, QoS, Sequence, SequenceStep};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{watch, Mutex, RwLock};
+
+pub const PROGRESS_EVENT: &str = "sequence-progress";
+
+#[derive(Error, Debug)]
+pub enum SequenceError {
+    #[error("Sequence not found: {0}")]
+    NotFound(String),
+    #[error("Button not found: {0}")]
+    ButtonNotFound(String),
+    #[error("No message on '{0}' within {1} ms")]
+    Timeout(String, u64),
+    #[error("Sequence cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Mqtt(#[from] MqttError),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Started,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequenceProgress {
+    pub run_id: String,
+    pub sequence_id: String,
+    pub status: RunStatus,
+    pub step: Option<usize>,
+    pub total_steps: usize,
+    pub message: Option<String>,
+}
+
+#[derive(Default)]
+pub struct SequenceRunner {
+    runs: Arc<Mutex<HashMap<String, watch::Sender<bool>>>>,
+}
+
+impl SequenceRunner {
+    pub async fn start<F>(
+        &self,
+        client: Arc<RwLock<MqttClient>>,
+        connection: Connection,
+        sequence_id: &str,
+        on_progress: F,
+    ) -> Result<String, SequenceError>
+    where
+        F: Fn(SequenceProgress) + Send + Sync + 'static,
+    {
+        let sequence = connection
+            .sequences
+            .iter()
+            .find(|s| s.id == sequence_id)
+            .cloned()
+            .ok_or_else(|| SequenceError::NotFound(sequence_id.to_string()))?;
+
+        let run_id = uuid::Uuid::new_v4().to_string();
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        self.runs.lock().await.insert(run_id.clone(), cancel_tx);
+
+        let runs = Arc::clone(&self.runs);
+        let id = run_id.clone();
+        tokio::spawn(async move {
+            info!("Running sequence '{}' ({})", sequence.name, id);
+            if let Err(e) = run(
+                &client,
+                &connection,
+                &sequence,
+                &id,
+                cancel_rx,
+                &on_progress,
+            )
+            .await
+            {
+                warn!("Sequence '{}' stopped: {}", sequence.name, e);
+            }
+            runs.lock().await.remove(&id);
+        });
+
+        Ok(run_id)
+    }
+
+    pub async fn cancel(&self, run_id: &str) -> bool {
+        match self.runs.lock().await.get(run_id) {
+            Some(tx) => {
+                let _ = tx.send(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn active_runs(&self) -> Vec<String> {
+        self.runs.lock().await.keys().cloned().collect()
+    }
+}
+
+pub async fn run<F>(
+    client: &RwLock<MqttClient>,
+    connection: &Connection,
+    sequence: &Sequence,
+    run_id: &str,
+    mut cancel: watch::Receiver<bool>,
+    on_progress: &F,
+) -> Result<HashMap<String, String>, SequenceError>
+where
+    F: Fn(SequenceProgress),
+{
+    let progress = |status, step, message| {
+        on_progress(SequenceProgress {
+            run_id: run_id.to_string(),
+            sequence_id: sequence.id.clone(),
+            status,
+            step,
+            total_steps: sequence.steps.len(),
+            message,
+        })
+    };
+
+    progress(RunStatus::Started, None, None);
+    let mut variables = connection.variables.clone();
+
+    for (index, step) in sequence.steps.iter().enumerate() {
+        progress(
+            RunStatus::Running,
+            Some(index),
+            Some(describe_step(step, connection)),
+        );
+        if let Err(e) = execute_step(client, connection, step, &mut variables, &mut cancel).await {
+            let status = match e {
+                SequenceError::Cancelled => RunStatus::Cancelled,
+                _ => RunStatus::Failed,
+            };
+            progress(status, Some(index), Some(e.to_string()));
+            return Err(e);
+        }
+    }
+
+    progress(RunStatus::Completed, None, None);
+    Ok(variables)
+}
+
+async fn execute_step(
+    client: &RwLock<MqttClient>,
+    connection: &Connection,
+    step: &SequenceStep,
+    variables: &mut HashMap<String, String>,
+    cancel: &mut watch::Receiver<bool>,
+) -> Result<(), SequenceError> {
+    if *cancel.borrow() {
+        return Err(SequenceError::Cancelled);
+    }
+
+    match step {
+        SequenceStep::PublishButton { button_id } => {
+            let button = connection
+                .buttons
+                .iter()
+                .find(|b| &b.id == button_id)
+                .ok_or_else(|| SequenceError::ButtonNotFound(button_id.clone()))?;
+            client
+                .read()
+                .await
+                .publish_button(button, variables)
+                .await?;
+        }
+        SequenceStep::Publish {
+            topic,
+            payload,
+            qos,
+            retain,
+        } => {
+            let topic = substitute_variables(topic, variables);
+            let payload = substitute_variables(payload.as_deref().unwrap_or(""), variables);
+            client
+                .read()
+                .await
+                .publish(&topic, &payload, *qos, *retain)
+                .await?;
+        }
+        SequenceStep::Wait { duration_ms } => {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(*duration_ms)) => {}
+                _ = cancelled(cancel) => return Err(SequenceError::Cancelled),
+            }
+        }
+        SequenceStep::WaitForMessage {
+            topic_filter,
+            payload_contains,
+            timeout_ms,
+            store_as,
+            continue_on_timeout,
+        } => {
+            let filter = substitute_variables(topic_filter, variables);
+            let contains = payload_contains
+                .as_deref()
+                .map(|p| substitute_variables(p, variables));
+
+            // Listen before subscribing so retained messages are not missed.
+            let mut messages = client.read().await.subscribe_messages();
+            let subscribed = client
+                .read()
+                .await
+                .subscribe_if_needed(&filter, QoS::AtMostOnce)
+                .await?;
+
+            let result = tokio::select! {
+                result = tokio::time::timeout(
+                    Duration::from_millis(*timeout_ms),
+                    next_message(&mut messages, |m| {
+                        matches_filter(&filter, &m.topic)
+                            && contains.as_deref().is_none_or(|c| m.payload.contains(c))
+                    }),
+                ) => Some(result),
+                _ = cancelled(cancel) => None,
+            };
+
+            if subscribed {
+                if let Err(e) = client.read().await.unsubscribe(&filter).await {
+                    warn!("Failed to unsubscribe from '{}': {}", filter, e);
+                }
+            }
+
+            match result {
+                None => return Err(SequenceError::Cancelled),
+                Some(Ok(message)) => {
+                    let message = message?;
+                    if let Some(name) = store_as {
+                        variables.insert(name.clone(), message.payload);
+                    }
+                }
+                Some(Err(_)) if *continue_on_timeout => {}
+                Some(Err(_)) => return Err(SequenceError::Timeout(filter, *timeout_ms)),
+            }
+        }
+        SequenceStep::SetVariable { name, value } => {
+            let value = substitute_variables(value, variables);
+            variables.insert(name.clone(), value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves once cancellation is requested; never if the runner is gone.
+async fn cancelled(cancel: &mut watch::Receiver<bool>) {
+    if cancel.wait_for(|c| *c).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+fn describe_step(step: &SequenceStep, connection: &Connection) -> String {
+    match step {
+        SequenceStep::PublishButton { button_id } => {
+            let name = connection
+                .buttons
+                .iter()
+                .find(|b| &b.id == button_id)
+                .map_or(button_id.as_str(), |b| b.name.as_str());
+            format!("Publishing button '{}'", name)
+        }
+        SequenceStep::Publish { topic, .. } => format!("Publishing to '{}'", topic),
+        SequenceStep::Wait { duration_ms } => format!("Waiting {} ms", duration_ms),
+        SequenceStep::WaitForMessage { topic_filter, .. } => {
+            format!("Waiting for a message on '{}'", topic_filter)
+        }
+        SequenceStep::SetVariable { name, .. } => format!("Setting variable '{}'", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::Message;
+    use crate::types::Button;
+    use std::sync::Mutex as StdMutex;
+
+    fn create_connection(steps: Vec<SequenceStep>) -> Connection {
+        Connection {
+            id: "c1".to_string(),
+            name: "Lab".to_string(),
+            broker_url: "localhost".to_string(),
+            client_id: "client".to_string(),
+            auto_connect: false,
+            variables: HashMap::from([("device".to_string(), "abc".to_string())]),
+            buttons: vec![Button {
+                id: "b1".to_string(),
+                name: "On".to_string(),
+                topic: "devices/{device}/cmd".to_string(),
+                payload: Some("ON".to_string()),
+                ..Default::default()
+            }],
+            sequences: vec![Sequence {
+                id: "s1".to_string(),
+                name: "Bring up".to_string(),
+                steps,
+                color: None,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn recorder() -> (
+        Arc<StdMutex<Vec<SequenceProgress>>>,
+        impl Fn(SequenceProgress),
+    ) {
+        let events = Arc::new(StdMutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        (events, move |p| sink.lock().unwrap().push(p))
+    }
+
+    fn statuses(events: &StdMutex<Vec<SequenceProgress>>) -> Vec<RunStatus> {
+        events.lock().unwrap().iter().map(|p| p.status).collect()
+    }
+
+    #[test]
+    fn test_step_serialization() {
+        let json = r#"[
+            {"type": "publishButton", "buttonId": "b1"},
+            {"type": "wait", "durationMs": 500},
+            {"type": "waitForMessage", "topicFilter": "devices/+/status", "timeoutMs": 1000, "storeAs": "status"},
+            {"type": "setVariable", "name": "mode", "value": "on"}
+        ]"#;
+        let steps: Vec<SequenceStep> = serde_json::from_str(json).unwrap();
+        assert!(
+            matches!(steps[0], SequenceStep::PublishButton { ref button_id } if button_id == "b1")
+        );
+        assert!(matches!(
+            steps[2],
+            SequenceStep::WaitForMessage {
+                timeout_ms: 1000,
+                continue_on_timeout: false,
+                ..
+            }
+        ));
+
+        let value = serde_json::to_value(&steps[1]).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"type": "wait", "durationMs": 500})
+        );
+    }
+
+    #[test]
+    fn test_set_variable_and_wait() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let connection = create_connection(vec![
+                SequenceStep::SetVariable {
+                    name: "topic".to_string(),
+                    value: "devices/{device}".to_string(),
+                },
+                SequenceStep::Wait { duration_ms: 10 },
+            ]);
+            let client = RwLock::new(MqttClient::new());
+            let (events, on_progress) = recorder();
+            let (_tx, rx) = watch::channel(false);
+
+            let variables = run(
+                &client,
+                &connection,
+                &connection.sequences[0],
+                "r1",
+                rx,
+                &on_progress,
+            )
+            .await
+            .unwrap();
+            assert_eq!(variables.get("topic").unwrap(), "devices/abc");
+            assert_eq!(
+                statuses(&events),
+                vec![
+                    RunStatus::Started,
+                    RunStatus::Running,
+                    RunStatus::Running,
+                    RunStatus::Completed
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_publish_without_connection_fails() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let connection = create_connection(vec![SequenceStep::PublishButton {
+                button_id: "b1".to_string(),
+            }]);
+            let client = RwLock::new(MqttClient::new());
+            let (events, on_progress) = recorder();
+            let (_tx, rx) = watch::channel(false);
+
+            let result = run(
+                &client,
+                &connection,
+                &connection.sequences[0],
+                "r1",
+                rx,
+                &on_progress,
+            )
+            .await;
+            assert!(matches!(
+                result,
+                Err(SequenceError::Mqtt(MqttError::NotConnected))
+            ));
+            let last = events.lock().unwrap().last().cloned().unwrap();
+            assert_eq!(last.status, RunStatus::Failed);
+            assert_eq!(last.step, Some(0));
+        });
+    }
+
+    #[test]
+    fn test_unknown_button_fails() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let connection = create_connection(vec![SequenceStep::PublishButton {
+                button_id: "missing".to_string(),
+            }]);
+            let client = RwLock::new(MqttClient::new());
+            let (_tx, rx) = watch::channel(false);
+            let result = run(
+                &client,
+                &connection,
+                &connection.sequences[0],
+                "r1",
+                rx,
+                &|_| {},
+            )
+            .await;
+            assert!(matches!(result, Err(SequenceError::ButtonNotFound(_))));
+        });
+    }
+
+    #[test]
+    fn test_wait_for_message_stores_payload() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let connection = create_connection(vec![SequenceStep::WaitForMessage {
+                topic_filter: "devices/{device}/status".to_string(),
+                payload_contains: Some("ready".to_string()),
+                timeout_ms: 2000,
+                store_as: Some("status".to_string()),
+                continue_on_timeout: false,
+            }]);
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            client.read().await.add_subscription("devices/#").await;
+            let (_tx, rx) = watch::channel(false);
+
+            let sender = Arc::clone(&client);
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let client = sender.read().await;
+                for (topic, payload) in [
+                    ("devices/xyz/status", "ready"),
+                    ("devices/abc/status", "booting"),
+                    ("devices/abc/status", "ready 1"),
+                ] {
+                    client.inject_message(Message {
+                        topic: topic.to_string(),
+                        payload: payload.to_string(),
+                        ..Default::default()
+                    });
+                }
+            });
+
+            let variables = run(
+                &client,
+                &connection,
+                &connection.sequences[0],
+                "r1",
+                rx,
+                &|_| {},
+            )
+            .await
+            .unwrap();
+            assert_eq!(variables.get("status").unwrap(), "ready 1");
+        });
+    }
+
+    #[test]
+    fn test_wait_for_message_timeout() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let step = |continue_on_timeout| SequenceStep::WaitForMessage {
+                topic_filter: "devices/abc/status".to_string(),
+                payload_contains: None,
+                timeout_ms: 20,
+                store_as: None,
+                continue_on_timeout,
+            };
+            let client = RwLock::new(MqttClient::new());
+            client
+                .read()
+                .await
+                .add_subscription("devices/abc/status")
+                .await;
+
+            let connection = create_connection(vec![step(false)]);
+            let (_tx, rx) = watch::channel(false);
+            let result = run(
+                &client,
+                &connection,
+                &connection.sequences[0],
+                "r1",
+                rx,
+                &|_| {},
+            )
+            .await;
+            assert!(matches!(result, Err(SequenceError::Timeout(_, 20))));
+
+            let connection = create_connection(vec![step(true)]);
+            let (_tx, rx) = watch::channel(false);
+            let result = run(
+                &client,
+                &connection,
+                &connection.sequences[0],
+                "r2",
+                rx,
+                &|_| {},
+            )
+            .await;
+            assert!(result.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_runner_cancel() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let connection = create_connection(vec![SequenceStep::Wait {
+                duration_ms: 10_000,
+            }]);
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            let runner = SequenceRunner::default();
+            let (events, on_progress) = recorder();
+
+            let run_id = runner
+                .start(client, connection, "s1", on_progress)
+                .await
+                .unwrap();
+            assert_eq!(runner.active_runs().await, vec![run_id.clone()]);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(runner.cancel(&run_id).await);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            assert_eq!(statuses(&events).last(), Some(&RunStatus::Cancelled));
+            assert!(runner.active_runs().await.is_empty());
+            assert!(!runner.cancel(&run_id).await);
+        });
+    }
+
+    #[test]
+    fn test_runner_unknown_sequence() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let runner = SequenceRunner::default();
+            let client = Arc::new(RwLock::new(MqttClient::new()));
+            let result = runner
+                .start(client, create_connection(vec![]), "nope", |_| {})
+                .await;
+            assert!(matches!(result, Err(SequenceError::NotFound(_))));
+        });
+    }
+}
@@ -0,0 +1,564 @@
+use crate::mqtt::build_options;
+use crate::template::substitute_variables;
+use crate::types::{Connection, ConnectionStatus, QoS};
+use log::{debug, info, warn};
+use rand::Rng;
+use rumqttc::{AsyncClient, Event, LastWill, Packet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock as StdRwLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+
+pub const SIMULATOR_EVENT: &str = "simulator-status";
+pub const MAX_DEVICES: u32 = 1000;
+const MIN_INTERVAL_MS: u64 = 100;
+const DEFAULT_CONNECT_INTERVAL_MS: u64 = 20;
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum SimulatorError {
+    #[error("Device count must be between 1 and {MAX_DEVICES}")]
+    InvalidDeviceCount,
+    #[error("Client id prefix must not be empty")]
+    EmptyPrefix,
+}
+
+/// Topic and payload may use `{n}` and `{client_id}` besides the connection variables.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Telemetry {
+    pub topic: String,
+    #[serde(default)]
+    pub payload: Option<String>,
+    pub interval_ms: u64,
+    #[serde(default)]
+    pub qos: QoS,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Will {
+    pub topic: String,
+    #[serde(default)]
+    pub payload: Option<String>,
+    #[serde(default)]
+    pub qos: QoS,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationConfig {
+    pub device_count: u32,
+    /// Devices connect as `{prefix}-{n}`, counting from 1.
+    pub client_id_prefix: String,
+    #[serde(default)]
+    pub telemetry: Vec<Telemetry>,
+    #[serde(default)]
+    pub will: Option<Will>,
+    /// Messages on it are counted as received.
+    #[serde(default)]
+    pub command_topic: Option<String>,
+    /// Delay between two devices connecting, to avoid a connect storm.
+    #[serde(default)]
+    pub connect_interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceStatus {
+    pub n: u32,
+    pub client_id: String,
+    pub status: ConnectionStatus,
+    pub sent: u64,
+    pub received: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationStatus {
+    pub running: bool,
+    pub connected: u32,
+    pub sent: u64,
+    pub devices: Vec<DeviceStatus>,
+}
+
+struct Device {
+    n: u32,
+    client_id: String,
+    status: StdRwLock<ConnectionStatus>,
+    sent: AtomicU64,
+    received: AtomicU64,
+    errors: AtomicU64,
+    last_error: StdRwLock<Option<String>>,
+}
+
+impl Device {
+    fn new(n: u32, client_id: String) -> Self {
+        Self {
+            n,
+            client_id,
+            status: StdRwLock::new(ConnectionStatus::Disconnected),
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            last_error: StdRwLock::new(None),
+        }
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        *self.status.write().unwrap() = status;
+    }
+
+    fn is_connected(&self) -> bool {
+        *self.status.read().unwrap() == ConnectionStatus::Connected
+    }
+
+    fn record_error(&self, error: String) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.write().unwrap() = Some(error);
+    }
+
+    fn snapshot(&self) -> DeviceStatus {
+        DeviceStatus {
+            n: self.n,
+            client_id: self.client_id.clone(),
+            status: self.status.read().unwrap().clone(),
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            last_error: self.last_error.read().unwrap().clone(),
+        }
+    }
+}
+
+struct Simulation {
+    devices: Arc<Vec<Arc<Device>>>,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct Simulator {
+    simulation: Mutex<Option<Simulation>>,
+}
+
+impl Simulator {
+    /// `on_status` is called every second while the simulation runs.
+    pub async fn start<F>(
+        &self,
+        connection: Connection,
+        config: SimulationConfig,
+        on_status: F,
+    ) -> Result<(), SimulatorError>
+    where
+        F: Fn(SimulationStatus) + Send + Sync + 'static,
+    {
+        if config.device_count == 0 || config.device_count > MAX_DEVICES {
+            return Err(SimulatorError::InvalidDeviceCount);
+        }
+        if config.client_id_prefix.trim().is_empty() {
+            return Err(SimulatorError::EmptyPrefix);
+        }
+        self.stop().await;
+
+        info!(
+            "Starting simulation of {} devices against {}",
+            config.device_count, connection.broker_url
+        );
+        let devices: Arc<Vec<Arc<Device>>> = Arc::new(
+            (1..=config.device_count)
+                .map(|n| Arc::new(Device::new(n, client_id(&config.client_id_prefix, n))))
+                .collect(),
+        );
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let connect_interval = config
+            .connect_interval_ms
+            .unwrap_or(DEFAULT_CONNECT_INTERVAL_MS);
+        let connection = Arc::new(connection);
+        let config = Arc::new(config);
+
+        let device_tasks: Vec<JoinHandle<()>> = devices
+            .iter()
+            .map(|device| {
+                let delay = Duration::from_millis(connect_interval * u64::from(device.n - 1));
+                tokio::spawn(run_device(
+                    Arc::clone(device),
+                    Arc::clone(&connection),
+                    Arc::clone(&config),
+                    delay,
+                    shutdown_rx.clone(),
+                ))
+            })
+            .collect();
+
+        // Reports until stopped, then once more after every device has
+        // disconnected.
+        let reported = Arc::clone(&devices);
+        let mut stop = shutdown_rx;
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(STATUS_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => on_status(summarize(&reported, true)),
+                    _ = stop.wait_for(|stopped| *stopped) => break,
+                }
+            }
+            for device_task in device_tasks {
+                let _ = device_task.await;
+            }
+            on_status(summarize(&reported, false));
+        });
+
+        *self.simulation.lock().await = Some(Simulation {
+            devices,
+            shutdown,
+            task,
+        });
+        Ok(())
+    }
+
+    pub async fn stop(&self) -> bool {
+        let Some(simulation) = self.simulation.lock().await.take() else {
+            return false;
+        };
+        info!("Stopping simulation");
+        let _ = simulation.shutdown.send(true);
+        let _ = simulation.task.await;
+        true
+    }
+
+    pub async fn status(&self) -> SimulationStatus {
+        match self.simulation.lock().await.as_ref() {
+            Some(simulation) => summarize(&simulation.devices, true),
+            None => SimulationStatus::default(),
+        }
+    }
+}
+
+fn client_id(prefix: &str, n: u32) -> String {
+    format!("{}-{}", prefix, n)
+}
+
+fn summarize(devices: &[Arc<Device>], running: bool) -> SimulationStatus {
+    let devices: Vec<DeviceStatus> = devices.iter().map(|d| d.snapshot()).collect();
+    SimulationStatus {
+        running,
+        connected: devices
+            .iter()
+            .filter(|d| d.status == ConnectionStatus::Connected)
+            .count() as u32,
+        sent: devices.iter().map(|d| d.sent).sum(),
+        devices,
+    }
+}
+
+fn device_variables(connection: &Connection, device: &Device) -> HashMap<String, String> {
+    let mut variables = connection.variables.clone();
+    variables.insert("n".to_string(), device.n.to_string());
+    variables.insert("client_id".to_string(), device.client_id.clone());
+    variables
+}
+
+async fn run_device(
+    device: Arc<Device>,
+    connection: Arc<Connection>,
+    config: Arc<SimulationConfig>,
+    delay: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => {}
+        _ = shutdown.wait_for(|stopped| *stopped) => return,
+    }
+
+    let variables = device_variables(&connection, &device);
+    let mut options = build_options(&connection, &device.client_id);
+    if let Some(ref will) = config.will {
+        options.set_last_will(LastWill::new(
+            substitute_variables(&will.topic, &variables),
+            substitute_variables(will.payload.as_deref().unwrap_or(""), &variables),
+            will.qos.into(),
+            will.retain,
+        ));
+    }
+    let command_topic = config
+        .command_topic
+        .as_deref()
+        .filter(|topic| !topic.trim().is_empty())
+        .map(|topic| substitute_variables(topic, &variables));
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    device.set_status(ConnectionStatus::Connecting);
+
+    let publishers: Vec<JoinHandle<()>> = config
+        .telemetry
+        .iter()
+        .map(|telemetry| {
+            tokio::spawn(publish_telemetry(
+                client.clone(),
+                Arc::clone(&device),
+                telemetry.clone(),
+                variables.clone(),
+                shutdown.clone(),
+            ))
+        })
+        .collect();
+
+    loop {
+        let event = tokio::select! {
+            _ = shutdown.wait_for(|stopped| *stopped) => break,
+            event = eventloop.poll() => event,
+        };
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                debug!("Simulated device '{}' connected", device.client_id);
+                device.set_status(ConnectionStatus::Connected);
+                if let Some(ref topic) = command_topic {
+                    // Clean sessions drop subscriptions, so renew on every connect.
+                    if let Err(e) = client.try_subscribe(topic, rumqttc::QoS::AtLeastOnce) {
+                        device.record_error(e.to_string());
+                    }
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(_))) => {
+                device.received.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                device.set_status(ConnectionStatus::Error);
+                device.record_error(e.to_string());
+                // The event loop reconnects on the next poll.
+                tokio::select! {
+                    _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                    _ = shutdown.wait_for(|stopped| *stopped) => break,
+                }
+                device.set_status(ConnectionStatus::Connecting);
+            }
+        }
+    }
+
+    for publisher in publishers {
+        let _ = publisher.await;
+    }
+    if device.is_connected() {
+        // Disconnect cleanly so the broker does not publish the last will.
+        let _ = client.try_disconnect();
+        let _ = tokio::time::timeout(Duration::from_millis(500), async {
+            while eventloop.poll().await.is_ok() {}
+        })
+        .await;
+    }
+    device.set_status(ConnectionStatus::Disconnected);
+}
+
+async fn publish_telemetry(
+    client: AsyncClient,
+    device: Arc<Device>,
+    telemetry: Telemetry,
+    variables: HashMap<String, String>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let interval = Duration::from_millis(telemetry.interval_ms.max(MIN_INTERVAL_MS));
+    // Spread the first publish of all devices over one interval.
+    let offset = rand::thread_rng().gen_range(Duration::ZERO..interval);
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + offset, interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait_for(|stopped| *stopped) => return,
+        }
+        if !device.is_connected() {
+            continue;
+        }
+        let topic = substitute_variables(&telemetry.topic, &variables);
+        let payload = substitute_variables(telemetry.payload.as_deref().unwrap_or(""), &variables);
+        match client.try_publish(topic, telemetry.qos.into(), telemetry.retain, payload) {
+            Ok(()) => {
+                device.sent.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                warn!(
+                    "Simulated device '{}' failed to publish: {}",
+                    device.client_id, e
+                );
+                device.record_error(e.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_connection(port: u16) -> Connection {
+        Connection {
+            id: "c1".to_string(),
+            name: "Fleet".to_string(),
+            broker_url: "127.0.0.1".to_string(),
+            port,
+            client_id: "app".to_string(),
+            auto_connect: false,
+            variables: HashMap::from([("site".to_string(), "lab".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    fn create_config(device_count: u32) -> SimulationConfig {
+        SimulationConfig {
+            device_count,
+            client_id_prefix: "sim".to_string(),
+            telemetry: vec![Telemetry {
+                topic: "{site}/{client_id}/telemetry".to_string(),
+                payload: Some(r#"{"n":{n}}"#.to_string()),
+                interval_ms: 1000,
+                qos: QoS::AtMostOnce,
+                retain: false,
+            }],
+            will: None,
+            command_topic: None,
+            connect_interval_ms: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_device_variables() {
+        let device = Device::new(7, client_id("sim", 7));
+        let variables = device_variables(&create_connection(1883), &device);
+        let telemetry = &create_config(1).telemetry[0];
+        assert_eq!(
+            substitute_variables(&telemetry.topic, &variables),
+            "lab/sim-7/telemetry"
+        );
+        assert_eq!(
+            substitute_variables(telemetry.payload.as_deref().unwrap(), &variables),
+            r#"{"n":7}"#
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let simulator = Simulator::default();
+            assert!(matches!(
+                simulator
+                    .start(create_connection(1883), create_config(0), |_| {})
+                    .await,
+                Err(SimulatorError::InvalidDeviceCount)
+            ));
+            assert!(matches!(
+                simulator
+                    .start(
+                        create_connection(1883),
+                        create_config(MAX_DEVICES + 1),
+                        |_| {}
+                    )
+                    .await,
+                Err(SimulatorError::InvalidDeviceCount)
+            ));
+            let config = SimulationConfig {
+                client_id_prefix: " ".to_string(),
+                ..create_config(1)
+            };
+            assert!(matches!(
+                simulator
+                    .start(create_connection(1883), config, |_| {})
+                    .await,
+                Err(SimulatorError::EmptyPrefix)
+            ));
+            assert!(!simulator.stop().await);
+        });
+    }
+
+    #[test]
+    fn test_devices_count_commands() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut connection = create_connection(crate::broker::test_port());
+            connection.broker_url = crate::broker::HOST.to_string();
+            // Each device publishes to its own command topic, so it receives
+            // its telemetry back.
+            let mut config = create_config(2);
+            config.client_id_prefix = format!("sim-{}", uuid::Uuid::new_v4().simple());
+            config.telemetry[0].topic = "{site}/{client_id}/cmd".to_string();
+            config.telemetry[0].interval_ms = MIN_INTERVAL_MS;
+            config.command_topic = Some("{site}/{client_id}/cmd".to_string());
+            let simulator = Simulator::default();
+            simulator.start(connection, config, |_| {}).await.unwrap();
+
+            let mut status = simulator.status().await;
+            for _ in 0..50 {
+                if status.devices.iter().all(|d| d.received > 0) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                status = simulator.status().await;
+            }
+            simulator.stop().await;
+            assert!(status
+                .devices
+                .iter()
+                .all(|d| d.received > 0 && d.received <= d.sent && d.errors == 0));
+        });
+    }
+
+    #[test]
+    fn test_unreachable_broker_reports_errors() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            // Nothing listens on this port, so every device fails to connect.
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let simulator = Simulator::default();
+            let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let sink = Arc::clone(&reports);
+            simulator
+                .start(create_connection(port), create_config(3), move |status| {
+                    sink.lock().unwrap().push(status)
+                })
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+
+            let status = simulator.status().await;
+            assert!(status.running);
+            assert_eq!(status.connected, 0);
+            assert_eq!(status.sent, 0);
+            let ids: Vec<_> = status
+                .devices
+                .iter()
+                .map(|d| d.client_id.as_str())
+                .collect();
+            assert_eq!(ids, vec!["sim-1", "sim-2", "sim-3"]);
+            assert!(status
+                .devices
+                .iter()
+                .all(|d| d.status == ConnectionStatus::Error && d.errors > 0));
+
+            assert!(simulator.stop().await);
+            assert!(!simulator.status().await.running);
+            let last = reports.lock().unwrap().last().cloned().unwrap();
+            assert!(!last.running);
+            assert!(last
+                .devices
+                .iter()
+                .all(|d| d.status == ConnectionStatus::Disconnected));
+        });
+    }
+}
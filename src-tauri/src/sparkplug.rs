@@ -0,0 +1,712 @@
+use crate::decode::{failed, structured, Decoded};
+use crate::types::DecoderKind;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::warn;
+use prost::Message as _;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+pub const NAMESPACE: &str = "spBv1.0";
+
+#[derive(Error, Debug)]
+pub enum SparkplugError {
+    #[error("Invalid Sparkplug payload: {0}")]
+    Decode(#[from] prost::DecodeError),
+    #[error("A command needs at least one metric")]
+    NoMetrics,
+    #[error("Invalid value for metric '{0}' of type {1:?}")]
+    InvalidValue(String, DataType),
+    #[error("Invalid Sparkplug name: '{0}'")]
+    InvalidName(String),
+}
+
+/// Data sets, templates, metadata and properties are not decoded.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Payload {
+    #[prost(uint64, optional, tag = "1")]
+    pub timestamp: Option<u64>,
+    #[prost(message, repeated, tag = "2")]
+    pub metrics: Vec<Metric>,
+    #[prost(uint64, optional, tag = "3")]
+    pub seq: Option<u64>,
+    #[prost(string, optional, tag = "4")]
+    pub uuid: Option<String>,
+    #[prost(bytes = "vec", optional, tag = "5")]
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Metric {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(uint64, optional, tag = "2")]
+    pub alias: Option<u64>,
+    #[prost(uint64, optional, tag = "3")]
+    pub timestamp: Option<u64>,
+    #[prost(uint32, optional, tag = "4")]
+    pub datatype: Option<u32>,
+    #[prost(bool, optional, tag = "5")]
+    pub is_historical: Option<bool>,
+    #[prost(bool, optional, tag = "6")]
+    pub is_transient: Option<bool>,
+    #[prost(bool, optional, tag = "7")]
+    pub is_null: Option<bool>,
+    #[prost(oneof = "MetricValue", tags = "10, 11, 12, 13, 14, 15, 16")]
+    pub value: Option<MetricValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum MetricValue {
+    #[prost(uint32, tag = "10")]
+    Int(u32),
+    #[prost(uint64, tag = "11")]
+    Long(u64),
+    #[prost(float, tag = "12")]
+    Float(f32),
+    #[prost(double, tag = "13")]
+    Double(f64),
+    #[prost(bool, tag = "14")]
+    Boolean(bool),
+    #[prost(string, tag = "15")]
+    String(String),
+    #[prost(bytes, tag = "16")]
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataType {
+    Int8 = 1,
+    Int16 = 2,
+    Int32 = 3,
+    Int64 = 4,
+    UInt8 = 5,
+    UInt16 = 6,
+    UInt32 = 7,
+    UInt64 = 8,
+    Float = 9,
+    Double = 10,
+    Boolean = 11,
+    String = 12,
+    DateTime = 13,
+    Text = 14,
+    Uuid = 15,
+    DataSet = 16,
+    Bytes = 17,
+    File = 18,
+    Template = 19,
+}
+
+impl DataType {
+    fn from_u32(value: u32) -> Option<Self> {
+        use DataType::*;
+        [
+            Int8, Int16, Int32, Int64, UInt8, UInt16, UInt32, UInt64, Float, Double, Boolean,
+            String, DateTime, Text, Uuid, DataSet, Bytes, File, Template,
+        ]
+        .into_iter()
+        .find(|t| *t as u32 == value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum MessageType {
+    #[serde(rename = "NBIRTH")]
+    NBirth,
+    #[serde(rename = "NDEATH")]
+    NDeath,
+    #[serde(rename = "DBIRTH")]
+    DBirth,
+    #[serde(rename = "DDEATH")]
+    DDeath,
+    #[serde(rename = "NDATA")]
+    NData,
+    #[serde(rename = "DDATA")]
+    DData,
+    #[serde(rename = "NCMD")]
+    NCmd,
+    #[serde(rename = "DCMD")]
+    DCmd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topic<'a> {
+    pub group: &'a str,
+    pub message_type: MessageType,
+    pub edge_node: &'a str,
+    pub device: Option<&'a str>,
+}
+
+/// `STATE` topics of host applications carry JSON and are left to the
+/// regular decoders.
+pub fn parse_topic(topic: &str) -> Option<Topic<'_>> {
+    let mut parts = topic.split('/');
+    if parts.next()? != NAMESPACE {
+        return None;
+    }
+    let group = parts.next()?;
+    let message_type = match parts.next()? {
+        "NBIRTH" => MessageType::NBirth,
+        "NDEATH" => MessageType::NDeath,
+        "DBIRTH" => MessageType::DBirth,
+        "DDEATH" => MessageType::DDeath,
+        "NDATA" => MessageType::NData,
+        "DDATA" => MessageType::DData,
+        "NCMD" => MessageType::NCmd,
+        "DCMD" => MessageType::DCmd,
+        _ => return None,
+    };
+    let edge_node = parts.next()?;
+    let device = parts.next();
+    let is_device = matches!(
+        message_type,
+        MessageType::DBirth | MessageType::DDeath | MessageType::DData | MessageType::DCmd
+    );
+    if parts.next().is_some() || is_device != device.is_some() {
+        return None;
+    }
+    Some(Topic {
+        group,
+        message_type,
+        edge_node,
+        device,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricStatus {
+    pub alias: Option<u64>,
+    pub datatype: Option<DataType>,
+    pub value: Value,
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceState {
+    pub online: bool,
+    pub metrics: BTreeMap<String, MetricStatus>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EdgeNodeState {
+    pub group: String,
+    pub edge_node: String,
+    pub online: bool,
+    pub seq: Option<u64>,
+    /// Messages that did not follow the previous sequence number.
+    pub seq_gaps: u64,
+    /// Milliseconds since the Unix epoch.
+    pub last_seen: u64,
+    pub metrics: BTreeMap<String, MetricStatus>,
+    pub devices: BTreeMap<String, DeviceState>,
+    /// Births may declare numeric aliases that later data messages use instead
+    /// of names; kept per node for all of its devices.
+    #[serde(skip)]
+    aliases: HashMap<u64, String>,
+    #[serde(skip)]
+    command_seq: u64,
+}
+
+/// The data type defaults to the one declared in the birth, or is inferred
+/// from the JSON value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandMetric {
+    pub name: String,
+    pub value: Value,
+    #[serde(default)]
+    pub datatype: Option<DataType>,
+}
+
+#[derive(Default)]
+pub struct Sparkplug {
+    nodes: HashMap<(String, String), EdgeNodeState>,
+}
+
+impl Sparkplug {
+    pub fn process(&mut self, topic: &Topic, payload: &[u8], now_ms: u64) -> Decoded {
+        match Payload::decode(payload) {
+            Ok(payload) => structured(DecoderKind::Sparkplug, self.apply(topic, &payload, now_ms)),
+            Err(e) => failed(
+                DecoderKind::Sparkplug,
+                payload,
+                SparkplugError::from(e).to_string(),
+            ),
+        }
+    }
+
+    fn apply(&mut self, topic: &Topic, payload: &Payload, now_ms: u64) -> Value {
+        let node = self
+            .nodes
+            .entry((topic.group.to_string(), topic.edge_node.to_string()))
+            .or_insert_with(|| EdgeNodeState {
+                group: topic.group.to_string(),
+                edge_node: topic.edge_node.to_string(),
+                ..Default::default()
+            });
+        node.last_seen = now_ms;
+
+        let from_node = !matches!(topic.message_type, MessageType::NCmd | MessageType::DCmd);
+        if from_node {
+            if let Some(seq) = payload.seq {
+                let expected = node.seq.map(|prev| prev.wrapping_add(1) % 256);
+                if topic.message_type != MessageType::NBirth && expected.is_some_and(|e| e != seq) {
+                    warn!(
+                        "Sparkplug sequence gap from {}/{}: expected {}, got {}",
+                        topic.group,
+                        topic.edge_node,
+                        expected.unwrap_or_default(),
+                        seq
+                    );
+                    node.seq_gaps += 1;
+                }
+                node.seq = Some(seq);
+            }
+        }
+
+        match topic.message_type {
+            MessageType::NBirth => {
+                node.online = true;
+                node.metrics.clear();
+                node.aliases.clear();
+                for device in node.devices.values_mut() {
+                    device.online = false;
+                }
+            }
+            MessageType::NDeath => {
+                node.online = false;
+                for device in node.devices.values_mut() {
+                    device.online = false;
+                }
+            }
+            MessageType::DBirth => {
+                let device = node
+                    .devices
+                    .entry(topic.device.unwrap_or_default().to_string())
+                    .or_default();
+                device.online = true;
+                device.metrics.clear();
+            }
+            MessageType::DDeath => {
+                if let Some(device) = topic.device.and_then(|d| node.devices.get_mut(d)) {
+                    device.online = false;
+                }
+            }
+            _ => {}
+        }
+
+        let is_birth = matches!(
+            topic.message_type,
+            MessageType::NBirth | MessageType::DBirth
+        );
+        let mut metrics = Vec::new();
+        for metric in &payload.metrics {
+            if is_birth {
+                if let (Some(alias), Some(name)) = (metric.alias, &metric.name) {
+                    node.aliases.insert(alias, name.clone());
+                }
+            }
+            let name = metric
+                .name
+                .clone()
+                .or_else(|| metric.alias.and_then(|a| node.aliases.get(&a).cloned()));
+            let metrics_state = match topic.device {
+                Some(device) => &mut node.devices.entry(device.to_string()).or_default().metrics,
+                None => &mut node.metrics,
+            };
+            let known = name.as_ref().and_then(|n| metrics_state.get(n));
+            let datatype = metric
+                .datatype
+                .and_then(DataType::from_u32)
+                .or(known.and_then(|m| m.datatype));
+            let status = MetricStatus {
+                alias: metric.alias.or(known.and_then(|m| m.alias)),
+                datatype,
+                value: metric_value(metric, datatype),
+                timestamp: metric.timestamp.or(payload.timestamp),
+            };
+            if from_node {
+                if let Some(name) = &name {
+                    metrics_state.insert(name.clone(), status.clone());
+                }
+            }
+            metrics.push(json!({
+                "name": name,
+                "alias": status.alias,
+                "datatype": status.datatype,
+                "value": status.value,
+                "timestamp": status.timestamp,
+            }));
+        }
+
+        json!({
+            "messageType": topic.message_type,
+            "group": topic.group,
+            "edgeNode": topic.edge_node,
+            "device": topic.device,
+            "timestamp": payload.timestamp,
+            "seq": payload.seq,
+            "metrics": metrics,
+        })
+    }
+
+    /// Sorted by group and name.
+    pub fn nodes(&self) -> Vec<EdgeNodeState> {
+        let mut nodes: Vec<_> = self.nodes.values().cloned().collect();
+        nodes.sort_by(|a, b| (&a.group, &a.edge_node).cmp(&(&b.group, &b.edge_node)));
+        nodes
+    }
+
+    /// An NCMD, or a DCMD when `device` is set.
+    pub fn command(
+        &mut self,
+        group: &str,
+        edge_node: &str,
+        device: Option<&str>,
+        metrics: &[CommandMetric],
+        now_ms: u64,
+    ) -> Result<(String, Vec<u8>), SparkplugError> {
+        if metrics.is_empty() {
+            return Err(SparkplugError::NoMetrics);
+        }
+        for name in [Some(group), Some(edge_node), device].into_iter().flatten() {
+            if name.is_empty() || name.contains(['/', '+', '#']) {
+                return Err(SparkplugError::InvalidName(name.to_string()));
+            }
+        }
+        let node = self
+            .nodes
+            .entry((group.to_string(), edge_node.to_string()))
+            .or_insert_with(|| EdgeNodeState {
+                group: group.to_string(),
+                edge_node: edge_node.to_string(),
+                ..Default::default()
+            });
+        let known = match device {
+            Some(device) => node.devices.get(device).map(|d| &d.metrics),
+            None => Some(&node.metrics),
+        };
+
+        let metrics = metrics
+            .iter()
+            .map(|metric| {
+                let birth = known.and_then(|m| m.get(&metric.name));
+                let datatype = metric
+                    .datatype
+                    .or(birth.and_then(|m| m.datatype))
+                    .unwrap_or_else(|| infer_type(&metric.value));
+                let value = encode_value(&metric.value, datatype)
+                    .ok_or_else(|| SparkplugError::InvalidValue(metric.name.clone(), datatype))?;
+                Ok(Metric {
+                    name: Some(metric.name.clone()),
+                    alias: birth.and_then(|m| m.alias),
+                    timestamp: Some(now_ms),
+                    datatype: Some(datatype as u32),
+                    value: Some(value),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>, SparkplugError>>()?;
+
+        let payload = Payload {
+            timestamp: Some(now_ms),
+            metrics,
+            seq: Some(node.command_seq),
+            ..Default::default()
+        };
+        node.command_seq = (node.command_seq + 1) % 256;
+
+        let topic = match device {
+            Some(device) => format!("{NAMESPACE}/{group}/DCMD/{edge_node}/{device}"),
+            None => format!("{NAMESPACE}/{group}/NCMD/{edge_node}"),
+        };
+        Ok((topic, payload.encode_to_vec()))
+    }
+}
+
+fn metric_value(metric: &Metric, datatype: Option<DataType>) -> Value {
+    if metric.is_null == Some(true) {
+        return Value::Null;
+    }
+    match &metric.value {
+        Some(MetricValue::Int(v)) => match datatype {
+            Some(DataType::Int8) => json!(*v as u8 as i8),
+            Some(DataType::Int16) => json!(*v as u16 as i16),
+            Some(DataType::Int32) => json!(*v as i32),
+            _ => json!(v),
+        },
+        Some(MetricValue::Long(v)) => match datatype {
+            Some(DataType::Int64) => json!(*v as i64),
+            _ => json!(v),
+        },
+        Some(MetricValue::Float(v)) => json!(v),
+        Some(MetricValue::Double(v)) => json!(v),
+        Some(MetricValue::Boolean(v)) => json!(v),
+        Some(MetricValue::String(v)) => json!(v),
+        Some(MetricValue::Bytes(v)) => json!(BASE64.encode(v)),
+        None => Value::Null,
+    }
+}
+
+fn infer_type(value: &Value) -> DataType {
+    match value {
+        Value::Bool(_) => DataType::Boolean,
+        Value::Number(n) if n.is_f64() => DataType::Double,
+        Value::Number(_) => DataType::Int64,
+        _ => DataType::String,
+    }
+}
+
+/// None if `value` does not fit `datatype`.
+fn encode_value(value: &Value, datatype: DataType) -> Option<MetricValue> {
+    use DataType::*;
+    let int = |min: i64, max: i64| value.as_i64().filter(|v| (min..=max).contains(v));
+    Some(match datatype {
+        Int8 => MetricValue::Int(int(i8::MIN.into(), i8::MAX.into())? as i8 as u8 as u32),
+        Int16 => MetricValue::Int(int(i16::MIN.into(), i16::MAX.into())? as i16 as u16 as u32),
+        Int32 => MetricValue::Int(int(i32::MIN.into(), i32::MAX.into())? as i32 as u32),
+        UInt8 => MetricValue::Int(int(0, u8::MAX.into())? as u32),
+        UInt16 => MetricValue::Int(int(0, u16::MAX.into())? as u32),
+        UInt32 => MetricValue::Int(int(0, u32::MAX.into())? as u32),
+        Int64 => MetricValue::Long(value.as_i64()? as u64),
+        UInt64 | DateTime => MetricValue::Long(value.as_u64()?),
+        Float => MetricValue::Float(value.as_f64()? as f32),
+        Double => MetricValue::Double(value.as_f64()?),
+        Boolean => MetricValue::Boolean(value.as_bool()?),
+        String | Text | Uuid => MetricValue::String(value.as_str()?.to_string()),
+        Bytes | File => MetricValue::Bytes(BASE64.decode(value.as_str()?).ok()?),
+        DataSet | Template => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(
+        name: Option<&str>,
+        alias: Option<u64>,
+        datatype: DataType,
+        value: MetricValue,
+    ) -> Metric {
+        Metric {
+            name: name.map(str::to_string),
+            alias,
+            datatype: Some(datatype as u32),
+            value: Some(value),
+            ..Default::default()
+        }
+    }
+
+    fn payload(seq: u64, metrics: Vec<Metric>) -> Vec<u8> {
+        Payload {
+            timestamp: Some(1000),
+            metrics,
+            seq: Some(seq),
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    fn process(sparkplug: &mut Sparkplug, topic: &str, payload: &[u8]) -> Value {
+        let topic = parse_topic(topic).unwrap();
+        let decoded = sparkplug.process(&topic, payload, 5000);
+        assert_eq!(decoded.format, DecoderKind::Sparkplug);
+        decoded.value.unwrap()
+    }
+
+    #[test]
+    fn test_parse_topic() {
+        assert_eq!(
+            parse_topic("spBv1.0/plant/DDATA/gw1/pump"),
+            Some(Topic {
+                group: "plant",
+                message_type: MessageType::DData,
+                edge_node: "gw1",
+                device: Some("pump"),
+            })
+        );
+        assert_eq!(
+            parse_topic("spBv1.0/plant/NBIRTH/gw1").unwrap().device,
+            None
+        );
+        assert_eq!(parse_topic("spBv1.0/plant/NDATA/gw1/pump"), None);
+        assert_eq!(parse_topic("spBv1.0/plant/DDATA/gw1"), None);
+        assert_eq!(parse_topic("spBv1.0/STATE/host"), None);
+        assert_eq!(parse_topic("sensors/plant/NDATA/gw1"), None);
+    }
+
+    #[test]
+    fn test_births_resolve_aliases() {
+        let mut sparkplug = Sparkplug::default();
+        process(
+            &mut sparkplug,
+            "spBv1.0/plant/NBIRTH/gw1",
+            &payload(
+                0,
+                vec![metric(
+                    Some("Node Control/Rebirth"),
+                    Some(1),
+                    DataType::Boolean,
+                    MetricValue::Boolean(false),
+                )],
+            ),
+        );
+        process(
+            &mut sparkplug,
+            "spBv1.0/plant/DBIRTH/gw1/pump",
+            &payload(
+                1,
+                vec![metric(
+                    Some("temperature"),
+                    Some(10),
+                    DataType::Int16,
+                    MetricValue::Int(20),
+                )],
+            ),
+        );
+        let data = process(
+            &mut sparkplug,
+            "spBv1.0/plant/DDATA/gw1/pump",
+            &payload(
+                2,
+                vec![Metric {
+                    alias: Some(10),
+                    value: Some(MetricValue::Int(-5i16 as u16 as u32)),
+                    ..Default::default()
+                }],
+            ),
+        );
+        assert_eq!(data["messageType"], "DDATA");
+        assert_eq!(data["metrics"][0]["name"], "temperature");
+        assert_eq!(data["metrics"][0]["value"], -5);
+        assert_eq!(data["metrics"][0]["datatype"], "int16");
+
+        let nodes = sparkplug.nodes();
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].online);
+        assert_eq!(nodes[0].seq_gaps, 0);
+        let pump = &nodes[0].devices["pump"];
+        assert!(pump.online);
+        assert_eq!(pump.metrics["temperature"].value, json!(-5));
+        assert_eq!(pump.metrics["temperature"].timestamp, Some(1000));
+        assert_eq!(nodes[0].last_seen, 5000);
+    }
+
+    #[test]
+    fn test_deaths_and_sequence_gaps() {
+        let mut sparkplug = Sparkplug::default();
+        process(&mut sparkplug, "spBv1.0/g/NBIRTH/n", &payload(0, vec![]));
+        process(&mut sparkplug, "spBv1.0/g/DBIRTH/n/d", &payload(1, vec![]));
+        process(&mut sparkplug, "spBv1.0/g/NDATA/n", &payload(3, vec![]));
+        assert_eq!(sparkplug.nodes()[0].seq_gaps, 1);
+        process(
+            &mut sparkplug,
+            "spBv1.0/g/NDATA/n",
+            &payload(u64::MAX, vec![]),
+        );
+        process(&mut sparkplug, "spBv1.0/g/NDATA/n", &payload(0, vec![]));
+        assert_eq!(sparkplug.nodes()[0].seq_gaps, 2);
+
+        process(
+            &mut sparkplug,
+            "spBv1.0/g/NDEATH/n",
+            &Payload::default().encode_to_vec(),
+        );
+        let node = &sparkplug.nodes()[0];
+        assert!(!node.online);
+        assert!(!node.devices["d"].online);
+
+        let decoded =
+            sparkplug.process(&parse_topic("spBv1.0/g/NDATA/n").unwrap(), &[0xff, 0xff], 0);
+        assert!(decoded.error.is_some());
+    }
+
+    #[test]
+    fn test_command_uses_birth_types_and_sequence() {
+        let mut sparkplug = Sparkplug::default();
+        process(
+            &mut sparkplug,
+            "spBv1.0/plant/DBIRTH/gw1/pump",
+            &payload(
+                0,
+                vec![metric(
+                    Some("speed"),
+                    Some(7),
+                    DataType::UInt8,
+                    MetricValue::Int(0),
+                )],
+            ),
+        );
+        let command = |sparkplug: &mut Sparkplug, value: Value| {
+            sparkplug.command(
+                "plant",
+                "gw1",
+                Some("pump"),
+                &[CommandMetric {
+                    name: "speed".to_string(),
+                    value,
+                    datatype: None,
+                }],
+                42,
+            )
+        };
+
+        let (topic, bytes) = command(&mut sparkplug, json!(200)).unwrap();
+        assert_eq!(topic, "spBv1.0/plant/DCMD/gw1/pump");
+        let sent = Payload::decode(bytes.as_slice()).unwrap();
+        assert_eq!(sent.seq, Some(0));
+        assert_eq!(sent.timestamp, Some(42));
+        assert_eq!(sent.metrics[0].alias, Some(7));
+        assert_eq!(sent.metrics[0].datatype, Some(DataType::UInt8 as u32));
+        assert_eq!(sent.metrics[0].value, Some(MetricValue::Int(200)));
+
+        let (_, bytes) = command(&mut sparkplug, json!(1)).unwrap();
+        assert_eq!(Payload::decode(bytes.as_slice()).unwrap().seq, Some(1));
+        assert!(matches!(
+            command(&mut sparkplug, json!(300)),
+            Err(SparkplugError::InvalidValue(..))
+        ));
+
+        let (topic, bytes) = sparkplug
+            .command(
+                "plant",
+                "gw1",
+                None,
+                &[CommandMetric {
+                    name: "Node Control/Rebirth".to_string(),
+                    value: json!(true),
+                    datatype: None,
+                }],
+                0,
+            )
+            .unwrap();
+        assert_eq!(topic, "spBv1.0/plant/NCMD/gw1");
+        let sent = Payload::decode(bytes.as_slice()).unwrap();
+        assert_eq!(sent.metrics[0].value, Some(MetricValue::Boolean(true)));
+        assert!(matches!(
+            sparkplug.command("plant", "gw/1", None, &[], 0),
+            Err(SparkplugError::NoMetrics)
+        ));
+        assert!(matches!(
+            sparkplug.command(
+                "plant",
+                "gw/1",
+                None,
+                &[CommandMetric {
+                    name: "x".to_string(),
+                    value: json!(1),
+                    datatype: None
+                }],
+                0
+            ),
+            Err(SparkplugError::InvalidName(_))
+        ));
+    }
+}
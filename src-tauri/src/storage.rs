@@ -1,47 +1,277 @@
+use crate::history::{History, HistoryLog};
+use crate::project_file;
 use crate::types::{AppData, Connection, LegacyProject};
+use log::warn;
+use serde::Serialize;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use uuid::Uuid;
 
+const APP_DIR_NAME: &str = "mqtt-topic-lab";
+const DATA_DIR_ENV: &str = "MQTT_TOPIC_LAB_DATA_DIR";
+const PORTABLE_ENV: &str = "MQTT_TOPIC_LAB_PORTABLE";
+const PORTABLE_MARKER: &str = "portable";
+const ACTIVE_WORKSPACE_FILE: &str = "active_workspace";
+const WORKSPACES_DIR: &str = "workspaces";
+pub const DEFAULT_WORKSPACE: &str = "default";
+const HISTORY_SUFFIX: &str = ".history";
+
 #[derive(Error, Debug)]
 pub enum StorageError {
     #[error("Failed to get app data directory")]
     NoAppDataDir,
+    #[error("Invalid workspace name: {0}")]
+    InvalidWorkspaceName(String),
+    #[error("Workspace not found: {0}")]
+    WorkspaceNotFound(String),
+    #[error("Cannot delete the active workspace")]
+    WorkspaceActive,
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("Project file error: {0}")]
+    ProjectFile(#[from] project_file::ProjectFileError),
+}
+
+/// Where the data directory lives and which workspace to open, taken from
+/// command line arguments and environment variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageOptions {
+    pub data_dir: Option<PathBuf>,
+    pub portable: bool,
+    pub workspace: Option<String>,
+}
+
+impl StorageOptions {
+    pub fn from_env() -> Self {
+        Self::parse(std::env::args().skip(1), |key| std::env::var(key).ok())
+    }
+
+    /// Recognises `--data-dir <path>`, `--portable` and `--workspace <name>`
+    /// (also in `--flag=value` form). Arguments win over environment variables.
+    fn parse<I, F>(args: I, env: F) -> Self
+    where
+        I: IntoIterator<Item = String>,
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut options = Self {
+            data_dir: env(DATA_DIR_ENV)
+                .filter(|v| !v.trim().is_empty())
+                .map(PathBuf::from),
+            portable: env(PORTABLE_ENV).is_some_and(|v| is_truthy(&v)),
+            workspace: None,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            match flag.as_str() {
+                "--data-dir" => {
+                    if let Some(value) = inline_value.or_else(|| args.next()) {
+                        options.data_dir = Some(PathBuf::from(value));
+                    }
+                }
+                "--workspace" => {
+                    options.workspace = inline_value.or_else(|| args.next());
+                }
+                "--portable" => options.portable = true,
+                _ => {}
+            }
+        }
+
+        options
+    }
+
+    /// Resolves the data directory. An explicit directory takes precedence,
+    /// then portable mode (a `data` folder next to the executable, enabled by
+    /// flag or by a `portable` marker file), then the platform data directory.
+    pub fn resolve_dir(&self) -> Result<PathBuf, StorageError> {
+        if let Some(ref dir) = self.data_dir {
+            return Ok(dir.clone());
+        }
+
+        let exe_dir = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf));
+        if let Some(exe_dir) = exe_dir {
+            if self.portable || exe_dir.join(PORTABLE_MARKER).exists() {
+                return Ok(exe_dir.join("data"));
+            }
+        }
+
+        let data_dir = dirs::data_dir().ok_or(StorageError::NoAppDataDir)?;
+        Ok(data_dir.join(APP_DIR_NAME))
+    }
+}
+
+fn is_truthy(value: &str) -> bool {
+    matches!(
+        value.trim().to_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+fn validate_workspace_name(name: &str) -> Result<(), StorageError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && !name.ends_with(HISTORY_SUFFIX)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ' '));
+    if valid {
+        Ok(())
+    } else {
+        Err(StorageError::InvalidWorkspaceName(name.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageInfo {
+    pub data_dir: PathBuf,
+    pub workspace: String,
+    pub workspaces: Vec<String>,
 }
 
 pub struct Storage {
+    app_dir: PathBuf,
+    workspace: String,
     data_path: PathBuf,
     legacy_path: PathBuf,
 }
 
 impl Storage {
-    pub fn new() -> Result<Self, StorageError> {
-        let data_dir = dirs::data_dir().ok_or(StorageError::NoAppDataDir)?;
-        let app_dir = data_dir.join("mqtt-topic-lab");
+    pub fn new(options: &StorageOptions) -> Result<Self, StorageError> {
+        let app_dir = options.resolve_dir()?;
+        Self::open(app_dir, options.workspace.as_deref())
+    }
 
+    /// Without an explicit workspace the last active one is reopened.
+    pub fn open(app_dir: PathBuf, workspace: Option<&str>) -> Result<Self, StorageError> {
         if !app_dir.exists() {
             fs::create_dir_all(&app_dir)?;
         }
 
-        Ok(Self {
-            data_path: app_dir.join("data.json"),
+        let workspace = match workspace {
+            Some(name) => name.to_string(),
+            None => fs::read_to_string(app_dir.join(ACTIVE_WORKSPACE_FILE))
+                .map(|name| name.trim().to_string())
+                .ok()
+                .filter(|name| validate_workspace_name(name).is_ok())
+                .unwrap_or_else(|| DEFAULT_WORKSPACE.to_string()),
+        };
+        validate_workspace_name(&workspace)?;
+
+        let storage = Self {
+            data_path: workspace_path(&app_dir, &workspace),
             legacy_path: app_dir.join("project.json"),
+            app_dir,
+            workspace,
+        };
+        storage.ensure_workspace_dir()?;
+        storage.remember_workspace()?;
+        Ok(storage)
+    }
+
+    pub fn app_dir(&self) -> &Path {
+        &self.app_dir
+    }
+
+    pub fn workspace(&self) -> &str {
+        &self.workspace
+    }
+
+    fn is_default_workspace(&self) -> bool {
+        self.workspace == DEFAULT_WORKSPACE
+    }
+
+    fn ensure_workspace_dir(&self) -> Result<(), StorageError> {
+        if let Some(parent) = self.data_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(())
+    }
+
+    fn remember_workspace(&self) -> Result<(), StorageError> {
+        fs::write(self.app_dir.join(ACTIVE_WORKSPACE_FILE), &self.workspace)?;
+        Ok(())
+    }
+
+    /// Lists all workspaces in the data directory, the default one first.
+    pub fn list_workspaces(&self) -> Result<Vec<String>, StorageError> {
+        let mut names = Vec::new();
+        let dir = self.app_dir.join(WORKSPACES_DIR);
+        if dir.exists() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                // History files are named `<workspace>.history.json` and are
+                // rejected as workspace names below.
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    if stem != DEFAULT_WORKSPACE && validate_workspace_name(stem).is_ok() {
+                        names.push(stem.to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.insert(0, DEFAULT_WORKSPACE.to_string());
+        if !names.contains(&self.workspace) {
+            names.push(self.workspace.clone());
+        }
+        Ok(names)
+    }
+
+    pub fn info(&self) -> Result<StorageInfo, StorageError> {
+        Ok(StorageInfo {
+            data_dir: self.app_dir.clone(),
+            workspace: self.workspace.clone(),
+            workspaces: self.list_workspaces()?,
         })
     }
 
+    /// Makes `name` the active workspace, creating it if it does not exist yet.
+    pub fn switch_workspace(&mut self, name: &str) -> Result<(), StorageError> {
+        validate_workspace_name(name)?;
+        self.workspace = name.to_string();
+        self.data_path = workspace_path(&self.app_dir, name);
+        self.ensure_workspace_dir()?;
+        self.remember_workspace()
+    }
+
+    pub fn delete_workspace(&self, name: &str) -> Result<(), StorageError> {
+        validate_workspace_name(name)?;
+        if name == self.workspace {
+            return Err(StorageError::WorkspaceActive);
+        }
+        let path = workspace_path(&self.app_dir, name);
+        if !path.exists() {
+            return Err(StorageError::WorkspaceNotFound(name.to_string()));
+        }
+        fs::remove_file(&path)?;
+        let history_path = history_path(&path);
+        if history_path.exists() {
+            fs::remove_file(history_path)?;
+        }
+        Ok(())
+    }
+
     pub fn load_data(&self) -> Result<AppData, StorageError> {
         if self.data_path.exists() {
             let content = fs::read_to_string(&self.data_path)?;
-            let data: AppData = serde_json::from_str(&content)?;
+            let mut data: AppData = serde_json::from_str(&content)?;
+            sync_from_project_files(&mut data);
             return Ok(data);
         }
 
-        if self.legacy_path.exists() {
+        if self.is_default_workspace() && self.legacy_path.exists() {
             let migrated = self.migrate_legacy()?;
             self.save_data(&migrated)?;
             fs::remove_file(&self.legacy_path)?;
@@ -69,32 +299,145 @@ impl Storage {
             auto_connect: legacy.connection.auto_connect,
             variables: legacy.variables,
             buttons: legacy.buttons,
-            subscriptions: vec![],
+            ..Default::default()
         };
 
         Ok(AppData {
             connections: vec![connection],
             last_connection_id: Some(connection_id),
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
         })
     }
 
     pub fn save_data(&self, data: &AppData) -> Result<(), StorageError> {
+        let previous = self.read_data_file()?;
+        self.write_data(data)?;
+        if let Some(previous) = previous {
+            let mut history = self.load_history();
+            if history.record(&previous, data) {
+                self.save_history(&history)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_data_file(&self) -> Result<Option<AppData>, StorageError> {
+        if !self.data_path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&self.data_path)?;
+        Ok(serde_json::from_str(&content).ok())
+    }
+
+    fn write_data(&self, data: &AppData) -> Result<(), StorageError> {
         let content = serde_json::to_string_pretty(data)?;
         fs::write(&self.data_path, content)?;
+        for connection in &data.connections {
+            if let Some(ref path) = connection.project_file {
+                project_file::write(connection, Path::new(path))?;
+            }
+        }
         Ok(())
     }
 
+    fn history_path(&self) -> PathBuf {
+        history_path(&self.data_path)
+    }
+
+    /// A corrupt history file only costs the undo stack, never the data.
+    fn load_history(&self) -> History {
+        fs::read_to_string(self.history_path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_history(&self, history: &History) -> Result<(), StorageError> {
+        fs::write(self.history_path(), serde_json::to_string(history)?)?;
+        Ok(())
+    }
+
+    pub fn history(&self) -> HistoryLog {
+        self.load_history().log()
+    }
+
+    pub fn undo(&self) -> Result<Option<AppData>, StorageError> {
+        let current = self.load_data()?;
+        let mut history = self.load_history();
+        let Some(restored) = history.undo(&current) else {
+            return Ok(None);
+        };
+        self.write_data(&restored)?;
+        self.save_history(&history)?;
+        Ok(Some(restored))
+    }
+
+    pub fn redo(&self) -> Result<Option<AppData>, StorageError> {
+        let current = self.load_data()?;
+        let mut history = self.load_history();
+        let Some(restored) = history.redo(&current) else {
+            return Ok(None);
+        };
+        self.write_data(&restored)?;
+        self.save_history(&history)?;
+        Ok(Some(restored))
+    }
+
     pub fn delete_data(&self) -> Result<(), StorageError> {
         if self.data_path.exists() {
             fs::remove_file(&self.data_path)?;
         }
-        if self.legacy_path.exists() {
+        if self.history_path().exists() {
+            fs::remove_file(self.history_path())?;
+        }
+        if self.is_default_workspace() && self.legacy_path.exists() {
             fs::remove_file(&self.legacy_path)?;
         }
         Ok(())
     }
 }
 
+/// Linked project files are the source of truth for their connection, so
+/// edits made outside the app (e.g. a `git pull`) are picked up on load. The
+/// stored id and password are kept; an unreadable file leaves the stored copy.
+fn sync_from_project_files(data: &mut AppData) {
+    for connection in &mut data.connections {
+        let Some(path) = connection.project_file.clone() else {
+            continue;
+        };
+        match project_file::read(Path::new(&path)) {
+            Ok(mut synced) => {
+                synced.id = connection.id.clone();
+                if synced.password.is_none() {
+                    synced.password = connection.password.take();
+                }
+                synced.project_file = Some(path);
+                *connection = synced;
+            }
+            Err(e) => warn!("Failed to read project file {}: {}", path, e),
+        }
+    }
+}
+
+/// The default workspace keeps using `data.json` so existing installs are
+/// picked up unchanged; named workspaces live under `workspaces/`.
+fn workspace_path(app_dir: &Path, workspace: &str) -> PathBuf {
+    if workspace == DEFAULT_WORKSPACE {
+        app_dir.join("data.json")
+    } else {
+        app_dir
+            .join(WORKSPACES_DIR)
+            .join(format!("{}.json", workspace))
+    }
+}
+
+fn history_path(data_path: &Path) -> PathBuf {
+    data_path.with_extension("history.json")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -103,11 +446,11 @@ mod tests {
     use tempfile::TempDir;
 
     fn create_test_storage(temp_dir: &TempDir) -> Storage {
-        let app_dir = temp_dir.path().to_path_buf();
-        Storage {
-            data_path: app_dir.join("data.json"),
-            legacy_path: app_dir.join("project.json"),
-        }
+        Storage::open(temp_dir.path().to_path_buf(), None).unwrap()
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
     }
 
     fn create_test_connection() -> Connection {
@@ -115,12 +458,7 @@ mod tests {
             id: "test-id".to_string(),
             name: "Test Connection".to_string(),
             broker_url: "localhost".to_string(),
-            port: 1883,
             client_id: "test-client".to_string(),
-            username: None,
-            password: None,
-            use_tls: false,
-            auto_connect: true,
             variables: HashMap::from([("device_id".to_string(), "abc123".to_string())]),
             buttons: vec![Button {
                 id: "btn1".to_string(),
@@ -128,12 +466,9 @@ mod tests {
                 topic: "devices/{device_id}/CMD".to_string(),
                 payload: Some("ON".to_string()),
                 qos: QoS::AtLeastOnce,
-                retain: false,
-                color: None,
-                multi_send_enabled: None,
-                multi_send_interval: None,
+                ..Default::default()
             }],
-            subscriptions: vec![],
+            ..Default::default()
         }
     }
 
@@ -154,12 +489,7 @@ mod tests {
                 id: "old-btn".to_string(),
                 name: "Old Button".to_string(),
                 topic: "old/topic".to_string(),
-                payload: None,
-                qos: QoS::AtMostOnce,
-                retain: false,
-                color: None,
-                multi_send_enabled: None,
-                multi_send_interval: None,
+                ..Default::default()
             }],
         }
     }
@@ -182,6 +512,10 @@ mod tests {
         let data = AppData {
             connections: vec![create_test_connection()],
             last_connection_id: Some("test-id".to_string()),
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
         };
 
         storage.save_data(&data).unwrap();
@@ -224,6 +558,10 @@ mod tests {
         let data = AppData {
             connections: vec![create_test_connection()],
             last_connection_id: Some("test-id".to_string()),
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
         };
 
         storage.save_data(&data).unwrap();
@@ -232,4 +570,268 @@ mod tests {
         storage.delete_data().unwrap();
         assert!(!storage.data_path.exists());
     }
+
+    #[test]
+    fn test_parse_storage_options_from_args() {
+        let options = StorageOptions::parse(
+            args(&[
+                "--data-dir",
+                "/tmp/lab",
+                "--workspace=customer-a",
+                "--portable",
+            ]),
+            |_| None,
+        );
+        assert_eq!(options.data_dir, Some(PathBuf::from("/tmp/lab")));
+        assert_eq!(options.workspace, Some("customer-a".to_string()));
+        assert!(options.portable);
+    }
+
+    #[test]
+    fn test_parse_storage_options_from_env() {
+        let options = StorageOptions::parse(args(&[]), |key| match key {
+            DATA_DIR_ENV => Some("/env/dir".to_string()),
+            PORTABLE_ENV => Some("true".to_string()),
+            _ => None,
+        });
+        assert_eq!(options.data_dir, Some(PathBuf::from("/env/dir")));
+        assert!(options.portable);
+
+        let options = StorageOptions::parse(args(&["--data-dir=/arg/dir"]), |key| match key {
+            DATA_DIR_ENV => Some("/env/dir".to_string()),
+            _ => None,
+        });
+        assert_eq!(options.data_dir, Some(PathBuf::from("/arg/dir")));
+    }
+
+    #[test]
+    fn test_explicit_data_dir_wins() {
+        let options = StorageOptions {
+            data_dir: Some(PathBuf::from("/custom")),
+            portable: true,
+            workspace: None,
+        };
+        assert_eq!(options.resolve_dir().unwrap(), PathBuf::from("/custom"));
+    }
+
+    #[test]
+    fn test_workspaces_are_isolated() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut storage = create_test_storage(&temp_dir);
+        assert_eq!(storage.workspace(), DEFAULT_WORKSPACE);
+
+        let data = AppData {
+            connections: vec![create_test_connection()],
+            last_connection_id: Some("test-id".to_string()),
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        };
+        storage.save_data(&data).unwrap();
+
+        storage.switch_workspace("customer-b").unwrap();
+        assert!(storage.load_data().unwrap().connections.is_empty());
+        storage.save_data(&AppData::default()).unwrap();
+
+        assert_eq!(
+            storage.list_workspaces().unwrap(),
+            vec![DEFAULT_WORKSPACE.to_string(), "customer-b".to_string()]
+        );
+
+        storage.switch_workspace(DEFAULT_WORKSPACE).unwrap();
+        assert_eq!(storage.load_data().unwrap().connections.len(), 1);
+    }
+
+    #[test]
+    fn test_history_files_are_not_listed_as_workspaces() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut storage = create_test_storage(&temp_dir);
+        storage.switch_workspace("lab").unwrap();
+        storage.save_data(&AppData::default()).unwrap();
+        let mut data = AppData::default();
+        data.connections.push(create_test_connection());
+        storage.save_data(&data).unwrap();
+
+        assert!(temp_dir.path().join("workspaces/lab.history.json").exists());
+        assert_eq!(
+            storage.list_workspaces().unwrap(),
+            vec![DEFAULT_WORKSPACE.to_string(), "lab".to_string()]
+        );
+        assert!(matches!(
+            storage.switch_workspace("lab.history"),
+            Err(StorageError::InvalidWorkspaceName(_))
+        ));
+    }
+
+    #[test]
+    fn test_active_workspace_is_remembered() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut storage = create_test_storage(&temp_dir);
+        storage.switch_workspace("usb").unwrap();
+
+        let reopened = create_test_storage(&temp_dir);
+        assert_eq!(reopened.workspace(), "usb");
+        assert!(reopened.data_path.ends_with("workspaces/usb.json"));
+    }
+
+    #[test]
+    fn test_invalid_workspace_name_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut storage = create_test_storage(&temp_dir);
+        for name in ["", "../escape", "a/b", ".hidden"] {
+            assert!(matches!(
+                storage.switch_workspace(name),
+                Err(StorageError::InvalidWorkspaceName(_))
+            ));
+        }
+        assert_eq!(storage.workspace(), DEFAULT_WORKSPACE);
+    }
+
+    #[test]
+    fn test_delete_workspace() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut storage = create_test_storage(&temp_dir);
+        storage.switch_workspace("old").unwrap();
+        storage.save_data(&AppData::default()).unwrap();
+
+        assert!(matches!(
+            storage.delete_workspace("old"),
+            Err(StorageError::WorkspaceActive)
+        ));
+
+        storage.switch_workspace(DEFAULT_WORKSPACE).unwrap();
+        storage.delete_workspace("old").unwrap();
+        assert_eq!(storage.list_workspaces().unwrap(), vec![DEFAULT_WORKSPACE]);
+        assert!(matches!(
+            storage.delete_workspace("old"),
+            Err(StorageError::WorkspaceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_linked_project_file_is_synced() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = create_test_storage(&temp_dir);
+        let project_path = temp_dir.path().join("lab.yaml");
+
+        let mut connection = create_test_connection();
+        connection.password = Some("local-secret".to_string());
+        connection.project_file = Some(project_path.to_string_lossy().to_string());
+        let data = AppData {
+            connections: vec![connection],
+            last_connection_id: Some("test-id".to_string()),
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        };
+        storage.save_data(&data).unwrap();
+
+        let written = fs::read_to_string(&project_path).unwrap();
+        assert!(written.contains("name: Test Connection"));
+        assert!(!written.contains("local-secret"));
+
+        fs::write(
+            &project_path,
+            written.replace("name: Test Connection", "name: Edited In Git"),
+        )
+        .unwrap();
+
+        let loaded = storage.load_data().unwrap();
+        assert_eq!(loaded.connections[0].name, "Edited In Git");
+        assert_eq!(loaded.connections[0].id, "test-id");
+        assert_eq!(
+            loaded.connections[0].password,
+            Some("local-secret".to_string())
+        );
+    }
+
+    #[test]
+    fn test_missing_project_file_keeps_stored_connection() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = create_test_storage(&temp_dir);
+
+        let mut connection = create_test_connection();
+        connection.project_file = Some(
+            temp_dir
+                .path()
+                .join("gone.toml")
+                .to_string_lossy()
+                .to_string(),
+        );
+        let data = AppData {
+            connections: vec![connection],
+            last_connection_id: None,
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        };
+        storage.save_data(&data).unwrap();
+        fs::remove_file(temp_dir.path().join("gone.toml")).unwrap();
+
+        let loaded = storage.load_data().unwrap();
+        assert_eq!(loaded.connections[0].name, "Test Connection");
+    }
+
+    #[test]
+    fn test_undo_redo_persisted_across_instances() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = create_test_storage(&temp_dir);
+
+        let mut data = AppData {
+            connections: vec![create_test_connection()],
+            last_connection_id: Some("test-id".to_string()),
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        };
+        storage.save_data(&data).unwrap();
+        data.connections[0].buttons.clear();
+        storage.save_data(&data).unwrap();
+
+        let reopened = create_test_storage(&temp_dir);
+        let log = reopened.history();
+        assert_eq!(
+            log.undo[0].changes,
+            vec!["Deleted button 'Test Button' from 'Test Connection'"]
+        );
+
+        let restored = reopened.undo().unwrap().unwrap();
+        assert_eq!(restored.connections[0].buttons.len(), 1);
+        assert_eq!(
+            reopened.load_data().unwrap().connections[0].buttons.len(),
+            1
+        );
+        assert!(reopened.undo().unwrap().is_none());
+
+        let redone = reopened.redo().unwrap().unwrap();
+        assert!(redone.connections[0].buttons.is_empty());
+        assert!(reopened.load_data().unwrap().connections[0]
+            .buttons
+            .is_empty());
+    }
+
+    #[test]
+    fn test_delete_data_clears_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = create_test_storage(&temp_dir);
+        let mut data = AppData {
+            connections: vec![create_test_connection()],
+            last_connection_id: None,
+            schedules: vec![],
+            schemas: vec![],
+            http_api: Default::default(),
+            embedded_broker: Default::default(),
+        };
+        storage.save_data(&data).unwrap();
+        data.connections.clear();
+        storage.save_data(&data).unwrap();
+        assert_eq!(storage.history().undo.len(), 1);
+
+        storage.delete_data().unwrap();
+        assert!(storage.history().undo.is_empty());
+    }
 }
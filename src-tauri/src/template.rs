@@ -0,0 +1,334 @@
+// Mirrors `src/utils/variables.ts` and `src/utils/builtins.ts`; keep them in sync.
+
+use chrono::{DateTime, Datelike, Duration, Local, Months, SecondsFormat, Timelike, Utc};
+use rand::Rng;
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const MAX_ITERATIONS: usize = 10;
+const BUILTIN_NAMES: &[&str] = &["now", "timestamp", "uuid", "random", "rand"];
+
+fn variable_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"\{([a-zA-Z_][a-zA-Z0-9_]*(?::[^}]+)?)\}").unwrap())
+}
+
+fn offset_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^([+-])(\d+)([smhdwMy])$").unwrap())
+}
+
+fn range_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^(\d+)-(\d+)$").unwrap())
+}
+
+/// Values may refer to other variables; unknown placeholders are left untouched.
+pub fn substitute_variables(template: &str, variables: &HashMap<String, String>) -> String {
+    let mut result = template.to_string();
+
+    for _ in 0..MAX_ITERATIONS {
+        let next = variable_pattern()
+            .replace_all(&result, |caps: &regex::Captures| {
+                let expression = &caps[1];
+                let (name, modifiers) = parse_variable_expression(expression);
+
+                if is_builtin_variable(name) {
+                    if let Some(resolved) = resolve_builtin(name, &modifiers) {
+                        return resolved;
+                    }
+                }
+                if modifiers.is_empty() {
+                    if let Some(value) = variables.get(name) {
+                        return value.clone();
+                    }
+                }
+                caps[0].to_string()
+            })
+            .into_owned();
+
+        if next == result {
+            break;
+        }
+        result = next;
+    }
+
+    result
+}
+
+pub fn is_builtin_variable(name: &str) -> bool {
+    BUILTIN_NAMES.contains(&name)
+}
+
+fn parse_variable_expression(expression: &str) -> (&str, Vec<&str>) {
+    let mut parts = expression.split(':');
+    let name = parts.next().unwrap_or_default();
+    (name, parts.collect())
+}
+
+fn resolve_builtin(name: &str, modifiers: &[&str]) -> Option<String> {
+    match name.to_lowercase().as_str() {
+        "now" | "timestamp" => Some(handle_now(modifiers)),
+        "uuid" => Some(uuid::Uuid::new_v4().to_string()),
+        "random" | "rand" => Some(handle_random(modifiers)),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct TimeModifiers<'a> {
+    offset: Option<(i64, char)>,
+    utc: bool,
+    format: Option<String>,
+    custom_format: Option<&'a str>,
+}
+
+fn parse_time_modifiers<'a>(modifiers: &[&'a str]) -> TimeModifiers<'a> {
+    let mut result = TimeModifiers::default();
+
+    for modifier in modifiers {
+        let lower = modifier.to_lowercase();
+        match lower.as_str() {
+            "utc" => result.utc = true,
+            "local" => result.utc = false,
+            "iso" | "unix" | "unixms" | "date" | "time" | "datetime" => {
+                result.format = Some(lower);
+            }
+            _ => {
+                if let Some(pattern) = modifier.strip_prefix("fmt:") {
+                    result.custom_format = Some(pattern);
+                } else if let Some(caps) = offset_pattern().captures(modifier) {
+                    let amount: i64 = caps[2].parse().unwrap_or(0);
+                    let sign = if &caps[1] == "-" { -1 } else { 1 };
+                    let unit = caps[3].chars().next().unwrap_or('s');
+                    result.offset = Some((amount * sign, unit));
+                }
+            }
+        }
+    }
+
+    result
+}
+
+fn apply_offset(date: DateTime<Local>, amount: i64, unit: char) -> DateTime<Local> {
+    let shift_months = |date: DateTime<Local>, months: i64| {
+        let magnitude = Months::new(months.unsigned_abs() as u32);
+        if months >= 0 {
+            date.checked_add_months(magnitude)
+        } else {
+            date.checked_sub_months(magnitude)
+        }
+        .unwrap_or(date)
+    };
+
+    match unit {
+        's' => date + Duration::seconds(amount),
+        'm' => date + Duration::minutes(amount),
+        'h' => date + Duration::hours(amount),
+        'd' => date + Duration::days(amount),
+        'w' => date + Duration::weeks(amount),
+        'M' => shift_months(date, amount),
+        'y' => shift_months(date, amount * 12),
+        _ => date,
+    }
+}
+
+fn handle_now(modifiers: &[&str]) -> String {
+    let parsed = parse_time_modifiers(modifiers);
+    let mut date = Local::now();
+    if let Some((amount, unit)) = parsed.offset {
+        date = apply_offset(date, amount, unit);
+    }
+
+    if let Some(pattern) = parsed.custom_format {
+        return if parsed.utc {
+            format_custom(&date.with_timezone(&Utc), pattern)
+        } else {
+            format_custom(&date, pattern)
+        };
+    }
+
+    format_date(date, parsed.format.as_deref().unwrap_or("iso"), parsed.utc)
+}
+
+fn format_date(date: DateTime<Local>, format: &str, utc: bool) -> String {
+    let utc_date = date.with_timezone(&Utc);
+    match format {
+        "unix" => date.timestamp().to_string(),
+        "unixms" => date.timestamp_millis().to_string(),
+        "date" if utc => utc_date.format("%Y-%m-%d").to_string(),
+        "date" => date.format("%Y-%m-%d").to_string(),
+        "time" if utc => utc_date.format("%H:%M:%S").to_string(),
+        "time" => date.format("%H:%M:%S").to_string(),
+        "datetime" if utc => utc_date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        "datetime" => date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        // Like `Date.toISOString()` in the UI, "local" ISO output is the UTC
+        // time without the `Z` suffix.
+        _ => {
+            let iso = utc_date.to_rfc3339_opts(SecondsFormat::Millis, true);
+            if utc {
+                iso
+            } else {
+                iso.trim_end_matches('Z').to_string()
+            }
+        }
+    }
+}
+
+/// Replaces the first occurrence of each token in turn, like the UI does.
+fn format_custom<Tz: chrono::TimeZone>(date: &DateTime<Tz>, pattern: &str) -> String {
+    let year = date.year();
+    let replacements = [
+        ("YYYY", year.to_string()),
+        ("YY", format!("{:02}", year % 100)),
+        ("MM", format!("{:02}", date.month())),
+        ("M", date.month().to_string()),
+        ("DD", format!("{:02}", date.day())),
+        ("D", date.day().to_string()),
+        ("HH", format!("{:02}", date.hour())),
+        ("H", date.hour().to_string()),
+        ("mm", format!("{:02}", date.minute())),
+        ("ss", format!("{:02}", date.second())),
+        ("SSS", format!("{:03}", date.timestamp_subsec_millis())),
+    ];
+
+    replacements
+        .iter()
+        .fold(pattern.to_string(), |result, (token, value)| {
+            result.replacen(token, value, 1)
+        })
+}
+
+fn handle_random(modifiers: &[&str]) -> String {
+    let (mut min, mut max) = (0u64, 100u64);
+    if let Some(caps) = modifiers.iter().find_map(|m| range_pattern().captures(m)) {
+        min = caps[1].parse().unwrap_or(0);
+        max = caps[2].parse().unwrap_or(100);
+    }
+    if min > max {
+        std::mem::swap(&mut min, &mut max);
+    }
+    rand::thread_rng().gen_range(min..=max).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_substitute_simple_variables() {
+        let variables = vars(&[("device_id", "abc123"), ("site", "ams")]);
+        assert_eq!(
+            substitute_variables("devices/{device_id}/{site}/cmd", &variables),
+            "devices/abc123/ams/cmd"
+        );
+    }
+
+    #[test]
+    fn test_unknown_variables_are_kept() {
+        assert_eq!(
+            substitute_variables("devices/{missing}/cmd", &HashMap::new()),
+            "devices/{missing}/cmd"
+        );
+        assert_eq!(substitute_variables("{}", &HashMap::new()), "{}");
+    }
+
+    #[test]
+    fn test_nested_variables() {
+        let variables = vars(&[("topic", "devices/{id}"), ("id", "42")]);
+        assert_eq!(
+            substitute_variables("{topic}/cmd", &variables),
+            "devices/42/cmd"
+        );
+    }
+
+    #[test]
+    fn test_self_reference_terminates() {
+        let variables = vars(&[("a", "{a}x")]);
+        let result = substitute_variables("{a}", &variables);
+        assert!(result.starts_with("{a}"));
+    }
+
+    #[test]
+    fn test_user_variable_with_modifier_is_not_substituted() {
+        let variables = vars(&[("id", "42")]);
+        assert_eq!(substitute_variables("{id:utc}", &variables), "{id:utc}");
+    }
+
+    #[test]
+    fn test_builtin_uuid_and_random() {
+        let uuid = substitute_variables("{uuid}", &HashMap::new());
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+
+        for _ in 0..50 {
+            let value: u64 = substitute_variables("{random:5-7}", &HashMap::new())
+                .parse()
+                .unwrap();
+            assert!((5..=7).contains(&value));
+        }
+        let value: u64 = substitute_variables("{rand}", &HashMap::new())
+            .parse()
+            .unwrap();
+        assert!(value <= 100);
+    }
+
+    #[test]
+    fn test_builtin_now_formats() {
+        let unix: i64 = substitute_variables("{now:unix}", &HashMap::new())
+            .parse()
+            .unwrap();
+        assert!((unix - Utc::now().timestamp()).abs() <= 1);
+
+        let unixms: i64 = substitute_variables("{timestamp:unixms}", &HashMap::new())
+            .parse()
+            .unwrap();
+        assert!((unixms - Utc::now().timestamp_millis()).abs() < 1000);
+
+        let iso = substitute_variables("{now:utc}", &HashMap::new());
+        assert!(iso.ends_with('Z'));
+        assert_eq!(iso.len(), 24);
+        assert!(!substitute_variables("{now}", &HashMap::new()).ends_with('Z'));
+
+        let date = substitute_variables("{now:date:utc}", &HashMap::new());
+        assert_eq!(date, Utc::now().format("%Y-%m-%d").to_string());
+    }
+
+    #[test]
+    fn test_builtin_now_offset() {
+        let later: i64 = substitute_variables("{now:+1h:unix}", &HashMap::new())
+            .parse()
+            .unwrap();
+        assert!((later - Utc::now().timestamp() - 3600).abs() <= 1);
+
+        let earlier: i64 = substitute_variables("{now:-2d:unix}", &HashMap::new())
+            .parse()
+            .unwrap();
+        assert!((Utc::now().timestamp() - earlier - 2 * 86400).abs() <= 1);
+    }
+
+    #[test]
+    fn test_format_custom() {
+        let date = Utc.with_ymd_and_hms(2024, 3, 7, 9, 5, 4).unwrap();
+        assert_eq!(
+            format_custom(&date, "YYYY-MM-DD HH:mm:ss.SSS"),
+            "2024-03-07 09:05:04.000"
+        );
+        assert_eq!(format_custom(&date, "D/M/YY H"), "7/3/24 9");
+    }
+
+    #[test]
+    fn test_custom_format_modifier() {
+        let result = handle_now(&["utc", "fmt:YYYY"]);
+        assert_eq!(result, Utc::now().year().to_string());
+    }
+}
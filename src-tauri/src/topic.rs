@@ -0,0 +1,89 @@
+pub fn validate_topic_name(topic: &str) -> Result<(), String> {
+    if topic.is_empty() {
+        return Err("topic must not be empty".to_string());
+    }
+    if topic.contains(['+', '#']) {
+        return Err("wildcards are not allowed in a publish topic".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_topic_filter(filter: &str) -> Result<(), String> {
+    if filter.is_empty() {
+        return Err("topic filter must not be empty".to_string());
+    }
+    let levels: Vec<&str> = filter.split('/').collect();
+    for (i, level) in levels.iter().enumerate() {
+        if level.contains('#') && (*level != "#" || i != levels.len() - 1) {
+            return Err("'#' must be the last level on its own".to_string());
+        }
+        if level.contains('+') && *level != "+" {
+            return Err("'+' must occupy a whole level".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Topics starting with `$` are not matched by a leading wildcard.
+pub fn matches_filter(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match level {
+            "#" => return true,
+            "+" => {
+                if topic_levels.next().is_none() {
+                    return false;
+                }
+            }
+            _ => {
+                if topic_levels.next() != Some(level) {
+                    return false;
+                }
+            }
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_topic_name() {
+        assert!(validate_topic_name("devices/abc/cmd").is_ok());
+        assert!(validate_topic_name("devices/{device_id}/cmd").is_ok());
+        assert!(validate_topic_name("").is_err());
+        assert!(validate_topic_name("devices/+/cmd").is_err());
+        assert!(validate_topic_name("devices/#").is_err());
+    }
+
+    #[test]
+    fn test_validate_topic_filter() {
+        assert!(validate_topic_filter("#").is_ok());
+        assert!(validate_topic_filter("sensor/+/data").is_ok());
+        assert!(validate_topic_filter("topic/b/#").is_ok());
+        assert!(validate_topic_filter("").is_err());
+        assert!(validate_topic_filter("a/#/b").is_err());
+        assert!(validate_topic_filter("a/b#").is_err());
+        assert!(validate_topic_filter("a/b+/c").is_err());
+    }
+
+    #[test]
+    fn test_matches_filter() {
+        assert!(matches_filter("devices/abc/status", "devices/abc/status"));
+        assert!(matches_filter("devices/+/status", "devices/abc/status"));
+        assert!(matches_filter("devices/#", "devices/abc/status"));
+        assert!(matches_filter("devices/#", "devices"));
+        assert!(matches_filter("#", "a/b/c"));
+        assert!(matches_filter("+/+", "a/"));
+        assert!(!matches_filter("devices/+", "devices/abc/status"));
+        assert!(!matches_filter("devices/abc", "devices/abc/status"));
+        assert!(!matches_filter("devices/abc/status", "devices/abc"));
+        assert!(!matches_filter("#", "$SYS/uptime"));
+        assert!(matches_filter("$SYS/#", "$SYS/uptime"));
+    }
+}
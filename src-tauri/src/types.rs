@@ -25,6 +25,26 @@ impl From<QoS> for rumqttc::QoS {
     }
 }
 
+impl From<QoS> for rumqttc::v5::mqttbytes::QoS {
+    fn from(qos: QoS) -> Self {
+        match qos {
+            QoS::AtMostOnce => Self::AtMostOnce,
+            QoS::AtLeastOnce => Self::AtLeastOnce,
+            QoS::ExactlyOnce => Self::ExactlyOnce,
+        }
+    }
+}
+
+impl From<rumqttc::QoS> for QoS {
+    fn from(qos: rumqttc::QoS) -> Self {
+        match qos {
+            rumqttc::QoS::AtMostOnce => QoS::AtMostOnce,
+            rumqttc::QoS::AtLeastOnce => QoS::AtLeastOnce,
+            rumqttc::QoS::ExactlyOnce => QoS::ExactlyOnce,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum ButtonColor {
@@ -37,8 +57,28 @@ pub enum ButtonColor {
     Teal,
 }
 
+fn default_response_timeout() -> u64 {
+    5000
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct ResponseConfig {
+    /// Topic filter the reply arrives on; may use `{variable}` placeholders.
+    pub topic: String,
+    #[serde(default = "default_response_timeout")]
+    pub timeout_ms: u64,
+    /// JSON path of a correlation id the reply echoes, e.g. `meta.id`.
+    #[serde(default)]
+    pub correlation_path: Option<String>,
+    /// Sends from a separate MQTT 5 session and matches the reply on its
+    /// correlation data instead.
+    #[serde(default)]
+    pub mqtt5: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Button {
     pub id: String,
     pub name: String,
@@ -55,6 +95,197 @@ pub struct Button {
     pub multi_send_enabled: Option<bool>,
     #[serde(default)]
     pub multi_send_interval: Option<u64>,
+    #[serde(default)]
+    pub multi_send_max_count: Option<u64>,
+    #[serde(default)]
+    pub multi_send_max_duration: Option<u64>,
+    /// Randomly shift each publish by up to this many milliseconds.
+    #[serde(default)]
+    pub multi_send_jitter: Option<u64>,
+    #[serde(default)]
+    pub response: Option<ResponseConfig>,
+    /// Id of a schema in `AppData::schemas` the resolved payload must match.
+    #[serde(default)]
+    pub schema_id: Option<String>,
+    #[serde(default)]
+    pub compression: Option<Compression>,
+}
+
+/// Topics, payloads and variable values may use `{variable}` placeholders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum SequenceStep {
+    PublishButton {
+        button_id: String,
+    },
+    Publish {
+        topic: String,
+        #[serde(default)]
+        payload: Option<String>,
+        #[serde(default)]
+        qos: QoS,
+        #[serde(default)]
+        retain: bool,
+    },
+    Wait {
+        duration_ms: u64,
+    },
+    WaitForMessage {
+        topic_filter: String,
+        #[serde(default)]
+        payload_contains: Option<String>,
+        timeout_ms: u64,
+        #[serde(default)]
+        store_as: Option<String>,
+        #[serde(default)]
+        continue_on_timeout: bool,
+    },
+    SetVariable {
+        name: String,
+        value: String,
+    },
+}
+
+/// Answers matching incoming messages like a device would. Responses are
+/// published from the receive loop; when they come back from the broker
+/// they do not trigger rules again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponderRule {
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub topic_filter: String,
+    /// Regex the payload must match. Named groups become variables.
+    #[serde(default)]
+    pub payload_pattern: Option<String>,
+    /// JSON path that must exist in the payload, available as `{match}`.
+    #[serde(default)]
+    pub json_path: Option<String>,
+    /// Value the JSON path must have, compared as text.
+    #[serde(default)]
+    pub json_value: Option<String>,
+    pub response_topic: String,
+    #[serde(default)]
+    pub response_payload: Option<String>,
+    #[serde(default)]
+    pub qos: QoS,
+    #[serde(default)]
+    pub retain: bool,
+    #[serde(default)]
+    pub delay_ms: Option<u64>,
+    /// Chance of answering a matching message, from 0 to 1.
+    #[serde(default)]
+    pub probability: Option<f64>,
+    #[serde(default)]
+    pub min_interval_ms: Option<u64>,
+}
+
+impl Default for ResponderRule {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            enabled: default_true(),
+            topic_filter: String::new(),
+            payload_pattern: None,
+            json_path: None,
+            json_value: None,
+            response_topic: String::new(),
+            response_payload: None,
+            qos: QoS::default(),
+            retain: false,
+            delay_ms: None,
+            probability: None,
+            min_interval_ms: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Script {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub source: String,
+    /// Stops the script after this long; 0 lets it run until cancelled.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DecoderKind {
+    /// Picks JSON for JSON text, and CBOR or MessagePack for binary payloads
+    /// that parse as such.
+    #[default]
+    Auto,
+    Json,
+    Cbor,
+    MsgPack,
+    Hex,
+    Base64,
+    Text,
+    Protobuf,
+    /// Set on Sparkplug B topics, which are always decoded as such.
+    Sparkplug,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zlib,
+    Zstd,
+}
+
+/// The first matching rule of a connection applies. Text published to a topic
+/// whose rule is `protobuf` is read as JSON and encoded to protobuf.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecoderRule {
+    pub topic_filter: String,
+    pub decoder: DecoderKind,
+    /// Compiled `FileDescriptorSet`, for `protobuf` rules.
+    #[serde(default)]
+    pub descriptor_set: Option<String>,
+    #[serde(default)]
+    pub message_type: Option<String>,
+    #[serde(default)]
+    pub compression: Option<Compression>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonSchema {
+    pub id: String,
+    pub name: String,
+    pub schema: serde_json::Value,
+}
+
+/// The first matching rule of a connection applies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaRule {
+    pub topic_filter: String,
+    pub schema_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sequence {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub steps: Vec<SequenceStep>,
+    #[serde(default)]
+    pub color: Option<ButtonColor>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -78,6 +309,74 @@ pub struct Connection {
     pub buttons: Vec<Button>,
     #[serde(default)]
     pub subscriptions: Vec<String>,
+    #[serde(default)]
+    pub sequences: Vec<Sequence>,
+    #[serde(default)]
+    pub responders: Vec<ResponderRule>,
+    #[serde(default)]
+    pub scripts: Vec<Script>,
+    #[serde(default)]
+    pub decoders: Vec<DecoderRule>,
+    #[serde(default)]
+    pub message_schemas: Vec<SchemaRule>,
+    /// Decompresses gzip, zlib and zstd payloads recognized by their header
+    /// on topics whose decoder rule sets no compression.
+    #[serde(default)]
+    pub auto_decompress: bool,
+    #[serde(default)]
+    pub diff_previous: bool,
+    #[serde(default)]
+    pub project_file: Option<String>,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            broker_url: String::new(),
+            port: 1883,
+            client_id: String::new(),
+            username: None,
+            password: None,
+            use_tls: false,
+            auto_connect: default_true(),
+            variables: HashMap::new(),
+            buttons: Vec::new(),
+            subscriptions: Vec::new(),
+            sequences: Vec::new(),
+            responders: Vec::new(),
+            scripts: Vec::new(),
+            decoders: Vec::new(),
+            message_schemas: Vec::new(),
+            auto_decompress: false,
+            diff_previous: false,
+            project_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleTimezone {
+    #[default]
+    Local,
+    Utc,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schedule {
+    pub id: String,
+    pub name: String,
+    pub connection_id: String,
+    pub button_id: String,
+    /// Cron expression with five fields, or six with leading seconds.
+    pub cron: String,
+    #[serde(default)]
+    pub timezone: ScheduleTimezone,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -86,6 +385,63 @@ pub struct AppData {
     pub connections: Vec<Connection>,
     #[serde(default)]
     pub last_connection_id: Option<String>,
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    #[serde(default)]
+    pub schemas: Vec<JsonSchema>,
+    #[serde(default)]
+    pub http_api: HttpApiSettings,
+    #[serde(default)]
+    pub embedded_broker: EmbeddedBrokerSettings,
+}
+
+fn default_http_api_port() -> u16 {
+    7878
+}
+
+/// Local HTTP control API, served on the loopback interface only.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpApiSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_http_api_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub token: String,
+}
+
+impl Default for HttpApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: default_http_api_port(),
+            token: String::new(),
+        }
+    }
+}
+
+fn default_embedded_broker_port() -> u16 {
+    1884
+}
+
+/// MQTT broker run inside the app, served on the loopback interface only.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedBrokerSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_embedded_broker_port")]
+    pub port: u16,
+}
+
+impl Default for EmbeddedBrokerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: default_embedded_broker_port(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
@@ -214,12 +570,8 @@ mod tests {
             id: "btn1".to_string(),
             name: "Test".to_string(),
             topic: "test/topic".to_string(),
-            payload: None,
-            qos: QoS::AtMostOnce,
-            retain: false,
             color: Some(ButtonColor::Purple),
-            multi_send_enabled: None,
-            multi_send_interval: None,
+            ..Default::default()
         };
         let json = serde_json::to_string(&button).unwrap();
         assert!(json.contains("\"color\":\"purple\""));
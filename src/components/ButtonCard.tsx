@@ -1,7 +1,8 @@
 import { useState, useRef, useEffect, memo } from 'react';
 import { confirm } from '@tauri-apps/plugin-dialog';
+import { listen } from '@tauri-apps/api/event';
 import { GripVertical, Pencil, Trash2, Repeat, CopyPlus, Check } from 'lucide-react';
-import type { Button } from '../types';
+import type { Button, RepeatStatus, Reply } from '../types';
 import { useApp } from '../contexts/AppContext';
 import { substituteVariables } from '../utils/variables';
 
@@ -47,15 +48,17 @@ const propsAreEqual = (prev: ButtonCardProps, next: ButtonCardProps) =>
     prev.isDimmed === next.isDimmed;
 
 export const ButtonCard = memo(function ButtonCard({ button, index, onEdit, onDuplicate, onSelect, onDragStart, onDragEnter, isDragging, isDragOver, isSelected, isAnimating, keyboardSent, isDimmed }: ButtonCardProps) {
-    const { activeConnection, publishButton, deleteButton, updateButton, connectionStatus } = useApp();
+    const { activeConnection, publishButton, sendRequest, startRepeat, stopRepeat, deleteButton, updateButton, connectionStatus } = useApp();
     const [publishing, setPublishing] = useState(false);
     const [lastResult, setLastResult] = useState<'success' | 'error' | null>(null);
     const [isMultiSending, setIsMultiSending] = useState(false);
     const [sendCount, setSendCount] = useState(0);
+    const [lastReply, setLastReply] = useState<Reply | null>(null);
     const [editingField, setEditingField] = useState<'topic' | 'payload' | null>(null);
     const editRef = useRef<HTMLElement>(null);
     const cardRef = useRef<HTMLDivElement>(null);
-    const intervalRef = useRef<number | null>(null);
+    const multiSendingRef = useRef(false);
+    const prevButtonRef = useRef(button);
     const timeoutRef = useRef<number | null>(null);
 
     const variables = activeConnection?.variables || {};
@@ -63,19 +66,29 @@ export const ButtonCard = memo(function ButtonCard({ button, index, onEdit, onDu
     const resolvedPayload = button.payload ? substituteVariables(button.payload, variables) : '';
 
     function stopMultiSend() {
-        if (intervalRef.current) {
-            clearInterval(intervalRef.current);
-            intervalRef.current = null;
+        if (multiSendingRef.current) {
+            stopRepeat(button.id);
         }
+        multiSendingRef.current = false;
         setIsMultiSending(false);
         setSendCount(0);
     }
 
     useEffect(() => {
-        if (connectionStatus !== 'connected' && isMultiSending) {
-            stopMultiSend();
-        }
-    }, [connectionStatus, isMultiSending]);
+        const unlisten = listen<RepeatStatus>('repeat-progress', (event) => {
+            const status = event.payload;
+            if (status.buttonId !== button.id) return;
+            setSendCount(status.count);
+            if (!status.active) {
+                multiSendingRef.current = false;
+                setIsMultiSending(false);
+                if (status.error) setLastResult('error');
+            }
+        });
+        return () => {
+            unlisten.then((fn) => fn());
+        };
+    }, [button.id]);
 
     useEffect(() => {
         if (!button.multiSendEnabled && isMultiSending) {
@@ -84,26 +97,20 @@ export const ButtonCard = memo(function ButtonCard({ button, index, onEdit, onDu
     }, [button.multiSendEnabled, isMultiSending]);
 
     useEffect(() => {
-        if (!isMultiSending || !intervalRef.current) return;
+        const changed = prevButtonRef.current !== button;
+        prevButtonRef.current = button;
+        if (!changed || !multiSendingRef.current || !button.multiSendEnabled) return;
 
-        clearInterval(intervalRef.current);
-        const newInterval = Math.max(100, button.multiSendInterval || 1000);
-        const publishOnce = async () => {
-            try {
-                await publishButton(button);
-                setSendCount((c) => c + 1);
-            } catch {
-                stopMultiSend();
-                setLastResult('error');
-            }
-        };
-        intervalRef.current = window.setInterval(publishOnce, newInterval);
-    }, [isMultiSending, button]);
+        startRepeat(button).catch(() => {
+            stopMultiSend();
+            setLastResult('error');
+        });
+    }, [button]);
 
     useEffect(() => {
         return () => {
-            if (intervalRef.current) {
-                clearInterval(intervalRef.current);
+            if (multiSendingRef.current) {
+                stopRepeat(button.id);
             }
             if (timeoutRef.current) {
                 clearTimeout(timeoutRef.current);
@@ -123,10 +130,15 @@ export const ButtonCard = memo(function ButtonCard({ button, index, onEdit, onDu
         setPublishing(true);
         setLastResult(null);
         try {
-            await publishButton(button);
+            if (button.response) {
+                setLastReply(await sendRequest(button));
+            } else {
+                await publishButton(button);
+            }
             setLastResult('success');
             timeoutRef.current = window.setTimeout(() => setLastResult(null), 2000);
         } catch {
+            setLastReply(null);
             setLastResult('error');
         } finally {
             setPublishing(false);
@@ -134,25 +146,16 @@ export const ButtonCard = memo(function ButtonCard({ button, index, onEdit, onDu
     };
 
     const startMultiSend = async () => {
-        const publishOnce = async (): Promise<boolean> => {
-            try {
-                await publishButton(button);
-                setSendCount((c) => c + 1);
-                return true;
-            } catch {
-                stopMultiSend();
-                setLastResult('error');
-                return false;
-            }
-        };
-
+        multiSendingRef.current = true;
         setIsMultiSending(true);
         setSendCount(0);
-
-        if (!(await publishOnce())) return;
-
-        const interval = Math.max(100, button.multiSendInterval || 1000);
-        intervalRef.current = window.setInterval(publishOnce, interval);
+        try {
+            await startRepeat(button);
+        } catch {
+            multiSendingRef.current = false;
+            setIsMultiSending(false);
+            setLastResult('error');
+        }
     };
 
     const handlePublish = async () => {
@@ -309,7 +312,14 @@ export const ButtonCard = memo(function ButtonCard({ button, index, onEdit, onDu
                     {button.qos !== 'atmostonce' && <span className="badge">{qosLabels[button.qos]}</span>}
                     {button.retain && <span className="badge">Retain</span>}
                     {button.multiSendEnabled && <span className="badge"><Repeat size={12} /> {formatInterval(button.multiSendInterval || 1000)}</span>}
+                    {button.response && <span className="badge">Request</span>}
                 </div>
+                {lastReply && (
+                    <div className="detail-row">
+                        <span className="detail-label">Reply ({lastReply.roundTripMs} ms):</span>
+                        <code className="detail-value">{lastReply.payload}</code>
+                    </div>
+                )}
             </div>
 
             <button
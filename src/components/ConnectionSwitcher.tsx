@@ -1,13 +1,14 @@
 import { useState, useRef, useEffect } from 'react';
-import { ChevronDown, Plus, Upload, Wifi, WifiOff, Loader } from 'lucide-react';
+import { ChevronDown, Plus, Upload, Server, Wifi, WifiOff, Loader } from 'lucide-react';
 import { useApp } from '../contexts/AppContext';
 
 interface ConnectionSwitcherProps {
     onAddNew: () => void;
     onImport: () => void;
+    onUseEmbeddedBroker: () => void;
 }
 
-export function ConnectionSwitcher({ onAddNew, onImport }: ConnectionSwitcherProps) {
+export function ConnectionSwitcher({ onAddNew, onImport, onUseEmbeddedBroker }: ConnectionSwitcherProps) {
     const { data, activeConnection, connectionStatus, switchConnection } = useApp();
     const [isOpen, setIsOpen] = useState(false);
     const dropdownRef = useRef<HTMLDivElement>(null);
@@ -52,6 +53,11 @@ export function ConnectionSwitcher({ onAddNew, onImport }: ConnectionSwitcherPro
         onImport();
     };
 
+    const handleUseEmbeddedBroker = () => {
+        setIsOpen(false);
+        onUseEmbeddedBroker();
+    };
+
     if (!activeConnection) return null;
 
     return (
@@ -83,6 +89,10 @@ export function ConnectionSwitcher({ onAddNew, onImport }: ConnectionSwitcherPro
                         <Upload size={16} />
                         <span>Import Connection</span>
                     </button>
+                    <button className="connection-option add-new" onClick={handleUseEmbeddedBroker}>
+                        <Server size={16} />
+                        <span>Use Embedded Broker</span>
+                    </button>
                 </div>
             )}
         </div>
@@ -15,7 +15,7 @@ import { ConnectionEditor } from './ConnectionEditor';
 import type { Button } from '../types';
 
 export function Dashboard() {
-    const { activeConnection, error, deleteConnection, deleteButton, reorderButtons, importConnection } = useApp();
+    const { activeConnection, error, deleteConnection, deleteButton, reorderButtons, importConnections, connectEmbeddedBroker } = useApp();
     const [showEditor, setShowEditor] = useState(false);
     const [editingButton, setEditingButton] = useState<Button | undefined>();
     const [showVariables, setShowVariables] = useState(false);
@@ -217,9 +217,18 @@ export function Dashboard() {
     };
 
     const handleImport = async () => {
-        const connectionData = await api.importConnection();
-        if (connectionData) {
-            await importConnection(connectionData);
+        try {
+            await importConnections();
+        } catch {
+            // error is surfaced through the app context
+        }
+    };
+
+    const handleUseEmbeddedBroker = async () => {
+        try {
+            await connectEmbeddedBroker();
+        } catch {
+            // error is surfaced through the app context
         }
     };
 
@@ -242,7 +251,7 @@ export function Dashboard() {
         <div className="dashboard">
             <header className="dashboard-header">
                 <div className="header-left">
-                    <ConnectionSwitcher onAddNew={handleAddConnection} onImport={handleImport} />
+                    <ConnectionSwitcher onAddNew={handleAddConnection} onImport={handleImport} onUseEmbeddedBroker={handleUseEmbeddedBroker} />
                     <ConnectionStatus />
                 </div>
                 <div className="header-right">
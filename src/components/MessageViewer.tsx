@@ -1,12 +1,14 @@
 import { useState, useEffect, useRef } from 'react';
 import { listen } from '@tauri-apps/api/event';
-import { ChevronDown, ChevronRight, Plus, X, Trash2 } from 'lucide-react';
-import type { Message, QoS } from '../types';
+import { ChevronDown, ChevronRight, Plus, X, Trash2, Filter } from 'lucide-react';
+import type { FilteredMessage, Message, MessageFilter, PayloadDiff, QoS } from '../types';
 import * as api from '../utils/api';
 import { useApp } from '../contexts/AppContext';
 import { substituteVariables } from '../utils/variables';
 import { preferences } from '../utils/preferences';
 
+const MAX_VIEW_MESSAGES = 100;
+
 interface MessageViewerProps {
     expanded: boolean;
     onToggle: (expanded: boolean) => void;
@@ -17,6 +19,10 @@ export function MessageViewer({ expanded, onToggle }: MessageViewerProps) {
     const [topic, setTopic] = useState('');
     const [subscriptions, setSubscriptions] = useState<string[]>([]);
     const [messages, setMessages] = useState<Message[]>([]);
+    const [topicFilter, setTopicFilter] = useState('');
+    const [payloadFilter, setPayloadFilter] = useState('');
+    const [filter, setFilter] = useState<MessageFilter>({});
+    const [filterError, setFilterError] = useState<string | null>(null);
     const [height, setHeight] = useState(() => preferences.messageViewerHeight);
     const messagesListRef = useRef<HTMLDivElement>(null);
     const wasAtBottomRef = useRef(true);
@@ -43,30 +49,80 @@ export function MessageViewer({ expanded, onToggle }: MessageViewerProps) {
     useEffect(() => {
         if (connectionStatus !== 'connected') {
             setSubscriptions([]);
-            setMessages([]);
             return;
         }
 
-        api.getMessages().then(setMessages).catch(() => { });
         resubscribeToSaved(savedSubscriptions);
+    }, [connectionStatus, savedSubscriptions]);
+
+    // Streams the messages matching the filter. Messages arriving before
+    // the initial query returns are held back and appended after it.
+    useEffect(() => {
+        if (connectionStatus !== 'connected') return;
+
+        let streamId: string | null = null;
+        let stopped = false;
+        let loaded = false;
+        const early: FilteredMessage[] = [];
 
-        const unlisten = listen<Message>('mqtt-message', (event) => {
+        const append = (incoming: Message[]) => {
             const list = messagesListRef.current;
             if (list) {
                 const threshold = 10;
                 wasAtBottomRef.current = list.scrollHeight - list.scrollTop - list.clientHeight < threshold;
             }
-            setMessages((prev) => {
-                const updated = [...prev, event.payload];
-                if (updated.length > 100) updated.shift();
-                return updated;
-            });
+            setMessages((prev) => [...prev, ...incoming].slice(-MAX_VIEW_MESSAGES));
+        };
+
+        const unlisten = listen<FilteredMessage>('mqtt-message-filtered', (event) => {
+            if (!loaded || streamId === null) {
+                early.push(event.payload);
+            } else if (event.payload.streamId === streamId) {
+                append([event.payload.message]);
+            }
         });
 
+        const stopStream = () => {
+            stopped = true;
+            if (streamId) api.stopMessageStream(streamId).catch(() => { });
+        };
+        window.addEventListener('beforeunload', stopStream);
+
+        (async () => {
+            try {
+                await unlisten;
+                streamId = await api.startMessageStream(filter);
+                if (stopped) {
+                    stopStream();
+                    return;
+                }
+                const initial = await api.queryMessages(filter, MAX_VIEW_MESSAGES);
+                const last = initial[initial.length - 1];
+                const missed = early
+                    .filter((m) => m.streamId === streamId)
+                    .map((m) => m.message)
+                    .filter((m) => !last || m.id > last.id);
+                loaded = true;
+                setFilterError(null);
+                setMessages([...initial, ...missed].slice(-MAX_VIEW_MESSAGES));
+            } catch (e) {
+                setFilterError(e instanceof Error ? e.message : String(e));
+                setMessages([]);
+            }
+        })();
+
         return () => {
+            window.removeEventListener('beforeunload', stopStream);
+            stopStream();
             unlisten.then((fn) => fn());
         };
-    }, [connectionStatus, savedSubscriptions]);
+    }, [connectionStatus, filter]);
+
+    useEffect(() => {
+        if (connectionStatus !== 'connected') {
+            setMessages([]);
+        }
+    }, [connectionStatus]);
 
     useEffect(() => {
         if (connectionStatus !== 'connected' || subscriptions.length === 0) return;
@@ -123,6 +179,14 @@ export function MessageViewer({ expanded, onToggle }: MessageViewerProps) {
         }
     };
 
+    const handleFilter = (e: React.FormEvent) => {
+        e.preventDefault();
+        setFilter({
+            topic: topicFilter.trim() || undefined,
+            payloadContains: payloadFilter || undefined,
+        });
+    };
+
     const handleClear = async () => {
         try {
             await api.clearMessages();
@@ -210,6 +274,30 @@ export function MessageViewer({ expanded, onToggle }: MessageViewerProps) {
                     </div>
 
                     <div className="messages-area">
+                        <form className="subscribe-form message-filter" onSubmit={handleFilter}>
+                            <input
+                                type="text"
+                                placeholder="Topic filter, e.g. sensors/#"
+                                value={topicFilter}
+                                onChange={(e) => setTopicFilter(e.target.value)}
+                                disabled={!isConnected}
+                                autoCorrect="off"
+                                autoCapitalize="off"
+                                spellCheck={false}
+                            />
+                            <input
+                                type="text"
+                                placeholder="Payload contains..."
+                                value={payloadFilter}
+                                onChange={(e) => setPayloadFilter(e.target.value)}
+                                disabled={!isConnected}
+                                spellCheck={false}
+                            />
+                            <button type="submit" className="btn btn-small" disabled={!isConnected} title="Apply filter">
+                                <Filter size={14} />
+                            </button>
+                        </form>
+                        {filterError && <div className="message-filter-error">{filterError}</div>}
                         {messages.length === 0 ? (
                             <div className="empty-messages">
                                 {subscriptions.length === 0
@@ -225,13 +313,34 @@ export function MessageViewer({ expanded, onToggle }: MessageViewerProps) {
                                     </button>
                                 </div>
                                 <div className="messages-list" ref={messagesListRef}>
-                                    {messages.map((msg, i) => (
-                                        <div key={`${msg.timestamp}-${i}`} className="message-item">
+                                    {messages.map((msg) => (
+                                        <div key={msg.id} className="message-item">
                                             <div className="message-meta">
                                                 <code className="message-topic">{msg.topic}</code>
+                                                {msg.decoded && (
+                                                    <span
+                                                        className="message-format"
+                                                        title={msg.decoded.error ?? undefined}
+                                                    >
+                                                        {msg.decoded.compression && `${msg.decoded.compression} · `}
+                                                        {msg.decoded.format}{msg.decoded.error && ' !'}
+                                                    </span>
+                                                )}
+                                                {msg.diff && (
+                                                    <span className="message-diff" title={describeDiff(msg.diff)}>
+                                                        {msg.diff.kind === 'json' ? msg.diff.changes.length : msg.diff.ranges.length} changed
+                                                    </span>
+                                                )}
+                                                {msg.violations && msg.violations.length > 0 && (
+                                                    <span className="message-violations" title={msg.violations.join('\n')}>
+                                                        schema
+                                                    </span>
+                                                )}
                                                 <span className="message-time">{formatTime(msg.timestamp)}</span>
                                             </div>
-                                            <pre className="message-payload">{msg.payload || '(empty)'}</pre>
+                                            <pre className="message-payload">
+                                                {msg.decoded?.text ?? (msg.payload || '(empty)')}
+                                            </pre>
                                         </div>
                                     ))}
                                 </div>
@@ -244,3 +353,11 @@ export function MessageViewer({ expanded, onToggle }: MessageViewerProps) {
         </div>
     );
 }
+
+function describeDiff(diff: PayloadDiff): string {
+    const lines = diff.kind === 'json'
+        ? diff.changes.map((c) => `${c.op} ${c.path}`)
+        : diff.ranges.map((r) => `@${r.offset}: ${r.before || '-'} → ${r.after || '-'}`);
+    if (diff.truncated) lines.push('…');
+    return lines.length > 0 ? lines.join('\n') : 'No changes';
+}
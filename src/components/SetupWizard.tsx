@@ -1,10 +1,9 @@
 import { useState } from 'react';
 import type { Connection } from '../types';
 import { useApp } from '../contexts/AppContext';
-import { importConnection } from '../utils/api';
 
 export function SetupWizard() {
-    const { addConnection } = useApp();
+    const { addConnection, importConnections } = useApp();
     const [step, setStep] = useState(1);
     const [error, setError] = useState<string | null>(null);
     const [saving, setSaving] = useState(false);
@@ -24,18 +23,10 @@ export function SetupWizard() {
         setError(null);
         setSaving(true);
         try {
-            const imported = await importConnection();
+            const imported = await importConnections();
             if (!imported) {
                 setSaving(false);
-                return;
             }
-
-            const connection: Connection = {
-                ...imported,
-                id: crypto.randomUUID(),
-            };
-
-            await addConnection(connection);
         } catch (e) {
             setError(e instanceof Error ? e.message : 'Failed to import connection');
             setSaving(false);
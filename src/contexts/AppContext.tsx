@@ -1,6 +1,6 @@
 import { createContext, useContext, useState, useEffect } from 'react';
 import { listen } from '@tauri-apps/api/event';
-import type { AppData, Connection, Button, ConnectionStatus } from '../types';
+import type { AppData, Connection, Button, ConnectionStatus, ConflictStrategy, Reply } from '../types';
 import * as api from '../utils/api';
 
 interface AppContextType {
@@ -10,7 +10,8 @@ interface AppContextType {
     loading: boolean;
     error: string | null;
     addConnection: (connection: Connection) => Promise<void>;
-    importConnection: (connection: Omit<Connection, 'id'>) => Promise<void>;
+    importConnections: (strategy?: ConflictStrategy) => Promise<boolean>;
+    connectEmbeddedBroker: () => Promise<void>;
     updateConnection: (connection: Connection) => Promise<void>;
     deleteConnection: (id: string) => Promise<void>;
     switchConnection: (id: string) => Promise<void>;
@@ -23,6 +24,9 @@ interface AppContextType {
     connect: () => Promise<void>;
     disconnect: () => Promise<void>;
     publishButton: (button: Button) => Promise<void>;
+    sendRequest: (button: Button) => Promise<Reply | null>;
+    startRepeat: (button: Button) => Promise<void>;
+    stopRepeat: (buttonId: string) => Promise<void>;
     resetAll: () => void;
 }
 
@@ -120,12 +124,50 @@ export function AppProvider({ children }: { children: React.ReactNode }) {
         await tryAutoConnect(connection);
     }
 
-    async function importConnection(connectionData: Omit<Connection, 'id'>) {
-        const connection: Connection = {
-            ...connectionData,
-            id: crypto.randomUUID(),
-        };
-        await addConnection(connection);
+    async function importConnections(strategy: ConflictStrategy = 'merge') {
+        let result;
+        try {
+            result = await api.importConnections(strategy);
+        } catch (e) {
+            const msg = e instanceof Error ? e.message : String(e);
+            setError(msg);
+            throw new Error(msg);
+        }
+        if (!result) return false;
+
+        const importedId = result.summary.connection_ids[0];
+        if (importedId && importedId !== activeConnectionId) {
+            await tryDisconnect();
+        }
+        setData(result.data);
+        setError(null);
+        if (importedId && importedId !== activeConnectionId) {
+            setActiveConnectionId(importedId);
+            await tryAutoConnect(result.data.connections.find((c) => c.id === importedId));
+        }
+        return true;
+    }
+
+    async function connectEmbeddedBroker() {
+        let newData;
+        try {
+            newData = await api.addEmbeddedBrokerConnection();
+        } catch (e) {
+            const msg = e instanceof Error ? e.message : String(e);
+            setError(msg);
+            throw new Error(msg);
+        }
+
+        const brokerId = newData.last_connection_id;
+        if (brokerId !== activeConnectionId) {
+            await tryDisconnect();
+        }
+        setData(newData);
+        setError(null);
+        if (brokerId && brokerId !== activeConnectionId) {
+            setActiveConnectionId(brokerId);
+            await tryAutoConnect(newData.connections.find((c) => c.id === brokerId));
+        }
     }
 
     async function updateConnection(connection: Connection) {
@@ -140,7 +182,12 @@ export function AppProvider({ children }: { children: React.ReactNode }) {
         const newLastConnectionId =
             data.last_connection_id === id ? newConnections[0]?.id : data.last_connection_id;
 
-        await saveData({ connections: newConnections, last_connection_id: newLastConnectionId });
+        await saveData({
+            ...data,
+            connections: newConnections,
+            last_connection_id: newLastConnectionId,
+            schedules: data.schedules?.filter((s) => s.connectionId !== id),
+        });
 
         if (activeConnectionId === id) {
             await tryDisconnect();
@@ -233,6 +280,36 @@ export function AppProvider({ children }: { children: React.ReactNode }) {
         }
     }
 
+    async function sendRequest(button: Button) {
+        if (!activeConnection) return null;
+        try {
+            return await api.sendRequest(button, activeConnection.variables);
+        } catch (e) {
+            const msg = e instanceof Error ? e.message : String(e);
+            setError(msg);
+            throw new Error(msg);
+        }
+    }
+
+    async function startRepeat(button: Button) {
+        if (!activeConnection) return;
+        try {
+            await api.startRepeat(button, activeConnection.variables);
+        } catch (e) {
+            const msg = e instanceof Error ? e.message : 'Failed to start multi-send';
+            setError(msg);
+            throw new Error(msg);
+        }
+    }
+
+    async function stopRepeat(buttonId: string) {
+        try {
+            await api.stopRepeat(buttonId);
+        } catch (e) {
+            console.error('Stopping multi-send failed:', e);
+        }
+    }
+
     function resetAll() {
         setData({ connections: [] });
         setActiveConnectionId(null);
@@ -261,7 +338,8 @@ export function AppProvider({ children }: { children: React.ReactNode }) {
                 loading,
                 error,
                 addConnection,
-                importConnection,
+                importConnections,
+                connectEmbeddedBroker,
                 updateConnection,
                 deleteConnection,
                 switchConnection,
@@ -274,6 +352,9 @@ export function AppProvider({ children }: { children: React.ReactNode }) {
                 connect,
                 disconnect,
                 publishButton,
+                sendRequest,
+                startRepeat,
+                stopRepeat,
                 resetAll,
             }}
         >
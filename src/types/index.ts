@@ -12,6 +12,96 @@ export interface Button {
     color?: ButtonColor;
     multiSendEnabled?: boolean;
     multiSendInterval?: number;
+    multiSendMaxCount?: number;
+    multiSendMaxDuration?: number;
+    multiSendJitter?: number;
+    response?: ResponseConfig;
+    schemaId?: string;
+    compression?: Compression;
+}
+
+export interface ResponseConfig {
+    topic: string;
+    timeoutMs: number;
+    correlationPath?: string;
+    mqtt5?: boolean;
+}
+
+export interface Reply {
+    topic: string;
+    payload: string;
+    roundTripMs: number;
+    correlationId?: string;
+}
+
+export interface RepeatStatus {
+    buttonId: string;
+    intervalMs: number;
+    count: number;
+    startedAt: number;
+    active: boolean;
+    error?: string;
+}
+
+export type SequenceStep =
+    | { type: 'publishButton'; buttonId: string }
+    | { type: 'publish'; topic: string; payload?: string; qos: QoS; retain: boolean }
+    | { type: 'wait'; durationMs: number }
+    | {
+          type: 'waitForMessage';
+          topicFilter: string;
+          payloadContains?: string;
+          timeoutMs: number;
+          storeAs?: string;
+          continueOnTimeout?: boolean;
+      }
+    | { type: 'setVariable'; name: string; value: string };
+
+export interface ResponderRule {
+    id: string;
+    name: string;
+    enabled: boolean;
+    topicFilter: string;
+    payloadPattern?: string;
+    jsonPath?: string;
+    jsonValue?: string;
+    responseTopic: string;
+    responsePayload?: string;
+    qos: QoS;
+    retain: boolean;
+    delayMs?: number;
+    probability?: number;
+    minIntervalMs?: number;
+}
+
+export interface Sequence {
+    id: string;
+    name: string;
+    steps: SequenceStep[];
+    color?: ButtonColor;
+}
+
+export interface SequenceProgress {
+    runId: string;
+    sequenceId: string;
+    status: 'started' | 'running' | 'completed' | 'failed' | 'cancelled';
+    step?: number;
+    totalSteps: number;
+    message?: string;
+}
+
+export interface Script {
+    id: string;
+    name: string;
+    source: string;
+    timeoutMs?: number;
+}
+
+export interface ScriptOutput {
+    runId: string;
+    scriptId: string;
+    kind: 'started' | 'log' | 'completed' | 'failed' | 'cancelled';
+    message?: string;
 }
 
 export interface Connection {
@@ -27,17 +117,304 @@ export interface Connection {
     variables: Record<string, string>;
     buttons: Button[];
     subscriptions: string[];
+    sequences?: Sequence[];
+    responders?: ResponderRule[];
+    scripts?: Script[];
+    decoders?: DecoderRule[];
+    message_schemas?: SchemaRule[];
+    auto_decompress?: boolean;
+    diff_previous?: boolean;
+    project_file?: string;
+}
+
+export type ScheduleTimezone = 'local' | 'utc';
+
+export interface Schedule {
+    id: string;
+    name: string;
+    connectionId: string;
+    buttonId: string;
+    cron: string;
+    timezone: ScheduleTimezone;
+    enabled: boolean;
+}
+
+export interface ScheduleRun {
+    scheduleId: string;
+    scheduleName: string;
+    timestamp: number;
+    topic?: string;
+    success: boolean;
+    error?: string;
 }
 
 export interface AppData {
     connections: Connection[];
     last_connection_id?: string;
+    schedules?: Schedule[];
+    schemas?: JsonSchema[];
+    http_api?: HttpApiSettings;
+    embedded_broker?: EmbeddedBrokerSettings;
+}
+
+export interface HttpApiSettings {
+    enabled: boolean;
+    port: number;
+    token: string;
+}
+
+export interface HttpApiStatus {
+    url?: string;
+    error?: string;
+}
+
+export interface EmbeddedBrokerSettings {
+    enabled: boolean;
+    port: number;
+}
+
+export interface EmbeddedBrokerStatus {
+    url?: string;
+    error?: string;
+    restartRequired: boolean;
+}
+
+export interface JsonSchema {
+    id: string;
+    name: string;
+    schema: unknown;
+}
+
+export interface SchemaRule {
+    topicFilter: string;
+    schemaId: string;
+}
+
+export type ConflictStrategy = 'merge' | 'replace' | 'duplicate';
+
+export interface ImportSummary {
+    added: number;
+    merged: number;
+    replaced: number;
+    buttons_added: number;
+    buttons_updated: number;
+    connection_ids: string[];
+}
+
+export interface ImportResult {
+    data: AppData;
+    summary: ImportSummary;
 }
 
 export type ConnectionStatus = 'disconnected' | 'connecting' | 'connected' | 'error';
 
 export interface Message {
+    /** Unique per received message; 0 for messages not from the broker. */
+    id: number;
     topic: string;
     payload: string;
     timestamp: number;
+    qos?: QoS;
+    retain?: boolean;
+    /** Base64 of the payload when it is not valid UTF-8. */
+    raw?: string;
+    decoded?: Decoded;
+    violations?: string[];
+    diff?: PayloadDiff;
+}
+
+export type ExportFormat = 'csv' | 'ndjson' | 'json';
+
+export type ChangeOp = 'added' | 'removed' | 'changed';
+
+export interface JsonChange {
+    path: string;
+    op: ChangeOp;
+    before?: unknown;
+    after?: unknown;
+}
+
+export interface ByteRange {
+    offset: number;
+    before: string;
+    after: string;
+}
+
+export type PayloadDiff =
+    | { kind: 'json'; changes: JsonChange[]; truncated: boolean }
+    | { kind: 'bytes'; beforeLen: number; afterLen: number; ranges: ByteRange[]; truncated: boolean };
+
+export type DecoderKind = 'auto' | 'json' | 'cbor' | 'msgpack' | 'hex' | 'base64' | 'text' | 'protobuf' | 'sparkplug';
+
+export interface DecoderRule {
+    topicFilter: string;
+    decoder: DecoderKind;
+    descriptorSet?: string;
+    messageType?: string;
+    compression?: Compression;
+}
+
+export type Compression = 'gzip' | 'zlib' | 'zstd';
+
+export interface Decoded {
+    format: DecoderKind;
+    value?: unknown;
+    text: string;
+    error?: string;
+    compression?: Compression;
+}
+
+export type JsonOperator = 'exists' | 'equals' | 'notequals' | 'contains' | 'greater' | 'less';
+
+export interface JsonPredicate {
+    path: string;
+    operator: JsonOperator;
+    value?: string;
+}
+
+export interface MessageFilter {
+    topic?: string;
+    payloadContains?: string;
+    payloadPattern?: string;
+    json?: JsonPredicate[];
+    since?: number;
+    until?: number;
+}
+
+export interface FilteredMessage {
+    streamId: string;
+    message: Message;
+}
+
+export type SparkplugDataType =
+    | 'int8' | 'int16' | 'int32' | 'int64'
+    | 'uint8' | 'uint16' | 'uint32' | 'uint64'
+    | 'float' | 'double' | 'boolean' | 'string' | 'datetime' | 'text'
+    | 'uuid' | 'dataset' | 'bytes' | 'file' | 'template';
+
+export interface SparkplugMetric {
+    alias?: number;
+    datatype?: SparkplugDataType;
+    value: unknown;
+    timestamp?: number;
+}
+
+export interface SparkplugDevice {
+    online: boolean;
+    metrics: Record<string, SparkplugMetric>;
+}
+
+export interface SparkplugEdgeNode {
+    group: string;
+    edgeNode: string;
+    online: boolean;
+    seq?: number;
+    seqGaps: number;
+    lastSeen: number;
+    metrics: Record<string, SparkplugMetric>;
+    devices: Record<string, SparkplugDevice>;
+}
+
+export interface SparkplugCommandMetric {
+    name: string;
+    value: unknown;
+    datatype?: SparkplugDataType;
+}
+
+export interface RecordingStatus {
+    path: string;
+    startedAt: number;
+    count: number;
+    dropped: number;
+    active: boolean;
+    error?: string;
+}
+
+export interface TopicRewrite {
+    pattern: string;
+    replacement: string;
+}
+
+export interface ReplayOptions {
+    speed: number;
+    rewrites: TopicRewrite[];
+    loop: boolean;
+}
+
+export interface ReplayProgress {
+    path: string;
+    running: boolean;
+    cancelled: boolean;
+    published: number;
+    total: number;
+    iteration: number;
+    error?: string;
+}
+
+export interface SimulationConfig {
+    deviceCount: number;
+    clientIdPrefix: string;
+    telemetry: {
+        topic: string;
+        payload?: string;
+        intervalMs: number;
+        qos: QoS;
+        retain: boolean;
+    }[];
+    will?: {
+        topic: string;
+        payload?: string;
+        qos: QoS;
+        retain: boolean;
+    };
+    commandTopic?: string;
+    connectIntervalMs?: number;
+}
+
+export interface DeviceStatus {
+    n: number;
+    clientId: string;
+    status: ConnectionStatus;
+    sent: number;
+    received: number;
+    errors: number;
+    lastError?: string;
+}
+
+export interface SimulationStatus {
+    running: boolean;
+    connected: number;
+    sent: number;
+    devices: DeviceStatus[];
+}
+
+export interface BenchmarkConfig {
+    clientCount: number;
+    qos: QoS;
+    payloadSize: number;
+    topic?: string;
+    rate?: number;
+    messageCount?: number;
+    durationMs?: number;
+    measureLatency: boolean;
+}
+
+export interface BenchmarkReport {
+    running: boolean;
+    cancelled: boolean;
+    elapsedMs: number;
+    sent: number;
+    acked: number;
+    received: number;
+    errors: number;
+    throughput: number;
+    latency?: {
+        minMs: number;
+        meanMs: number;
+        p50Ms: number;
+        p90Ms: number;
+        p99Ms: number;
+        maxMs: number;
+    };
+    error?: string;
 }
@@ -9,15 +9,10 @@ vi.mock('@tauri-apps/plugin-dialog', () => ({
     open: vi.fn(),
 }));
 
-vi.mock('@tauri-apps/plugin-fs', () => ({
-    writeTextFile: vi.fn(),
-    readTextFile: vi.fn(),
-}));
-
+import { invoke } from '@tauri-apps/api/core';
 import { save, open } from '@tauri-apps/plugin-dialog';
-import { writeTextFile, readTextFile } from '@tauri-apps/plugin-fs';
-import { exportConnection, importConnection } from './api';
-import type { Connection } from '../types';
+import { exportConnection, importConnections } from './api';
+import type { Connection, ImportResult } from '../types';
 
 const mockConnection: Connection = {
     id: 'test-uuid-123',
@@ -53,12 +48,12 @@ describe('exportConnection', () => {
         const result = await exportConnection(mockConnection);
 
         expect(result).toBe(false);
-        expect(writeTextFile).not.toHaveBeenCalled();
+        expect(invoke).not.toHaveBeenCalled();
     });
 
-    it('should export connection without id field', async () => {
+    it('should export connection through the backend without secrets', async () => {
         vi.mocked(save).mockResolvedValue('/path/to/file.json');
-        vi.mocked(writeTextFile).mockResolvedValue(undefined);
+        vi.mocked(invoke).mockResolvedValue(undefined);
 
         const result = await exportConnection(mockConnection);
 
@@ -67,21 +62,28 @@ describe('exportConnection', () => {
             defaultPath: 'Test Connection.json',
             filters: [{ name: 'JSON', extensions: ['json'] }],
         });
+        expect(invoke).toHaveBeenCalledWith('export_connections', {
+            path: '/path/to/file.json',
+            connectionIds: ['test-uuid-123'],
+            includeSecrets: false,
+        });
+    });
 
-        const writeCall = vi.mocked(writeTextFile).mock.calls[0];
-        expect(writeCall[0]).toBe('/path/to/file.json');
+    it('should include secrets when requested', async () => {
+        vi.mocked(save).mockResolvedValue('/path/to/file.json');
+        vi.mocked(invoke).mockResolvedValue(undefined);
+
+        await exportConnection(mockConnection, true);
 
-        const writtenData = JSON.parse(writeCall[1] as string);
-        expect(writtenData).not.toHaveProperty('id');
-        expect(writtenData.name).toBe('Test Connection');
-        expect(writtenData.broker_url).toBe('broker.example.com');
-        expect(writtenData.variables).toEqual({ device_id: 'abc123' });
-        expect(writtenData.buttons).toHaveLength(1);
+        expect(invoke).toHaveBeenCalledWith(
+            'export_connections',
+            expect.objectContaining({ includeSecrets: true })
+        );
     });
 
     it('should use connection name as default filename', async () => {
         vi.mocked(save).mockResolvedValue('/path/to/file.json');
-        vi.mocked(writeTextFile).mockResolvedValue(undefined);
+        vi.mocked(invoke).mockResolvedValue(undefined);
 
         const connWithSpecialName = { ...mockConnection, name: 'My Special Connection' };
         await exportConnection(connWithSpecialName);
@@ -92,21 +94,21 @@ describe('exportConnection', () => {
             })
         );
     });
-
-    it('should format JSON with indentation', async () => {
-        vi.mocked(save).mockResolvedValue('/path/to/file.json');
-        vi.mocked(writeTextFile).mockResolvedValue(undefined);
-
-        await exportConnection(mockConnection);
-
-        const writeCall = vi.mocked(writeTextFile).mock.calls[0];
-        const writtenContent = writeCall[1] as string;
-        expect(writtenContent).toContain('\n');
-        expect(writtenContent).toContain('  ');
-    });
 });
 
-describe('importConnection', () => {
+describe('importConnections', () => {
+    const importResult: ImportResult = {
+        data: { connections: [mockConnection], last_connection_id: mockConnection.id },
+        summary: {
+            added: 1,
+            merged: 0,
+            replaced: 0,
+            buttons_added: 1,
+            buttons_updated: 0,
+            connection_ids: [mockConnection.id],
+        },
+    };
+
     beforeEach(() => {
         vi.clearAllMocks();
     });
@@ -114,96 +116,54 @@ describe('importConnection', () => {
     it('should return null if user cancels open dialog', async () => {
         vi.mocked(open).mockResolvedValue(null);
 
-        const result = await importConnection();
+        const result = await importConnections();
 
         expect(result).toBeNull();
-        expect(readTextFile).not.toHaveBeenCalled();
+        expect(invoke).not.toHaveBeenCalled();
     });
 
     it('should return null if open returns non-string (multiple files)', async () => {
         vi.mocked(open).mockResolvedValue(['/file1.json', '/file2.json']);
 
-        const result = await importConnection();
+        const result = await importConnections();
 
         expect(result).toBeNull();
     });
 
-    it('should parse and return connection data', async () => {
-        const connectionData = {
-            name: 'Imported Connection',
-            broker_url: 'imported.broker.com',
-            port: 8883,
-            client_id: 'imported-client',
-            use_tls: true,
-            auto_connect: false,
-            variables: { key: 'value' },
-            buttons: [],
-            subscriptions: [],
-        };
-
+    it('should import through the backend with merge by default', async () => {
         vi.mocked(open).mockResolvedValue('/path/to/import.json');
-        vi.mocked(readTextFile).mockResolvedValue(JSON.stringify(connectionData));
+        vi.mocked(invoke).mockResolvedValue(importResult);
 
-        const result = await importConnection();
+        const result = await importConnections();
 
-        expect(result).toEqual(connectionData);
+        expect(result).toEqual(importResult);
         expect(open).toHaveBeenCalledWith({
             filters: [{ name: 'JSON', extensions: ['json'] }],
         });
-        expect(readTextFile).toHaveBeenCalledWith('/path/to/import.json');
+        expect(invoke).toHaveBeenCalledWith('import_connections', {
+            path: '/path/to/import.json',
+            strategy: 'merge',
+        });
     });
 
-    it('should handle connection with all optional fields', async () => {
-        const connectionData = {
-            name: 'Full Connection',
-            broker_url: 'broker.example.com',
-            port: 1883,
-            client_id: 'client-123',
-            username: 'user',
-            password: 'pass',
-            use_tls: true,
-            auto_connect: true,
-            variables: { a: '1', b: '2' },
-            buttons: [{ id: 'b1', name: 'Button', topic: 't', qos: 'atleastonce', retain: true, color: 'blue' }],
-            subscriptions: ['topic/#'],
-        };
-
+    it('should pass the conflict strategy', async () => {
         vi.mocked(open).mockResolvedValue('/path/to/import.json');
-        vi.mocked(readTextFile).mockResolvedValue(JSON.stringify(connectionData));
-
-        const result = await importConnection();
-
-        expect(result).toEqual(connectionData);
-        expect(result?.username).toBe('user');
-        expect(result?.password).toBe('pass');
-    });
-});
+        vi.mocked(invoke).mockResolvedValue(importResult);
 
-describe('export/import roundtrip', () => {
-    it('should preserve all data except id through export/import', async () => {
-        let exportedContent = '';
+        await importConnections('replace');
 
-        vi.mocked(save).mockResolvedValue('/path/to/file.json');
-        vi.mocked(writeTextFile).mockImplementation(async (_path, content) => {
-            exportedContent = content as string;
+        expect(invoke).toHaveBeenCalledWith('import_connections', {
+            path: '/path/to/import.json',
+            strategy: 'replace',
         });
+    });
 
-        await exportConnection(mockConnection);
-
-        vi.mocked(open).mockResolvedValue('/path/to/file.json');
-        vi.mocked(readTextFile).mockResolvedValue(exportedContent);
-
-        const imported = await importConnection();
+    it('should propagate backend validation errors', async () => {
+        vi.mocked(open).mockResolvedValue('/path/to/import.json');
+        vi.mocked(invoke).mockRejectedValue(
+            'Invalid bundle: connections[0].port: must be between 1 and 65535'
+        );
 
-        expect(imported).not.toHaveProperty('id');
-        expect(imported?.name).toBe(mockConnection.name);
-        expect(imported?.broker_url).toBe(mockConnection.broker_url);
-        expect(imported?.port).toBe(mockConnection.port);
-        expect(imported?.client_id).toBe(mockConnection.client_id);
-        expect(imported?.use_tls).toBe(mockConnection.use_tls);
-        expect(imported?.auto_connect).toBe(mockConnection.auto_connect);
-        expect(imported?.variables).toEqual(mockConnection.variables);
-        expect(imported?.buttons).toEqual(mockConnection.buttons);
-        expect(imported?.subscriptions).toEqual(mockConnection.subscriptions);
+        await expect(importConnections()).rejects.toContain('connections[0].port');
     });
 });
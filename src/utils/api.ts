@@ -1,8 +1,6 @@
 import { invoke } from '@tauri-apps/api/core';
 import { save, open } from '@tauri-apps/plugin-dialog';
-import { writeTextFile, readTextFile } from '@tauri-apps/plugin-fs';
-import type { AppData, Connection, Button, QoS, Message } from '../types';
-import { substituteVariables } from './variables';
+import type { AppData, Connection, Button, QoS, Message, MessageFilter, PayloadDiff, ExportFormat, HttpApiStatus, EmbeddedBrokerStatus, ConflictStrategy, ImportResult, RepeatStatus, Reply } from '../types';
 
 export async function getData(): Promise<AppData> {
     return invoke<AppData>('get_data');
@@ -29,9 +27,19 @@ export async function publish(topic: string, payload: string, qos: QoS, retain:
 }
 
 export async function publishButton(button: Button, variables: Record<string, string>): Promise<void> {
-    const topic = substituteVariables(button.topic, variables);
-    const payload = button.payload ? substituteVariables(button.payload, variables) : '';
-    return publish(topic, payload, button.qos, button.retain);
+    return invoke('publish_button', { button, variables });
+}
+
+export async function sendRequest(button: Button, variables: Record<string, string>): Promise<Reply> {
+    return invoke<Reply>('send_request', { button, variables });
+}
+
+export async function startRepeat(button: Button, variables: Record<string, string>): Promise<RepeatStatus> {
+    return invoke<RepeatStatus>('start_repeat', { button, variables });
+}
+
+export async function stopRepeat(buttonId: string): Promise<void> {
+    return invoke('stop_repeat', { buttonId });
 }
 
 export async function subscribe(topic: string, qos: QoS): Promise<void> {
@@ -46,6 +54,33 @@ export async function getMessages(): Promise<Message[]> {
     return invoke<Message[]>('get_messages');
 }
 
+export async function queryMessages(filter: MessageFilter, limit?: number): Promise<Message[]> {
+    return invoke<Message[]>('query_messages', { filter, limit });
+}
+
+export async function exportMessages(format: ExportFormat, filter?: MessageFilter, limit?: number): Promise<number | null> {
+    const filePath = await save({
+        defaultPath: `messages.${format}`,
+        filters: [{ name: format.toUpperCase(), extensions: [format] }],
+    });
+
+    if (!filePath) return null;
+
+    return invoke<number>('export_messages', { path: filePath, format, filter, limit });
+}
+
+export async function diffMessages(before: number, after: number): Promise<PayloadDiff> {
+    return invoke<PayloadDiff>('diff_messages', { before, after });
+}
+
+export async function startMessageStream(filter: MessageFilter): Promise<string> {
+    return invoke<string>('start_message_stream', { filter });
+}
+
+export async function stopMessageStream(streamId: string): Promise<void> {
+    return invoke('stop_message_stream', { streamId });
+}
+
 export async function clearMessages(): Promise<void> {
     return invoke('clear_messages');
 }
@@ -54,7 +89,7 @@ export async function getSubscriptions(): Promise<string[]> {
     return invoke<string[]>('get_subscriptions');
 }
 
-export async function exportConnection(connection: Connection): Promise<boolean> {
+export async function exportConnection(connection: Connection, includeSecrets = false): Promise<boolean> {
     const filePath = await save({
         defaultPath: `${connection.name}.json`,
         filters: [{ name: 'JSON', extensions: ['json'] }],
@@ -62,22 +97,28 @@ export async function exportConnection(connection: Connection): Promise<boolean>
 
     if (!filePath) return false;
 
-    const { id, ...exportData } = connection;
-    await writeTextFile(filePath, JSON.stringify(exportData, null, 2));
+    await invoke('export_connections', { path: filePath, connectionIds: [connection.id], includeSecrets });
     return true;
 }
 
-export async function importConnection(): Promise<Omit<Connection, 'id'> | null> {
+export async function importConnections(strategy: ConflictStrategy = 'merge'): Promise<ImportResult | null> {
     const filePath = await open({
         filters: [{ name: 'JSON', extensions: ['json'] }],
     });
 
     if (!filePath || typeof filePath !== 'string') return null;
 
-    const content = await readTextFile(filePath);
-    try {
-        return JSON.parse(content);
-    } catch {
-        throw new Error('Invalid JSON file');
-    }
+    return invoke<ImportResult>('import_connections', { path: filePath, strategy });
+}
+
+export async function getHttpApiStatus(): Promise<HttpApiStatus> {
+    return invoke<HttpApiStatus>('get_http_api_status');
+}
+
+export async function getEmbeddedBrokerStatus(): Promise<EmbeddedBrokerStatus> {
+    return invoke<EmbeddedBrokerStatus>('get_embedded_broker_status');
+}
+
+export async function addEmbeddedBrokerConnection(): Promise<AppData> {
+    return invoke<AppData>('add_embedded_broker_connection');
 }